@@ -1,4 +1,5 @@
 use std::fmt;
+use std::path::Path;
 use wasmer_wasm_interface::Interface;
 
 /// The ABI is a hint to WebAssembly runtimes about what additional imports to insert.
@@ -46,6 +47,20 @@ impl Default for Abi {
 }
 
 impl Abi {
+    /// The ABIs the runtime can run, by the file name of the runtime executable. `wasmer-js`
+    /// has no emscripten support, other runtimes are expected to support every ABI.
+    pub fn supported_by_runtime(runtime: &str) -> &'static [Abi] {
+        let is_wasmer_js = Path::new(runtime)
+            .file_stem()
+            .map(|stem| stem == "wasmer-js")
+            .unwrap_or(false);
+        if is_wasmer_js {
+            &[Abi::None, Abi::Wasi]
+        } else {
+            &[Abi::None, Abi::Wasi, Abi::Emscripten]
+        }
+    }
+
     pub fn get_interface(&self) -> Option<Interface> {
         match self {
             Abi::Emscripten => None,
@@ -54,3 +69,33 @@ impl Abi {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::abi::Abi;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Module {
+        abi: Abi,
+    }
+
+    #[test]
+    fn abi_round_trips_as_lowercase_string() {
+        for (abi, name) in &[
+            (Abi::None, "none"),
+            (Abi::Wasi, "wasi"),
+            (Abi::Emscripten, "emscripten"),
+        ] {
+            let toml_string = toml::to_string(&Module { abi: *abi }).unwrap();
+            assert_eq!(format!("abi = \"{}\"\n", name), toml_string);
+            let module: Module = toml::from_str(&toml_string).unwrap();
+            assert_eq!(*abi, module.abi);
+        }
+    }
+
+    #[test]
+    fn unknown_abi_is_rejected() {
+        let result = toml::from_str::<Module>("abi = \"bogus\"");
+        assert!(result.is_err());
+    }
+}
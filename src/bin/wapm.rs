@@ -1,5 +1,6 @@
 use std::{env, path};
 use structopt::{clap::AppSettings, StructOpt};
+use wapm_cli::data::lock::lockfile::LockfileError;
 #[cfg(feature = "update-notifications")]
 use wapm_cli::update_notifier;
 use wapm_cli::{commands, logging};
@@ -63,6 +64,10 @@ enum Command {
     /// List the currently installed packages and their commands
     List(commands::ListOpt),
 
+    #[structopt(name = "graph")]
+    /// Print the dependency graph of the lockfile in the DOT language
+    Graph(commands::GraphOpt),
+
     #[cfg(feature = "packagesigning")]
     #[structopt(name = "keys")]
     /// Manage minisign keys for verifying packages
@@ -167,6 +172,7 @@ fn main() {
         Command::Validate(validate_options) => commands::validate(validate_options),
         Command::Init(init_options) => commands::init(init_options),
         Command::List(list_options) => commands::list(list_options),
+        Command::Graph(graph_options) => commands::graph(graph_options),
         #[cfg(feature = "packagesigning")]
         Command::Keys(key_options) => commands::keys(key_options),
         Command::Completions(completion_options) => {
@@ -210,6 +216,15 @@ fn main() {
         {
             drop(_guard);
         };
-        std::process::exit(-1);
+        // lockfile errors have their own exit codes, so scripts can tell them apart
+        let exit_code = result
+            .err()
+            .and_then(|e| {
+                e.chain()
+                    .find_map(|cause| cause.downcast_ref::<LockfileError>())
+                    .map(LockfileError::exit_code)
+            })
+            .unwrap_or(-1);
+        std::process::exit(exit_code);
     }
 }
@@ -1,6 +1,7 @@
 //! Module for wax, executes a module immediately
 
 //use crate::constants::RFC3339_FORMAT_STRING_WITH_TIMEZONE;
+use crate::commands::run::{check_runtime_support, ModuleToRun};
 use crate::config;
 use crate::data::wax_index;
 use crate::dataflow::find_command_result::FindCommandResult;
@@ -279,23 +280,30 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
             source,
             manifest_dir,
             args: _,
+            default_args,
             module_name,
+            abi,
             prehashed_cache_key,
+            origin,
         } => {
             debug!(
                 "Wax command found locally in {}",
                 current_dir.to_string_lossy()
             );
+            check_runtime_support(&module_name, abi)?;
             // run it and return
             crate::commands::run::do_run(
-                current_dir,
-                source,
-                manifest_dir,
-                command_name,
-                &module_name,
+                ModuleToRun {
+                    run_dir: current_dir,
+                    source,
+                    manifest_dir,
+                    command_name,
+                    module_name: &module_name,
+                    prehashed_cache_key,
+                    origin: &origin,
+                },
                 &opt.pre_opened_directories,
-                &opt.args,
-                prehashed_cache_key,
+                &crate::commands::run::with_default_args(&default_args, &opt.args),
             )?;
             return Ok(());
         }
@@ -477,6 +485,7 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
         let installed_packages = InstalledPackages::install::<RegistryInstaller>(
             &install_loc,
             resolved_packages,
+            None,
             !opt.verify_signature,
         )?;
         let added_lockfile_data = LockfilePackages::from_installed_packages(&installed_packages)
@@ -487,7 +496,7 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
         let final_lockfile_data =
             MergedLockfilePackages::merge(added_lockfile_data, retained_lockfile_packages);
         final_lockfile_data
-            .generate_lockfile(&install_loc)
+            .generate_lockfile(&install_loc, None, None)
             .map_err(|e| ExecuteError::InstallationError(e.to_string()))?;
 
         debug!("Wax package installed to {}", install_loc.to_string_lossy());
@@ -535,19 +544,26 @@ fn run(
             source,
             manifest_dir,
             args: _,
+            default_args,
             module_name,
+            abi,
             prehashed_cache_key,
+            origin,
         } => {
+            check_runtime_support(&module_name, abi)?;
             crate::logging::clear_stdout()?;
             return crate::commands::run::do_run(
-                location,
-                source,
-                manifest_dir,
-                command_name,
-                &module_name,
+                ModuleToRun {
+                    run_dir: location,
+                    source,
+                    manifest_dir,
+                    command_name,
+                    module_name: &module_name,
+                    prehashed_cache_key,
+                    origin: &origin,
+                },
                 pre_opened_directories,
-                args,
-                prehashed_cache_key,
+                &crate::commands::run::with_default_args(&default_args, args),
             );
         }
         FindCommandResult::Error(e) => return Err(e),
@@ -0,0 +1,33 @@
+//! Subcommand for printing the dependency graph of the lockfile
+
+use crate::dataflow::lockfile_packages::LockfileResult;
+use std::env;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct GraphOpt {
+    /// Print the graph as JSON instead of DOT
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+pub fn graph(options: GraphOpt) -> anyhow::Result<()> {
+    let cwd = env::current_dir()?;
+    let lockfile = match LockfileResult::find_in_directory(cwd) {
+        LockfileResult::Lockfile(lockfile) => lockfile.into_readonly(),
+        LockfileResult::NoLockfile => return Err(anyhow!("No lockfile in current directory")),
+        LockfileResult::LockfileError(e) => {
+            return Err(anyhow!(
+                "Failed to read lock file in current directory: {}",
+                e
+            ))
+        }
+    };
+    let graph = lockfile.dependency_graph();
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&graph)?);
+    } else {
+        print!("{}", graph.to_dot());
+    }
+    Ok(())
+}
@@ -6,10 +6,18 @@ use graphql_client::*;
 
 use crate::config::Config;
 use crate::dataflow;
+use crate::dataflow::cache_resolver::CacheResolver;
+use crate::dataflow::chain_resolver::{BoxedResolver, ChainResolver};
+use crate::dataflow::prefer_cache_resolver::PreferCacheResolver;
+use crate::dataflow::resolution_observer::{ResolutionEvent, ResolutionObserver};
+use crate::dataflow::resolution_plan::ResolutionPlan;
+use crate::dataflow::resolved_packages::{RegistryResolver, VersionSelector};
+use crate::dataflow::retry_resolver::RetryResolver;
 use crate::util;
 use std::borrow::Cow;
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use structopt::StructOpt;
 use thiserror::Error;
 
@@ -23,6 +31,50 @@ pub struct InstallOpt {
     /// Agree to all prompts. Useful for non-interactive uses. (WARNING: this may cause undesired behavior)
     #[structopt(long = "force-yes", short = "y")]
     force_yes: bool,
+    /// Print the packages that would be installed, kept, and removed without changing anything
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+    /// Install from a directory of downloaded package tarballs instead of the registry. Given
+    /// more than once, packages are installed from the first directory that has them
+    #[structopt(long = "cache-dir", parse(from_os_str))]
+    cache_dir: Vec<PathBuf>,
+    /// Resolve packages missing from the cache directories from the registry and download them
+    /// into the first cache directory
+    #[structopt(long = "populate-cache", requires = "cache-dir")]
+    populate_cache: bool,
+    /// Fail instead of updating the lockfile, e.g. when the manifest changed since it was locked
+    #[structopt(long = "frozen")]
+    frozen: bool,
+    /// Install from the lockfile alone, failing before any package would be resolved or fetched,
+    /// e.g. for builds without network access
+    #[structopt(long = "locked")]
+    locked: bool,
+    /// Comma separated features of the manifest that enable optional dependencies
+    #[structopt(long = "features", use_delimiter = true)]
+    features: Vec<String>,
+    /// Keep the locked version of a changed dependency when it can not be resolved, e.g. offline
+    #[structopt(long = "fallback-to-lockfile")]
+    fallback_to_lockfile: bool,
+    /// Install without the dev dependencies of the manifest, like consumers of the package
+    #[structopt(long = "no-dev")]
+    no_dev: bool,
+    /// Resolve this dependency of the manifest again, the other dependencies keep their locked
+    /// versions
+    #[structopt(long = "update")]
+    update: Vec<String>,
+    /// Resolve version ranges to the oldest matching version instead of the newest, e.g. to test
+    /// the minimum versions of the dependencies
+    #[structopt(long = "lowest-versions")]
+    lowest_versions: bool,
+    /// How often to retry resolving packages from the registry after a network error
+    #[structopt(long = "retries", default_value = "2")]
+    retries: u32,
+    /// Fail if the lockfile would have a longer chain of dependents than this, e.g. in CI
+    #[structopt(long = "max-depth")]
+    max_depth: Option<usize>,
+    /// Fail instead of warning when two commands differ only by case, e.g. `Run` and `run`
+    #[structopt(long = "deny-case-collisions")]
+    deny_case_collisions: bool,
 }
 
 #[derive(Debug, Error)]
@@ -34,10 +86,10 @@ enum InstallError {
     NoVersionsAvailable { name: String },
 
     #[error("Failed to install packages. {0}")]
-    CannotRegenLockFile(dataflow::Error),
+    CannotRegenLockFile(#[source] dataflow::Error),
 
     #[error("Failed to install packages in manifest. {0}")]
-    FailureInstallingPackages(dataflow::Error),
+    FailureInstallingPackages(#[source] dataflow::Error),
 
     #[error(
         "Failed to install package because package identifier {0} is invalid, expected <name>@<version> or <name>",
@@ -46,6 +98,8 @@ enum InstallError {
     InvalidPackageIdentifier { name: String },
     #[error("Must supply package names to install command when using --global/-g flag.")]
     MustSupplyPackagesWithGlobalFlag,
+    #[error("Must supply a version for package {name} when using --cache-dir, expected <name>@<version>")]
+    MustSupplyVersionWithCacheDir { name: String },
 }
 
 #[derive(GraphQLQuery)]
@@ -81,22 +135,38 @@ pub fn install(options: InstallOpt) -> anyhow::Result<()> {
             // install all global packages - unacceptable use case
             return Err(InstallError::MustSupplyPackagesWithGlobalFlag.into());
         }
+        (global_flag::LOCAL_INSTALL, package_args::NO_PACKAGES) if options.dry_run => {
+            let plan = dataflow::plan(
+                vec![],
+                vec![],
+                &current_directory,
+                &options.update_options(),
+            )
+            .map_err(InstallError::FailureInstallingPackages)?;
+            print_plan(&plan);
+        }
         (global_flag::LOCAL_INSTALL, package_args::NO_PACKAGES) => {
             // install all packages locally
             let added_packages = vec![];
-            dataflow::update(added_packages, vec![], &current_directory)
-                .map_err(|err| InstallError::FailureInstallingPackages(err))?;
+            update(added_packages, &current_directory, &options)
+                .map_err(InstallError::FailureInstallingPackages)?;
             println!("Packages installed to wapm_packages!");
         }
         (_, package_args::SOME_PACKAGES) => {
             let mut packages = vec![];
-            for name in options.packages {
+            for name in options.packages.iter() {
                 let name_with_version: Vec<&str> = name.split("@").collect();
 
                 match &name_with_version[..] {
                     [package_name, package_version] => {
                         packages.push((package_name.to_string(), package_version.to_string()));
                     }
+                    [name] if !options.cache_dir.is_empty() && !options.populate_cache => {
+                        return Err(InstallError::MustSupplyVersionWithCacheDir {
+                            name: name.to_string(),
+                        }
+                        .into());
+                    }
                     [name] => {
                         let q = GetPackageQuery::build_query(get_package_query::Variables {
                             name: name.to_string(),
@@ -138,8 +208,20 @@ pub fn install(options: InstallOpt) -> anyhow::Result<()> {
                 false => Cow::Borrowed(&current_directory),
             };
 
-            let changes_applied = dataflow::update(installed_packages, vec![], install_directory)
-                .map_err(|err| InstallError::CannotRegenLockFile(err))?;
+            if options.dry_run {
+                let plan = dataflow::plan(
+                    installed_packages,
+                    vec![],
+                    install_directory,
+                    &options.update_options(),
+                )
+                .map_err(InstallError::CannotRegenLockFile)?;
+                print_plan(&plan);
+                return Ok(());
+            }
+
+            let changes_applied = update(installed_packages, install_directory, &options)
+                .map_err(InstallError::CannotRegenLockFile)?;
 
             if changes_applied {
                 if options.global {
@@ -154,3 +236,209 @@ pub fn install(options: InstallOpt) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+impl InstallOpt {
+    fn update_options(&self) -> dataflow::UpdateOptions {
+        dataflow::UpdateOptions {
+            features: self.features.iter().cloned().collect(),
+            fallback_to_lockfile: self.fallback_to_lockfile,
+            exclude_dev_dependencies: self.no_dev,
+            update_packages: self.update.clone(),
+            max_depth: self.max_depth,
+            locked: self.locked,
+            deny_case_collisions: self.deny_case_collisions,
+        }
+    }
+
+    fn version_selector(&self) -> VersionSelector {
+        if self.lowest_versions {
+            VersionSelector::Lowest
+        } else {
+            VersionSelector::Highest
+        }
+    }
+
+    /// The registry resolver, retrying failed queries with a backoff of 500ms, then 1s, and so on
+    fn registry_resolver(&self) -> RetryResolver<RegistryResolver> {
+        RetryResolver::new(
+            RegistryResolver::new(self.version_selector()),
+            self.retries + 1,
+            Duration::from_millis(500),
+        )
+    }
+}
+
+/// Install the packages, resolving them from the cache directory if one was given. In frozen
+/// mode nothing is installed unless the lockfile would stay the same.
+fn update<P: AsRef<Path>>(
+    added_packages: Vec<(&str, &str)>,
+    directory: P,
+    options: &InstallOpt,
+) -> Result<bool, dataflow::Error> {
+    let update_options = options.update_options();
+    if options.frozen {
+        dataflow::plan(
+            added_packages.clone(),
+            vec![],
+            directory.as_ref(),
+            &update_options,
+        )?
+        .assert_unchanged()?;
+    }
+    let (changed, report) = match options.cache_dir.as_slice() {
+        [cache_dir, other_cache_dirs @ ..] if options.populate_cache => {
+            let mut resolvers = cache_resolvers(other_cache_dirs, options.version_selector());
+            let registry_resolver: BoxedResolver = Box::new(options.registry_resolver());
+            resolvers.push(("the registry".to_string(), registry_resolver));
+            dataflow::update_with_resolver_observed(
+                added_packages,
+                vec![],
+                directory,
+                &update_options,
+                &PreferCacheResolver::new(
+                    cache_dir,
+                    ChainResolver::new(resolvers),
+                    options.version_selector(),
+                ),
+                &LogObserver,
+            )
+        }
+        [cache_dir] => dataflow::update_with_resolver_observed(
+            added_packages,
+            vec![],
+            directory,
+            &update_options,
+            &CacheResolver::new(cache_dir, options.version_selector()),
+            &LogObserver,
+        ),
+        [] => dataflow::update_with_resolver_observed(
+            added_packages,
+            vec![],
+            directory,
+            &update_options,
+            &options.registry_resolver(),
+            &LogObserver,
+        ),
+        cache_dirs => dataflow::update_with_resolver_observed(
+            added_packages,
+            vec![],
+            directory,
+            &update_options,
+            &ChainResolver::new(cache_resolvers(cache_dirs, options.version_selector())),
+            &LogObserver,
+        ),
+    }?;
+    for warning in report.warnings {
+        warn!("{}", warning);
+    }
+    Ok(changed)
+}
+
+/// Logs the progress of an install as debug lines
+struct LogObserver;
+
+impl ResolutionObserver for LogObserver {
+    fn observe(&self, event: &ResolutionEvent) {
+        match event {
+            ResolutionEvent::Started => {}
+            ResolutionEvent::Resolving { packages } => {
+                debug!("Resolving {}", packages.join(", "))
+            }
+            ResolutionEvent::Resolved { packages, elapsed } => debug!(
+                "Resolved {} in {} ms",
+                packages.join(", "),
+                elapsed.as_millis()
+            ),
+            ResolutionEvent::ResolveFailed {
+                packages,
+                error,
+                elapsed,
+            } => debug!(
+                "Could not resolve {} in {} ms: {}",
+                packages.join(", "),
+                elapsed.as_millis(),
+                error
+            ),
+            ResolutionEvent::Finished { report, elapsed } => {
+                if let Some(report) = report {
+                    for (label, packages) in &[
+                        ("Reused", &report.reused),
+                        ("Fetched", &report.fetched),
+                        ("Removed", &report.removed),
+                    ] {
+                        if !packages.is_empty() {
+                            info!("{}: {}", label, packages.join(", "));
+                        }
+                    }
+                }
+                debug!("Finished in {} ms", elapsed.as_millis());
+            }
+        }
+    }
+}
+
+/// A cache resolver for each cache directory, named by the directory for errors
+fn cache_resolvers(
+    cache_dirs: &[PathBuf],
+    version_selector: VersionSelector,
+) -> Vec<(String, BoxedResolver)> {
+    cache_dirs
+        .iter()
+        .map(|cache_dir| {
+            let resolver: BoxedResolver = Box::new(CacheResolver::new(cache_dir, version_selector));
+            (cache_dir.display().to_string(), resolver)
+        })
+        .collect()
+}
+
+/// Print each group of packages in the plan, sorted by name
+fn print_plan(plan: &ResolutionPlan) {
+    let local_package = plan.local_package.as_ref();
+    // describe upgrades and downgrades of manifest dependencies
+    let mut installed: Vec<String> = plan
+        .added_packages
+        .packages
+        .iter()
+        .map(
+            |key| match plan.changes.iter().find(|change| change.key() == key) {
+                Some(change) => change.to_string(),
+                None => key.to_string(),
+            },
+        )
+        .collect();
+    installed.extend(
+        plan.git_packages
+            .iter()
+            .map(|git_package| git_package.dependency.to_string()),
+    );
+    let mut retained: Vec<String> = plan
+        .retained_packages
+        .packages
+        .keys()
+        .map(|key| key.to_string())
+        .collect();
+    let mut removed: Vec<String> = plan
+        .removed_packages
+        .packages
+        .keys()
+        .filter(|key| Some(*key) != local_package)
+        .map(|key| key.to_string())
+        .collect();
+    if installed.is_empty() && removed.is_empty() {
+        println!("No packages to install");
+    }
+    for (title, packages) in &mut [
+        ("Packages to install:", &mut installed),
+        ("Packages to keep:", &mut retained),
+        ("Packages to remove:", &mut removed),
+    ] {
+        if packages.is_empty() {
+            continue;
+        }
+        packages.sort();
+        println!("{}", title);
+        for package in packages.iter() {
+            println!("  {}", package);
+        }
+    }
+}
@@ -16,9 +16,29 @@ pub struct ListOpt {
     /// List both locally and globally installed packages
     #[structopt(short = "a", long = "all")]
     all: bool,
+
+    /// Print the locked packages as `name==version` lines, like a pip requirements file
+    #[structopt(long = "requirements", conflicts_with = "all")]
+    requirements: bool,
 }
 
 pub fn list(options: ListOpt) -> anyhow::Result<()> {
+    if options.requirements {
+        let directory = if options.global {
+            config::Config::get_globals_directory()?
+        } else {
+            env::current_dir()?
+        };
+        match LockfileResult::find_in_directory(directory) {
+            LockfileResult::Lockfile(lockfile) => print!("{}", lockfile.to_requirements_txt()),
+            LockfileResult::NoLockfile => {}
+            LockfileResult::LockfileError(e) => {
+                return Err(anyhow!("Failed to read lock file: {}", e));
+            }
+        }
+        return Ok(());
+    }
+
     let mut local = false;
     let mut global = false;
     match (options.global, options.all) {
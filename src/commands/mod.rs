@@ -5,6 +5,7 @@ mod bin;
 mod completions;
 mod config;
 mod execute;
+mod graph;
 mod init;
 mod install;
 mod keys;
@@ -24,6 +25,7 @@ pub use self::bin::{bin, BinOpt};
 pub use self::completions::CompletionOpt;
 pub use self::config::{config, ConfigOpt};
 pub use self::execute::{execute, ExecuteOpt};
+pub use self::graph::{graph, GraphOpt};
 pub use self::init::{init, InitOpt};
 pub use self::install::{install, InstallOpt};
 pub use self::keys::{keys, KeyOpt};
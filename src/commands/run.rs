@@ -1,9 +1,13 @@
+use crate::abi::Abi;
 use crate::config::Config;
 use crate::constants::DEFAULT_RUNTIME;
 use crate::data::lock::is_lockfile_out_of_date;
+use crate::data::lock::lockfile::{Lockfile, LockfileError};
+use crate::data::lock::lockfile_module::ModuleOrigin;
 use crate::dataflow;
 use crate::dataflow::find_command_result;
 use crate::dataflow::find_command_result::get_command_from_anywhere;
+use crate::dataflow::lockfile_packages::LockfileResult;
 use crate::dataflow::manifest_packages::ManifestResult;
 use crate::util::get_runtime_with_args;
 use std::env;
@@ -15,7 +19,7 @@ use thiserror::Error;
 
 #[derive(StructOpt, Debug)]
 pub struct RunOpt {
-    /// Command name
+    /// Command name, optionally with the package that provides it, e.g. `_/foo:run`
     command: String,
     /// WASI pre-opened directory
     #[structopt(long = "dir", multiple = true, group = "wasi")]
@@ -32,7 +36,7 @@ pub fn run(run_options: RunOpt) -> anyhow::Result<()> {
 
     // always update the local lockfile if the manifest has changed
     match is_lockfile_out_of_date(&current_dir) {
-        Ok(false) => {}
+        Ok(false) => warn_if_inconsistent(&current_dir),
         _ => dataflow::update(vec![], vec![], &current_dir)
             .map(|_| ())
             .map_err(|e| RunError::CannotRegenLockfile(command_name.to_string(), e))?,
@@ -42,11 +46,28 @@ pub fn run(run_options: RunOpt) -> anyhow::Result<()> {
         source: source_path_buf,
         manifest_dir,
         args: _,
+        default_args,
         module_name,
+        abi,
         is_global,
         prehashed_cache_key,
+        origin,
     } = match get_command_from_anywhere(command_name) {
         Err(find_command_result::Error::CommandNotFound(command)) => {
+            // a typo of a local command is more likely than a command of another package
+            if let LockfileResult::Lockfile(lockfile) =
+                LockfileResult::find_in_directory(&current_dir)
+            {
+                if let Err(
+                    e @ LockfileError::CommandNotFound {
+                        suggestion: Some(_),
+                        ..
+                    },
+                ) = lockfile.get_command(&command)
+                {
+                    return Err(e.into());
+                }
+            }
             let package_info = find_command_result::PackageInfoFromCommand::get(command)?;
             return Err(anyhow!("Command {} not found, but package {} version {} has this command. You can install it with `wapm install {}@{}`",
                   &package_info.command,
@@ -67,28 +88,95 @@ pub fn run(run_options: RunOpt) -> anyhow::Result<()> {
 
     let manifest_dir = run_dir.join(manifest_dir);
 
+    check_runtime_support(&module_name, abi)?;
+
     do_run(
-        run_dir,
-        source_path_buf,
-        manifest_dir,
-        command_name,
-        &module_name,
+        ModuleToRun {
+            run_dir,
+            source: source_path_buf,
+            manifest_dir,
+            command_name,
+            module_name: &module_name,
+            prehashed_cache_key,
+            origin: &origin,
+        },
         &run_options.pre_opened_directories,
-        &args,
-        prehashed_cache_key,
+        &with_default_args(&default_args, args),
     )
 }
 
+/// Errors if the runtime does not support the ABI of the module that is run, see
+/// `Lockfile::check_module_runtime_support`
+pub(crate) fn check_runtime_support(module_name: &str, abi: Abi) -> Result<(), LockfileError> {
+    let (runtime, _) = get_runtime_with_args();
+    Lockfile::check_module_runtime_support(module_name, abi, Abi::supported_by_runtime(&runtime))
+}
+
+/// The arguments of a command followed by the arguments it is run with
+pub(crate) fn with_default_args(default_args: &[String], args: &[OsString]) -> Vec<OsString> {
+    default_args
+        .iter()
+        .map(OsString::from)
+        .chain(args.iter().cloned())
+        .collect()
+}
+
+/// Warns if the lockfile is newer than the manifest, but still does not have all of its
+/// dependencies and commands, e.g. after checking out an older lockfile
+fn warn_if_inconsistent(directory: &Path) {
+    let manifest = match ManifestResult::find_in_directory(directory) {
+        ManifestResult::Manifest(manifest) => manifest,
+        _ => return,
+    };
+    if let LockfileResult::Lockfile(lockfile) = LockfileResult::find_in_directory(directory) {
+        let report = lockfile.consistency_report(&manifest);
+        if !report.is_consistent() {
+            let missing: Vec<String> = report
+                .missing_dependencies
+                .into_iter()
+                .chain(
+                    report
+                        .missing_commands
+                        .into_iter()
+                        .map(|command| format!("command {}", command)),
+                )
+                .collect();
+            warn!(
+                "The lockfile is missing {} from the manifest, run `wapm install` to update it",
+                missing.join(", ")
+            );
+        }
+    }
+}
+
+/// A module found for a command, and where to run it from
+pub(crate) struct ModuleToRun<'a> {
+    /// The directory the module is run in, the project or the globals directory
+    pub run_dir: PathBuf,
+    /// The wasm file, relative to `run_dir`
+    pub source: PathBuf,
+    /// The directory of the manifest of the module's package
+    pub manifest_dir: PathBuf,
+    pub command_name: &'a str,
+    pub module_name: &'a str,
+    pub prehashed_cache_key: Option<String>,
+    pub origin: &'a ModuleOrigin,
+}
+
 pub(crate) fn do_run(
-    run_dir: PathBuf,
-    source_path_buf: PathBuf,
-    manifest_dir: PathBuf,
-    command_name: &str,
-    module_name: &str,
+    module: ModuleToRun,
     pre_opened_directories: &[String],
     args: &[OsString],
-    prehashed_cache_key: Option<String>,
 ) -> anyhow::Result<()> {
+    let ModuleToRun {
+        run_dir,
+        source: source_path_buf,
+        manifest_dir,
+        command_name,
+        module_name,
+        prehashed_cache_key,
+        origin,
+    } = module;
     debug!(
         "Running module located at {:?}",
         &run_dir.join(&source_path_buf)
@@ -99,6 +187,7 @@ pub(crate) fn do_run(
             command_name.to_string(),
             module_name.to_string(),
             source_path_buf.to_string_lossy().to_string(),
+            origin.to_string(),
         )
     })?;
 
@@ -251,11 +340,11 @@ mod test {
 #[derive(Debug, Error)]
 enum RunError {
     #[error("Failed to run command \"{0}\". {1}")]
-    CannotRegenLockfile(String, dataflow::Error),
+    CannotRegenLockfile(String, #[source] dataflow::Error),
     #[error(
-        "The command \"{0}\" for module \"{1}\" is defined but the source at \"{2}\" does not exist.",
+        "The command \"{0}\" for module \"{1}\" is defined but the source at \"{2}\" from {3} does not exist.",
     )]
-    SourceForCommandNotFound(String, String, String),
+    SourceForCommandNotFound(String, String, String, String),
     #[error("Failed to run {0}: {1}")]
     ProcessFailed(String, String),
 }
@@ -0,0 +1,140 @@
+use crate::data::lock::lockfile::Lockfile;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// A package in the dependency graph
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GraphNode {
+    /// The package as `name@version`
+    pub package: String,
+    /// The locked modules of the package. Empty for dependents that are not locked, like the
+    /// manifest package.
+    pub modules: Vec<String>,
+}
+
+/// A dependency of one package on another, both as `name@version`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GraphEdge {
+    pub dependent: String,
+    pub dependency: String,
+}
+
+/// The packages of a lockfile and which packages required them, built from the `dependents` of
+/// the locked modules. Lockfiles without dependents, e.g. for global installs and older
+/// lockfiles, give a graph of nodes without edges.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DependencyGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl DependencyGraph {
+    pub fn new(lockfile: &Lockfile) -> Self {
+        // package -> modules
+        let mut nodes: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        // (dependent, dependency)
+        let mut edges: BTreeSet<(String, String)> = BTreeSet::new();
+        for (_, module) in lockfile.iter_modules() {
+            let package = format!("{}@{}", module.package_name, module.package_version);
+            nodes
+                .entry(package.clone())
+                .or_default()
+                .insert(module.name.clone());
+            for dependent in module.dependents.iter() {
+                edges.insert((dependent.clone(), package.clone()));
+            }
+        }
+        for (dependent, _) in edges.iter() {
+            nodes.entry(dependent.clone()).or_default();
+        }
+        DependencyGraph {
+            nodes: nodes
+                .into_iter()
+                .map(|(package, modules)| GraphNode {
+                    package,
+                    modules: modules.into_iter().collect(),
+                })
+                .collect(),
+            edges: edges
+                .into_iter()
+                .map(|(dependent, dependency)| GraphEdge {
+                    dependent,
+                    dependency,
+                })
+                .collect(),
+        }
+    }
+
+    /// The graph in the DOT language of Graphviz, e.g. for `wapm graph | dot -Tpng`
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dependencies {\n");
+        for node in self.nodes.iter() {
+            if node.modules.is_empty() {
+                writeln!(dot, "    {:?};", node.package).unwrap();
+            } else {
+                let label = format!("{}\n{}", node.package, node.modules.join(", "));
+                writeln!(dot, "    {:?} [label={:?}];", node.package, label).unwrap();
+            }
+        }
+        for edge in self.edges.iter() {
+            writeln!(dot, "    {:?} -> {:?};", edge.dependent, edge.dependency).unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::lock::lockfile::Lockfile;
+
+    #[test]
+    fn graph_from_dependents() {
+        let lockfile_toml = toml! {
+            [modules."_/foo"."1.0.0".foo]
+            name = "foo"
+            package_version = "1.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@1.0.0"
+            resolved = ""
+            resolved_source = "registry+foo"
+            abi = "none"
+            source = "foo.wasm"
+            dependents = ["_/test@0.1.0"]
+            [modules."_/bar"."2.0.0".bar]
+            name = "bar"
+            package_version = "2.0.0"
+            package_name = "_/bar"
+            package_path = "_/bar@2.0.0"
+            resolved = ""
+            resolved_source = "registry+bar"
+            abi = "none"
+            source = "bar.wasm"
+            dependents = ["_/foo@1.0.0"]
+            [commands]
+        };
+        let lockfile: Lockfile = lockfile_toml.try_into().unwrap();
+
+        let graph = lockfile.dependency_graph();
+        let packages: Vec<&str> = graph.nodes.iter().map(|n| n.package.as_str()).collect();
+        assert_eq!(vec!["_/bar@2.0.0", "_/foo@1.0.0", "_/test@0.1.0"], packages);
+        assert!(graph.nodes[2].modules.is_empty());
+        assert_eq!(2, graph.edges.len());
+        assert_eq!(
+            "digraph dependencies {\n    \"_/bar@2.0.0\" [label=\"_/bar@2.0.0\\nbar\"];\n    \"_/foo@1.0.0\" [label=\"_/foo@1.0.0\\nfoo\"];\n    \"_/test@0.1.0\";\n    \"_/foo@1.0.0\" -> \"_/bar@2.0.0\";\n    \"_/test@0.1.0\" -> \"_/foo@1.0.0\";\n}\n",
+            graph.to_dot()
+        );
+
+        let mut lockfile = lockfile;
+        for version_map in lockfile.modules.values_mut() {
+            for module_map in version_map.values_mut() {
+                for module in module_map.values_mut() {
+                    module.dependents.clear();
+                }
+            }
+        }
+        let graph = lockfile.dependency_graph();
+        assert_eq!(2, graph.nodes.len());
+        assert!(graph.edges.is_empty());
+    }
+}
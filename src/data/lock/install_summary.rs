@@ -0,0 +1,145 @@
+use crate::data::lock::lockfile::Lockfile;
+use std::collections::BTreeMap;
+
+/// A locked module in the install summary
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SummaryModule {
+    pub package: String,
+    pub version: String,
+    pub module: String,
+    /// Where the module was installed from, e.g. the download url of the package or `local`
+    pub source: String,
+    /// The sha256 of the wasm module as `sha256-<base64>`, if it was computed
+    pub integrity: Option<String>,
+}
+
+/// The module a command of the install summary runs
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SummaryCommand {
+    pub package: String,
+    pub version: String,
+    pub module: String,
+}
+
+/// The packages and commands of a lockfile for other tools, e.g. as the output of a CI step. The
+/// summary only has plain fields and stays the same when the layout of the lockfile changes, so
+/// tools should read it instead of the lockfile. New fields may be added, existing fields are not
+/// renamed or removed.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct InstallSummary {
+    /// Sorted by package, version, and module
+    pub modules: Vec<SummaryModule>,
+    /// Command name -> command
+    pub commands: BTreeMap<String, SummaryCommand>,
+}
+
+impl InstallSummary {
+    pub fn new(lockfile: &Lockfile) -> Self {
+        let mut modules: Vec<SummaryModule> = lockfile
+            .iter_modules()
+            .map(|(name, module)| SummaryModule {
+                package: module.package_name.clone(),
+                version: module.package_version.clone(),
+                module: name.to_string(),
+                source: module.resolved.clone(),
+                integrity: module.integrity.clone(),
+            })
+            .collect();
+        // the versions of the module map are sorted as strings
+        modules.sort_by(|a, b| {
+            (&a.package, version_key(&a.version), &a.module).cmp(&(
+                &b.package,
+                version_key(&b.version),
+                &b.module,
+            ))
+        });
+        let commands = lockfile
+            .iter_commands()
+            .map(|(name, command)| {
+                (
+                    name.to_string(),
+                    SummaryCommand {
+                        package: command.package_name.clone(),
+                        version: command.package_version.to_string(),
+                        module: command.module.clone(),
+                    },
+                )
+            })
+            .collect();
+        InstallSummary { modules, commands }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("the install summary only has plain fields")
+    }
+}
+
+fn version_key(version: &str) -> Result<semver::Version, &str> {
+    semver::Version::parse(version).map_err(|_| version)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::lock::lockfile::Lockfile;
+
+    #[test]
+    fn summary_json_snapshot() {
+        let lockfile_toml = toml! {
+            [modules."_/foo"."10.0.0".foo]
+            name = "foo"
+            package_version = "10.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@10.0.0"
+            resolved = "https://registry.wapm.io/foo-10.0.0.tar.gz"
+            resolved_source = "registry+foo"
+            abi = "wasi"
+            source = "foo.wasm"
+            integrity = "sha256-Zm9v"
+            [modules."_/foo"."9.0.0".foo]
+            name = "foo"
+            package_version = "9.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@9.0.0"
+            resolved = "https://registry.wapm.io/foo-9.0.0.tar.gz"
+            resolved_source = "registry+foo"
+            abi = "wasi"
+            source = "foo.wasm"
+            [commands.foo]
+            name = "foo"
+            package_name = "_/foo"
+            package_version = "10.0.0"
+            module = "foo"
+            is_top_level_dependency = true
+        };
+        let lockfile: Lockfile = lockfile_toml.try_into().unwrap();
+
+        assert_eq!(
+            r#"{
+  "modules": [
+    {
+      "package": "_/foo",
+      "version": "9.0.0",
+      "module": "foo",
+      "source": "https://registry.wapm.io/foo-9.0.0.tar.gz",
+      "integrity": null
+    },
+    {
+      "package": "_/foo",
+      "version": "10.0.0",
+      "module": "foo",
+      "source": "https://registry.wapm.io/foo-10.0.0.tar.gz",
+      "integrity": "sha256-Zm9v"
+    }
+  ],
+  "commands": {
+    "foo": {
+      "package": "_/foo",
+      "version": "10.0.0",
+      "module": "foo"
+    }
+  }
+}"#,
+            lockfile.to_summary_json()
+        );
+    }
+}
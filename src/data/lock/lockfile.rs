@@ -1,14 +1,31 @@
+use crate::abi::Abi;
+use crate::data::lock::dependency_graph::DependencyGraph;
+use crate::data::lock::install_summary::InstallSummary;
+use crate::data::lock::lockfile_command;
 use crate::data::lock::lockfile_command::LockfileCommand;
+use crate::data::lock::lockfile_diff::LockfileDiff;
 use crate::data::lock::lockfile_module::{
-    LockfileModule, LockfileModuleV2, LockfileModuleV3, LockfileModuleV4,
+    LockfileModule, LockfileModuleV2, LockfileModuleV3, LockfileModuleV4, ModuleOrigin,
 };
-use crate::data::lock::{LOCKFILE_HEADER, LOCKFILE_NAME};
+use crate::data::lock::readonly_lockfile::ReadonlyLockfile;
+use crate::data::lock::{LOCKFILE_HEADER, LOCKFILE_NAME, LOCKFILE_VERSION};
+use crate::data::manifest::{Dependency, Manifest, PACKAGES_DIR_NAME};
+use crate::dataflow::bin_script::BIN_DIR_NAME;
+use crate::dataflow::installed_packages::open_package_download;
+use crate::dataflow::manifest_packages::ManifestPackages;
+use crate::dataflow::resolved_packages::Resolve;
+use crate::dataflow::{normalize_global_namespace_package_name, PackageKey, WapmPackageKey};
+use crate::util;
+use chrono::{DateTime, Utc};
 use semver::Version;
-use std::collections::BTreeMap;
-use std::fs::File;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
 use std::io;
-use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
 pub type ModuleMapV2 = BTreeMap<String, BTreeMap<Version, BTreeMap<String, LockfileModuleV2>>>;
@@ -35,11 +52,134 @@ pub struct LockfileV3 {
 pub type ModuleMap = BTreeMap<String, BTreeMap<Version, BTreeMap<String, LockfileModuleV4>>>;
 pub type CommandMap = BTreeMap<String, LockfileCommand>;
 
+/// The first wapm that installs modules locked to a git commit, see `required_cli_version`
+pub const GIT_SOURCES_CLI_VERSION: &str = "0.5.1";
+/// The first wapm that installs the per-target sources of modules
+pub const TARGET_SOURCES_CLI_VERSION: &str = "0.5.1";
+
 /// The latest Lockfile version
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Lockfile {
+    /// The `Manifest::dependencies_hash` of the manifest the lockfile was generated from. Missing
+    /// from older lockfiles and lockfiles without a manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dependencies_hash: Option<String>,
+    /// The oldest wapm that can install from the lockfile. Set when the lockfile uses something
+    /// older versions would mishandle instead of reject, e.g. git dependencies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_cli_version: Option<Version>,
+    /// The manifest package the lockfile was generated for, as `<name> <version>`. Missing from
+    /// older lockfiles and lockfiles without a manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root_package: Option<String>,
+    /// Which wapm generated the lockfile and when. Missing from older lockfiles and lockfiles
+    /// migrated from an older version. Boxed, as it is rarely read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Box<LockfileMetadata>>,
+    /// Prefer `iter_modules`, the map type may change.
     pub modules: ModuleMap, // PackageName -> VersionNumber -> ModuleName -> Module
+    /// Prefer `iter_commands`, the map type may change.
     pub commands: CommandMap, // CommandName -> Command
+    /// The modules of the build dependencies of the manifest, e.g. code generators. They are
+    /// kept apart from `modules`, so lookups for running commands never find them. Prefer
+    /// `iter_build_modules`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub build_modules: ModuleMap,
+    /// Package name -> the version the manifest's `[overrides]` locked it at, instead of the
+    /// versions its requirements ask for
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overrides: BTreeMap<String, Version>,
+}
+
+/// The `[metadata]` table of a lockfile, set when the lockfile is generated
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct LockfileMetadata {
+    /// The version of the wapm that generated the lockfile
+    pub cli_version: Version,
+    pub generated_at: DateTime<Utc>,
+    /// The `LOCKFILE_VERSION` the lockfile was generated with. Unlike the version line, it is not
+    /// bumped when an older lockfile is migrated.
+    pub format_version: usize,
+}
+
+impl LockfileMetadata {
+    /// The metadata of a lockfile this wapm generates now
+    pub fn current() -> Self {
+        LockfileMetadata {
+            cli_version: Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+            generated_at: Utc::now(),
+            format_version: LOCKFILE_VERSION,
+        }
+    }
+}
+
+/// Parses a lockfile of the current version, e.g. the output of `Lockfile::to_string`, without
+/// touching the filesystem.
+impl FromStr for Lockfile {
+    type Err = LockfileError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        toml::from_str(source).map_err(LockfileError::TomlParseError)
+    }
+}
+
+/// Something that looks wrong about a lockfile but does not keep it from being read
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum LockfileWarning {
+    #[error(
+        "The lockfile starts with \"{0}\" instead of \"# Lockfile v{}\".",
+        LOCKFILE_VERSION
+    )]
+    UnexpectedVersionLine(String),
+    #[error(
+        "The lockfile header is missing or altered, the lockfile may have been edited by hand."
+    )]
+    AlteredHeader,
+    #[error("The command \"{command}\" of package {package} has the name of a wapm subcommand, run it with `wapm run {command}`.")]
+    ShadowedCommand { command: String, package: String },
+}
+
+impl LockfileWarning {
+    /// Checks that the lockfile starts with the version line and header that `save` writes.
+    pub fn from_lockfile_string(source: &str) -> Vec<Self> {
+        let mut warnings = vec![];
+        let (version_line, rest) = source.split_once('\n').unwrap_or((source, ""));
+        if version_line.trim_end() != format!("# Lockfile v{}", LOCKFILE_VERSION) {
+            warnings.push(LockfileWarning::UnexpectedVersionLine(
+                version_line.trim_end().to_string(),
+            ));
+        }
+        if !rest.replace("\r\n", "\n").starts_with(LOCKFILE_HEADER) {
+            warnings.push(LockfileWarning::AlteredHeader);
+        }
+        warnings
+    }
+}
+
+/// Counts of what a lockfile locks, for a quick summary of the dependency tree
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LockfileStats {
+    pub packages: usize,
+    pub modules: usize,
+    pub commands: usize,
+    /// The longest chain of dependents from a locked package up to the manifest package. `None`
+    /// if no module records its dependents, e.g. for global installs and older lockfiles.
+    pub max_depth: Option<usize>,
+}
+
+/// What of the manifest is missing from the lockfile, see `Lockfile::consistency_report`
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ConsistencyReport {
+    /// Dependencies of the manifest, as `name version`, that no locked package satisfies
+    pub missing_dependencies: Vec<String>,
+    /// Commands of the manifest that are not locked, or are locked for another module
+    pub missing_commands: Vec<String>,
+}
+
+impl ConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.missing_dependencies.is_empty() && self.missing_commands.is_empty()
+    }
 }
 
 pub type LockfileV4 = Lockfile;
@@ -47,16 +187,169 @@ pub type ModuleMapV4 = ModuleMap;
 pub type CommandMapV4 = CommandMap;
 
 impl<'a> Lockfile {
+    /// Read the lockfile in the directory. Only lockfiles of the current version are read, use
+    /// `LockfileResult::find_in_directory` to migrate older lockfiles as well.
+    pub fn open<P: AsRef<Path>>(directory: P) -> Result<Lockfile, LockfileError> {
+        Self::open_named(directory, LOCKFILE_NAME)
+    }
+
+    /// Like `open`, but reads the lockfile with the given name instead of `wapm.lock`
+    pub fn open_named<P: AsRef<Path>>(
+        directory: P,
+        filename: &str,
+    ) -> Result<Lockfile, LockfileError> {
+        let (lockfile, warnings) = Self::open_named_with_diagnostics(directory, filename)?;
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        Ok(lockfile)
+    }
+
+    /// Read the lockfile in the directory like `open`, and also return what looks wrong about
+    /// the file without keeping it from being read, e.g. a changed header.
+    pub fn open_with_diagnostics<P: AsRef<Path>>(
+        directory: P,
+    ) -> Result<(Lockfile, Vec<LockfileWarning>), LockfileError> {
+        Self::open_named_with_diagnostics(directory, LOCKFILE_NAME)
+    }
+
+    /// Like `open_with_diagnostics`, but reads the lockfile with the given name
+    pub fn open_named_with_diagnostics<P: AsRef<Path>>(
+        directory: P,
+        filename: &str,
+    ) -> Result<(Lockfile, Vec<LockfileWarning>), LockfileError> {
+        let lockfile_path = directory.as_ref().join(filename);
+        let file = fs::File::open(&lockfile_path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => LockfileError::MissingLockfile,
+            _ => LockfileError::FileIoErrorReadingLockfile(Arc::new(e)),
+        })?;
+        Self::read_with_diagnostics(file)
+    }
+
+    /// Read a lockfile from any reader, e.g. a lockfile stored in an archive or an in-memory
+    /// buffer. Like `open`, only lockfiles of the current version are read.
+    pub fn read<R: io::Read>(reader: R) -> Result<Lockfile, LockfileError> {
+        let (lockfile, warnings) = Self::read_with_diagnostics(reader)?;
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        Ok(lockfile)
+    }
+
+    /// Like `read`, but also returns what looks wrong about the lockfile, like
+    /// `open_with_diagnostics`
+    pub fn read_with_diagnostics<R: io::Read>(
+        mut reader: R,
+    ) -> Result<(Lockfile, Vec<LockfileWarning>), LockfileError> {
+        let mut source = String::new();
+        reader
+            .read_to_string(&mut source)
+            .map_err(|e| LockfileError::FileIoErrorReadingLockfile(Arc::new(e)))?;
+        let lockfile: Lockfile = source.parse()?;
+        lockfile.check_cli_version()?;
+        Ok((lockfile, LockfileWarning::from_lockfile_string(&source)))
+    }
+
+    /// Errors if a module has an ABI that is not in `supported`, e.g. an emscripten module for a
+    /// runtime that only runs WASI. Check before running a command, reading the lockfile does not.
+    pub fn check_runtime_support(&self, supported: &[Abi]) -> Result<(), LockfileError> {
+        self.iter_modules().try_for_each(|(name, module)| {
+            Self::check_module_runtime_support(name, module.abi, supported)
+        })
+    }
+
+    /// Like `check_runtime_support` for only the module of the command that is run, so modules
+    /// of other commands the runtime does not support do not keep it from running.
+    pub fn check_module_runtime_support(
+        module_name: &str,
+        abi: Abi,
+        supported: &[Abi],
+    ) -> Result<(), LockfileError> {
+        if supported.contains(&abi) {
+            Ok(())
+        } else {
+            Err(LockfileError::UnsupportedAbi {
+                module: module_name.to_string(),
+                abi,
+            })
+        }
+    }
+
+    /// Errors if the lockfile's `min_cli_version` is newer than this wapm.
+    pub fn check_cli_version(&self) -> Result<(), LockfileError> {
+        let current = Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+        match &self.min_cli_version {
+            Some(required) if *required > current => Err(LockfileError::UnsupportedVersion {
+                required: required.to_string(),
+                current: current.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The `min_cli_version` the lockfile needs: the newest of the first wapm versions that
+    /// support what the lockfile uses, e.g. `GIT_SOURCES_CLI_VERSION` if a module is locked to a
+    /// git commit. Older versions would install such lockfiles wrongly. None if any wapm can
+    /// install the lockfile.
+    pub fn required_cli_version(&self) -> Option<Version> {
+        self.all_modules()
+            .flat_map(|module| {
+                let git_sources = Some(GIT_SOURCES_CLI_VERSION)
+                    .filter(|_| module.resolved_source.starts_with("git+"));
+                let target_sources =
+                    Some(TARGET_SOURCES_CLI_VERSION).filter(|_| !module.target_sources.is_empty());
+                git_sources.into_iter().chain(target_sources)
+            })
+            .map(|version| Version::parse(version).expect("the minimum versions are valid"))
+            .max()
+    }
+
     /// Save the lockfile to the directory.
+    ///
+    /// The lockfile is written to a temporary file in the same directory, synced to disk, and
+    /// then renamed over `wapm.lock`, so an interrupted save leaves the old lockfile intact
+    /// instead of a truncated one. The rename replaces an existing lockfile on Windows too.
     pub fn save<P: AsRef<Path>>(&self, directory: P) -> anyhow::Result<()> {
-        let lockfile_string = toml::to_string(self)?;
-        let lockfile_string = format!("{}\n{}", LOCKFILE_HEADER, lockfile_string);
-        let lockfile_path = directory.as_ref().join(LOCKFILE_NAME);
-        let mut file = File::create(&lockfile_path)?;
-        file.write_all(lockfile_string.as_bytes())?;
+        self.save_named(directory, LOCKFILE_NAME)
+    }
+
+    /// Like `save`, but writes the lockfile with the given name instead of `wapm.lock`
+    pub fn save_named<P: AsRef<Path>>(&self, directory: P, filename: &str) -> anyhow::Result<()> {
+        let directory = directory.as_ref();
+        let lockfile_path = directory.join(filename);
+        let mut file = tempfile::NamedTempFile::new_in(directory)?;
+        // temporary files are only readable by their owner, lockfiles are usually shared
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.as_file()
+                .set_permissions(fs::Permissions::from_mode(0o644))?;
+        }
+        self.write(&mut file)?;
+        file.as_file().sync_all()?;
+        file.persist(&lockfile_path)?;
         Ok(())
     }
 
+    /// Write the lockfile as `save` does, but to any writer. `read` reads the output back.
+    pub fn write<W: io::Write>(&self, mut writer: W) -> anyhow::Result<()> {
+        writer.write_all(self.to_string()?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Renders the lockfile as it is written to disk: the version line, the header, and the toml.
+    ///
+    /// The output is deterministic. Modules are ordered by package name, package version and
+    /// module name, commands by command name, and the fields of every entry are written in
+    /// declaration order. Saving an unchanged lockfile therefore produces identical bytes.
+    pub fn to_string(&self) -> anyhow::Result<String> {
+        let lockfile_string = toml::to_string(self)?;
+        Ok(format!(
+            "# Lockfile v{}\n{}\n{}",
+            LOCKFILE_VERSION, LOCKFILE_HEADER, lockfile_string
+        ))
+    }
+
     /// Looks up the prehashed cache key based on data in the Command
     pub fn get_prehashed_cache_key_from_command(
         &self,
@@ -66,13 +359,282 @@ impl<'a> Lockfile {
             .get(&command.package_name)
             .and_then(|version_map| version_map.get(&command.package_version))
             .and_then(|module_map| module_map.get(&command.module))
-            .and_then(|module| module.prehashed_module_key.clone())
+            .and_then(|module| module.prehashed_module_key_for_host())
+            .map(str::to_string)
     }
 
     pub fn get_command(&self, command_name: &str) -> Result<&LockfileCommand, LockfileError> {
         self.commands
             .get(command_name)
-            .ok_or(LockfileError::CommandNotFound(command_name.to_string()).into())
+            .ok_or_else(|| LockfileError::CommandNotFound {
+                command: command_name.to_string(),
+                suggestion: util::closest_match(command_name, self.command_names())
+                    .map(str::to_string),
+            })
+    }
+
+    /// Looks up several commands at once, failing with the first name that is not in the lockfile
+    pub fn get_commands(&self, names: &[&str]) -> Result<Vec<&LockfileCommand>, LockfileError> {
+        names.iter().map(|name| self.get_command(name)).collect()
+    }
+
+    /// A lockfile with only the named commands and the modules they run, e.g. to install just what
+    /// one command needs. Locked modules do not record the modules they use, so each command
+    /// requires only its own module. Build modules are left out, and so is the dependencies hash,
+    /// as the lockfile no longer matches the manifest.
+    pub fn minimal_for_commands(&self, names: &[&str]) -> Result<Lockfile, LockfileError> {
+        let mut minimal = Lockfile {
+            dependencies_hash: None,
+            min_cli_version: self.min_cli_version.clone(),
+            root_package: self.root_package.clone(),
+            metadata: self.metadata.clone(),
+            modules: BTreeMap::new(),
+            commands: BTreeMap::new(),
+            build_modules: BTreeMap::new(),
+            overrides: self.overrides.clone(),
+        };
+        for name in names {
+            let command = self.get_command(name)?;
+            let module = self
+                .modules
+                .get(&command.package_name)
+                .and_then(|version_map| version_map.get(&command.package_version))
+                .and_then(|module_map| module_map.get(&command.module))
+                .ok_or_else(|| LockfileError::DanglingCommand {
+                    command: name.to_string(),
+                    package: command.package_key().to_string(),
+                    module: command.module.clone(),
+                })?;
+            minimal
+                .modules
+                .entry(command.package_name.clone())
+                .or_default()
+                .entry(command.package_version.clone())
+                .or_default()
+                .insert(command.module.clone(), module.clone());
+            minimal.commands.insert(name.to_string(), command.clone());
+        }
+        Ok(minimal)
+    }
+
+    /// Like `get_commands`, but returns the commands that were found and the names that were not
+    /// instead of failing
+    pub fn get_commands_partial<'n>(
+        &self,
+        names: &[&'n str],
+    ) -> (Vec<&LockfileCommand>, Vec<&'n str>) {
+        let mut found = vec![];
+        let mut missing = vec![];
+        for name in names {
+            match self.commands.get(*name) {
+                Some(command) => found.push(command),
+                None => missing.push(*name),
+            }
+        }
+        (found, missing)
+    }
+
+    /// Like `get_command`, but also accepts `package::command` to only find the command if it is
+    /// provided by that package, e.g. `_/foo::run`, and `package:command`, which looks the command
+    /// up with `get_command_in_package`. Packages in the global namespace may omit the `_/`. A
+    /// bare command name is never ambiguous, since generating a lockfile fails when two packages
+    /// provide the same command.
+    pub fn get_command_qualified(
+        &self,
+        qualified_command_name: &str,
+    ) -> Result<&LockfileCommand, LockfileError> {
+        let (package_name, command_name) = match qualified_command_name.rfind("::") {
+            Some(index) => (
+                &qualified_command_name[..index],
+                &qualified_command_name[index + 2..],
+            ),
+            None => {
+                return match qualified_command_name.rfind(':') {
+                    // a locked command may have a `:` in its name
+                    Some(index) if !self.commands.contains_key(qualified_command_name) => self
+                        .get_command_in_package(
+                            &qualified_command_name[..index],
+                            &qualified_command_name[index + 1..],
+                        ),
+                    _ => self.get_command(qualified_command_name),
+                };
+            }
+        };
+        let command = self.get_command(command_name)?;
+        let in_package = command.package_name == package_name
+            || (!package_name.contains('/')
+                && command.package_name == format!("_/{}", package_name));
+        if in_package {
+            Ok(command)
+        } else {
+            Err(LockfileError::CommandNotInPackage {
+                command: command_name.to_string(),
+                package: package_name.to_string(),
+                provided_by: command.package_name.clone(),
+            })
+        }
+    }
+
+    /// Finds the command of the named package, e.g. `run` of `_/foo`. Packages in the global
+    /// namespace may omit the `_/`. Unlike `get_command`, the errors tell a package that is not
+    /// locked apart from a locked package without the command.
+    pub fn get_command_in_package(
+        &self,
+        package_name: &str,
+        command_name: &str,
+    ) -> Result<&LockfileCommand, LockfileError> {
+        let package_name =
+            if !self.modules.contains_key(package_name) && !package_name.contains('/') {
+                Cow::Owned(format!("_/{}", package_name))
+            } else {
+                Cow::Borrowed(package_name)
+            };
+        if !self.modules.contains_key(package_name.as_ref()) {
+            return Err(LockfileError::PackageNotFound {
+                package: package_name.to_string(),
+                suggestion: util::closest_match(
+                    &package_name,
+                    self.modules.keys().map(String::as_str),
+                )
+                .map(str::to_string),
+            });
+        }
+        match self.commands.get(command_name) {
+            Some(command) if command.package_name == package_name => Ok(command),
+            Some(command) => Err(LockfileError::CommandNotInPackage {
+                command: command_name.to_string(),
+                package: package_name.to_string(),
+                provided_by: command.package_name.clone(),
+            }),
+            None => Err(LockfileError::CommandNotFoundInPackage {
+                command: command_name.to_string(),
+                package: package_name.to_string(),
+                suggestion: util::closest_match(
+                    command_name,
+                    self.iter_commands()
+                        .filter(|(_, command)| command.package_name == package_name)
+                        .map(|(name, _)| name),
+                )
+                .map(str::to_string),
+            }),
+        }
+    }
+
+    /// Iterates over the locked modules and their names, sorted by package name, package version,
+    /// then module name.
+    pub fn iter_modules(&self) -> impl Iterator<Item = (&str, &LockfileModule)> {
+        self.modules
+            .values()
+            .flat_map(|version_map| version_map.values())
+            .flat_map(|module_map| module_map.iter())
+            .map(|(name, module)| (name.as_str(), module))
+    }
+
+    /// Iterates over the modules of the build dependencies and their names, sorted like
+    /// `iter_modules`.
+    pub fn iter_build_modules(&self) -> impl Iterator<Item = (&str, &LockfileModule)> {
+        self.build_modules
+            .values()
+            .flat_map(|version_map| version_map.values())
+            .flat_map(|module_map| module_map.iter())
+            .map(|(name, module)| (name.as_str(), module))
+    }
+
+    /// Iterates over the locked commands and their names, sorted by command name.
+    pub fn iter_commands(&self) -> impl Iterator<Item = (&str, &LockfileCommand)> {
+        self.commands
+            .iter()
+            .map(|(name, command)| (name.as_str(), command))
+    }
+
+    /// The names of the locked commands, sorted
+    pub fn command_names(&self) -> impl Iterator<Item = &str> {
+        self.commands.keys().map(String::as_str)
+    }
+
+    /// The pairs of command names that differ only by case, e.g. `Run` and `run`. The lockfile
+    /// keeps both, but their bin scripts overwrite each other on case-insensitive filesystems.
+    /// Each pair and the list of pairs are sorted.
+    pub fn case_collisions(&self) -> Vec<(String, String)> {
+        let mut names_by_lowercase: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for name in self.command_names() {
+            names_by_lowercase
+                .entry(name.to_lowercase())
+                .or_default()
+                .push(name);
+        }
+        let mut collisions = vec![];
+        for names in names_by_lowercase.values() {
+            for (i, first) in names.iter().enumerate() {
+                for second in &names[i + 1..] {
+                    collisions.push((first.to_string(), second.to_string()));
+                }
+            }
+        }
+        collisions.sort();
+        collisions
+    }
+
+    /// The package name, package version, and name of every locked module, sorted in that order
+    pub fn module_keys(&self) -> impl Iterator<Item = (&str, &Version, &str)> {
+        self.modules.iter().flat_map(|(package_name, version_map)| {
+            version_map.iter().flat_map(move |(version, module_map)| {
+                module_map
+                    .keys()
+                    .map(move |module_name| (package_name.as_str(), version, module_name.as_str()))
+            })
+        })
+    }
+
+    /// Whether the command is locked, like `get_command` without building an error for a miss
+    pub fn contains_command(&self, command_name: &str) -> bool {
+        self.commands.contains_key(command_name)
+    }
+
+    /// Whether the module of a build dependency is locked. Build modules are not found by
+    /// `contains_module` and `get_module`.
+    pub fn contains_build_module(
+        &self,
+        package_name: &str,
+        package_version: &Version,
+        module_name: &str,
+    ) -> bool {
+        self.build_modules
+            .get(package_name)
+            .and_then(|version_map| version_map.get(package_version))
+            .map(|module_map| module_map.contains_key(module_name))
+            .unwrap_or(false)
+    }
+
+    /// Whether the module is locked, like `get_module` without building an error for a miss
+    pub fn contains_module(
+        &self,
+        package_name: &str,
+        package_version: &Version,
+        module_name: &str,
+    ) -> bool {
+        self.modules
+            .get(package_name)
+            .and_then(|version_map| version_map.get(package_version))
+            .map(|module_map| module_map.contains_key(module_name))
+            .unwrap_or(false)
+    }
+
+    /// Lists the commands provided by a module, sorted by command name. Unknown modules provide
+    /// no commands.
+    pub fn commands_for_module(
+        &self,
+        package_name: &str,
+        package_version: &Version,
+        module_name: &str,
+    ) -> Vec<(&str, &LockfileCommand)> {
+        self.iter_commands()
+            .filter(|(_, command)| {
+                command.package_name == package_name
+                    && &command.package_version == package_version
+                    && command.module == module_name
+            })
+            .collect()
     }
 
     pub fn get_module(
@@ -97,24 +659,965 @@ impl<'a> Lockfile {
             )
             .into(),
         )?;
-        let module = module_map.get(module_name).ok_or::<anyhow::Error>(
-            LockfileError::ModuleForPackageVersionNotFound(
+        let module = module_map.get(module_name).ok_or_else(|| {
+            anyhow::Error::from(LockfileError::ModuleForPackageVersionNotFound(
                 package_name.to_string(),
                 package_version.to_string(),
                 module_name.to_string(),
-            )
-            .into(),
-        )?;
+                util::closest_match(module_name, module_map.keys().map(String::as_str))
+                    .map(str::to_string),
+            ))
+        })?;
         Ok(module)
     }
+
+    /// Removes a module and every command that runs it, so no command is left referring to a
+    /// module that is not in the lockfile. Returns the removed module and commands, or `None` if
+    /// the module is not in the lockfile.
+    pub fn remove_module(
+        &mut self,
+        package_name: &str,
+        package_version: &Version,
+        module_name: &str,
+    ) -> Option<(LockfileModule, Vec<LockfileCommand>)> {
+        let version_map = self.modules.get_mut(package_name)?;
+        let module_map = version_map.get_mut(package_version)?;
+        let module = module_map.remove(module_name)?;
+        if module_map.is_empty() {
+            version_map.remove(package_version);
+        }
+        if version_map.is_empty() {
+            self.modules.remove(package_name);
+        }
+
+        let command_names: Vec<String> = self
+            .commands_for_module(package_name, package_version, module_name)
+            .into_iter()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        let commands = command_names
+            .iter()
+            .filter_map(|name| self.commands.remove(name))
+            .collect();
+        Some((module, commands))
+    }
+
+    /// Renames a locked package, e.g. after it was renamed in the registry. The modules move to
+    /// the new name with their versions and hashes, and the commands and dependents that refer
+    /// to the package are updated. `package_path` is kept, so the modules are still found where
+    /// they were downloaded. Returns whether the package was in the lockfile.
+    pub fn rename_package(
+        &mut self,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<bool, LockfileError> {
+        if old_name == new_name {
+            return Ok(self.modules.contains_key(old_name));
+        }
+        if self.modules.contains_key(new_name) {
+            return Err(LockfileError::PackageAlreadyLocked(new_name.to_string()));
+        }
+        let mut version_map = match self.modules.remove(old_name) {
+            Some(version_map) => version_map,
+            None => return Ok(false),
+        };
+        for module in version_map
+            .values_mut()
+            .flat_map(|module_map| module_map.values_mut())
+        {
+            module.package_name = new_name.to_string();
+        }
+        self.modules.insert(new_name.to_string(), version_map);
+
+        let old_prefix = format!("{}@", old_name);
+        for version_map in self.modules.values_mut() {
+            for module in version_map
+                .values_mut()
+                .flat_map(|module_map| module_map.values_mut())
+            {
+                for dependent in module.dependents.iter_mut() {
+                    if let Some(version) = dependent.strip_prefix(&old_prefix) {
+                        *dependent = format!("{}@{}", new_name, version);
+                    }
+                }
+            }
+        }
+        for command in self.commands.values_mut() {
+            if command.package_name == old_name {
+                command.package_name = new_name.to_string();
+            }
+        }
+        Ok(true)
+    }
+
+    /// Removes a command, leaving its module in the lockfile. Returns the removed command.
+    pub fn remove_command(&mut self, command_name: &str) -> Option<LockfileCommand> {
+        self.commands.remove(command_name)
+    }
+
+    /// The keys, (package name, version, module name), of the modules that no command runs and no
+    /// other package depends on, which are safe to prune. The manifest's package depends on every
+    /// package of a manifest project, but only uses their modules through commands, so it does not
+    /// keep a module on its own. The modules of the manifest's own package are always used.
+    pub fn unused_modules(&self) -> Vec<(&str, &Version, &str)> {
+        let root = self
+            .root_package
+            .as_ref()
+            .and_then(|root| root.parse::<WapmPackageKey>().ok())
+            .map(|root| format!("{}@{}", root.name, root.version));
+        self.modules
+            .iter()
+            .flat_map(|(package_name, version_map)| {
+                version_map.iter().flat_map(move |(version, module_map)| {
+                    module_map
+                        .iter()
+                        .map(move |(name, module)| (package_name.as_str(), version, name, module))
+                })
+            })
+            .filter(|(package_name, version, name, module)| {
+                module.origin() != ModuleOrigin::Local
+                    && module
+                        .dependents
+                        .iter()
+                        .all(|dependent| Some(dependent) == root.as_ref())
+                    && self
+                        .commands_for_module(package_name, version, name)
+                        .is_empty()
+            })
+            .map(|(package_name, version, name, _)| (package_name, version, name.as_str()))
+            .collect()
+    }
+
+    /// Groups the modules whose wasm is byte-identical, by their `integrity`, so e.g. a cache can
+    /// store one copy. Modules without an integrity, like the ones not downloaded yet, are left
+    /// out, and so are modules that have no identical copy.
+    pub fn dedup_by_hash(&self) -> Vec<Vec<&LockfileModule>> {
+        let mut modules_by_integrity: BTreeMap<&str, Vec<&LockfileModule>> = BTreeMap::new();
+        for module in self.all_modules() {
+            match module.integrity.as_deref() {
+                Some(integrity) if !integrity.is_empty() => modules_by_integrity
+                    .entry(integrity)
+                    .or_default()
+                    .push(module),
+                _ => {}
+            }
+        }
+        modules_by_integrity
+            .into_values()
+            .filter(|modules| modules.len() > 1)
+            .collect()
+    }
+
+    /// The modules that were fetched from their source longer ago than `older_than`, e.g. to warn
+    /// about old pins. Modules without a resolution time are not stale.
+    pub fn stale_modules(&self, older_than: Duration) -> Vec<&LockfileModule> {
+        let cutoff = chrono::Duration::from_std(older_than)
+            .ok()
+            .and_then(|older_than| Utc::now().checked_sub_signed(older_than));
+        self.all_modules()
+            .filter(|module| match (module.resolved_at, cutoff) {
+                (Some(resolved_at), Some(cutoff)) => resolved_at < cutoff,
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// Keeps the resolution time of the modules that `previous` locked at the same version with
+    /// the same integrity, e.g. when a missing package is downloaded again, so `stale_modules`
+    /// still sees when they were first fetched. Other modules keep the time they have.
+    pub fn keep_resolved_at(&mut self, previous: &Lockfile) {
+        for modules in [&mut self.modules, &mut self.build_modules].iter_mut() {
+            for (package_name, version_map) in modules.iter_mut() {
+                for (package_version, module_map) in version_map.iter_mut() {
+                    for (module_name, module) in module_map.iter_mut() {
+                        let previous_module = [&previous.modules, &previous.build_modules]
+                            .iter()
+                            .find_map(|previous_modules| {
+                                previous_modules
+                                    .get(package_name)?
+                                    .get(package_version)?
+                                    .get(module_name)
+                            });
+                        if let Some(previous_module) = previous_module {
+                            if module.integrity.is_some()
+                                && previous_module.integrity == module.integrity
+                                && previous_module.resolved_at.is_some()
+                            {
+                                module.resolved_at = previous_module.resolved_at;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Computes the modules and commands that were added, removed, or changed version in
+    /// `other` when compared to this lockfile.
+    pub fn diff(&self, other: &Lockfile) -> LockfileDiff {
+        LockfileDiff::new(self, other)
+    }
+
+    /// Whether both lockfiles resolve to the same modules and commands. Unlike `==`, only the
+    /// name, package version, hash, integrity, ABI, and sources of each module and the module and
+    /// arguments of each command are compared, so fields that change without changing the resolution, e.g.
+    /// `resolved_at`, a `resolved` url with a rotated token, or the `metadata`, are ignored.
+    pub fn semantic_eq(&self, other: &Lockfile) -> bool {
+        #[derive(PartialEq)]
+        struct ModuleKey<'a> {
+            package_name: &'a str,
+            package_version: &'a str,
+            name: &'a str,
+            prehashed_module_key: Option<&'a str>,
+            integrity: Option<&'a str>,
+            target_integrity: &'a BTreeMap<String, String>,
+            abi: Abi,
+            source: &'a str,
+            target_sources: &'a BTreeMap<String, String>,
+        }
+        fn module_key(module: &LockfileModule) -> ModuleKey<'_> {
+            ModuleKey {
+                package_name: &module.package_name,
+                package_version: &module.package_version,
+                name: &module.name,
+                prehashed_module_key: module.prehashed_module_key.as_deref(),
+                integrity: module.integrity.as_deref(),
+                target_integrity: &module.target_integrity,
+                abi: module.abi,
+                source: &module.source,
+                target_sources: &module.target_sources,
+            }
+        }
+        #[derive(PartialEq)]
+        struct CommandKey<'a> {
+            name: &'a str,
+            package_name: &'a str,
+            package_version: &'a Version,
+            module: &'a str,
+            main_args: Option<&'a str>,
+            args: &'a [String],
+        }
+        fn command_key<'a>((name, command): (&'a str, &'a LockfileCommand)) -> CommandKey<'a> {
+            CommandKey {
+                name,
+                package_name: &command.package_name,
+                package_version: &command.package_version,
+                module: &command.module,
+                main_args: command.main_args.as_deref(),
+                args: &command.args,
+            }
+        }
+        self.iter_modules()
+            .map(|(_, module)| module_key(module))
+            .eq(other.iter_modules().map(|(_, module)| module_key(module)))
+            && self
+                .iter_build_modules()
+                .map(|(_, module)| module_key(module))
+                .eq(other
+                    .iter_build_modules()
+                    .map(|(_, module)| module_key(module)))
+            && self
+                .iter_commands()
+                .map(command_key)
+                .eq(other.iter_commands().map(command_key))
+    }
+
+    /// The locked packages and the packages that required them, see `DependencyGraph`.
+    pub fn dependency_graph(&self) -> DependencyGraph {
+        DependencyGraph::new(self)
+    }
+
+    /// The locked modules and commands in a format for other tools, see `InstallSummary`.
+    pub fn install_summary(&self) -> InstallSummary {
+        InstallSummary::new(self)
+    }
+
+    /// The lockfile without the methods that save or change it, see `ReadonlyLockfile`.
+    pub fn into_readonly(self) -> ReadonlyLockfile {
+        ReadonlyLockfile::new(self)
+    }
+
+    /// The `install_summary` as pretty printed JSON
+    pub fn to_summary_json(&self) -> String {
+        self.install_summary().to_json()
+    }
+
+    /// Every locked package as a `name==version` line, like a pip requirements file, sorted by
+    /// name and version. A package with several modules is listed once, build dependencies are
+    /// left out.
+    pub fn to_requirements_txt(&self) -> String {
+        self.modules
+            .iter()
+            .flat_map(|(package_name, version_map)| {
+                version_map
+                    .iter()
+                    .filter(|(_, module_map)| !module_map.is_empty())
+                    .map(move |(version, _)| format!("{}=={}\n", package_name, version))
+            })
+            .collect()
+    }
+
+    /// Whether every dependency and command of the manifest is in the lockfile, see
+    /// `consistency_report`
+    pub fn is_consistent_with(&self, manifest: &Manifest) -> bool {
+        self.consistency_report(manifest).is_consistent()
+    }
+
+    /// Checks that a locked package satisfies every dependency of the manifest, and that every
+    /// command of the manifest is locked as the manifest declares it. This never resolves
+    /// anything, so it is a cheap check for whether the lockfile needs to be updated. Optional
+    /// and dev dependencies depend on how the lockfile was installed and are not checked.
+    pub fn consistency_report(&self, manifest: &Manifest) -> ConsistencyReport {
+        let mut report = ConsistencyReport::default();
+        for (name, dependency) in manifest.dependencies.iter().flatten() {
+            let name = normalize_global_namespace_package_name(Cow::Borrowed(name));
+            let is_locked = match dependency {
+                // an exact version is a pin, like when the manifest is resolved
+                Dependency::Version(version) => {
+                    ManifestPackages::parse_wapm_package_key((&name, version))
+                        .map(|key| {
+                            self.module_keys()
+                                .any(|(package_name, package_version, _)| {
+                                    package_name == name
+                                        && match &key {
+                                            PackageKey::WapmPackage(key) => {
+                                                key.version == *package_version
+                                            }
+                                            PackageKey::WapmPackageRange(range) => {
+                                                range.version_req.matches(package_version)
+                                            }
+                                        }
+                                })
+                        })
+                        .unwrap_or(false)
+                }
+                Dependency::Git { git, rev } => {
+                    let source_prefix = format!("git+{}?rev={}#", git, rev);
+                    self.iter_modules().any(|(_, module)| {
+                        module.package_name == name
+                            && module.resolved_source.starts_with(&source_prefix)
+                    })
+                }
+            };
+            if !is_locked {
+                let requirement = match dependency {
+                    Dependency::Version(version) => version.clone(),
+                    Dependency::Git { git, rev } => format!("(git {} {})", git, rev),
+                };
+                report
+                    .missing_dependencies
+                    .push(format!("{} {}", name, requirement));
+            }
+        }
+        report.missing_dependencies.sort();
+
+        let package_name = manifest.package.name.as_str();
+        let package_version = &manifest.package.version;
+        match LockfileCommand::from_manifest(package_name, package_version, manifest) {
+            Ok(commands) => {
+                for command in commands {
+                    let is_locked = self.commands.get(&command.name).map(|locked| {
+                        locked.package_key() == command.package_key()
+                            && locked.module == command.module
+                    });
+                    if !is_locked.unwrap_or(false) {
+                        report.missing_commands.push(command.name);
+                    }
+                }
+            }
+            // commands that can not be locked are certainly not in the lockfile
+            Err(_) => {
+                report.missing_commands = manifest
+                    .command
+                    .iter()
+                    .flatten()
+                    .map(|command| command.name.clone())
+                    .collect()
+            }
+        }
+        report.missing_commands.sort();
+        report
+    }
+
+    /// Counts the locked packages, modules, and commands, and how deep the dependents go.
+    pub fn stats(&self) -> LockfileStats {
+        // package as `name@version` -> the packages that required it
+        let mut dependents: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for module in self.all_modules() {
+            dependents
+                .entry(format!(
+                    "{}@{}",
+                    module.package_name, module.package_version
+                ))
+                .or_default()
+                .extend(module.dependents.iter().cloned());
+        }
+        let max_depth = if dependents.values().all(BTreeSet::is_empty) {
+            None
+        } else {
+            dependents
+                .keys()
+                .map(|package| dependency_depth(package, &dependents, &mut vec![]))
+                .max()
+        };
+        LockfileStats {
+            packages: dependents.len(),
+            modules: self.all_modules().count(),
+            commands: self.commands.len(),
+            max_depth,
+        }
+    }
+
+    /// Errors if a chain of dependents is longer than the limit, see `LockfileStats::max_depth`.
+    /// Lockfiles without dependents pass any limit.
+    pub fn check_depth(&self, limit: usize) -> Result<(), LockfileError> {
+        match self.stats().max_depth {
+            Some(depth) if depth > limit => Err(LockfileError::DepthExceeded { depth, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The bytes left to download for the modules that are not cached yet, e.g.
+    /// `|module| !module.resolve_entry_path(&packages_dir).exists()`, to report the progress of an
+    /// install. The modules of a package share its archive, so every package is counted once, and
+    /// modules without a `size` count as nothing.
+    pub fn total_download_size<F: Fn(&LockfileModule) -> bool>(&self, not_yet_cached: F) -> u64 {
+        let mut package_sizes: BTreeMap<(&str, &str), u64> = BTreeMap::new();
+        for module in self.all_modules().filter(|module| not_yet_cached(module)) {
+            if let Some(size) = module.size {
+                package_sizes.insert((&module.package_name, &module.package_version), size);
+            }
+        }
+        package_sizes.values().sum()
+    }
+
+    /// Recomputes the hash of the host's source of every downloaded module in the directory and
+    /// compares it against the integrity stored in the lockfile for it, or the prehashed module
+    /// key for lockfiles written before modules had an integrity.
+    ///
+    /// Modules that are not downloaded yet, local modules, and modules without a stored hash are
+    /// skipped.
+    pub fn verify_integrity<P: AsRef<Path>>(&self, directory: P) -> Result<(), LockfileError> {
+        let directory = directory.as_ref();
+        for module in self.all_modules() {
+            if module.resolved == "local" {
+                continue;
+            }
+            let path = module.get_canonical_source_path_from_lockfile_dir(directory.into());
+            if !path.exists() {
+                continue;
+            }
+            let (expected, actual) = match (
+                module.integrity_for_target(None),
+                module.prehashed_module_key_for_host(),
+            ) {
+                (Some(expected), _) => (
+                    expected,
+                    util::compute_module_integrity(&path)
+                        .ok()
+                        .map(|(_, integrity)| integrity),
+                ),
+                (None, Some(expected)) => (expected, util::get_hashed_module_key(&path)),
+                (None, None) => continue,
+            };
+            if let Some(actual) = actual {
+                if actual != expected {
+                    return Err(LockfileError::IntegrityMismatch {
+                        package: module.package_path.clone(),
+                        module: module.name.clone(),
+                        expected: expected.to_string(),
+                        actual,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares the bytes of a downloaded wasm module against the integrity the lockfile stored
+    /// for it, so a registry republishing a version with different bytes is noticed. Modules
+    /// locked without an integrity, e.g. by older versions of wapm, are accepted.
+    pub fn verify_download(
+        &self,
+        package_name: &str,
+        package_version: &Version,
+        module_name: &str,
+        downloaded_bytes: &[u8],
+    ) -> Result<(), LockfileError> {
+        let module = [&self.modules, &self.build_modules]
+            .iter()
+            .filter_map(|modules| modules.get(package_name))
+            .filter_map(|version_map| version_map.get(package_version))
+            .find_map(|module_map| module_map.get(module_name))
+            .ok_or_else(|| {
+                LockfileError::ModuleForPackageVersionNotFound(
+                    package_name.to_string(),
+                    package_version.to_string(),
+                    module_name.to_string(),
+                    None,
+                )
+            })?;
+        let expected = match module.integrity.as_deref() {
+            Some(expected) if !expected.is_empty() => expected,
+            _ => return Ok(()),
+        };
+        let (_, actual) = util::compute_integrity(downloaded_bytes);
+        if actual != expected {
+            return Err(LockfileError::IntegrityMismatch {
+                package: module.package_path.clone(),
+                module: module.name.clone(),
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks the files of a package extracted into `package_dir` against the integrity of its
+    /// locked modules, before the package is moved into `wapm_packages`: the `source` of every
+    /// module, and the source it uses on each of its targets. Packages that are not locked,
+    /// modules without an integrity and files the package does not contain are accepted.
+    pub fn verify_package_dir(
+        &self,
+        package_name: &str,
+        package_version: &Version,
+        package_dir: &Path,
+    ) -> Result<(), LockfileError> {
+        for module in self.package_modules(package_name, package_version) {
+            let target_sources =
+                module
+                    .target_integrity
+                    .iter()
+                    .filter_map(|(target, integrity)| {
+                        module
+                            .target_sources
+                            .get(target)
+                            .map(|source| (source, integrity))
+                    });
+            let sources = module
+                .integrity
+                .iter()
+                .map(|integrity| (&module.source, integrity))
+                .chain(target_sources);
+            for (source, expected) in sources {
+                let path = package_dir.join(source);
+                if expected.is_empty() || !path.exists() {
+                    continue;
+                }
+                let actual = match util::compute_module_integrity(&path) {
+                    Ok((_, actual)) => actual,
+                    Err(_) => continue,
+                };
+                if &actual != expected {
+                    return Err(LockfileError::IntegrityMismatch {
+                        package: module.package_path.clone(),
+                        module: module.name.clone(),
+                        expected: expected.to_string(),
+                        actual,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks the resolver for a fresh download url of every locked registry package, e.g. when
+    /// the signed urls of the registry expired, without resolving any version again. Only the
+    /// `resolved` url of the modules changes. A package whose url changed is downloaded from the
+    /// new url and its modules are checked against their integrity first, so a url pointing at
+    /// different bytes is an error and leaves the lockfile as it was.
+    ///
+    /// Returns the keys of the packages whose url changed.
+    pub fn touch<R: for<'r> Resolve<'r>>(
+        &mut self,
+        resolver: &R,
+    ) -> Result<Vec<String>, LockfileError> {
+        let locked_packages: BTreeSet<(String, Version)> = self
+            .all_modules()
+            .filter(|module| module.resolved_source.starts_with("registry+"))
+            .filter_map(|module| {
+                let version = Version::parse(&module.package_version).ok()?;
+                Some((module.package_name.clone(), version))
+            })
+            .collect();
+        if locked_packages.is_empty() {
+            return Ok(vec![]);
+        }
+        let keys = locked_packages
+            .iter()
+            .map(|(name, version)| PackageKey::new_registry_package(name.clone(), version.clone()))
+            .collect();
+        let resolved = resolver
+            .sync_packages(keys)
+            .map_err(|e| LockfileError::TouchFailed(e.to_string()))?;
+
+        let mut new_urls: BTreeMap<(String, Version), String> = BTreeMap::new();
+        for (key, (download_url, _)) in resolved {
+            let package = (key.name.to_string(), key.version.clone());
+            // a resolver that answers with another version does not refresh anything
+            if !locked_packages.contains(&package) {
+                continue;
+            }
+            let changed = self
+                .package_modules(&package.0, &package.1)
+                .iter()
+                .any(|module| module.resolved != download_url);
+            if changed {
+                self.verify_artifact(&key, &download_url)?;
+                new_urls.insert(package, download_url);
+            }
+        }
+
+        for modules in [&mut self.modules, &mut self.build_modules].iter_mut() {
+            for ((name, version), download_url) in new_urls.iter() {
+                let module_map = modules
+                    .get_mut(name)
+                    .and_then(|version_map| version_map.get_mut(version));
+                for module in module_map.into_iter().flat_map(|m| m.values_mut()) {
+                    module.resolved = download_url.clone();
+                }
+            }
+        }
+        Ok(new_urls
+            .keys()
+            .map(|(name, version)| format!("{} {}", name, version))
+            .collect())
+    }
+
+    /// The keys of the locked registry packages whose version the resolver reports as yanked,
+    /// sorted, so the user can be warned without updating anything. A package that the resolver
+    /// can not tell about is not reported.
+    pub fn check_yanked<R: for<'r> Resolve<'r>>(&self, resolver: &R) -> Vec<String> {
+        let locked_packages: BTreeSet<(&str, Version)> = self
+            .all_modules()
+            .filter(|module| module.resolved_source.starts_with("registry+"))
+            .filter_map(|module| {
+                let version = Version::parse(&module.package_version).ok()?;
+                Some((module.package_name.as_str(), version))
+            })
+            .collect();
+        locked_packages
+            .into_iter()
+            .filter(|(name, version)| match resolver.is_yanked(name, version) {
+                Ok(is_yanked) => is_yanked,
+                Err(e) => {
+                    warn!(
+                        "Could not check whether {} {} is yanked: {}",
+                        name, version, e
+                    );
+                    false
+                }
+            })
+            .map(|(name, version)| format!("{} {}", name, version))
+            .collect()
+    }
+
+    /// The modules and build modules of one locked package version
+    fn package_modules(
+        &self,
+        package_name: &str,
+        package_version: &Version,
+    ) -> Vec<&LockfileModule> {
+        [&self.modules, &self.build_modules]
+            .iter()
+            .filter_map(|modules| modules.get(package_name))
+            .filter_map(|version_map| version_map.get(package_version))
+            .flat_map(|module_map| module_map.values())
+            .collect()
+    }
+
+    /// Downloads the package tarball and compares the wasm files in it against the integrity of
+    /// the package's modules. Modules without an integrity, or whose source the tarball does not
+    /// contain, are accepted.
+    fn verify_artifact(
+        &self,
+        key: &WapmPackageKey,
+        download_url: &str,
+    ) -> Result<(), LockfileError> {
+        let touch_failed =
+            |e: String| LockfileError::TouchFailed(format!("Could not download {}: {}", key, e));
+        let modules: Vec<&LockfileModule> = self
+            .package_modules(&key.name, &key.version)
+            .into_iter()
+            .filter(|module| matches!(module.integrity.as_deref(), Some(i) if !i.is_empty()))
+            .collect();
+        if modules.is_empty() {
+            return Ok(());
+        }
+        let download =
+            open_package_download(key, download_url).map_err(|e| touch_failed(e.to_string()))?;
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(download));
+        let entries = archive.entries().map_err(|e| touch_failed(e.to_string()))?;
+        for entry in entries {
+            let mut entry = entry.map_err(|e| touch_failed(e.to_string()))?;
+            let path = entry
+                .path()
+                .map_err(|e| touch_failed(e.to_string()))?
+                .into_owned();
+            let matching: Vec<&&LockfileModule> = modules
+                .iter()
+                .filter(|module| path.ends_with(&module.source))
+                .collect();
+            if matching.is_empty() {
+                continue;
+            }
+            let mut bytes = vec![];
+            io::Read::read_to_end(&mut entry, &mut bytes)
+                .map_err(|e| touch_failed(e.to_string()))?;
+            for module in matching {
+                self.verify_download(&key.name, &key.version, &module.name, &bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the downloaded packages in the `wapm_packages` directory that are no longer
+    /// referenced by any module in the lockfile. Returns the removed package directories.
+    pub fn prune_cache<P: AsRef<Path>>(&self, directory: P) -> anyhow::Result<Vec<PathBuf>> {
+        let packages_dir = directory.as_ref().join(PACKAGES_DIR_NAME);
+        let mut removed_paths = vec![];
+        if !packages_dir.is_dir() {
+            return Ok(removed_paths);
+        }
+        let locked_package_dirs: HashSet<PathBuf> = self
+            .all_modules()
+            .map(|module| packages_dir.join(&module.package_path))
+            .collect();
+        // packages are stored as `wapm_packages/<namespace>/<name>@<version>`
+        for namespace_entry in fs::read_dir(&packages_dir)? {
+            let namespace_dir = namespace_entry?.path();
+            if !namespace_dir.is_dir() || namespace_dir.ends_with(BIN_DIR_NAME) {
+                continue;
+            }
+            for package_entry in fs::read_dir(&namespace_dir)? {
+                let package_dir = package_entry?.path();
+                if package_dir.is_dir() && !locked_package_dirs.contains(&package_dir) {
+                    fs::remove_dir_all(&package_dir)?;
+                    removed_paths.push(package_dir);
+                }
+            }
+        }
+        removed_paths.sort();
+        Ok(removed_paths)
+    }
+
+    /// Checks that every command refers to a module that exists in the lockfile.
+    pub fn validate(&self) -> Result<(), LockfileError> {
+        for (command_name, command) in self.commands.iter() {
+            if !self.contains_module(
+                &command.package_name,
+                &command.package_version,
+                &command.module,
+            ) {
+                return Err(LockfileError::DanglingCommand {
+                    command: command_name.clone(),
+                    package: command.package_key().to_string(),
+                    module: command.module.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the commands of the manifest's own package with the commands in the manifest,
+    /// without touching the modules, for when only the `[[command]]` entries of the manifest
+    /// changed. Commands of dependencies are kept, a new command with the name of one of them is
+    /// a conflict. `provided_commands` lists the commands each dependency provides itself,
+    /// package key -> command names: the other commands of a listed dependency were declared by
+    /// the manifest for its modules, and are removed unless the manifest still declares them.
+    /// Dependencies that are not listed keep all of their commands. Commands of features that are
+    /// not enabled are left out.
+    pub fn refresh_commands(
+        &mut self,
+        manifest: &Manifest,
+        features: &HashSet<String>,
+        provided_commands: &HashMap<String, HashSet<String>>,
+    ) -> Result<(), LockfileError> {
+        let package_name = manifest.package.name.as_str();
+        let package_version = &manifest.package.version;
+        let new_commands = LockfileCommand::from_manifest_with_features(
+            package_name,
+            package_version,
+            manifest,
+            features,
+        )
+        .map_err(LockfileError::InvalidManifestCommand)?;
+        // a failed refresh leaves the commands as they were
+        let mut commands = self.commands.clone();
+        commands.retain(|_, command| {
+            if command.package_name == package_name && command.package_version == *package_version {
+                return false;
+            }
+            let name = command.original_name.as_ref().unwrap_or(&command.name);
+            provided_commands
+                .get(&command.package_key().to_string())
+                .is_none_or(|provided| provided.contains(name))
+        });
+        for command in new_commands {
+            if let Some(existing) = commands.get(&command.name) {
+                if existing.package_key() != command.package_key() {
+                    let mut packages = vec![
+                        existing.package_key().to_string(),
+                        command.package_key().to_string(),
+                    ];
+                    packages.sort();
+                    return Err(LockfileError::CommandConflict {
+                        command: command.name,
+                        packages,
+                    });
+                }
+            }
+            commands.insert(command.name.clone(), command);
+        }
+        let old_commands = std::mem::replace(&mut self.commands, commands);
+        if let Err(e) = self.validate() {
+            self.commands = old_commands;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Combines two resolved lockfiles, e.g. the lockfiles of the packages in a workspace.
+    /// Entries present in both lockfiles must agree: a module or build module must have the same
+    /// source and hash, and a command must refer to the same module.
+    pub fn merge(mut self, other: Lockfile) -> Result<Lockfile, LockfileError> {
+        // the merged lockfile was not generated from either manifest
+        if self.dependencies_hash != other.dependencies_hash {
+            self.dependencies_hash = None;
+        }
+        if self.root_package != other.root_package {
+            self.root_package = None;
+        }
+        self.overrides.extend(other.overrides);
+        merge_modules(&mut self.build_modules, other.build_modules)?;
+        merge_modules(&mut self.modules, other.modules)?;
+        for (command_name, command) in other.commands {
+            match self.commands.get(&command_name) {
+                Some(existing)
+                    if existing.package_name != command.package_name
+                        || existing.package_version != command.package_version
+                        || existing.module != command.module =>
+                {
+                    return Err(LockfileError::MergeConflict {
+                        entry: format!("command \"{}\"", command_name),
+                        reason: format!(
+                            "refers to module \"{}\" in package \"{} {}\" and module \"{}\" in package \"{} {}\"",
+                            existing.module,
+                            existing.package_name,
+                            existing.package_version,
+                            command.module,
+                            command.package_name,
+                            command.package_version
+                        ),
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    self.commands.insert(command_name, command);
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    fn all_modules(&self) -> impl Iterator<Item = &LockfileModule> {
+        self.iter_modules()
+            .chain(self.iter_build_modules())
+            .map(|(_, module)| module)
+    }
+}
+
+/// Merges the modules of another lockfile into `merged`, see `Lockfile::merge`. A module both
+/// lockfiles lock must have the same source, hash, and integrity in both.
+fn merge_modules(merged: &mut ModuleMap, other: ModuleMap) -> Result<(), LockfileError> {
+    for (package_name, version_map) in other {
+        for (version, module_map) in version_map {
+            let merged_module_map = merged
+                .entry(package_name.clone())
+                .or_default()
+                .entry(version.clone())
+                .or_default();
+            for (module_name, module) in module_map {
+                let existing = match merged_module_map.get_mut(&module_name) {
+                    Some(existing) => existing,
+                    None => {
+                        merged_module_map.insert(module_name, module);
+                        continue;
+                    }
+                };
+                let conflict = |reason: String| LockfileError::MergeConflict {
+                    entry: format!(
+                        "module \"{}\" in package \"{} {}\"",
+                        module_name, package_name, version
+                    ),
+                    reason,
+                };
+                if existing.source != module.source {
+                    return Err(conflict(format!(
+                        "has the sources {} and {}",
+                        existing.source, module.source
+                    )));
+                }
+                if existing.prehashed_module_key != module.prehashed_module_key {
+                    return Err(conflict(format!(
+                        "has the hashes {} and {}",
+                        existing.prehashed_module_key.as_deref().unwrap_or("none"),
+                        module.prehashed_module_key.as_deref().unwrap_or("none")
+                    )));
+                }
+                if existing.integrity != module.integrity {
+                    return Err(conflict(format!(
+                        "has the integrities {} and {}",
+                        existing.integrity.as_deref().unwrap_or("none"),
+                        module.integrity.as_deref().unwrap_or("none")
+                    )));
+                }
+                existing.dependents.extend(module.dependents);
+                existing.dependents.sort();
+                existing.dependents.dedup();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The number of locked packages from this package up to its deepest dependent. Dependents that
+/// are not locked, like the manifest package, are where the chain ends.
+fn dependency_depth<'a>(
+    package: &'a str,
+    dependents: &'a BTreeMap<String, BTreeSet<String>>,
+    path: &mut Vec<&'a str>,
+) -> usize {
+    // stop at cycles instead of recursing forever
+    if path.contains(&package) {
+        return 0;
+    }
+    match dependents.get(package) {
+        Some(package_dependents) => {
+            path.push(package);
+            let depth = package_dependents
+                .iter()
+                .map(|dependent| dependency_depth(dependent, dependents, path))
+                .max()
+                .unwrap_or(0);
+            path.pop();
+            depth + 1
+        }
+        None => 0,
+    }
 }
 
-#[derive(Debug, Error)]
+#[derive(Clone, Debug, Error)]
 pub enum LockfileError {
-    #[error("Command not found: {0}")]
-    CommandNotFound(String),
-    #[error("module {2} in package \"{0} {1}\" was not found")]
-    ModuleForPackageVersionNotFound(String, String, String),
+    #[error("Command not found: {command}{}", did_you_mean(.suggestion))]
+    CommandNotFound {
+        command: String,
+        /// The locked command with the closest name, if it looks like a typo of it
+        suggestion: Option<String>,
+    },
+    /// The package, version, and module, and the module of the package with the closest name
+    #[error("module {2} in package \"{0} {1}\" was not found{}", did_you_mean(.3))]
+    ModuleForPackageVersionNotFound(String, String, String, Option<String>),
     #[error("Module \"{2}\" with package name \"{0}\" and version \"{1}\" was not found.")]
     PackageWithVersionNotFoundWhenFindingModule(String, String, String),
     #[error(
@@ -124,9 +1627,1823 @@ pub enum LockfileError {
     #[error("Lockfile file not found.")]
     MissingLockfile,
     #[error("File I/O error reading lockfile. I/O error: {0:?}")]
-    FileIoErrorReadingLockfile(io::Error),
+    FileIoErrorReadingLockfile(Arc<io::Error>),
     #[error(
         "Failed to parse lockfile toml. Did you modify the generated lockfile? Toml error: {0:?}"
     )]
     TomlParseError(toml::de::Error),
+    #[error("Integrity check failed for module \"{module}\" in package \"{package}\": expected hash {expected}, found {actual}. Delete `wapm_packages` and reinstall.")]
+    IntegrityMismatch {
+        package: String,
+        module: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("command \"{command}\" refers to module \"{module}\" in package \"{package}\", which is not in the lockfile")]
+    DanglingCommand {
+        command: String,
+        package: String,
+        module: String,
+    },
+    #[error("Command \"{command}\" is provided by package \"{provided_by}\", not \"{package}\"")]
+    CommandNotInPackage {
+        command: String,
+        package: String,
+        provided_by: String,
+    },
+    #[error("Could not merge lockfiles because {entry} {reason}")]
+    MergeConflict { entry: String, reason: String },
+    #[error("No version of package \"{package}\" satisfies all of its requirements: {}", .requirements.join(", "))]
+    VersionConflict {
+        package: String,
+        requirements: Vec<String>,
+    },
+    #[error("The lockfile requires wapm {required} or newer, this is wapm {current}. Update wapm and try again.")]
+    UnsupportedVersion { required: String, current: String },
+    #[error("Package \"{0}\" is already in the lockfile")]
+    PackageAlreadyLocked(String),
+    #[error("Module \"{module}\" uses the {abi} ABI, which the runtime does not support")]
+    UnsupportedAbi { module: String, abi: Abi },
+    #[error("Could not read the commands of the manifest. {0}")]
+    InvalidManifestCommand(lockfile_command::Error),
+    #[error("Command \"{command}\" is provided by more than one package: {}", .packages.join(", "))]
+    CommandConflict {
+        command: String,
+        packages: Vec<String>,
+    },
+    #[error("The dependency tree is {depth} packages deep, deeper than the limit of {limit}")]
+    DepthExceeded { depth: usize, limit: usize },
+    #[error("Could not refresh the download urls of the lockfile. {0}")]
+    TouchFailed(String),
+    #[error("Package \"{name}\" is not locked or not installed, and locked mode never resolves packages")]
+    NotLocked { name: String },
+    #[error("Package \"{package}\" is not in the lockfile{}", did_you_mean(.suggestion))]
+    PackageNotFound {
+        package: String,
+        suggestion: Option<String>,
+    },
+    #[error("Package \"{package}\" does not provide a command \"{command}\"{}", did_you_mean(.suggestion))]
+    CommandNotFoundInPackage {
+        command: String,
+        package: String,
+        suggestion: Option<String>,
+    },
+}
+
+/// The hint appended to "not found" errors when a similar name exists
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(suggestion) => format!(". Did you mean \"{}\"?", suggestion),
+        None => String::new(),
+    }
+}
+
+impl LockfileError {
+    /// The process exit code for this error, so scripts can tell why a command failed. The codes
+    /// are stable, new variants get new codes:
+    ///
+    /// | code | error |
+    /// |------|-------|
+    /// | 10 | `MissingLockfile` |
+    /// | 11 | `FileIoErrorReadingLockfile`, often transient |
+    /// | 12 | `TomlParseError`, the lockfile is corrupt |
+    /// | 13 | `DanglingCommand`, the lockfile is corrupt |
+    /// | 14 | `IntegrityMismatch` |
+    /// | 15 | `MergeConflict` |
+    /// | 16 | `CommandNotFound` |
+    /// | 17 | `CommandNotInPackage` |
+    /// | 18 | `PackageWithVersionNotFoundWhenFindingModule` |
+    /// | 19 | `VersionNotFoundForPackageWhenFindingModule` |
+    /// | 20 | `ModuleForPackageVersionNotFound` |
+    /// | 21 | `VersionConflict` |
+    /// | 22 | `UnsupportedVersion` |
+    /// | 23 | `PackageAlreadyLocked` |
+    /// | 24 | `UnsupportedAbi` |
+    /// | 25 | `InvalidManifestCommand` |
+    /// | 26 | `CommandConflict` |
+    /// | 27 | `DepthExceeded` |
+    /// | 28 | `TouchFailed` |
+    /// | 29 | `NotLocked` |
+    /// | 30 | `PackageNotFound` |
+    /// | 31 | `CommandNotFoundInPackage` |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LockfileError::MissingLockfile => 10,
+            LockfileError::FileIoErrorReadingLockfile(_) => 11,
+            LockfileError::TomlParseError(_) => 12,
+            LockfileError::DanglingCommand { .. } => 13,
+            LockfileError::IntegrityMismatch { .. } => 14,
+            LockfileError::MergeConflict { .. } => 15,
+            LockfileError::CommandNotFound { .. } => 16,
+            LockfileError::CommandNotInPackage { .. } => 17,
+            LockfileError::PackageWithVersionNotFoundWhenFindingModule(..) => 18,
+            LockfileError::VersionNotFoundForPackageWhenFindingModule(..) => 19,
+            LockfileError::ModuleForPackageVersionNotFound(..) => 20,
+            LockfileError::VersionConflict { .. } => 21,
+            LockfileError::UnsupportedVersion { .. } => 22,
+            LockfileError::PackageAlreadyLocked(_) => 23,
+            LockfileError::UnsupportedAbi { .. } => 24,
+            LockfileError::InvalidManifestCommand(_) => 25,
+            LockfileError::CommandConflict { .. } => 26,
+            LockfileError::DepthExceeded { .. } => 27,
+            LockfileError::TouchFailed(_) => 28,
+            LockfileError::NotLocked { .. } => 29,
+            LockfileError::PackageNotFound { .. } => 30,
+            LockfileError::CommandNotFoundInPackage { .. } => 31,
+        }
+    }
+}
+
+/// The lockfile of the package `_/foo 1.0.0` with the module `foo` and no commands
+#[cfg(test)]
+pub(crate) fn foo_lockfile() -> Lockfile {
+    let lockfile_toml = toml! {
+        [modules."_/foo"."1.0.0".foo]
+        name = "foo"
+        package_version = "1.0.0"
+        package_name = "_/foo"
+        package_path = "_/foo@1.0.0"
+        resolved = ""
+        resolved_source = "registry+foo"
+        abi = "none"
+        source = "foo.wasm"
+        [commands]
+    };
+    lockfile_toml.try_into().unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::abi::Abi;
+    use crate::data::lock::lockfile::{
+        Lockfile, LockfileError, LockfileMetadata, LockfileStats, LockfileWarning,
+        GIT_SOURCES_CLI_VERSION, TARGET_SOURCES_CLI_VERSION,
+    };
+    use crate::data::lock::lockfile_command::LockfileCommand;
+    use crate::data::lock::lockfile_module::LockfileModule;
+    use crate::data::lock::{LOCKFILE_HEADER, LOCKFILE_VERSION};
+    use crate::data::manifest::Manifest;
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::fs;
+    use std::io;
+
+    fn lockfile_with_module(module: LockfileModule) -> Lockfile {
+        let mut modules = BTreeMap::new();
+        let version = semver::Version::parse(&module.package_version).unwrap();
+        modules
+            .entry(module.package_name.clone())
+            .or_insert_with(BTreeMap::new)
+            .entry(version)
+            .or_insert_with(BTreeMap::new)
+            .insert(module.name.clone(), module);
+        Lockfile {
+            dependencies_hash: None,
+            min_cli_version: None,
+            root_package: None,
+            metadata: None,
+            modules,
+            commands: BTreeMap::new(),
+            build_modules: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+        }
+    }
+
+    fn foo_module(prehashed_module_key: Option<String>) -> LockfileModule {
+        LockfileModule {
+            name: "foo".to_string(),
+            package_version: "1.0.0".to_string(),
+            package_name: "_/foo".to_string(),
+            package_path: "_/foo@1.0.0".to_string(),
+            resolved: "https://registry.wapm.io/foo-1.0.0.tar.gz".to_string(),
+            resolved_source: "registry+foo".to_string(),
+            source: "foo.wasm".to_string(),
+            prehashed_module_key,
+            ..Default::default()
+        }
+    }
+
+    fn foo_command(name: &str, module: &str) -> LockfileCommand {
+        LockfileCommand {
+            name: name.to_string(),
+            package_name: "_/foo".to_string(),
+            package_version: semver::Version::new(1, 0, 0),
+            module: module.to_string(),
+            is_top_level_dependency: true,
+            main_args: None,
+            args: vec![],
+            abi: None,
+            original_name: None,
+            extra: Default::default(),
+        }
+    }
+
+    #[test]
+    fn commands_for_module() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        for (name, module) in &[("foo", "foo"), ("foo-cli", "foo"), ("bar", "bar")] {
+            lockfile
+                .commands
+                .insert(name.to_string(), foo_command(name, module));
+        }
+        let version = semver::Version::new(1, 0, 0);
+
+        let command_names: Vec<&str> = lockfile
+            .commands_for_module("_/foo", &version, "foo")
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(vec!["foo", "foo-cli"], command_names);
+        assert!(lockfile
+            .commands_for_module("_/foo", &semver::Version::new(2, 0, 0), "foo")
+            .is_empty());
+        assert!(lockfile
+            .commands_for_module("_/unknown", &version, "foo")
+            .is_empty());
+    }
+
+    #[test]
+    fn minimal_lockfile_keeps_only_the_modules_of_the_commands() {
+        let mut serve_module = foo_module(None);
+        serve_module.name = "serve".to_string();
+        let mut bar_module = foo_module(None);
+        bar_module.package_name = "_/bar".to_string();
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile.dependencies_hash = Some("hash".to_string());
+        lockfile
+            .modules
+            .extend(lockfile_with_module(bar_module).modules);
+        lockfile
+            .modules
+            .get_mut("_/foo")
+            .unwrap()
+            .values_mut()
+            .for_each(|module_map| {
+                module_map.insert("serve".to_string(), serve_module.clone());
+            });
+        lockfile.build_modules = lockfile_with_module(foo_module(None)).modules;
+        for (name, module) in &[("foo", "foo"), ("serve", "serve")] {
+            lockfile
+                .commands
+                .insert(name.to_string(), foo_command(name, module));
+        }
+
+        let minimal = lockfile.minimal_for_commands(&["serve"]).unwrap();
+        assert_eq!(vec!["serve"], minimal.command_names().collect::<Vec<_>>());
+        let modules: Vec<(&str, &str)> = minimal
+            .iter_modules()
+            .map(|(name, module)| (module.package_name.as_str(), name))
+            .collect();
+        assert_eq!(vec![("_/foo", "serve")], modules);
+        assert!(minimal.build_modules.is_empty());
+        assert!(minimal.dependencies_hash.is_none());
+        minimal.validate().unwrap();
+
+        match lockfile.minimal_for_commands(&["serve", "server"]) {
+            Err(LockfileError::CommandNotFound { command, .. }) => assert_eq!("server", command),
+            result => panic!("expected a command not found error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn validate_detects_dangling_command() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+        lockfile.validate().unwrap();
+
+        lockfile
+            .commands
+            .insert("bar".to_string(), foo_command("bar", "bar"));
+        match lockfile.validate() {
+            Err(LockfileError::DanglingCommand {
+                command, module, ..
+            }) => {
+                assert_eq!("bar", command);
+                assert_eq!("bar", module);
+            }
+            result => panic!("expected a dangling command, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn save_is_byte_identical_after_round_trip() {
+        use crate::data::lock::LOCKFILE_NAME;
+        use crate::dataflow::lockfile_packages::LockfileResult;
+        use std::fs;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let mut lockfile = lockfile_with_module(foo_module(Some("hash".to_string())));
+        let mut bar_module = foo_module(None);
+        bar_module.name = "bar".to_string();
+        bar_module.source = "bar.wasm".to_string();
+        lockfile
+            .modules
+            .get_mut("_/foo")
+            .unwrap()
+            .get_mut(&semver::Version::new(1, 0, 0))
+            .unwrap()
+            .insert("bar".to_string(), bar_module);
+        for (name, module) in &[("foo", "foo"), ("bar", "bar"), ("baz", "bar")] {
+            lockfile
+                .commands
+                .insert(name.to_string(), foo_command(name, module));
+        }
+
+        lockfile.save(tmp_dir.path()).unwrap();
+        let first = fs::read_to_string(tmp_dir.path().join(LOCKFILE_NAME)).unwrap();
+        let reopened = match LockfileResult::find_in_directory(tmp_dir.path()) {
+            LockfileResult::Lockfile(l) => l,
+            _ => panic!("the saved lockfile should be valid"),
+        };
+        reopened.save(tmp_dir.path()).unwrap();
+        let second = fs::read_to_string(tmp_dir.path().join(LOCKFILE_NAME)).unwrap();
+
+        assert_eq!(lockfile, reopened);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn case_collisions_pair_commands_that_differ_only_by_case() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        for name in &["run", "Run", "RUN", "build", "test-Build"] {
+            lockfile
+                .commands
+                .insert(name.to_string(), foo_command(name, "foo"));
+        }
+        assert_eq!(
+            vec![
+                ("RUN".to_string(), "Run".to_string()),
+                ("RUN".to_string(), "run".to_string()),
+                ("Run".to_string(), "run".to_string()),
+            ],
+            lockfile.case_collisions()
+        );
+        lockfile.commands.remove("RUN");
+        lockfile.commands.remove("Run");
+        assert!(lockfile.case_collisions().is_empty());
+    }
+
+    #[test]
+    fn total_download_size_counts_every_package_once() {
+        let mut lockfile = lockfile_with_module(LockfileModule {
+            size: Some(100),
+            ..foo_module(None)
+        });
+        let versions = lockfile.modules.get_mut("_/foo").unwrap();
+        let modules = versions.get_mut(&semver::Version::new(1, 0, 0)).unwrap();
+        modules.insert(
+            "foo-helper".to_string(),
+            LockfileModule {
+                name: "foo-helper".to_string(),
+                size: Some(100),
+                ..foo_module(None)
+            },
+        );
+        let bar_module = LockfileModule {
+            package_name: "_/bar".to_string(),
+            size: Some(50),
+            ..foo_module(None)
+        };
+        let baz_module = LockfileModule {
+            package_name: "_/baz".to_string(),
+            ..foo_module(None)
+        };
+        for module in [bar_module, baz_module] {
+            lockfile
+                .modules
+                .entry(module.package_name.clone())
+                .or_default()
+                .entry(semver::Version::new(1, 0, 0))
+                .or_default()
+                .insert(module.name.clone(), module);
+        }
+
+        assert_eq!(150, lockfile.total_download_size(|_| true));
+        assert_eq!(
+            100,
+            lockfile.total_download_size(|module| module.package_name != "_/bar")
+        );
+        assert_eq!(0, lockfile.total_download_size(|_| false));
+    }
+
+    #[test]
+    fn metadata_is_saved_and_missing_metadata_is_unknown() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        let without_metadata = lockfile.to_string().unwrap();
+        assert!(!without_metadata.contains("[metadata]"));
+        let parsed: Lockfile = without_metadata.parse().unwrap();
+        assert_eq!(None, parsed.metadata);
+
+        lockfile.metadata = Some(Box::new(LockfileMetadata::current()));
+        let with_metadata = lockfile.to_string().unwrap();
+        assert!(with_metadata.contains("[metadata]"));
+        let parsed: Lockfile = with_metadata.parse().unwrap();
+        assert_eq!(lockfile.metadata, parsed.metadata);
+        assert_eq!(
+            LOCKFILE_VERSION,
+            parsed.metadata.as_ref().unwrap().format_version
+        );
+        assert!(lockfile.semantic_eq(&parsed));
+    }
+
+    #[test]
+    fn refresh_commands_keeps_modules_and_dependency_commands() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile
+            .commands
+            .insert("old".to_string(), foo_command("old", "foo"));
+        let mut bar_module = foo_module(None);
+        bar_module.name = "bar".to_string();
+        bar_module.package_name = "_/bar".to_string();
+        lockfile
+            .modules
+            .entry("_/bar".to_string())
+            .or_default()
+            .entry(semver::Version::new(1, 0, 0))
+            .or_default()
+            .insert("bar".to_string(), bar_module);
+        let mut bar_command = foo_command("bar", "bar");
+        bar_command.package_name = "_/bar".to_string();
+        lockfile.commands.insert("bar".to_string(), bar_command);
+        let manifest_toml = |command: &str| {
+            format!(
+                "[package]\nname = \"_/foo\"\nversion = \"1.0.0\"\ndescription = \"\"\n\n[[module]]\nname = \"foo\"\nsource = \"foo.wasm\"\n\n[[command]]\nname = \"{}\"\nmodule = \"foo\"\n",
+                command
+            )
+        };
+
+        let manifest: Manifest = toml::from_str(&manifest_toml("new")).unwrap();
+        lockfile
+            .refresh_commands(&manifest, &HashSet::new(), &HashMap::new())
+            .unwrap();
+        let command_names: Vec<&str> = lockfile.command_names().collect();
+        assert_eq!(vec!["bar", "new"], command_names);
+        assert_eq!(2, lockfile.iter_modules().count());
+
+        let manifest: Manifest = toml::from_str(&manifest_toml("bar")).unwrap();
+        match lockfile.refresh_commands(&manifest, &HashSet::new(), &HashMap::new()) {
+            Err(LockfileError::CommandConflict { command, packages }) => {
+                assert_eq!("bar", command);
+                assert_eq!(vec!["_/bar 1.0.0", "_/foo 1.0.0"], packages);
+            }
+            result => panic!("expected a command conflict, got {:?}", result),
+        }
+        assert!(lockfile.contains_command("new"));
+
+        // a command the manifest declared for a dependency is removed with the declaration
+        let mut declared = foo_command("bar-extra", "bar");
+        declared.package_name = "_/bar".to_string();
+        lockfile.commands.insert("bar-extra".to_string(), declared);
+        let provided_commands: HashMap<String, HashSet<String>> = vec![(
+            "_/bar 1.0.0".to_string(),
+            vec!["bar".to_string()].into_iter().collect(),
+        )]
+        .into_iter()
+        .collect();
+        let manifest: Manifest = toml::from_str(&manifest_toml("new")).unwrap();
+        lockfile
+            .refresh_commands(&manifest, &HashSet::new(), &provided_commands)
+            .unwrap();
+        let command_names: Vec<&str> = lockfile.command_names().collect();
+        assert_eq!(vec!["bar", "new"], command_names);
+    }
+
+    #[test]
+    fn consistency_with_the_manifest() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        let mut test_module = foo_module(None);
+        test_module.name = "test".to_string();
+        test_module.package_name = "_/test".to_string();
+        test_module.package_version = "0.1.0".to_string();
+        lockfile
+            .modules
+            .entry("_/test".to_string())
+            .or_default()
+            .entry(semver::Version::new(0, 1, 0))
+            .or_default()
+            .insert("test".to_string(), test_module);
+        let mut test_command = foo_command("test", "test");
+        test_command.package_name = "_/test".to_string();
+        test_command.package_version = semver::Version::new(0, 1, 0);
+        lockfile.commands.insert("test".to_string(), test_command);
+        let manifest = |dependencies: &str| -> Manifest {
+            toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n\n[[module]]\nname = \"test\"\nsource = \"test.wasm\"\n\n[[command]]\nname = \"test\"\nmodule = \"test\"\n\n[dependencies]\n{}",
+                dependencies
+            ))
+            .unwrap()
+        };
+
+        assert!(lockfile.is_consistent_with(&manifest("foo = \"^1\"")));
+        // an exact version is a pin, a newer compatible version does not satisfy it
+        assert!(lockfile.is_consistent_with(&manifest("foo = \"1.0.0\"")));
+        let mut newer_foo = foo_module(None);
+        newer_foo.package_version = "1.1.0".to_string();
+        let report =
+            lockfile_with_module(newer_foo).consistency_report(&manifest("foo = \"1.0.0\""));
+        assert_eq!(vec!["_/foo 1.0.0".to_string()], report.missing_dependencies);
+        let report = lockfile.consistency_report(&manifest(
+            "foo = \"2\"\n\"_/bar\" = { git = \"https://example.com/bar.git\", rev = \"main\" }",
+        ));
+        assert_eq!(
+            vec![
+                "_/bar (git https://example.com/bar.git main)".to_string(),
+                "_/foo 2".to_string()
+            ],
+            report.missing_dependencies
+        );
+        assert!(report.missing_commands.is_empty());
+
+        lockfile.commands.clear();
+        let report = lockfile.consistency_report(&manifest(""));
+        assert_eq!(vec!["test".to_string()], report.missing_commands);
+        assert!(!report.is_consistent());
+    }
+
+    #[test]
+    fn check_runtime_support() {
+        let mut module = foo_module(None);
+        module.abi = Abi::Emscripten;
+        let lockfile = lockfile_with_module(module);
+
+        lockfile
+            .check_runtime_support(Abi::supported_by_runtime("wasmer"))
+            .unwrap();
+        match lockfile.check_runtime_support(Abi::supported_by_runtime("/usr/bin/wasmer-js")) {
+            Err(e @ LockfileError::UnsupportedAbi { .. }) => {
+                assert_eq!(24, e.exit_code());
+                assert_eq!(
+                    "Module \"foo\" uses the emscripten ABI, which the runtime does not support",
+                    e.to_string()
+                );
+            }
+            result => panic!("expected an unsupported abi error, got {:?}", result),
+        }
+        // only the module that is run has to be supported
+        Lockfile::check_module_runtime_support(
+            "bar",
+            Abi::Wasi,
+            Abi::supported_by_runtime("/usr/bin/wasmer-js"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn read_and_write_without_the_filesystem() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+
+        let mut buffer = vec![];
+        lockfile.write(&mut buffer).unwrap();
+        assert_eq!(lockfile.to_string().unwrap().as_bytes(), &buffer[..]);
+        let (read, warnings) = Lockfile::read_with_diagnostics(io::Cursor::new(&buffer)).unwrap();
+        assert_eq!(lockfile, read);
+        assert!(warnings.is_empty());
+
+        match Lockfile::read(io::Cursor::new("not a lockfile")) {
+            Err(LockfileError::TomlParseError(_)) => {}
+            result => panic!("expected a parse error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn verify_integrity_skips_modules_that_are_not_downloaded() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let lockfile = lockfile_with_module(foo_module(Some("bogus".to_string())));
+        lockfile.verify_integrity(tmp_dir.path()).unwrap();
+    }
+
+    #[test]
+    fn prune_cache_removes_unlocked_packages() {
+        use crate::data::manifest::PACKAGES_DIR_NAME;
+        use std::fs;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let packages_dir = tmp_dir.path().join(PACKAGES_DIR_NAME);
+        for package_dir in &["_/foo@1.0.0", "_/foo@0.9.0", "someone/bar@2.0.0", ".bin"] {
+            fs::create_dir_all(packages_dir.join(package_dir)).unwrap();
+        }
+        fs::write(packages_dir.join("_/foo@1.0.0/foo.wasm"), b"\0asm").unwrap();
+        let lockfile = lockfile_with_module(foo_module(None));
+
+        let removed_paths = lockfile.prune_cache(tmp_dir.path()).unwrap();
+
+        assert_eq!(
+            vec![
+                packages_dir.join("_").join("foo@0.9.0"),
+                packages_dir.join("someone").join("bar@2.0.0"),
+            ],
+            removed_paths
+        );
+        assert!(packages_dir.join("_/foo@1.0.0/foo.wasm").exists());
+        assert!(packages_dir.join(".bin").exists());
+    }
+
+    #[cfg(feature = "prehash-module")]
+    #[test]
+    fn verify_integrity_detects_modified_module() {
+        use crate::data::manifest::PACKAGES_DIR_NAME;
+        use std::fs;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let package_dir = tmp_dir.path().join(PACKAGES_DIR_NAME).join("_/foo@1.0.0");
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("foo.wasm"), b"\0asm").unwrap();
+        let lockfile = lockfile_with_module(foo_module(Some("bogus".to_string())));
+        assert!(lockfile.verify_integrity(tmp_dir.path()).is_err());
+    }
+
+    #[test]
+    fn verify_integrity_checks_module_integrity() {
+        use crate::data::manifest::PACKAGES_DIR_NAME;
+        use crate::util;
+        use std::fs;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let package_dir = tmp_dir.path().join(PACKAGES_DIR_NAME).join("_/foo@1.0.0");
+        fs::create_dir_all(&package_dir).unwrap();
+        let module_path = package_dir.join("foo.wasm");
+        fs::write(&module_path, b"\0asm").unwrap();
+        let mut module = foo_module(None);
+        module.integrity = Some(util::compute_module_integrity(&module_path).unwrap().1);
+        let lockfile = lockfile_with_module(module);
+        lockfile.verify_integrity(tmp_dir.path()).unwrap();
+
+        fs::write(&module_path, b"\0asm modified").unwrap();
+        match lockfile.verify_integrity(tmp_dir.path()) {
+            Err(LockfileError::IntegrityMismatch { module, .. }) => assert_eq!("foo", module),
+            result => panic!("expected an integrity mismatch, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn verify_integrity_checks_the_source_of_the_host() {
+        use crate::data::manifest::{Module, PACKAGES_DIR_NAME};
+        use crate::util;
+        use std::fs;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let package_dir = tmp_dir.path().join(PACKAGES_DIR_NAME).join("_/foo@1.0.0");
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("foo.wasm"), b"\0asm").unwrap();
+        let host_path = package_dir.join("foo-host.wasm");
+        fs::write(&host_path, b"\0asm host").unwrap();
+        let host_target = format!(
+            "{}-unknown-{}",
+            std::env::consts::ARCH,
+            std::env::consts::OS
+        );
+        let manifest_module: Module = toml::from_str(&format!(
+            "name = \"foo\"\nsource = \"foo.wasm\"\nabi = \"wasi\"\n[targets]\n\"{}\" = \"foo-host.wasm\"",
+            host_target
+        ))
+        .unwrap();
+        let mut module = LockfileModule::from_module(
+            &package_dir,
+            "_/foo",
+            &semver::Version::new(1, 0, 0),
+            &manifest_module,
+            "https://registry.wapm.io/foo-1.0.0.tar.gz",
+        );
+        let host_integrity = util::compute_module_integrity(&host_path).unwrap().1;
+        assert_eq!(
+            Some(host_integrity.as_str()),
+            module.integrity_for_target(None)
+        );
+        assert_ne!(
+            module.integrity.as_deref(),
+            module.integrity_for_target(None)
+        );
+        // the prehashed key is the hash of `source`, which the host does not run
+        module.prehashed_module_key = Some("foo-hash".to_string());
+        assert_eq!(None, module.prehashed_module_key_for_host());
+
+        let mut lockfile = lockfile_with_module(module);
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+        lockfile.verify_integrity(tmp_dir.path()).unwrap();
+        assert_eq!(
+            None,
+            lockfile.get_prehashed_cache_key_from_command(&lockfile.commands["foo"])
+        );
+
+        fs::write(&host_path, b"\0asm modified").unwrap();
+        match lockfile.verify_integrity(tmp_dir.path()) {
+            Err(LockfileError::IntegrityMismatch { module, .. }) => assert_eq!("foo", module),
+            result => panic!("expected an integrity mismatch, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn verify_download_checks_module_integrity() {
+        use crate::util;
+
+        let version = semver::Version::new(1, 0, 0);
+        let lockfile = lockfile_with_module(foo_module(None));
+        // modules locked without an integrity accept any download
+        lockfile
+            .verify_download("_/foo", &version, "foo", b"\0asm")
+            .unwrap();
+
+        let mut module = foo_module(None);
+        module.integrity = Some(util::compute_integrity(b"\0asm").1);
+        let lockfile = lockfile_with_module(module);
+        lockfile
+            .verify_download("_/foo", &version, "foo", b"\0asm")
+            .unwrap();
+        match lockfile.verify_download("_/foo", &version, "foo", b"\0asm republished") {
+            Err(LockfileError::IntegrityMismatch { expected, .. }) => {
+                assert_eq!(util::compute_integrity(b"\0asm").1, expected)
+            }
+            result => panic!("expected an integrity mismatch, got {:?}", result),
+        }
+        assert!(lockfile
+            .verify_download("_/foo", &version, "bar", b"\0asm")
+            .is_err());
+    }
+
+    #[test]
+    fn verify_package_dir_checks_every_target_source() {
+        use crate::util;
+
+        let version = semver::Version::new(1, 0, 0);
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(tmp_dir.path().join("foo.wasm"), b"\0asm").unwrap();
+        fs::write(tmp_dir.path().join("foo-host.wasm"), b"\0asm host").unwrap();
+        let mut module = foo_module(None);
+        module.integrity = Some(util::compute_integrity(b"\0asm").1);
+        module
+            .target_sources
+            .insert("host".to_string(), "foo-host.wasm".to_string());
+        module
+            .target_integrity
+            .insert("host".to_string(), util::compute_integrity(b"\0asm host").1);
+        let lockfile = lockfile_with_module(module);
+        lockfile
+            .verify_package_dir("_/foo", &version, tmp_dir.path())
+            .unwrap();
+        // packages that are not locked are not checked
+        lockfile
+            .verify_package_dir("_/foo", &semver::Version::new(2, 0, 0), tmp_dir.path())
+            .unwrap();
+
+        fs::write(tmp_dir.path().join("foo-host.wasm"), b"\0asm republished").unwrap();
+        match lockfile.verify_package_dir("_/foo", &version, tmp_dir.path()) {
+            Err(LockfileError::IntegrityMismatch { expected, .. }) => {
+                assert_eq!(util::compute_integrity(b"\0asm host").1, expected)
+            }
+            result => panic!("expected an integrity mismatch, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn check_yanked_reports_yanked_registry_packages() {
+        use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage};
+        use crate::dataflow::PackageKey;
+
+        /// Reports `_/foo 1.0.0` and `_/local` as yanked, and can not tell about `_/baz`
+        struct YankingResolver;
+
+        impl<'a> Resolve<'a> for YankingResolver {
+            fn sync_packages(
+                &self,
+                _added_packages: Vec<PackageKey<'a>>,
+            ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+                unreachable!("checking for yanked versions does not resolve packages")
+            }
+
+            fn is_yanked(&self, name: &str, version: &semver::Version) -> Result<bool, Error> {
+                match name {
+                    "_/baz" => Err(Error::CouldNotResolvePackages("offline".to_string())),
+                    "_/local" => Ok(true),
+                    _ => Ok(name == "_/foo" && *version == semver::Version::new(1, 0, 0)),
+                }
+            }
+        }
+
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        for (name, version, resolved_source) in &[
+            ("_/foo", "2.0.0", "registry+foo"),
+            ("_/bar", "1.0.0", "registry+foo"),
+            ("_/baz", "1.0.0", "registry+foo"),
+            ("_/local", "1.0.0", "local"),
+        ] {
+            let mut module = foo_module(None);
+            module.package_name = name.to_string();
+            module.package_version = version.to_string();
+            module.resolved_source = resolved_source.to_string();
+            let other = lockfile_with_module(module);
+            for (name, versions) in other.modules {
+                lockfile.modules.entry(name).or_default().extend(versions);
+            }
+        }
+        lockfile.build_modules = lockfile_with_module(foo_module(None)).modules;
+
+        assert_eq!(vec!["_/foo 1.0.0"], lockfile.check_yanked(&YankingResolver));
+    }
+
+    #[test]
+    fn touch_refreshes_urls_of_the_same_artifact() {
+        use crate::dataflow::cache_resolver::{self, CacheResolver};
+        use crate::dataflow::resolved_packages::VersionSelector;
+        use crate::util;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let write_tarball = |wasm: &[u8]| {
+            let manifest = "[package]\nname = \"_/foo\"\nversion = \"1.0.0\"\ndescription = \"\"\n";
+            cache_resolver::write_tarball(
+                &tmp_dir.path().join("_-foo-1.0.0.tar.gz"),
+                &[("wapm.toml", manifest.as_bytes()), ("foo.wasm", wasm)],
+            );
+        };
+        let resolver = CacheResolver::new(tmp_dir.path(), VersionSelector::Highest);
+        let mut module = foo_module(Some("hash".to_string()));
+        module.integrity = Some(util::compute_integrity(b"\0asm").1);
+        let mut local_module = foo_module(None);
+        local_module.package_name = "_/local".to_string();
+        local_module.resolved = "local".to_string();
+        local_module.resolved_source = "local".to_string();
+        let mut expired = lockfile_with_module(module);
+        expired
+            .modules
+            .extend(lockfile_with_module(local_module).modules);
+
+        write_tarball(b"\0asm");
+        let mut lockfile = expired.clone();
+        assert_eq!(vec!["_/foo 1.0.0"], lockfile.touch(&resolver).unwrap());
+        let module = &lockfile.modules["_/foo"][&semver::Version::new(1, 0, 0)]["foo"];
+        assert!(module.resolved.starts_with("file://"));
+        // only the url changes
+        assert_eq!(Some("hash"), module.prehashed_module_key.as_deref());
+        assert_eq!(
+            "local",
+            lockfile.modules["_/local"][&semver::Version::new(1, 0, 0)]["foo"].resolved
+        );
+        assert!(lockfile.touch(&resolver).unwrap().is_empty());
+
+        // a url pointing at different bytes is not taken
+        write_tarball(b"\0asm republished");
+        let mut lockfile = expired.clone();
+        match lockfile.touch(&resolver) {
+            Err(LockfileError::IntegrityMismatch { module, .. }) => assert_eq!("foo", module),
+            result => panic!("expected an integrity mismatch, got {:?}", result),
+        }
+        assert_eq!(expired, lockfile);
+    }
+
+    #[test]
+    fn merge_lockfiles() {
+        let mut first = lockfile_with_module(foo_module(Some("hash".to_string())));
+        first
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+        let mut bar_module = foo_module(None);
+        bar_module.name = "bar".to_string();
+        bar_module.source = "bar.wasm".to_string();
+        let mut second = lockfile_with_module(bar_module);
+        second
+            .modules
+            .get_mut("_/foo")
+            .unwrap()
+            .get_mut(&semver::Version::new(1, 0, 0))
+            .unwrap()
+            .insert("foo".to_string(), foo_module(Some("hash".to_string())));
+        second
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+        second
+            .commands
+            .insert("bar".to_string(), foo_command("bar", "bar"));
+
+        let merged = first.clone().merge(second).unwrap();
+        let version = semver::Version::new(1, 0, 0);
+
+        assert!(merged.get_module("_/foo", &version, "foo").is_ok());
+        assert!(merged.get_module("_/foo", &version, "bar").is_ok());
+        assert_eq!(2, merged.commands.len());
+        assert_eq!(first.clone(), first.clone().merge(first).unwrap());
+    }
+
+    #[test]
+    fn merge_conflicts() {
+        let first = lockfile_with_module(foo_module(Some("hash".to_string())));
+        let second = lockfile_with_module(foo_module(Some("other hash".to_string())));
+        match first.clone().merge(second) {
+            Err(LockfileError::MergeConflict { entry, .. }) => {
+                assert_eq!("module \"foo\" in package \"_/foo 1.0.0\"", entry)
+            }
+            result => panic!("expected a merge conflict, got {:?}", result),
+        }
+
+        let mut with_command = first.clone();
+        with_command
+            .commands
+            .insert("run".to_string(), foo_command("run", "foo"));
+        let mut bar_module = foo_module(Some("hash".to_string()));
+        bar_module.name = "bar".to_string();
+        let mut other_command = lockfile_with_module(bar_module);
+        other_command
+            .commands
+            .insert("run".to_string(), foo_command("run", "bar"));
+        match with_command.merge(other_command) {
+            Err(LockfileError::MergeConflict { entry, .. }) => {
+                assert_eq!("command \"run\"", entry)
+            }
+            result => panic!("expected a merge conflict, got {:?}", result),
+        }
+
+        // build modules must agree as well
+        let as_build_module = |lockfile: Lockfile| Lockfile {
+            build_modules: lockfile.modules,
+            modules: BTreeMap::new(),
+            ..lockfile
+        };
+        let second = lockfile_with_module(foo_module(Some("other hash".to_string())));
+        match as_build_module(first).merge(as_build_module(second)) {
+            Err(LockfileError::MergeConflict { entry, .. }) => {
+                assert_eq!("module \"foo\" in package \"_/foo 1.0.0\"", entry)
+            }
+            result => panic!("expected a merge conflict, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn save_replaces_the_lockfile_without_leaving_temporary_files() {
+        use crate::data::lock::LOCKFILE_NAME;
+        use std::fs;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(tmp_dir.path().join(LOCKFILE_NAME), "truncated").unwrap();
+        let lockfile = lockfile_with_module(foo_module(None));
+
+        lockfile.save(tmp_dir.path()).unwrap();
+
+        let saved = fs::read_to_string(tmp_dir.path().join(LOCKFILE_NAME)).unwrap();
+        assert!(saved.starts_with("# Lockfile v"));
+        let entries: Vec<_> = fs::read_dir(tmp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(vec![std::ffi::OsString::from(LOCKFILE_NAME)], entries);
+    }
+
+    #[test]
+    fn get_command_qualified() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile
+            .commands
+            .insert("run".to_string(), foo_command("run", "foo"));
+
+        assert_eq!("run", lockfile.get_command_qualified("run").unwrap().name);
+        assert_eq!(
+            "run",
+            lockfile.get_command_qualified("_/foo::run").unwrap().name
+        );
+        assert_eq!(
+            "run",
+            lockfile.get_command_qualified("foo::run").unwrap().name
+        );
+        match lockfile.get_command_qualified("bar::run") {
+            Err(LockfileError::CommandNotInPackage { provided_by, .. }) => {
+                assert_eq!("_/foo", provided_by)
+            }
+            result => panic!(
+                "expected the command to be in another package, got {:?}",
+                result
+            ),
+        }
+        match lockfile.get_command_qualified("foo::missing") {
+            Err(LockfileError::CommandNotFound { command, .. }) => assert_eq!("missing", command),
+            result => panic!("expected a missing command, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn get_command_in_package() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        let bar_module = LockfileModule {
+            package_name: "_/bar".to_string(),
+            ..foo_module(None)
+        };
+        lockfile
+            .modules
+            .entry("_/bar".to_string())
+            .or_default()
+            .entry(semver::Version::new(1, 0, 0))
+            .or_default()
+            .insert("foo".to_string(), bar_module);
+        lockfile
+            .commands
+            .insert("run".to_string(), foo_command("run", "foo"));
+
+        let found = lockfile.get_command_in_package("_/foo", "run").unwrap();
+        assert_eq!(
+            ("run", "_/foo"),
+            (found.name.as_str(), found.package_name.as_str())
+        );
+        assert_eq!(
+            "run",
+            lockfile.get_command_in_package("foo", "run").unwrap().name
+        );
+        assert_eq!(
+            "run",
+            lockfile.get_command_qualified("foo:run").unwrap().name
+        );
+
+        match lockfile.get_command_in_package("fo", "run") {
+            Err(LockfileError::PackageNotFound {
+                package,
+                suggestion,
+            }) => {
+                assert_eq!("_/fo", package);
+                assert_eq!(Some("_/foo".to_string()), suggestion);
+            }
+            result => panic!("expected a missing package, got {:?}", result),
+        }
+        match lockfile.get_command_in_package("_/foo", "rnu") {
+            Err(LockfileError::CommandNotFoundInPackage {
+                command,
+                package,
+                suggestion,
+            }) => {
+                assert_eq!(("rnu", "_/foo"), (command.as_str(), package.as_str()));
+                assert_eq!(Some("run".to_string()), suggestion);
+            }
+            result => panic!("expected a missing command, got {:?}", result),
+        }
+        lockfile
+            .commands
+            .insert("foo:run".to_string(), foo_command("foo:run", "foo"));
+        assert_eq!(
+            "foo:run",
+            lockfile.get_command_qualified("foo:run").unwrap().name
+        );
+        match lockfile.get_command_qualified("bar:run") {
+            Err(LockfileError::CommandNotInPackage { provided_by, .. }) => {
+                assert_eq!("_/foo", provided_by)
+            }
+            result => panic!(
+                "expected the command to be in another package, got {:?}",
+                result
+            ),
+        }
+    }
+
+    #[test]
+    fn save_keeps_unknown_fields() {
+        use crate::data::lock::LOCKFILE_NAME;
+        use crate::dataflow::lockfile_packages::LockfileResult;
+        use std::fs;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+        lockfile.save(tmp_dir.path()).unwrap();
+        // simulate a lockfile written by a newer version of wapm
+        let lockfile_path = tmp_dir.path().join(LOCKFILE_NAME);
+        let newer_lockfile = fs::read_to_string(&lockfile_path)
+            .unwrap()
+            .replace(
+                "source = \"foo.wasm\"\n",
+                "source = \"foo.wasm\"\nfuture_module_field = \"kept\"\n",
+            )
+            .replace(
+                "is_top_level_dependency",
+                "future_command_field = 1
+is_top_level_dependency",
+            );
+        assert!(newer_lockfile.contains("future_module_field"));
+        fs::write(&lockfile_path, newer_lockfile).unwrap();
+
+        let reopened = match LockfileResult::find_in_directory(tmp_dir.path()) {
+            LockfileResult::Lockfile(l) => l,
+            _ => panic!("the newer lockfile should be valid"),
+        };
+        reopened.save(tmp_dir.path()).unwrap();
+
+        let saved = fs::read_to_string(&lockfile_path).unwrap();
+        assert!(saved.contains("future_module_field = \"kept\""));
+        assert!(saved.contains("future_command_field = 1"));
+    }
+
+    #[test]
+    fn lockfile_stats() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+        assert_eq!(
+            LockfileStats {
+                packages: 1,
+                modules: 1,
+                commands: 1,
+                max_depth: None,
+            },
+            lockfile.stats()
+        );
+
+        let mut foo = foo_module(None);
+        foo.dependents = vec!["_/test@0.1.0".to_string()];
+        let mut bar = foo_module(None);
+        bar.name = "bar".to_string();
+        bar.package_name = "_/bar".to_string();
+        bar.dependents = vec!["_/foo@1.0.0".to_string()];
+        let mut lockfile = lockfile_with_module(foo);
+        let bar_lockfile = lockfile_with_module(bar);
+        lockfile.modules.extend(bar_lockfile.modules);
+        let stats = lockfile.stats();
+        assert_eq!(2, stats.packages);
+        assert_eq!(Some(2), stats.max_depth);
+    }
+
+    #[test]
+    fn requirements_txt_lists_every_package_once() {
+        let lockfile_toml = toml! {
+            [modules."_/foo"."10.0.0".foo]
+            name = "foo"
+            package_version = "10.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@10.0.0"
+            resolved = ""
+            resolved_source = "registry+foo"
+            abi = "none"
+            source = "foo.wasm"
+            [modules."_/foo"."10.0.0".foo-cli]
+            name = "foo-cli"
+            package_version = "10.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@10.0.0"
+            resolved = ""
+            resolved_source = "registry+foo-cli"
+            abi = "none"
+            source = "foo-cli.wasm"
+            [modules."_/foo"."9.0.0".foo]
+            name = "foo"
+            package_version = "9.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@9.0.0"
+            resolved = ""
+            resolved_source = "registry+foo"
+            abi = "none"
+            source = "foo.wasm"
+            [modules."_/bar"."1.0.0".bar]
+            name = "bar"
+            package_version = "1.0.0"
+            package_name = "_/bar"
+            package_path = "_/bar@1.0.0"
+            resolved = ""
+            resolved_source = "registry+bar"
+            abi = "none"
+            source = "bar.wasm"
+            [build_modules."_/gen"."1.0.0".gen]
+            name = "gen"
+            package_version = "1.0.0"
+            package_name = "_/gen"
+            package_path = "_/gen@1.0.0"
+            resolved = ""
+            resolved_source = "registry+gen"
+            abi = "none"
+            source = "gen.wasm"
+            [commands]
+        };
+        let lockfile: Lockfile = lockfile_toml.try_into().unwrap();
+
+        assert_eq!(
+            "_/bar==1.0.0\n_/foo==9.0.0\n_/foo==10.0.0\n",
+            lockfile.to_requirements_txt()
+        );
+    }
+
+    #[test]
+    fn chains_deeper_than_the_limit_are_rejected() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile.modules.clear();
+        // _/test <- _/p0 <- _/p1 <- _/p2 <- _/p3
+        for depth in 0..4 {
+            let dependent = match depth {
+                0 => "_/test@0.1.0".to_string(),
+                _ => format!("_/p{}@1.0.0", depth - 1),
+            };
+            let module = LockfileModule {
+                name: format!("p{}", depth),
+                package_name: format!("_/p{}", depth),
+                dependents: vec![dependent],
+                ..foo_module(None)
+            };
+            lockfile
+                .modules
+                .extend(lockfile_with_module(module).modules);
+        }
+
+        assert!(lockfile.check_depth(4).is_ok());
+        match lockfile.check_depth(3) {
+            Err(LockfileError::DepthExceeded { depth, limit }) => {
+                assert_eq!(4, depth);
+                assert_eq!(3, limit);
+            }
+            result => panic!("expected the depth to exceed the limit, got {:?}", result),
+        }
+        // without dependents there is no chain to limit
+        assert!(lockfile_with_module(foo_module(None))
+            .check_depth(0)
+            .is_ok());
+    }
+
+    #[test]
+    fn parse_and_render_without_the_filesystem() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+
+        let rendered = lockfile.to_string().unwrap();
+        assert!(rendered.starts_with(&format!(
+            "# Lockfile v{}\n{}\n",
+            LOCKFILE_VERSION, LOCKFILE_HEADER
+        )));
+        assert_eq!(lockfile, rendered.parse::<Lockfile>().unwrap());
+        match "modules = 1".parse::<Lockfile>() {
+            Err(e @ LockfileError::TomlParseError(_)) => assert_eq!(12, e.exit_code()),
+            result => panic!("expected a toml parse error, got {:?}", result),
+        }
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        match Lockfile::open(tmp_dir.path()) {
+            Err(LockfileError::MissingLockfile) => {}
+            result => panic!("expected a missing lockfile error, got {:?}", result),
+        }
+        lockfile.save(tmp_dir.path()).unwrap();
+        assert_eq!(lockfile, Lockfile::open(tmp_dir.path()).unwrap());
+    }
+
+    #[test]
+    fn open_warns_about_an_altered_header() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let lockfile = lockfile_with_module(foo_module(None));
+        lockfile.save(tmp_dir.path()).unwrap();
+        let (opened, warnings) = Lockfile::open_with_diagnostics(tmp_dir.path()).unwrap();
+        assert_eq!(lockfile, opened);
+        assert!(warnings.is_empty());
+
+        let rendered = lockfile.to_string().unwrap();
+        let edited = rendered.replacen("automatically generated", "generated", 1);
+        fs::write(tmp_dir.path().join("wapm.lock"), edited).unwrap();
+        let (opened, warnings) = Lockfile::open_with_diagnostics(tmp_dir.path()).unwrap();
+        assert_eq!(lockfile, opened);
+        assert_eq!(vec![LockfileWarning::AlteredHeader], warnings);
+
+        let edited = rendered.lines().skip(3).collect::<Vec<_>>().join("\n");
+        fs::write(tmp_dir.path().join("wapm.lock"), edited).unwrap();
+        let (_, warnings) = Lockfile::open_with_diagnostics(tmp_dir.path()).unwrap();
+        assert_eq!(2, warnings.len());
+        assert!(matches!(
+            warnings[0],
+            LockfileWarning::UnexpectedVersionLine(_)
+        ));
+    }
+
+    #[test]
+    fn unused_modules() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        let module_map = lockfile
+            .modules
+            .get_mut("_/foo")
+            .unwrap()
+            .get_mut(&semver::Version::new(1, 0, 0))
+            .unwrap();
+        for name in &["bar", "baz", "local"] {
+            let mut module = foo_module(None);
+            module.name = name.to_string();
+            module_map.insert(name.to_string(), module);
+        }
+        module_map.get_mut("baz").unwrap().dependents = vec!["_/test@0.1.0".to_string()];
+        module_map.get_mut("local").unwrap().resolved_source = "local".to_string();
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+
+        let version = semver::Version::new(1, 0, 0);
+        assert_eq!(vec![("_/foo", &version, "bar")], lockfile.unused_modules());
+        lockfile.remove_command("foo");
+        assert_eq!(
+            vec![("_/foo", &version, "bar"), ("_/foo", &version, "foo")],
+            lockfile.unused_modules()
+        );
+    }
+
+    #[test]
+    fn unused_modules_of_a_manifest_project() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile.root_package = Some("_/test 0.1.0".to_string());
+        let module_map = lockfile
+            .modules
+            .get_mut("_/foo")
+            .unwrap()
+            .get_mut(&semver::Version::new(1, 0, 0))
+            .unwrap();
+        for name in &["bar", "baz"] {
+            let mut module = foo_module(None);
+            module.name = name.to_string();
+            module_map.insert(name.to_string(), module);
+        }
+        // every package of a manifest project is a dependency of the manifest's package
+        for module in module_map.values_mut() {
+            module.dependents = vec!["_/test@0.1.0".to_string()];
+        }
+        module_map
+            .get_mut("baz")
+            .unwrap()
+            .dependents
+            .push("_/qux@1.0.0".to_string());
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+
+        let version = semver::Version::new(1, 0, 0);
+        assert_eq!(vec![("_/foo", &version, "bar")], lockfile.unused_modules());
+    }
+
+    #[test]
+    fn open_and_save_named_lockfile() {
+        use crate::data::lock::LOCKFILE_NAME;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let lockfile = lockfile_with_module(foo_module(None));
+        lockfile.save_named(tmp_dir.path(), "other.lock").unwrap();
+
+        assert!(!tmp_dir.path().join(LOCKFILE_NAME).exists());
+        assert_eq!(
+            lockfile,
+            Lockfile::open_named(tmp_dir.path(), "other.lock").unwrap()
+        );
+        match Lockfile::open(tmp_dir.path()) {
+            Err(LockfileError::MissingLockfile) => {}
+            result => panic!("expected no wapm.lock, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn required_cli_version_is_the_first_version_with_the_used_features() {
+        let mut module = foo_module(None);
+        assert_eq!(
+            None,
+            lockfile_with_module(module.clone()).required_cli_version()
+        );
+
+        module.resolved_source = "git+https://github.com/foo/foo#abc".to_string();
+        assert_eq!(
+            Some(semver::Version::parse(GIT_SOURCES_CLI_VERSION).unwrap()),
+            lockfile_with_module(module.clone()).required_cli_version()
+        );
+        module.target_sources.insert(
+            "x86_64-unknown-linux".to_string(),
+            "foo-linux.wasm".to_string(),
+        );
+        let newest = std::cmp::max(
+            semver::Version::parse(GIT_SOURCES_CLI_VERSION).unwrap(),
+            semver::Version::parse(TARGET_SOURCES_CLI_VERSION).unwrap(),
+        );
+        assert_eq!(
+            Some(newest.clone()),
+            lockfile_with_module(module.clone()).required_cli_version()
+        );
+
+        // build modules are installed too
+        let lockfile = lockfile_with_module(module);
+        let lockfile = Lockfile {
+            build_modules: lockfile.modules.clone(),
+            modules: BTreeMap::new(),
+            ..lockfile
+        };
+        assert_eq!(Some(newest), lockfile.required_cli_version());
+    }
+
+    #[test]
+    fn lockfiles_requiring_a_newer_wapm_are_not_opened() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile.min_cli_version = Some(semver::Version::new(0, 1, 0));
+        lockfile.save(tmp_dir.path()).unwrap();
+        assert_eq!(lockfile, Lockfile::open(tmp_dir.path()).unwrap());
+
+        lockfile.min_cli_version = Some(semver::Version::new(999, 0, 0));
+        lockfile.save(tmp_dir.path()).unwrap();
+        match Lockfile::open(tmp_dir.path()) {
+            Err(LockfileError::UnsupportedVersion { required, current }) => {
+                assert_eq!("999.0.0", required);
+                assert_eq!(env!("CARGO_PKG_VERSION"), current);
+            }
+            result => panic!("expected an unsupported version error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn dedup_by_hash() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        let mut foo_2 = foo_module(None);
+        foo_2.package_version = "2.0.0".to_string();
+        let mut modules = BTreeMap::new();
+        for (name, integrity) in &[
+            ("foo", Some("sha256-foo")),
+            ("bar", Some("sha256-bar")),
+            ("baz", Some("")),
+            ("qux", None),
+        ] {
+            let mut module = foo_2.clone();
+            module.name = name.to_string();
+            module.integrity = integrity.map(str::to_string);
+            modules.insert(name.to_string(), module);
+        }
+        lockfile
+            .modules
+            .get_mut("_/foo")
+            .unwrap()
+            .insert(semver::Version::new(2, 0, 0), modules);
+        lockfile
+            .modules
+            .get_mut("_/foo")
+            .unwrap()
+            .get_mut(&semver::Version::new(1, 0, 0))
+            .unwrap()
+            .get_mut("foo")
+            .unwrap()
+            .integrity = Some("sha256-foo".to_string());
+
+        let identical: Vec<Vec<String>> = lockfile
+            .dedup_by_hash()
+            .iter()
+            .map(|modules| {
+                modules
+                    .iter()
+                    .map(|module| format!("{}@{}", module.name, module.package_version))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            vec![vec!["foo@1.0.0".to_string(), "foo@2.0.0".to_string()]],
+            identical
+        );
+    }
+
+    #[test]
+    fn stale_modules() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        let module_map = lockfile
+            .modules
+            .get_mut("_/foo")
+            .unwrap()
+            .get_mut(&semver::Version::new(1, 0, 0))
+            .unwrap();
+        for (name, days_ago) in &[("old", 400), ("new", 1)] {
+            let mut module = foo_module(None);
+            module.name = name.to_string();
+            module.resolved_at = Some(chrono::Utc::now() - chrono::Duration::days(*days_ago));
+            module_map.insert(name.to_string(), module);
+        }
+
+        let year = std::time::Duration::from_secs(365 * 24 * 60 * 60);
+        let stale: Vec<&str> = lockfile
+            .stale_modules(year)
+            .iter()
+            .map(|module| module.name.as_str())
+            .collect();
+        assert_eq!(vec!["old"], stale);
+        assert_eq!(2, lockfile.stale_modules(std::time::Duration::ZERO).len());
+
+        let serialized = lockfile.to_string().unwrap();
+        let parsed: Lockfile = serialized.parse().unwrap();
+        assert_eq!(lockfile.modules, parsed.modules);
+    }
+
+    #[test]
+    fn reinstalled_modules_keep_their_resolution_time() {
+        let resolved_at =
+            |lockfile: &Lockfile| lockfile.iter_modules().next().unwrap().1.resolved_at;
+        let mut first_module = foo_module(None);
+        first_module.integrity = Some("sha256-foo".to_string());
+        first_module.resolved_at = Some(chrono::Utc::now() - chrono::Duration::days(400));
+        let previous = lockfile_with_module(first_module.clone());
+
+        let mut reinstalled = first_module.clone();
+        reinstalled.resolved_at = Some(chrono::Utc::now());
+        let mut lockfile = lockfile_with_module(reinstalled.clone());
+        lockfile.keep_resolved_at(&previous);
+        assert_eq!(first_module.resolved_at, resolved_at(&lockfile));
+
+        // other bytes at the same version were fetched anew
+        let mut changed = reinstalled.clone();
+        changed.integrity = Some("sha256-bar".to_string());
+        let mut lockfile = lockfile_with_module(changed);
+        lockfile.keep_resolved_at(&previous);
+        assert_eq!(reinstalled.resolved_at, resolved_at(&lockfile));
+    }
+
+    #[test]
+    fn iterate_modules_and_commands() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        let mut bar = foo_module(None);
+        bar.name = "bar".to_string();
+        lockfile
+            .modules
+            .get_mut("_/foo")
+            .unwrap()
+            .get_mut(&semver::Version::new(1, 0, 0))
+            .unwrap()
+            .insert("bar".to_string(), bar);
+        for name in &["foo-cli", "foo"] {
+            lockfile
+                .commands
+                .insert(name.to_string(), foo_command(name, "foo"));
+        }
+
+        let modules: Vec<(&str, &str)> = lockfile
+            .iter_modules()
+            .map(|(name, module)| (name, module.name.as_str()))
+            .collect();
+        assert_eq!(vec![("bar", "bar"), ("foo", "foo")], modules);
+        let command_names: Vec<&str> = lockfile.iter_commands().map(|(name, _)| name).collect();
+        assert_eq!(vec!["foo", "foo-cli"], command_names);
+    }
+
+    #[test]
+    fn namespaced_pre_release_packages_round_trip() {
+        use crate::dataflow::lockfile_packages::LockfileResult;
+
+        let version = semver::Version::parse("1.0.0-beta.1").unwrap();
+        let mut module = foo_module(None);
+        module.name = "tool".to_string();
+        module.package_name = "someuser/somepackage".to_string();
+        module.package_version = version.to_string();
+        module.package_path = format!("someuser/somepackage@{}", version);
+        let mut command = foo_command("tool", "tool");
+        command.package_name = "someuser/somepackage".to_string();
+        command.package_version = version.clone();
+        let mut lockfile = lockfile_with_module(module);
+        lockfile.commands.insert("tool".to_string(), command);
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        lockfile.save(tmp_dir.path()).unwrap();
+        let reopened = match LockfileResult::find_in_directory(tmp_dir.path()) {
+            LockfileResult::Lockfile(l) => l,
+            _ => panic!("the saved lockfile should be valid"),
+        };
+        assert_eq!(lockfile, reopened);
+
+        let command = reopened
+            .get_command_qualified("someuser/somepackage::tool")
+            .unwrap();
+        assert_eq!(version, command.package_version);
+        let module = reopened
+            .get_module(
+                &command.package_name,
+                &command.package_version,
+                &command.module,
+            )
+            .unwrap();
+        assert_eq!("someuser/somepackage@1.0.0-beta.1", module.package_path);
+        assert!(reopened.validate().is_ok());
+    }
+
+    #[test]
+    fn remove_module_and_commands() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+        lockfile
+            .commands
+            .insert("foo-cli".to_string(), foo_command("foo-cli", "foo"));
+        let version = semver::Version::new(1, 0, 0);
+
+        let removed = lockfile.remove_command("foo-cli").unwrap();
+        assert_eq!("foo-cli", removed.name);
+        assert!(lockfile.remove_command("foo-cli").is_none());
+        assert!(lockfile.get_module("_/foo", &version, "foo").is_ok());
+
+        let (module, commands) = lockfile.remove_module("_/foo", &version, "foo").unwrap();
+        assert_eq!("foo", module.name);
+        assert_eq!(vec![foo_command("foo", "foo")], commands);
+        assert!(lockfile.modules.is_empty());
+        assert!(lockfile.commands.is_empty());
+        assert!(lockfile.remove_module("_/foo", &version, "foo").is_none());
+    }
+
+    #[test]
+    fn contains_commands_and_modules() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+        let version = semver::Version::new(1, 0, 0);
+
+        assert!(lockfile.contains_command("foo"));
+        assert!(!lockfile.contains_command("bar"));
+        assert!(lockfile.contains_module("_/foo", &version, "foo"));
+        assert!(!lockfile.contains_module("_/foo", &version, "bar"));
+        assert!(!lockfile.contains_module("_/foo", &semver::Version::new(2, 0, 0), "foo"));
+        assert_eq!(vec!["foo"], lockfile.command_names().collect::<Vec<_>>());
+        assert_eq!(
+            vec![("_/foo", &version, "foo")],
+            lockfile.module_keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn get_commands() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        for name in &["foo", "foo-cli"] {
+            lockfile
+                .commands
+                .insert(name.to_string(), foo_command(name, "foo"));
+        }
+
+        let commands = lockfile.get_commands(&["foo-cli", "foo"]).unwrap();
+        let names: Vec<&str> = commands.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(vec!["foo-cli", "foo"], names);
+        match lockfile.get_commands(&["foo", "bar", "baz"]) {
+            Err(LockfileError::CommandNotFound { command, .. }) => assert_eq!("bar", command),
+            result => panic!("expected a command not found error, got {:?}", result),
+        }
+
+        let (found, missing) = lockfile.get_commands_partial(&["foo", "bar", "baz"]);
+        assert_eq!(1, found.len());
+        assert_eq!("foo", found[0].name);
+        assert_eq!(vec!["bar", "baz"], missing);
+    }
+
+    #[test]
+    fn semantic_eq_ignores_volatile_fields() {
+        let mut lockfile = lockfile_with_module(LockfileModule {
+            integrity: Some("sha256-foo".to_string()),
+            ..foo_module(Some("foo-hash".to_string()))
+        });
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+        let with_module = |change: &dyn Fn(&mut LockfileModule)| {
+            let mut other = lockfile.clone();
+            for version_map in other.modules.values_mut() {
+                for module_map in version_map.values_mut() {
+                    module_map.values_mut().for_each(change);
+                }
+            }
+            other
+        };
+
+        let refetched = with_module(&|module| {
+            module.resolved = "https://registry.wapm.io/foo.tar.gz?token=rotated".to_string();
+            module.resolved_at = Some(chrono::Utc::now());
+        });
+        assert!(lockfile.semantic_eq(&refetched));
+        assert_ne!(lockfile, refetched);
+
+        let changed = with_module(&|module| module.integrity = Some("sha256-bar".to_string()));
+        assert!(!lockfile.semantic_eq(&changed));
+        let mut changed = lockfile.clone();
+        changed
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "bar"));
+        assert!(!lockfile.semantic_eq(&changed));
+
+        // the arguments of a command change how it runs
+        let with_command = |change: &dyn Fn(&mut LockfileCommand)| {
+            let mut other = lockfile.clone();
+            other.commands.values_mut().for_each(change);
+            other
+        };
+        let changed = with_command(&|command| command.args = vec!["--verbose".to_string()]);
+        assert!(!lockfile.semantic_eq(&changed));
+        let changed = with_command(&|command| command.main_args = Some("--verbose".to_string()));
+        assert!(!lockfile.semantic_eq(&changed));
+    }
+
+    #[test]
+    fn not_found_errors_suggest_close_names() {
+        let mut lockfile = lockfile_with_module(foo_module(None));
+        lockfile
+            .commands
+            .insert("foo-cli".to_string(), foo_command("foo-cli", "foo"));
+        let version = semver::Version::new(1, 0, 0);
+
+        let error = lockfile.get_command("foo-clu").unwrap_err();
+        assert_eq!(
+            "Command not found: foo-clu. Did you mean \"foo-cli\"?",
+            error.to_string()
+        );
+        let error = lockfile.get_command("bar").unwrap_err();
+        assert_eq!("Command not found: bar", error.to_string());
+
+        let error = lockfile.get_module("_/foo", &version, "fo").unwrap_err();
+        assert!(error.to_string().ends_with(". Did you mean \"foo\"?"));
+    }
+
+    #[test]
+    fn rename_package() {
+        let mut lockfile = lockfile_with_module(LockfileModule {
+            integrity: Some("sha256-foo".to_string()),
+            ..foo_module(Some("foo-hash".to_string()))
+        });
+        let bar = LockfileModule {
+            name: "bar".to_string(),
+            package_name: "_/bar".to_string(),
+            package_path: "_/bar@1.0.0".to_string(),
+            dependents: vec!["_/foo@1.0.0".to_string()],
+            ..foo_module(None)
+        };
+        lockfile
+            .modules
+            .entry("_/bar".to_string())
+            .or_default()
+            .entry(semver::Version::new(1, 0, 0))
+            .or_default()
+            .insert("bar".to_string(), bar);
+        lockfile
+            .commands
+            .insert("foo".to_string(), foo_command("foo", "foo"));
+        let version = semver::Version::new(1, 0, 0);
+
+        assert!(lockfile.rename_package("_/foo", "_/renamed").unwrap());
+        assert!(!lockfile.modules.contains_key("_/foo"));
+        let module = lockfile.get_module("_/renamed", &version, "foo").unwrap();
+        assert_eq!("_/renamed", module.package_name);
+        assert_eq!(Some("foo-hash"), module.prehashed_module_key.as_deref());
+        assert_eq!(Some("sha256-foo"), module.integrity.as_deref());
+        assert_eq!("_/renamed", lockfile.commands["foo"].package_name);
+        let bar = lockfile.get_module("_/bar", &version, "bar").unwrap();
+        assert_eq!(vec!["_/renamed@1.0.0".to_string()], bar.dependents);
+        assert!(lockfile.validate().is_ok());
+
+        assert!(!lockfile.rename_package("_/foo", "_/other").unwrap());
+        match lockfile.rename_package("_/renamed", "_/bar") {
+            Err(LockfileError::PackageAlreadyLocked(name)) => assert_eq!("_/bar", name),
+            result => panic!("expected a package already locked error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn renamed_modules_are_found_where_they_were_downloaded() {
+        use crate::data::manifest::PACKAGES_DIR_NAME;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let package_dir = tmp_dir.path().join(PACKAGES_DIR_NAME).join("_/foo@1.0.0");
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("foo.wasm"), b"\0asm").unwrap();
+        let mut lockfile = lockfile_with_module(foo_module(None));
+
+        assert!(lockfile.rename_package("_/foo", "_/renamed").unwrap());
+        let module = lockfile
+            .get_module("_/renamed", &semver::Version::new(1, 0, 0), "foo")
+            .unwrap();
+        let path = module.get_canonical_source_path_from_lockfile_dir(tmp_dir.path().into());
+        assert_eq!(package_dir.join("foo.wasm"), path);
+        assert!(path.exists());
+        assert!(lockfile.prune_cache(tmp_dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            LockfileError::MissingLockfile,
+            LockfileError::CommandNotFound {
+                command: "foo".to_string(),
+                suggestion: None,
+            },
+            LockfileError::IntegrityMismatch {
+                package: "_/foo@1.0.0".to_string(),
+                module: "foo".to_string(),
+                expected: "a".to_string(),
+                actual: "b".to_string(),
+            },
+            LockfileError::MergeConflict {
+                entry: "command \"foo\"".to_string(),
+                reason: "differs".to_string(),
+            },
+            LockfileError::VersionConflict {
+                package: "_/foo".to_string(),
+                requirements: vec![],
+            },
+            LockfileError::DepthExceeded { depth: 2, limit: 1 },
+            LockfileError::TouchFailed("offline".to_string()),
+            LockfileError::NotLocked {
+                name: "_/foo".to_string(),
+            },
+            LockfileError::PackageNotFound {
+                package: "_/foo".to_string(),
+                suggestion: None,
+            },
+            LockfileError::CommandNotFoundInPackage {
+                command: "foo".to_string(),
+                package: "_/foo".to_string(),
+                suggestion: None,
+            },
+        ];
+        let codes: Vec<i32> = errors.iter().map(LockfileError::exit_code).collect();
+        assert_eq!(vec![10, 16, 14, 15, 21, 27, 28, 29, 30, 31], codes);
+    }
 }
@@ -1,7 +1,41 @@
-use crate::data::manifest::Command;
+use crate::abi::Abi;
+use crate::data::manifest::{Command, Manifest};
+use crate::dataflow::WapmPackageKey;
 use semver::Version;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
 use thiserror::Error;
 
+/// The names of wapm's own subcommands and executables. A command of a dependency with one of
+/// these names is easily mistaken for wapm's own, and its `.bin` script shadows `wapm` or `wax`.
+pub fn reserved_command_names() -> &'static [&'static str] {
+    &[
+        "add",
+        "bin",
+        "completions",
+        "config",
+        "execute",
+        "graph",
+        "help",
+        "init",
+        "install",
+        "keys",
+        "list",
+        "login",
+        "logout",
+        "package",
+        "publish",
+        "remove",
+        "run",
+        "search",
+        "uninstall",
+        "validate",
+        "wapm",
+        "wax",
+        "whoami",
+    ]
+}
+
 /// Describes a command for a wapm module
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct LockfileCommand {
@@ -11,9 +45,119 @@ pub struct LockfileCommand {
     pub module: String,
     pub is_top_level_dependency: bool,
     pub main_args: Option<String>,
+    /// The arguments of the manifest command, passed to the module before the arguments the
+    /// command is run with. Lockfiles of older versions do not have them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// The ABI the manifest command asked for, `module` is the module built for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abi: Option<Abi>,
+    /// The name the package gave the command, if the manifest installs it under an alias
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_name: Option<String>,
+    /// Fields written by newer versions of wapm, kept so saving the lockfile does not drop them
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, toml::Value>,
 }
 
 impl<'a> LockfileCommand {
+    /// Builds the lockfile commands for all commands in a package's manifest. Commands of the
+    /// package itself must name one of the package's modules, as a package can have several.
+    /// Commands that come with a feature are left out, see `from_manifest_with_features`.
+    pub fn from_manifest(
+        package_name: &str,
+        package_version: &Version,
+        manifest: &'a Manifest,
+    ) -> Result<Vec<Self>, Error> {
+        Self::from_manifest_with_features(package_name, package_version, manifest, &HashSet::new())
+    }
+
+    /// Like `from_manifest`, but also builds the commands of the enabled features. The commands
+    /// of other features are skipped before their module is looked up.
+    pub fn from_manifest_with_features(
+        package_name: &str,
+        package_version: &Version,
+        manifest: &'a Manifest,
+        features: &HashSet<String>,
+    ) -> Result<Vec<Self>, Error> {
+        Self::from_manifest_commands(package_name, package_version, manifest, |command| {
+            match command.feature {
+                Some(ref feature) => features.contains(feature),
+                None => true,
+            }
+        })
+    }
+
+    /// The commands a dependency installs for the packages that depend on it. Like
+    /// `from_manifest`, with the private commands of the dependency left out as well.
+    pub fn from_dependency_manifest(
+        package_name: &str,
+        package_version: &Version,
+        manifest: &'a Manifest,
+    ) -> Result<Vec<Self>, Error> {
+        Self::from_manifest_commands(package_name, package_version, manifest, |command| {
+            command.feature.is_none() && !command.private
+        })
+    }
+
+    /// Builds the lockfile commands for the manifest commands that are included
+    fn from_manifest_commands(
+        package_name: &str,
+        package_version: &Version,
+        manifest: &'a Manifest,
+        include: impl Fn(&Command) -> bool,
+    ) -> Result<Vec<Self>, Error> {
+        let commands = match manifest.command {
+            Some(ref commands) => commands,
+            None => return Ok(vec![]),
+        };
+        commands
+            .iter()
+            .filter(|command| include(command))
+            .map(|command| {
+                let mut lockfile_command =
+                    Self::from_command(package_name, package_version.clone(), command)?;
+                if command.package.is_none() {
+                    lockfile_command.module = Self::module_for_command(manifest, command)?;
+                }
+                Ok(lockfile_command)
+            })
+            .collect()
+    }
+
+    /// The module of the manifest that a command of the package itself runs. A command that asks
+    /// for an ABI its module is not built for runs the module with the same interfaces that is.
+    fn module_for_command(manifest: &Manifest, command: &Command) -> Result<String, Error> {
+        let modules = manifest.module.as_deref().unwrap_or_default();
+        let module = modules
+            .iter()
+            .find(|module| module.name == command.module)
+            .ok_or_else(|| {
+                Error::ModuleForCommandDoesNotExist(command.name.clone(), command.module.clone())
+            })?;
+        match command.abi {
+            Some(abi) if abi != module.abi => modules
+                .iter()
+                .find(|other| other.abi == abi && other.interfaces == module.interfaces)
+                .map(|other| other.name.clone())
+                .ok_or_else(|| Error::NoModuleWithAbiForCommand(command.name.clone(), abi)),
+            _ => Ok(module.name.clone()),
+        }
+    }
+
+    /// The key of the package whose module the command runs
+    pub fn package_key(&self) -> WapmPackageKey<'_> {
+        WapmPackageKey {
+            name: Cow::Borrowed(&self.package_name),
+            version: self.package_version.clone(),
+        }
+    }
+
+    /// Whether the command has the name of one of wapm's own subcommands or executables
+    pub fn shadows_built_in(&self) -> bool {
+        reserved_command_names().contains(&self.name.as_str())
+    }
+
     pub fn from_command(
         local_package_name: &str,
         local_package_version: Version,
@@ -22,34 +166,31 @@ impl<'a> LockfileCommand {
         // split the "package" field of the command if it exists
         // otherwise assume that this is a command for a local module
         // extract the package name and version for this command and insert into the lockfile command
-        let (package_name, package_version): (&str, Version) = match &command.package {
+        let (package_name, package_version) = match &command.package {
             Some(package_string) => {
-                let split = package_string.as_str().split(' ').collect::<Vec<_>>();
-                match &split[..] {
-                    [package_name, package_version] => {
-                        // this string must be parsed again because the package field on a command is a concatenated string
-                        // e.g. "_/pkg 1.0.0"
-                        let package_version = Version::parse(package_version).unwrap();
-                        (package_name, package_version)
-                    }
-                    _ => {
-                        return Err(Error::CouldNotParsePackageVersionForCommand(
-                            package_string.clone(),
-                            command.name.clone(),
-                        ));
-                    }
-                }
+                // the package field on a command is a package key, e.g. "_/pkg 1.0.0"
+                let key: WapmPackageKey = package_string.parse().map_err(|_| {
+                    Error::CouldNotParsePackageVersionForCommand(
+                        package_string.clone(),
+                        command.name.clone(),
+                    )
+                })?;
+                (key.name.into_owned(), key.version)
             }
-            None => (local_package_name, local_package_version),
+            None => (local_package_name.to_string(), local_package_version),
         };
 
         let lockfile_command = LockfileCommand {
             name: command.name.to_string(),
-            package_name: package_name.to_string(),
+            package_name,
             package_version,
             module: command.module.to_string(),
             main_args: command.main_args.clone(),
+            args: command.args.clone(),
             is_top_level_dependency: true,
+            abi: command.abi,
+            original_name: None,
+            extra: BTreeMap::new(),
         };
         Ok(lockfile_command)
     }
@@ -57,8 +198,302 @@ impl<'a> LockfileCommand {
 
 #[derive(Clone, Debug, Error)]
 pub enum Error {
-    #[error("The module for this command does not exist. Did you modify the wapm.lock?")]
-    ModuleForCommandDoesNotExist,
-    #[error("Could not parse the package name and version \"{0}\" for the command \"{}\".")]
+    #[error("The module \"{1}\" for the command \"{0}\" does not exist in the package.")]
+    ModuleForCommandDoesNotExist(String, String),
+    #[error("Could not parse the package name and version \"{0}\" for the command \"{1}\".")]
     CouldNotParsePackageVersionForCommand(String, String),
+    #[error(
+        "The command \"{0}\" runs with the {1} ABI, but no module of the package is built for it."
+    )]
+    NoModuleWithAbiForCommand(String, Abi),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::abi::Abi;
+    use crate::data::lock::lockfile_command::{Error, LockfileCommand};
+    use crate::data::manifest::{Command, Manifest};
+    use std::collections::HashSet;
+
+    fn manifest(commands: &str) -> Manifest {
+        let manifest = format!(
+            r#"
+            [package]
+            name = "_/multi"
+            version = "1.0.0"
+            description = ""
+
+            [[module]]
+            name = "first"
+            source = "first.wasm"
+            abi = "wasi"
+
+            [[module]]
+            name = "second"
+            source = "second.wasm"
+            abi = "wasi"
+
+            {}
+            "#,
+            commands
+        );
+        toml::from_str(&manifest).unwrap()
+    }
+
+    #[test]
+    fn commands_named_like_subcommands_shadow_built_ins() {
+        let manifest = manifest(
+            r#"
+            [[command]]
+            name = "install"
+            module = "first"
+
+            [[command]]
+            name = "installer"
+            module = "first"
+            "#,
+        );
+        let commands =
+            LockfileCommand::from_manifest("_/multi", &semver::Version::new(1, 0, 0), &manifest)
+                .unwrap();
+
+        let shadowing: Vec<&str> = commands
+            .iter()
+            .filter(|command| command.shadows_built_in())
+            .map(|command| command.name.as_str())
+            .collect();
+        assert_eq!(vec!["install"], shadowing);
+    }
+
+    #[test]
+    fn commands_target_their_own_module() {
+        let manifest = manifest(
+            r#"
+            [[command]]
+            name = "one"
+            module = "first"
+
+            [[command]]
+            name = "two"
+            module = "second"
+            "#,
+        );
+        let commands =
+            LockfileCommand::from_manifest("_/multi", &semver::Version::new(1, 0, 0), &manifest)
+                .unwrap();
+        let modules: Vec<(&str, &str)> = commands
+            .iter()
+            .map(|c| (c.name.as_str(), c.module.as_str()))
+            .collect();
+        assert_eq!(vec![("one", "first"), ("two", "second")], modules);
+    }
+
+    #[test]
+    fn command_for_missing_module_is_an_error() {
+        let manifest = manifest(
+            r#"
+            [[command]]
+            name = "three"
+            module = "third"
+            "#,
+        );
+        match LockfileCommand::from_manifest("_/multi", &semver::Version::new(1, 0, 0), &manifest) {
+            Err(Error::ModuleForCommandDoesNotExist(command, module)) => {
+                assert_eq!("three", command);
+                assert_eq!("third", module);
+            }
+            otherwise => panic!("expected a missing module error, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn private_commands_are_only_locked_for_the_package_itself() {
+        let manifest = manifest(
+            r#"
+            [[command]]
+            name = "one"
+            module = "first"
+
+            [[command]]
+            name = "helper"
+            module = "second"
+            private = true
+            "#,
+        );
+        let version = semver::Version::new(1, 0, 0);
+        let names = |commands: Vec<LockfileCommand>| -> Vec<String> {
+            commands.into_iter().map(|command| command.name).collect()
+        };
+
+        let own_commands = LockfileCommand::from_manifest_with_features(
+            "_/multi",
+            &version,
+            &manifest,
+            &HashSet::new(),
+        )
+        .unwrap();
+        assert_eq!(vec!["one", "helper"], names(own_commands));
+        let dependency_commands =
+            LockfileCommand::from_dependency_manifest("_/multi", &version, &manifest).unwrap();
+        assert_eq!(vec!["one"], names(dependency_commands));
+    }
+
+    #[test]
+    fn commands_of_disabled_features_are_skipped() {
+        let manifest = manifest(
+            r#"
+            [[command]]
+            name = "one"
+            module = "first"
+
+            [[command]]
+            name = "debug-dump"
+            module = "second"
+            feature = "debug"
+
+            [[command]]
+            name = "trace"
+            module = "third"
+            feature = "trace"
+            "#,
+        );
+        let version = semver::Version::new(1, 0, 0);
+        let names = |features: &[&str]| -> Vec<String> {
+            let features = features.iter().map(|f| f.to_string()).collect();
+            LockfileCommand::from_manifest_with_features("_/multi", &version, &manifest, &features)
+                .unwrap()
+                .into_iter()
+                .map(|command| command.name)
+                .collect()
+        };
+
+        // the module of a disabled command is never looked up, "third" does not exist
+        assert_eq!(vec!["one"], names(&[]));
+        assert_eq!(vec!["one", "debug-dump"], names(&["debug"]));
+        let commands = LockfileCommand::from_manifest("_/multi", &version, &manifest).unwrap();
+        assert_eq!(1, commands.len());
+    }
+
+    #[test]
+    fn command_package_with_namespace_and_pre_release_version() {
+        let command: Command = toml::from_str(
+            r#"
+            name = "tool"
+            module = "tool"
+            package = "someuser/somepackage 1.0.0-beta.1"
+            "#,
+        )
+        .unwrap();
+        let lockfile_command =
+            LockfileCommand::from_command("_/multi", semver::Version::new(1, 0, 0), &command)
+                .unwrap();
+        assert_eq!("someuser/somepackage", lockfile_command.package_name);
+        assert_eq!(
+            semver::Version::parse("1.0.0-beta.1").unwrap(),
+            lockfile_command.package_version
+        );
+
+        for package in &["someuser/somepackage", "someuser/somepackage 1.0", " 1.0.0"] {
+            let command = Command {
+                package: Some(package.to_string()),
+                ..command.clone()
+            };
+            match LockfileCommand::from_command("_/multi", semver::Version::new(1, 0, 0), &command)
+            {
+                Err(Error::CouldNotParsePackageVersionForCommand(package_string, command_name)) => {
+                    assert_eq!(*package, package_string);
+                    assert_eq!("tool", command_name);
+                }
+                otherwise => panic!(
+                    "expected a parse error for {}, got {:?}",
+                    package, otherwise
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn commands_run_the_module_built_for_their_abi() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "_/multi"
+            version = "1.0.0"
+            description = ""
+
+            [[module]]
+            name = "tool"
+            source = "tool.wasm"
+            abi = "wasi"
+            interfaces = { wasi = "0.0.0-unstable" }
+
+            [[module]]
+            name = "tool-emscripten"
+            source = "tool-emscripten.wasm"
+            abi = "emscripten"
+            interfaces = { wasi = "0.0.0-unstable" }
+
+            [[command]]
+            name = "tool"
+            module = "tool"
+
+            [[command]]
+            name = "tool-emscripten"
+            module = "tool"
+            abi = "emscripten"
+            "#,
+        )
+        .unwrap();
+        let version = semver::Version::new(1, 0, 0);
+        let commands = LockfileCommand::from_manifest("_/multi", &version, &manifest).unwrap();
+        let modules: Vec<(&str, &str, Option<Abi>)> = commands
+            .iter()
+            .map(|c| (c.name.as_str(), c.module.as_str(), c.abi))
+            .collect();
+        assert_eq!(
+            vec![
+                ("tool", "tool", None),
+                ("tool-emscripten", "tool-emscripten", Some(Abi::Emscripten)),
+            ],
+            modules
+        );
+
+        let mut manifest = manifest;
+        manifest.module.as_mut().unwrap().pop();
+        match LockfileCommand::from_manifest("_/multi", &version, &manifest) {
+            Err(e @ Error::NoModuleWithAbiForCommand(..)) => assert_eq!(
+                "The command \"tool-emscripten\" runs with the emscripten ABI, but no module of the package is built for it.",
+                e.to_string()
+            ),
+            otherwise => panic!("expected a missing ABI error, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn command_args_round_trip_through_the_lockfile() {
+        let manifest = manifest(
+            r#"
+            [[command]]
+            name = "serve"
+            module = "first"
+            args = ["--port", "8080"]
+
+            [[command]]
+            name = "plain"
+            module = "second"
+            "#,
+        );
+        let commands =
+            LockfileCommand::from_manifest("_/multi", &semver::Version::new(1, 0, 0), &manifest)
+                .unwrap();
+        assert_eq!(vec!["--port", "8080"], commands[0].args);
+
+        for command in commands.iter() {
+            let command_toml = toml::to_string(command).unwrap();
+            let read: LockfileCommand = toml::from_str(&command_toml).unwrap();
+            assert_eq!(*command, read);
+        }
+        // commands without arguments are written as before
+        assert!(!toml::to_string(&commands[1]).unwrap().contains("args"));
+    }
 }
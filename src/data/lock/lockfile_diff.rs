@@ -0,0 +1,224 @@
+use crate::data::lock::lockfile::Lockfile;
+use semver::Version;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// A module in a lockfile identified by its package name, package version, and module name
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DiffModule {
+    pub package_name: String,
+    pub package_version: Version,
+    pub module: String,
+}
+
+/// A module that exists in both lockfiles, but at a different package version
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UpdatedModule {
+    pub package_name: String,
+    pub module: String,
+    pub old_version: Version,
+    pub new_version: Version,
+}
+
+impl fmt::Display for UpdatedModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} -> {}",
+            self.package_name, self.old_version, self.new_version
+        )
+    }
+}
+
+/// The changes between two lockfiles, e.g. before and after an update.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LockfileDiff {
+    pub added_modules: Vec<DiffModule>,
+    pub removed_modules: Vec<DiffModule>,
+    pub updated_modules: Vec<UpdatedModule>,
+    pub added_commands: Vec<String>,
+    pub removed_commands: Vec<String>,
+}
+
+impl LockfileDiff {
+    pub fn new(old: &Lockfile, new: &Lockfile) -> Self {
+        let old_modules = module_versions(old);
+        let new_modules = module_versions(new);
+        let mut diff = LockfileDiff::default();
+
+        for ((package_name, module), old_versions) in old_modules.iter() {
+            let new_versions = new_modules
+                .get(&(package_name.clone(), module.clone()))
+                .cloned()
+                .unwrap_or_default();
+            if old_versions.len() == 1 && new_versions.len() == 1 && *old_versions != new_versions {
+                diff.updated_modules.push(UpdatedModule {
+                    package_name: package_name.clone(),
+                    module: module.clone(),
+                    old_version: old_versions.iter().next().cloned().unwrap(),
+                    new_version: new_versions.iter().next().cloned().unwrap(),
+                });
+                continue;
+            }
+            for version in old_versions.difference(&new_versions) {
+                diff.removed_modules.push(DiffModule {
+                    package_name: package_name.clone(),
+                    package_version: version.clone(),
+                    module: module.clone(),
+                });
+            }
+        }
+
+        for ((package_name, module), new_versions) in new_modules.iter() {
+            let old_versions = old_modules
+                .get(&(package_name.clone(), module.clone()))
+                .cloned()
+                .unwrap_or_default();
+            if old_versions.len() == 1 && new_versions.len() == 1 {
+                // this is either unchanged or already reported as updated
+                continue;
+            }
+            for version in new_versions.difference(&old_versions) {
+                diff.added_modules.push(DiffModule {
+                    package_name: package_name.clone(),
+                    package_version: version.clone(),
+                    module: module.clone(),
+                });
+            }
+        }
+
+        diff.added_commands = new
+            .commands
+            .keys()
+            .filter(|name| !old.commands.contains_key(*name))
+            .cloned()
+            .collect();
+        diff.removed_commands = old
+            .commands
+            .keys()
+            .filter(|name| !new.commands.contains_key(*name))
+            .cloned()
+            .collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_modules.is_empty()
+            && self.removed_modules.is_empty()
+            && self.updated_modules.is_empty()
+            && self.added_commands.is_empty()
+            && self.removed_commands.is_empty()
+    }
+}
+
+/// (package name, module name) -> locked package versions
+fn module_versions(lockfile: &Lockfile) -> BTreeMap<(String, String), BTreeSet<Version>> {
+    let mut module_versions: BTreeMap<(String, String), BTreeSet<Version>> = BTreeMap::new();
+    for (package_name, version_map) in lockfile.modules.iter() {
+        for (version, module_map) in version_map.iter() {
+            for module_name in module_map.keys() {
+                module_versions
+                    .entry((package_name.clone(), module_name.clone()))
+                    .or_default()
+                    .insert(version.clone());
+            }
+        }
+    }
+    module_versions
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::lock::lockfile::Lockfile;
+    use crate::data::lock::lockfile_diff::{DiffModule, UpdatedModule};
+
+    #[test]
+    fn diff_lockfiles() {
+        let old_lockfile_toml = toml! {
+            [modules."_/foo"."1.0.0".foo]
+            name = "foo"
+            package_version = "1.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@1.0.0"
+            resolved = ""
+            resolved_source = "registry+foo"
+            abi = "none"
+            source = "foo.wasm"
+            [modules."_/bar"."2.0.0".bar]
+            name = "bar"
+            package_version = "2.0.0"
+            package_name = "_/bar"
+            package_path = "_/bar@2.0.0"
+            resolved = ""
+            resolved_source = "registry+bar"
+            abi = "none"
+            source = "bar.wasm"
+            [commands.bar]
+            name = "bar"
+            package_name = "_/bar"
+            package_version = "2.0.0"
+            module = "bar"
+            is_top_level_dependency = true
+        };
+        let new_lockfile_toml = toml! {
+            [modules."_/foo"."1.0.2".foo]
+            name = "foo"
+            package_version = "1.0.2"
+            package_name = "_/foo"
+            package_path = "_/foo@1.0.2"
+            resolved = ""
+            resolved_source = "registry+foo"
+            abi = "none"
+            source = "foo.wasm"
+            [modules."_/baz"."3.0.0".baz]
+            name = "baz"
+            package_version = "3.0.0"
+            package_name = "_/baz"
+            package_path = "_/baz@3.0.0"
+            resolved = ""
+            resolved_source = "registry+baz"
+            abi = "none"
+            source = "baz.wasm"
+            [commands.baz]
+            name = "baz"
+            package_name = "_/baz"
+            package_version = "3.0.0"
+            module = "baz"
+            is_top_level_dependency = true
+        };
+        let old_lockfile: Lockfile = old_lockfile_toml.try_into().unwrap();
+        let new_lockfile: Lockfile = new_lockfile_toml.try_into().unwrap();
+
+        let diff = old_lockfile.diff(&new_lockfile);
+
+        assert_eq!(
+            vec![UpdatedModule {
+                package_name: "_/foo".to_string(),
+                module: "foo".to_string(),
+                old_version: semver::Version::new(1, 0, 0),
+                new_version: semver::Version::new(1, 0, 2),
+            }],
+            diff.updated_modules
+        );
+        assert_eq!("_/foo 1.0.0 -> 1.0.2", diff.updated_modules[0].to_string());
+        assert_eq!(
+            vec![DiffModule {
+                package_name: "_/baz".to_string(),
+                package_version: semver::Version::new(3, 0, 0),
+                module: "baz".to_string(),
+            }],
+            diff.added_modules
+        );
+        assert_eq!(
+            vec![DiffModule {
+                package_name: "_/bar".to_string(),
+                package_version: semver::Version::new(2, 0, 0),
+                module: "bar".to_string(),
+            }],
+            diff.removed_modules
+        );
+        assert_eq!(vec!["baz".to_string()], diff.added_commands);
+        assert_eq!(vec!["bar".to_string()], diff.removed_commands);
+        assert!(old_lockfile.diff(&old_lockfile).is_empty());
+    }
+}
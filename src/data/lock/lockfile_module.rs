@@ -1,7 +1,10 @@
 use crate::abi::Abi;
 use crate::data::manifest::{Module, PACKAGES_DIR_NAME};
 use crate::util;
+use chrono::{DateTime, Utc};
 use semver::Version;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::path::{Path, PathBuf};
 
 /// legacy Lockfile module struct; which is only used to parse legacy lockfiles which get
@@ -48,12 +51,76 @@ pub struct LockfileModule {
     pub abi: Abi,
     /// The source path is where the wasm module lives
     pub source: String,
+    /// Target triple -> the source path used instead of `source` on that target
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub target_sources: BTreeMap<String, String>,
     /// The hash of the wasm module cached here for faster startup time
     pub prehashed_module_key: Option<String>,
+    /// The packages that required this module, as `name@version`. Direct dependencies of a
+    /// manifest list the manifest's package. Empty for global installs and older lockfiles.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependents: Vec<String>,
+    /// The sha256 of the downloaded wasm module as `sha256-<base64>`, checked by
+    /// `Lockfile::verify_integrity`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
+    /// Target triple -> the integrity of the source used instead of `source` on that target, see
+    /// `target_sources`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub target_integrity: BTreeMap<String, String>,
+    /// The size in bytes of the package archive the module was downloaded in, the same for every
+    /// module of a package. Empty for modules that are not downloaded and older lockfiles.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// When the module was fetched from its source, kept as is while the lockfile reuses it.
+    /// Empty for local modules and older lockfiles.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<DateTime<Utc>>,
+    /// Fields written by newer versions of wapm, kept so saving the lockfile does not drop them
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, toml::Value>,
 }
 
 pub type LockfileModuleV4 = LockfileModule;
 
+fn target_sources(module: &Module) -> BTreeMap<String, String> {
+    module
+        .targets
+        .iter()
+        .flatten()
+        .map(|(target, source)| (target.clone(), source.to_string_lossy().to_string()))
+        .collect()
+}
+
+/// Where a locked module came from, parsed from its `resolved_source`
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModuleOrigin {
+    /// `registry+<module>`, downloaded from the wapm registry
+    Registry { download_url: String },
+    /// `local`, a module of the manifest's own package
+    Local,
+    /// `path+<path>`, a module in a directory on disk
+    Path(PathBuf),
+    /// `git+<url>?rev=<rev>#<commit>`, a module in a git repository at a locked commit
+    Git(String),
+    /// a source this version of wapm does not understand
+    Unknown(String),
+}
+
+impl fmt::Display for ModuleOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModuleOrigin::Registry { download_url } => {
+                write!(f, "the registry ({})", download_url)
+            }
+            ModuleOrigin::Local => write!(f, "the local package"),
+            ModuleOrigin::Path(path) => write!(f, "local path {}", path.display()),
+            ModuleOrigin::Git(url) => write!(f, "git repository {}", url),
+            ModuleOrigin::Unknown(source) => write!(f, "unknown source \"{}\"", source),
+        }
+    }
+}
+
 impl LockfileModule {
     pub fn from_module(
         manifest_base_dir_path: &Path,
@@ -82,6 +149,15 @@ impl LockfileModule {
             }
         };
 
+        let target_sources = target_sources(module);
+        let target_integrity = target_sources
+            .iter()
+            .filter_map(|(target, source)| {
+                util::compute_module_integrity(&path.join(source))
+                    .ok()
+                    .map(|(_, integrity)| (target.clone(), integrity))
+            })
+            .collect();
         let lockfile_module = LockfileModule {
             name: module.name.to_string(),
             package_version: version.to_string(),
@@ -91,7 +167,16 @@ impl LockfileModule {
             resolved_source: format!("registry+{}", module.name),
             abi: module.abi.clone(),
             prehashed_module_key: util::get_hashed_module_key(&path.join(&source)),
+            integrity: util::compute_module_integrity(&path.join(&source))
+                .ok()
+                .map(|(_, integrity)| integrity),
+            target_integrity,
+            size: None,
             source,
+            target_sources,
+            dependents: vec![],
+            resolved_at: Some(Utc::now()),
+            extra: BTreeMap::new(),
         };
         lockfile_module
     }
@@ -114,20 +199,94 @@ impl LockfileModule {
             resolved_source: "local".to_string(),
             abi: module.abi.clone(),
             source: module.source.to_string_lossy().to_string(),
+            target_sources: target_sources(module),
             prehashed_module_key: util::get_hashed_module_key(&wasm_module_full_path),
+            dependents: vec![],
+            integrity: None,
+            target_integrity: BTreeMap::new(),
+            size: None,
+            resolved_at: None,
+            extra: BTreeMap::new(),
         }
     }
 
-    /// Returns the full, absolute path to the WASM module
-    pub fn get_canonical_source_path_from_lockfile_dir(
-        &self,
-        mut lockfile_dir: PathBuf,
-    ) -> PathBuf {
-        lockfile_dir.push(PACKAGES_DIR_NAME);
-        lockfile_dir.push(&self.package_path);
-        lockfile_dir.push(&self.source);
+    /// Parses `resolved_source` to describe where the module came from.
+    pub fn origin(&self) -> ModuleOrigin {
+        let source = self.resolved_source.as_str();
+        if source == "local" {
+            ModuleOrigin::Local
+        } else if source.starts_with("registry+") {
+            ModuleOrigin::Registry {
+                download_url: self.resolved.clone(),
+            }
+        } else if let Some(path) = source.strip_prefix("path+") {
+            ModuleOrigin::Path(PathBuf::from(path))
+        } else if let Some(url) = source.strip_prefix("git+") {
+            ModuleOrigin::Git(url.to_string())
+        } else {
+            ModuleOrigin::Unknown(source.to_string())
+        }
+    }
 
-        lockfile_dir
+    /// The source path of the module on a target, or on the host without a target. Modules
+    /// without a source for the target use `source`.
+    pub fn source_for_target(&self, target: Option<&str>) -> &str {
+        self.target_source(target)
+            .map(|(_, source)| source.as_str())
+            .unwrap_or(&self.source)
+    }
+
+    /// The integrity of the source used on a target, or on the host without a target, see
+    /// `source_for_target`
+    pub fn integrity_for_target(&self, target: Option<&str>) -> Option<&str> {
+        match self.target_source(target) {
+            Some((target, _)) => self.target_integrity.get(target),
+            None => self.integrity.as_ref(),
+        }
+        .map(String::as_str)
+    }
+
+    /// The `prehashed_module_key` if the host runs `source`, it is not the hash of the source of
+    /// another target
+    pub fn prehashed_module_key_for_host(&self) -> Option<&str> {
+        match self.target_source(None) {
+            Some(_) => None,
+            None => self.prehashed_module_key.as_deref(),
+        }
+    }
+
+    /// The target and source of `target_sources` used on a target, or on the host without one
+    fn target_source(&self, target: Option<&str>) -> Option<(&String, &String)> {
+        match target {
+            Some(target) => self.target_sources.get_key_value(target),
+            None => self
+                .target_sources
+                .iter()
+                .find(|(target, _)| util::is_host_target(target)),
+        }
+    }
+
+    /// The path of the module's wasm file for the host in a directory of installed packages,
+    /// like the `wapm_packages` directory next to a lockfile. Each package version is unpacked
+    /// into its `package_path`, usually `<namespace>/<name>@<version>`, and the module's source
+    /// is relative to it:
+    ///
+    /// ```text
+    /// wapm_packages/_/lua@0.1.3/lua.wasm
+    /// ```
+    ///
+    /// The `package_path` is kept when a package is renamed, so renamed modules are still found
+    /// where they were downloaded. The modules of the manifest's own package are not installed
+    /// there.
+    pub fn resolve_entry_path(&self, packages_dir: &Path) -> PathBuf {
+        packages_dir
+            .join(&self.package_path)
+            .join(self.source_for_target(None))
+    }
+
+    /// Returns the full, absolute path to the WASM module for the host
+    pub fn get_canonical_source_path_from_lockfile_dir(&self, lockfile_dir: PathBuf) -> PathBuf {
+        self.resolve_entry_path(&lockfile_dir.join(PACKAGES_DIR_NAME))
     }
 
     /// Returns the Manifest path from the lockfile
@@ -154,3 +313,133 @@ impl LockfileModule {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::data::lock::lockfile_module::{LockfileModule, ModuleOrigin};
+    use std::path::{Path, PathBuf};
+
+    fn module_with_source(resolved: &str, resolved_source: &str) -> LockfileModule {
+        LockfileModule {
+            resolved: resolved.to_string(),
+            resolved_source: resolved_source.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn origin_is_parsed_from_resolved_source() {
+        assert_eq!(
+            ModuleOrigin::Registry {
+                download_url: "https://registry.wapm.io/foo.tar.gz".to_string()
+            },
+            module_with_source("https://registry.wapm.io/foo.tar.gz", "registry+foo").origin()
+        );
+        assert_eq!(
+            ModuleOrigin::Local,
+            module_with_source("local", "local").origin()
+        );
+        assert_eq!(
+            ModuleOrigin::Path(PathBuf::from("../foo")),
+            module_with_source("", "path+../foo").origin()
+        );
+        assert_eq!(
+            ModuleOrigin::Git("https://github.com/wasmerio/foo".to_string()),
+            module_with_source("", "git+https://github.com/wasmerio/foo").origin()
+        );
+        assert_eq!(
+            ModuleOrigin::Unknown("ftp+foo".to_string()),
+            module_with_source("", "ftp+foo").origin()
+        );
+        assert_eq!(
+            "local path ../foo",
+            module_with_source("", "path+../foo").origin().to_string()
+        );
+    }
+
+    #[test]
+    fn source_for_target_falls_back_to_source() {
+        let mut module = module_with_source("", "local");
+        module.source = "foo.wasm".to_string();
+        module.target_sources.insert(
+            "wasm32-unknown-unknown".to_string(),
+            "foo-any.wasm".to_string(),
+        );
+        let host_target = format!(
+            "{}-unknown-{}",
+            std::env::consts::ARCH,
+            std::env::consts::OS
+        );
+        assert_eq!("foo.wasm", module.source_for_target(None));
+        assert_eq!(
+            "foo-any.wasm",
+            module.source_for_target(Some("wasm32-unknown-unknown"))
+        );
+
+        module
+            .target_sources
+            .insert(host_target.clone(), "foo-host.wasm".to_string());
+        assert_eq!("foo-host.wasm", module.source_for_target(None));
+        assert_eq!(
+            "foo-host.wasm",
+            module.source_for_target(Some(&host_target))
+        );
+        assert_eq!("foo.wasm", module.source_for_target(Some("riscv64-linux")));
+    }
+
+    #[test]
+    fn entry_path_follows_the_package_layout() {
+        let module = LockfileModule {
+            name: "lua".to_string(),
+            package_name: "_/lua".to_string(),
+            package_version: "0.1.3".to_string(),
+            package_path: "_/lua@0.1.3".to_string(),
+            source: "target/lua.wasm".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Path::new("/project/wapm_packages/_/lua@0.1.3/target/lua.wasm"),
+            module.resolve_entry_path(Path::new("/project/wapm_packages"))
+        );
+        assert_eq!(
+            module.resolve_entry_path(Path::new("/project/wapm_packages")),
+            module.get_canonical_source_path_from_lockfile_dir(PathBuf::from("/project"))
+        );
+
+        // the directory comes from the package path, not the package name
+        let renamed = LockfileModule {
+            package_name: "_/luajit".to_string(),
+            ..module
+        };
+        assert_eq!(
+            Path::new("/project/wapm_packages/_/lua@0.1.3/target/lua.wasm"),
+            renamed.resolve_entry_path(Path::new("/project/wapm_packages"))
+        );
+    }
+
+    #[test]
+    fn dependents_are_optional() {
+        let module: LockfileModule = toml::from_str(
+            r#"
+            name = "foo"
+            package_version = "1.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@1.0.0"
+            resolved = ""
+            resolved_source = "registry+foo"
+            abi = "none"
+            source = "foo.wasm"
+            "#,
+        )
+        .unwrap();
+        assert!(module.dependents.is_empty());
+        assert!(!toml::to_string(&module).unwrap().contains("dependents"));
+
+        let module = LockfileModule {
+            dependents: vec!["_/test@0.1.0".to_string()],
+            ..module
+        };
+        let serialized = toml::to_string(&module).unwrap();
+        assert_eq!(module, toml::from_str(&serialized).unwrap());
+    }
+}
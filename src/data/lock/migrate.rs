@@ -6,6 +6,7 @@
 use crate::data::lock::lockfile::{LockfileV2, LockfileV3, LockfileV4};
 use crate::data::lock::lockfile_command::LockfileCommand;
 use crate::data::lock::lockfile_module::{LockfileModuleV2, LockfileModuleV3, LockfileModuleV4};
+use crate::data::lock::LOCKFILE_VERSION;
 use crate::data::manifest::PACKAGES_DIR_NAME;
 use crate::dataflow::lockfile_packages::LockfileError;
 use crate::dataflow::normalize_global_namespace_package_name;
@@ -62,7 +63,8 @@ impl LockfileVersion {
                 Ok(LockfileVersion::V4(lockfile_v4))
             }
             0 => Err(LockfileError::InvalidOrMissingVersion),
-            _ => Err(LockfileError::VersionTooHigh),
+            v if v > LOCKFILE_VERSION => Err(LockfileError::VersionTooHigh),
+            _ => unreachable!("Every lockfile version up to the current one must be handled"),
         }
     }
 }
@@ -158,8 +160,15 @@ pub fn convert_lockfilev3_to_v4(lockfile: LockfileV3, directory: &Path) -> Lockf
                             .to_string_lossy()
                             .to_string()
                     },
+                    target_sources: Default::default(),
                     package_path,
                     prehashed_module_key: module_data.prehashed_module_key,
+                    dependents: vec![],
+                    integrity: None,
+                    target_integrity: Default::default(),
+                    size: None,
+                    resolved_at: None,
+                    extra: Default::default(),
                 };
                 name_map.insert(k3, module);
             }
@@ -168,8 +177,14 @@ pub fn convert_lockfilev3_to_v4(lockfile: LockfileV3, directory: &Path) -> Lockf
         modules.insert(k1, ver_map);
     }
     LockfileV4 {
+        dependencies_hash: None,
+        min_cli_version: None,
+        root_package: None,
+        metadata: None,
         modules,
         commands: lockfile.commands,
+        build_modules: BTreeMap::new(),
+        overrides: BTreeMap::new(),
     }
 }
 
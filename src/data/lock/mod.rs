@@ -1,12 +1,19 @@
+pub mod dependency_graph;
+pub mod install_summary;
 pub mod lockfile;
 pub mod lockfile_command;
+pub mod lockfile_diff;
 pub mod lockfile_module;
 pub mod migrate;
+pub mod readonly_lockfile;
 
 pub static LOCKFILE_NAME: &str = "wapm.lock";
 
-static LOCKFILE_HEADER: &str = r#"# Lockfile v4
-# This file is automatically generated by Wapm.
+/// The current schema version of the lockfile. It is written into the first line of the
+/// lockfile as `# Lockfile vN`, older versions are migrated when the lockfile is read.
+pub const LOCKFILE_VERSION: usize = 4;
+
+static LOCKFILE_HEADER: &str = r#"# This file is automatically generated by Wapm.
 # It is not intended for manual editing. The schema of this file may change."#;
 
 use crate::data::manifest::MANIFEST_FILE_NAME;
@@ -0,0 +1,145 @@
+use crate::data::lock::dependency_graph::DependencyGraph;
+use crate::data::lock::install_summary::InstallSummary;
+use crate::data::lock::lockfile::{Lockfile, LockfileError, LockfileStats};
+use crate::data::lock::lockfile_command::LockfileCommand;
+use crate::data::lock::lockfile_module::LockfileModule;
+use semver::Version;
+use std::path::Path;
+
+/// A lockfile that can be read but not saved or changed, e.g. for commands that only inspect the
+/// lockfile or verify it in CI. Unlike frozen mode, which fails at runtime when an update would
+/// change the lockfile, code holding a `ReadonlyLockfile` can not write it at all.
+#[derive(Clone, Debug)]
+pub struct ReadonlyLockfile(Lockfile);
+
+impl ReadonlyLockfile {
+    pub fn new(lockfile: Lockfile) -> Self {
+        ReadonlyLockfile(lockfile)
+    }
+
+    /// The `dependencies_hash` of the manifest the lockfile was generated from
+    pub fn dependencies_hash(&self) -> Option<&str> {
+        self.0.dependencies_hash.as_deref()
+    }
+
+    pub fn get_command(&self, command_name: &str) -> Result<&LockfileCommand, LockfileError> {
+        self.0.get_command(command_name)
+    }
+
+    pub fn get_command_qualified(
+        &self,
+        qualified_command_name: &str,
+    ) -> Result<&LockfileCommand, LockfileError> {
+        self.0.get_command_qualified(qualified_command_name)
+    }
+
+    pub fn get_command_in_package(
+        &self,
+        package_name: &str,
+        command_name: &str,
+    ) -> Result<&LockfileCommand, LockfileError> {
+        self.0.get_command_in_package(package_name, command_name)
+    }
+
+    pub fn get_module(
+        &self,
+        package_name: &str,
+        package_version: &Version,
+        module_name: &str,
+    ) -> anyhow::Result<&LockfileModule> {
+        self.0
+            .get_module(package_name, package_version, module_name)
+    }
+
+    pub fn contains_command(&self, command_name: &str) -> bool {
+        self.0.contains_command(command_name)
+    }
+
+    pub fn contains_module(
+        &self,
+        package_name: &str,
+        package_version: &Version,
+        module_name: &str,
+    ) -> bool {
+        self.0
+            .contains_module(package_name, package_version, module_name)
+    }
+
+    pub fn iter_modules(&self) -> impl Iterator<Item = (&str, &LockfileModule)> {
+        self.0.iter_modules()
+    }
+
+    pub fn iter_commands(&self) -> impl Iterator<Item = (&str, &LockfileCommand)> {
+        self.0.iter_commands()
+    }
+
+    pub fn command_names(&self) -> impl Iterator<Item = &str> {
+        self.0.command_names()
+    }
+
+    pub fn verify_integrity<P: AsRef<Path>>(&self, directory: P) -> Result<(), LockfileError> {
+        self.0.verify_integrity(directory)
+    }
+
+    pub fn stats(&self) -> LockfileStats {
+        self.0.stats()
+    }
+
+    pub fn dependency_graph(&self) -> DependencyGraph {
+        self.0.dependency_graph()
+    }
+
+    pub fn install_summary(&self) -> InstallSummary {
+        self.0.install_summary()
+    }
+
+    /// The lockfile as it would be saved, without saving it
+    pub fn to_string(&self) -> anyhow::Result<String> {
+        self.0.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::lock::lockfile::Lockfile;
+
+    #[test]
+    fn readonly_lockfile_reads_like_the_lockfile() {
+        let lockfile_toml = toml! {
+            [modules."_/foo"."1.0.0".foo]
+            name = "foo"
+            package_version = "1.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@1.0.0"
+            resolved = ""
+            resolved_source = "registry+foo"
+            abi = "none"
+            source = "foo.wasm"
+            [commands.foo]
+            name = "foo"
+            package_name = "_/foo"
+            package_version = "1.0.0"
+            module = "foo"
+            is_top_level_dependency = true
+        };
+        let lockfile: Lockfile = lockfile_toml.try_into().unwrap();
+        let readonly = lockfile.clone().into_readonly();
+        let version = semver::Version::new(1, 0, 0);
+
+        assert_eq!(
+            lockfile.get_command("foo").unwrap(),
+            readonly.get_command("foo").unwrap()
+        );
+        assert!(readonly.get_command("bar").is_err());
+        assert_eq!(
+            "foo.wasm",
+            readonly
+                .get_module("_/foo", &version, "foo")
+                .unwrap()
+                .source
+        );
+        assert_eq!(1, readonly.iter_modules().count());
+        assert_eq!(vec!["foo"], readonly.command_names().collect::<Vec<_>>());
+        assert_eq!(lockfile.to_string().unwrap(), readonly.to_string().unwrap());
+    }
+}
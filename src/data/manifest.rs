@@ -1,9 +1,15 @@
 //! The Manifest file is where the core metadata of a wapm package lives
 use crate::abi::Abi;
-use semver::Version;
+use crate::dataflow::normalize_global_namespace_package_name;
+use crate::util;
+use semver::{Version, VersionReq};
+use std::borrow::Cow;
 use std::collections::hash_map::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use thiserror::Error;
 
 /// The name of the manifest file. This is hard-coded for now.
@@ -43,13 +49,40 @@ pub struct Package {
     pub rename_commands_to_raw_command_name: bool,
 }
 
+/// A dependency of the manifest, either `"<version>"` or `{ git = "<url>", rev = "<rev>" }`
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum Dependency {
+    /// A version or version range of a registry package, `"*"` allows any version and resolves to
+    /// the newest one. An empty version is an error, not any version.
+    Version(String),
+    /// A package in a git repository at a branch, tag, or commit. The lockfile pins the commit.
+    Git { git: String, rev: String },
+}
+
 /// Describes a command for a wapm module
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Command {
     pub name: String,
     pub module: String,
     pub main_args: Option<String>,
+    /// Arguments the command always passes to the module, before the arguments it is run with,
+    /// e.g. `["--port", "8080"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
     pub package: Option<String>,
+    /// The ABI the command runs with. A package that builds its module for several ABIs gives
+    /// each build the same interfaces, and the command runs the build with this ABI.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abi: Option<Abi>,
+    /// The feature the command comes with. The command is only locked when the feature is
+    /// enabled, dependencies have no features enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feature: Option<String>,
+    /// A command the package only uses itself, e.g. a helper of its other commands. It is not
+    /// installed for the packages that depend on the package.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -62,6 +95,20 @@ pub struct Module {
     pub fs: Option<Table>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub interfaces: Option<HashMap<String, String>>,
+    /// Target triple -> the wasm file to use instead of `source` on that target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub targets: Option<BTreeMap<String, PathBuf>>,
+}
+
+impl Module {
+    /// The wasm file of the module on the machine wapm runs on
+    pub fn source_for_host(&self) -> &Path {
+        self.targets
+            .iter()
+            .flatten()
+            .find(|(target, _)| util::is_host_target(target))
+            .map_or(&self.source, |(_, source)| source)
+    }
 }
 
 /// The manifest represents the file used to describe a Wasm package.
@@ -76,9 +123,38 @@ pub struct Module {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Manifest {
     pub package: Package,
-    pub dependencies: Option<HashMap<String, String>>,
+    pub dependencies: Option<HashMap<String, Dependency>>,
+    /// Dependencies that are only installed when an enabled feature lists them
+    #[serde(
+        rename = "optional-dependencies",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub optional_dependencies: Option<HashMap<String, String>>,
+    /// Dependencies for working on the package itself, e.g. for tests, that its consumers do not
+    /// need
+    #[serde(rename = "dev-dependencies", skip_serializing_if = "Option::is_none")]
+    pub dev_dependencies: Option<HashMap<String, String>>,
+    /// Dependencies that are only needed to build the module, e.g. code generators. They are
+    /// locked apart from the runtime modules, and their commands are not installed.
+    #[serde(rename = "build-dependencies", skip_serializing_if = "Option::is_none")]
+    pub build_dependencies: Option<HashMap<String, String>>,
+    /// Package name -> the exact version every requirement on the package resolves to, whatever
+    /// version the dependency sections ask for, e.g. to pick up a security fix. Overridden
+    /// packages are recorded in the lockfile.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overrides: Option<HashMap<String, String>>,
+    /// Named features, each listing the optional dependencies it enables
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub features: Option<HashMap<String, Vec<String>>>,
+    /// A package can have several modules; a single `[module]` table is also accepted
+    #[serde(default, deserialize_with = "one_or_many")]
     pub module: Option<Vec<Module>>,
     pub command: Option<Vec<Command>>,
+    /// Alias -> command, installing a command of a dependency under another name, e.g. to avoid
+    /// a conflict with a command of another package. The command may be qualified with its
+    /// package, e.g. `web = "_/server::serve"`
+    #[serde(rename = "command-aliases", skip_serializing_if = "Option::is_none")]
+    pub command_aliases: Option<HashMap<String, String>>,
     /// Of the form Guest -> Host path
     pub fs: Option<HashMap<String, PathBuf>>,
     /// private data
@@ -87,26 +163,102 @@ pub struct Manifest {
     pub base_directory_path: PathBuf,
 }
 
+/// Deserializes either a single table or an array of tables, e.g. `[module]` or `[[module]]`
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    use serde::de::Error;
+    use serde::Deserialize;
+    match toml::Value::deserialize(deserializer)? {
+        table @ toml::Value::Table(_) => table.try_into().map(|one| Some(vec![one])),
+        many => many.try_into().map(Some),
+    }
+    .map_err(D::Error::custom)
+}
+
 impl Manifest {
     /// Construct a manifest by searching in the specified directory for a manifest file
     #[cfg(not(feature = "integration_tests"))]
     pub fn find_in_directory<T: AsRef<Path>>(path: T) -> Result<Self, ManifestError> {
+        Self::find_named_in_directory(path, MANIFEST_FILE_NAME)
+    }
+
+    /// Like `find_in_directory`, but reads the manifest file with the given name instead of
+    /// `wapm.toml`
+    pub fn find_named_in_directory<T: AsRef<Path>>(
+        path: T,
+        filename: &str,
+    ) -> Result<Self, ManifestError> {
         if !path.as_ref().is_dir() {
             return Err(ManifestError::MissingManifest(
                 path.as_ref().to_string_lossy().to_string(),
             ));
         }
-        let manifest_path_buf = path.as_ref().join(MANIFEST_FILE_NAME);
+        let manifest_path_buf = path.as_ref().join(filename);
         let contents = fs::read_to_string(&manifest_path_buf).map_err(|_e| {
             ManifestError::MissingManifest(manifest_path_buf.to_string_lossy().to_string())
         })?;
-        let manifest: Self = toml::from_str(contents.as_str())
-            .map_err(|e| ManifestError::TomlParseError(e.to_string()))?;
-        manifest.validate()?;
+        let mut manifest: Self = contents.parse()?;
+        manifest.set_base_directory(path.as_ref());
         Ok(manifest)
     }
 
+    /// Remember the directory the manifest was read from. A relative directory is made absolute,
+    /// so paths in the manifest resolve the same after the working directory changes.
+    pub fn set_base_directory(&mut self, directory: &Path) {
+        self.base_directory_path = if directory.is_relative() {
+            env::current_dir()
+                .map(|current_dir| current_dir.join(directory))
+                .unwrap_or_else(|_| directory.to_path_buf())
+        } else {
+            directory.to_path_buf()
+        };
+    }
+
+    /// Resolves a path of the manifest, like a module source, relative to the manifest's
+    /// directory instead of the working directory. Absolute paths are kept as they are.
+    pub fn resolve_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.base_directory_path.join(path)
+    }
+
+    /// Checks what deserializing does not: the package has a name, every dependency version is a
+    /// semantic version or version requirement, every override is a semantic version, and every
+    /// command runs a module of the manifest that has an ABI.
     pub fn validate(&self) -> Result<(), ManifestError> {
+        if self.package.name.trim().is_empty() {
+            return Err(ManifestError::ValidationError(
+                ValidationError::EmptyPackageName,
+            ));
+        }
+
+        let dependency_versions = self
+            .dependencies
+            .iter()
+            .flatten()
+            .filter_map(|(name, dependency)| match dependency {
+                Dependency::Version(version) => Some((name, version)),
+                Dependency::Git { .. } => None,
+            })
+            .chain(self.optional_dependencies.iter().flatten())
+            .chain(self.dev_dependencies.iter().flatten())
+            .chain(self.build_dependencies.iter().flatten());
+        for (name, version) in dependency_versions {
+            if Version::parse(version).is_err() && VersionReq::parse(version).is_err() {
+                return Err(ManifestError::ValidationError(
+                    ValidationError::InvalidDependencyVersion(name.clone(), version.clone()),
+                ));
+            }
+        }
+        for (name, version) in self.overrides.iter().flatten() {
+            if Version::parse(version).is_err() {
+                return Err(ManifestError::ValidationError(
+                    ValidationError::InvalidOverrideVersion(name.clone(), version.clone()),
+                ));
+            }
+        }
+
         let module_map = self
             .module
             .as_ref()
@@ -120,18 +272,29 @@ impl Manifest {
 
         if let Some(ref commands) = self.command {
             for command in commands {
+                if let Some(ref feature) = command.feature {
+                    let is_defined = self.features.iter().flatten().any(|(f, _)| f == feature);
+                    if !is_defined {
+                        return Err(ManifestError::ValidationError(
+                            ValidationError::UndefinedFeatureForCommand(
+                                feature.clone(),
+                                command.name.clone(),
+                            ),
+                        ));
+                    }
+                }
                 if let Some(ref module) = module_map.get(&command.module) {
                     if module.abi == Abi::None {
                         return Err(ManifestError::ValidationError(ValidationError::MissingABI(
-                            command.name.clone(),
                             module.name.clone(),
+                            command.name.clone(),
                         )));
                     }
                 } else {
                     return Err(ManifestError::ValidationError(
                         ValidationError::MissingModuleForCommand(
-                            command.name.clone(),
                             command.module.clone(),
+                            command.name.clone(),
                         ),
                     ));
                 }
@@ -140,18 +303,102 @@ impl Manifest {
         Ok(())
     }
 
+    /// The normalized names of the build dependencies that are not also runtime or dev
+    /// dependencies. A package listed in both is a runtime package.
+    pub fn build_only_dependencies(&self) -> HashSet<String> {
+        let normalize = |name: &String| {
+            normalize_global_namespace_package_name(Cow::Borrowed(name.as_str())).to_string()
+        };
+        let other_dependencies: HashSet<String> = self
+            .dependencies
+            .iter()
+            .flat_map(HashMap::keys)
+            .chain(self.optional_dependencies.iter().flat_map(HashMap::keys))
+            .chain(self.dev_dependencies.iter().flat_map(HashMap::keys))
+            .map(normalize)
+            .collect();
+        self.build_dependencies
+            .iter()
+            .flat_map(HashMap::keys)
+            .map(normalize)
+            .filter(|name| !other_dependencies.contains(name))
+            .collect()
+    }
+
+    /// The overrides by normalized package name. Overrides that are not a semantic version are
+    /// left out, `validate` rejects them.
+    pub fn normalized_overrides(&self) -> HashMap<String, Version> {
+        self.overrides
+            .iter()
+            .flatten()
+            .filter_map(|(name, version)| {
+                let name = normalize_global_namespace_package_name(Cow::Borrowed(name.as_str()));
+                Version::parse(version)
+                    .ok()
+                    .map(|version| (name.to_string(), version))
+            })
+            .collect()
+    }
+
     /// add a dependency
     pub fn add_dependency(&mut self, dependency_name: String, dependency_version: String) {
         let dependencies = self.dependencies.get_or_insert(Default::default());
-        dependencies.insert(dependency_name, dependency_version);
+        dependencies.insert(dependency_name, Dependency::Version(dependency_version));
     }
 
     /// remove dependency by package name
-    pub fn remove_dependency(&mut self, dependency_name: &str) -> Option<String> {
+    pub fn remove_dependency(&mut self, dependency_name: &str) -> Option<Dependency> {
         let dependencies = self.dependencies.get_or_insert(Default::default());
         dependencies.remove(dependency_name)
     }
 
+    /// A sha256 of everything that decides the manifest's dependencies: the dependencies, the
+    /// optional dependencies and features, which features are enabled, the dev dependencies
+    /// if they are included, the build dependencies, the overrides, and the command aliases.
+    /// Lockfiles store it to tell when the dependencies have not changed since the lockfile was
+    /// generated.
+    pub fn dependencies_hash(
+        &self,
+        features: &HashSet<String>,
+        include_dev_dependencies: bool,
+    ) -> String {
+        use sha2::{Digest, Sha256};
+
+        // sort everything, so the hash does not depend on the order of the manifest tables
+        let dependencies: BTreeMap<&String, &Dependency> =
+            self.dependencies.iter().flatten().collect();
+        let optional_dependencies: BTreeMap<&String, &String> =
+            self.optional_dependencies.iter().flatten().collect();
+        let manifest_features: BTreeMap<&String, &Vec<String>> =
+            self.features.iter().flatten().collect();
+        let enabled_features: BTreeSet<&String> = features.iter().collect();
+        let dev_dependencies: Option<BTreeMap<&String, &String>> = if include_dev_dependencies {
+            Some(self.dev_dependencies.iter().flatten().collect())
+        } else {
+            None
+        };
+        let build_dependencies: BTreeMap<&String, &String> =
+            self.build_dependencies.iter().flatten().collect();
+        let overrides: BTreeMap<&String, &String> = self.overrides.iter().flatten().collect();
+        let command_aliases: BTreeMap<&String, &String> =
+            self.command_aliases.iter().flatten().collect();
+        let encoded = serde_json::to_vec(&(
+            dependencies,
+            optional_dependencies,
+            manifest_features,
+            enabled_features,
+            dev_dependencies,
+            build_dependencies,
+            overrides,
+            command_aliases,
+        ))
+        .expect("manifest dependencies can always be serialized");
+        Sha256::digest(&encoded)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
     pub fn to_string(&self) -> anyhow::Result<String> {
         Ok(toml::to_string(self)?)
     }
@@ -163,8 +410,14 @@ impl Manifest {
     /// Write the manifest to permanent storage
     #[cfg(not(feature = "integration_tests"))]
     pub fn save(&self) -> anyhow::Result<()> {
+        self.save_named(MANIFEST_FILE_NAME)
+    }
+
+    /// Like `save`, but writes the manifest file with the given name in the manifest's directory
+    /// instead of `wapm.toml`
+    pub fn save_named(&self, filename: &str) -> anyhow::Result<()> {
         let manifest_string = self.to_string()?;
-        let manifest_path = self.manifest_path();
+        let manifest_path = self.base_directory_path.join(filename);
         fs::write(manifest_path, &manifest_string)
             .map_err(|e| ManifestError::CannotSaveManifest(e.to_string()))?;
         Ok(())
@@ -186,10 +439,7 @@ impl Manifest {
         // ignore path for now
         crate::integration_tests::data::RAW_MANIFEST_DATA.with(|rmd| {
             if let Some(ref manifest_toml) = *rmd.borrow() {
-                let manifest: Self = toml::from_str(&manifest_toml)
-                    .map_err(|e| ManifestError::TomlParseError(e.to_string()))?;
-                manifest.validate()?;
-                Ok(manifest)
+                manifest_toml.parse()
             } else {
                 Err(ManifestError::MissingManifest(
                     "Integration test manifest not found".to_string(),
@@ -199,6 +449,20 @@ impl Manifest {
     }
 }
 
+/// Parses and validates a manifest without touching the filesystem, e.g. one received over the
+/// network. The base directory is left empty, so relative paths resolve against the working
+/// directory until `set_base_directory` is called.
+impl FromStr for Manifest {
+    type Err = ManifestError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let manifest: Self =
+            toml::from_str(source).map_err(|e| ManifestError::TomlParseError(e.to_string()))?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ManifestError {
     #[error("Manifest file not found at {0}")]
@@ -223,6 +487,16 @@ pub enum ValidationError {
     MissingABI(String, String),
     #[error("missing module {0} in manifest used by command {1}")]
     MissingModuleForCommand(String, String),
+    #[error("the package name is empty")]
+    EmptyPackageName,
+    #[error(
+        "version \"{1}\" of dependency {0} must be a semantic version or a semantic version requirement"
+    )]
+    InvalidDependencyVersion(String, String),
+    #[error("version \"{1}\" of override {0} must be a semantic version")]
+    InvalidOverrideVersion(String, String),
+    #[error("feature {0} of command {1} is not one of the manifest's features")]
+    UndefinedFeatureForCommand(String, String),
 }
 
 #[cfg(test)]
@@ -277,9 +551,68 @@ mod command_tests {
     }
 }
 
+#[cfg(test)]
+mod module_tests {
+    use crate::data::manifest::Manifest;
+
+    #[test]
+    fn single_module_table() {
+        let wapm_toml = toml! {
+            [package]
+            name = "test"
+            version = "1.0.0"
+            description = "The best package."
+            [module]
+            name = "test"
+            source = "test.wasm"
+        };
+        let manifest: Manifest = wapm_toml.try_into().unwrap();
+        let modules = manifest.module.unwrap();
+        assert_eq!(1, modules.len());
+        assert_eq!("test", modules[0].name);
+    }
+
+    #[test]
+    fn multiple_modules() {
+        let wapm_toml = toml! {
+            [package]
+            name = "test"
+            version = "1.0.0"
+            description = "The best package."
+            [[module]]
+            name = "first"
+            source = "first.wasm"
+            [[module]]
+            name = "second"
+            source = "second.wasm"
+        };
+        let manifest: Manifest = wapm_toml.try_into().unwrap();
+        let names: Vec<String> = manifest
+            .module
+            .unwrap()
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        assert_eq!(vec!["first", "second"], names);
+    }
+
+    #[test]
+    fn no_modules() {
+        let wapm_toml = toml! {
+            [package]
+            name = "test"
+            version = "1.0.0"
+            description = "The best package."
+        };
+        let manifest: Manifest = wapm_toml.try_into().unwrap();
+        assert!(manifest.module.is_none());
+    }
+}
+
 #[cfg(test)]
 mod dependency_tests {
     use crate::data::manifest::{Manifest, MANIFEST_FILE_NAME};
+    use std::collections::HashSet;
     use std::fs::File;
     use std::io::Write;
 
@@ -321,6 +654,28 @@ mod dependency_tests {
         );
         assert_eq!(2, manifest.dependencies.as_ref().unwrap().len());
     }
+
+    #[test]
+    fn dependencies_hash() {
+        let manifest_with = |dependencies: &str| -> Manifest {
+            toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"1.0.0\"\ndescription = \"\"\n[dependencies]\n{}\n[optional-dependencies]\n\"_/baz\" = \"1.0.0\"\n[features]\nbaz = [\"_/baz\"]",
+                dependencies
+            ))
+            .unwrap()
+        };
+        let manifest = manifest_with("\"_/foo\" = \"1.0.0\"\n\"_/bar\" = \"^2\"");
+        let no_features = HashSet::new();
+        let hash = manifest.dependencies_hash(&no_features, true);
+
+        assert_eq!(64, hash.len());
+        let reordered = manifest_with("\"_/bar\" = \"^2\"\n\"_/foo\" = \"1.0.0\"");
+        assert_eq!(hash, reordered.dependencies_hash(&no_features, true));
+        let upgraded = manifest_with("\"_/foo\" = \"1.0.1\"\n\"_/bar\" = \"^2\"");
+        assert_ne!(hash, upgraded.dependencies_hash(&no_features, true));
+        let features: HashSet<String> = vec!["baz".to_string()].into_iter().collect();
+        assert_ne!(hash, manifest.dependencies_hash(&features, true));
+    }
 }
 
 #[cfg(test)]
@@ -355,4 +710,99 @@ module = "mod"
             Some(&"0.0.0-unstable".to_string())
         )
     }
+
+    #[test]
+    fn validate_manifest() {
+        let manifest_with = |extra: &str| -> Manifest {
+            toml::from_str(&format!(
+                "[package]\nname = \"test\"\nversion = \"0.1.0\"\ndescription = \"\"\n{}",
+                extra
+            ))
+            .unwrap()
+        };
+        let validation_error = |manifest: Manifest| match manifest.validate() {
+            Err(ManifestError::ValidationError(e)) => e.to_string(),
+            result => panic!("expected a validation error, got {:?}", result),
+        };
+
+        manifest_with("[dependencies]\nfoo = \"^1\"\n[dev-dependencies]\nbar = \"1.0.0\"")
+            .validate()
+            .unwrap();
+
+        let mut manifest = manifest_with("");
+        manifest.package.name = " ".to_string();
+        assert_eq!("the package name is empty", validation_error(manifest));
+        assert_eq!(
+            "version \"latest\" of dependency foo must be a semantic version or a semantic version requirement",
+            validation_error(manifest_with("[dev-dependencies]\nfoo = \"latest\""))
+        );
+        assert_eq!(
+            "version \"^1\" of override foo must be a semantic version",
+            validation_error(manifest_with("[overrides]\nfoo = \"^1\""))
+        );
+        assert_eq!(
+            "missing module foo in manifest used by command bar",
+            validation_error(manifest_with(
+                "[[command]]\nname = \"bar\"\nmodule = \"foo\""
+            ))
+        );
+        assert_eq!(
+            "missing ABI field on module foo used by command bar; an ABI of `wasi` or `emscripten` is required",
+            validation_error(manifest_with(
+                "[[module]]\nname = \"foo\"\nsource = \"foo.wasm\"\n[[command]]\nname = \"bar\"\nmodule = \"foo\""
+            ))
+        );
+        assert_eq!(
+            "feature debug of command bar is not one of the manifest's features",
+            validation_error(manifest_with(
+                "[[module]]\nname = \"foo\"\nsource = \"foo.wasm\"\nabi = \"wasi\"\n[[command]]\nname = \"bar\"\nmodule = \"foo\"\nfeature = \"debug\""
+            ))
+        );
+    }
+
+    #[test]
+    fn paths_resolve_relative_to_the_manifest_directory() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let package_dir = tmp_dir.path().join("package");
+        std::fs::create_dir(&package_dir).unwrap();
+        std::fs::write(
+            package_dir.join(MANIFEST_FILE_NAME),
+            "[package]\nname = \"_/test\"\nversion = \"1.0.0\"\ndescription = \"\"\n",
+        )
+        .unwrap();
+
+        // the working directory of the tests is not the manifest's directory
+        let mut manifest = Manifest::find_in_directory(&package_dir).unwrap();
+        assert_eq!(package_dir, manifest.base_directory_path);
+        assert_eq!(package_dir.join("../foo"), manifest.resolve_path("../foo"));
+
+        manifest.add_dependency("_/foo".to_string(), "1.0.0".to_string());
+        manifest.save().unwrap();
+        let saved = Manifest::find_in_directory(&package_dir).unwrap();
+        assert_eq!(1, saved.dependencies.unwrap().len());
+
+        manifest.set_base_directory(Path::new("relative"));
+        assert!(manifest.base_directory_path.is_absolute());
+    }
+
+    #[test]
+    fn manifests_parse_from_strings() {
+        let manifest: Manifest =
+            "[package]\nname = \"_/test\"\nversion = \"1.0.0\"\ndescription = \"\"\n[dependencies]\n\"_/foo\" = \"^1\""
+                .parse()
+                .unwrap();
+        assert_eq!("_/test", manifest.package.name);
+        assert_eq!(1, manifest.dependencies.unwrap().len());
+        assert_eq!(PathBuf::new(), manifest.base_directory_path);
+
+        match "[package]".parse::<Manifest>() {
+            Err(ManifestError::TomlParseError(_)) => {}
+            result => panic!("expected a parse error, got {:?}", result),
+        }
+        match "[package]\nname = \"\"\nversion = \"1.0.0\"\ndescription = \"\"".parse::<Manifest>()
+        {
+            Err(ManifestError::ValidationError(ValidationError::EmptyPackageName)) => {}
+            result => panic!("expected a validation error, got {:?}", result),
+        }
+    }
 }
@@ -10,7 +10,7 @@ pub enum Error {
 }
 
 /// Holds packages that are added via the command line
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct AddedPackages<'a> {
     pub packages: HashSet<PackageKey<'a>>,
 }
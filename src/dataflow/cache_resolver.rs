@@ -0,0 +1,338 @@
+use crate::data::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage, VersionSelector};
+use crate::dataflow::{PackageKey, WapmPackageKey, WapmPackageRange};
+use flate2::read::GzDecoder;
+use semver::Version;
+use std::borrow::Cow::Owned;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tar::Archive;
+
+/// The Cache Resolver resolves dependencies from a directory of downloaded package tarballs
+/// instead of a wapm.io server, which allows installing without network access.
+///
+/// The name and version of every cached package is read from the `wapm.toml` inside its
+/// tarball, and the resolved download url is a `file://` url pointing at the tarball.
+pub struct CacheResolver {
+    cache_dir: PathBuf,
+    version_selector: VersionSelector,
+}
+
+impl CacheResolver {
+    /// The version selector picks the cached version of package ranges. A relative cache
+    /// directory is relative to the current directory, the `file://` download urls need an
+    /// absolute path.
+    pub fn new<P: AsRef<Path>>(cache_dir: P, version_selector: VersionSelector) -> Self {
+        let cache_dir = cache_dir.as_ref();
+        let cache_dir = match env::current_dir() {
+            Ok(current_dir) if cache_dir.is_relative() => current_dir.join(cache_dir),
+            _ => cache_dir.to_path_buf(),
+        };
+        Self {
+            cache_dir,
+            version_selector,
+        }
+    }
+
+    /// Reads every tarball in the cache directory. Resolving many packages from one index, see
+    /// `resolve_cached`, reads each tarball only once.
+    pub(crate) fn cached_packages(&self) -> Result<CachedPackages, Error> {
+        let mut cached_packages = CachedPackages::new();
+        if !self.cache_dir.is_dir() {
+            return Ok(cached_packages);
+        }
+        let entries = fs::read_dir(&self.cache_dir)
+            .map_err(|e| Error::CouldNotResolvePackages(e.to_string()))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|e| Error::CouldNotResolvePackages(e.to_string()))?
+                .path();
+            if !path.is_file() || !path.to_string_lossy().ends_with(".tar.gz") {
+                continue;
+            }
+            match read_manifest_from_tarball(&path) {
+                Ok(manifest) => {
+                    cached_packages
+                        .entry(manifest.package.name)
+                        .or_default()
+                        .insert(manifest.package.version, path);
+                }
+                Err(e) => warn!("Skipping cached package {}: {}", path.display(), e),
+            }
+        }
+        Ok(cached_packages)
+    }
+
+    /// Resolves the package from an index of the cache, see `cached_packages`. A package missing
+    /// from the cache is a `PackageNotCached` error.
+    pub(crate) fn resolve_cached<'a>(
+        &self,
+        cached_packages: &CachedPackages,
+        added_package: PackageKey<'a>,
+    ) -> Result<ResolvedPackage<'a>, Error> {
+        let cached = match added_package {
+            PackageKey::WapmPackage(ref key) => cached_packages
+                .get(key.name.as_ref())
+                .and_then(|versions| versions.get_key_value(&key.version)),
+            PackageKey::WapmPackageRange(ref range) => cached_packages
+                .get(range.name.as_ref())
+                .and_then(|versions| {
+                    self.version_selector
+                        .select(&range.version_req, versions.keys())
+                        .and_then(|version| versions.get_key_value(version))
+                }),
+        };
+        let (version, path) = cached.ok_or_else(|| Error::PackageNotCached {
+            package: added_package.to_string(),
+            cache_dir: self.cache_dir.to_string_lossy().to_string(),
+        })?;
+        let download_url = reqwest::Url::from_file_path(path)
+            .map_err(|_| {
+                Error::CouldNotResolvePackages(format!(
+                    "{} is not an absolute path",
+                    path.display()
+                ))
+            })?
+            .to_string();
+        let name = match added_package {
+            PackageKey::WapmPackage(WapmPackageKey { name, .. }) => name,
+            PackageKey::WapmPackageRange(WapmPackageRange { name, .. }) => name,
+        };
+        let key = WapmPackageKey {
+            name: Owned(name.to_string()),
+            version: version.clone(),
+        };
+        Ok((key, (download_url, None)))
+    }
+}
+
+/// package name -> version -> tarball path
+pub(crate) type CachedPackages = BTreeMap<String, BTreeMap<Version, PathBuf>>;
+
+/// The file name of the package's tarball in a cache directory. The `/` of the package name can
+/// not be part of a file name, so it is percent-encoded along with `%` and the `@` before the
+/// version, which keeps the file names of different packages apart, unlike replacing the `/`
+/// with a `-`: `_/foo-bar 1.0.0` is `_%2Ffoo-bar@1.0.0.tar.gz`. The name and version the cache
+/// resolves are read from the tarball, not from its file name.
+pub fn cache_file_name(key: &WapmPackageKey) -> String {
+    let name = key
+        .name
+        .replace('%', "%25")
+        .replace('/', "%2F")
+        .replace('@', "%40");
+    format!("{}@{}.tar.gz", name, key.version)
+}
+
+fn read_manifest_from_tarball(path: &Path) -> anyhow::Result<Manifest> {
+    let mut archive = Archive::new(GzDecoder::new(fs::File::open(path)?));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()? == Path::new(MANIFEST_FILE_NAME) {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            return Ok(toml::from_str(&contents)?);
+        }
+    }
+    Err(anyhow!(
+        "the package does not contain a {}",
+        MANIFEST_FILE_NAME
+    ))
+}
+
+impl<'a> Resolve<'a> for CacheResolver {
+    fn sync_packages(
+        &self,
+        added_packages: Vec<PackageKey<'a>>,
+    ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+        let cached_packages = self.cached_packages()?;
+        added_packages
+            .into_iter()
+            .map(|added_package| self.resolve_cached(&cached_packages, added_package))
+            .collect()
+    }
+
+    fn available_versions(&self, name: &str) -> Result<Vec<Version>, Error> {
+        Ok(self
+            .cached_packages()?
+            .remove(name)
+            .map(|versions| versions.into_keys().collect())
+            .unwrap_or_default())
+    }
+}
+
+/// Writes a gzipped tarball of the files, like the package tarballs of the registry
+#[cfg(test)]
+pub(crate) fn write_tarball(path: &Path, files: &[(&str, &[u8])]) {
+    let file = fs::File::create(path).unwrap();
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+        file,
+        flate2::Compression::default(),
+    ));
+    for (file_path, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, file_path, *contents)
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap();
+}
+
+/// Writes the tarball of a package with only a manifest into the directory, returning its path
+#[cfg(test)]
+pub(crate) fn write_cached_package(directory: &Path, name: &str, version: &str) -> PathBuf {
+    let manifest = format!(
+        "[package]\nname = \"{}\"\nversion = \"{}\"\ndescription = \"\"\n",
+        name, version
+    );
+    let tarball_path = directory.join(format!("{}-{}.tar.gz", name.replace('/', "-"), version));
+    write_tarball(&tarball_path, &[(MANIFEST_FILE_NAME, manifest.as_bytes())]);
+    tarball_path
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dataflow::cache_resolver::{cache_file_name, write_cached_package, CacheResolver};
+    use crate::dataflow::resolved_packages::{Error, Resolve, VersionSelector};
+    use crate::dataflow::{PackageKey, WapmPackageKey};
+
+    #[test]
+    fn resolve_from_cache() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        write_cached_package(tmp_dir.path(), "_/foo", "1.0.0");
+        write_cached_package(tmp_dir.path(), "_/foo", "1.2.0");
+        write_cached_package(tmp_dir.path(), "_/bar", "2.0.0");
+        let resolver = CacheResolver::new(tmp_dir.path(), VersionSelector::Highest);
+
+        let resolved = resolver
+            .sync_packages(vec![
+                PackageKey::new_registry_package("_/bar", semver::Version::new(2, 0, 0)),
+                PackageKey::new_registry_package_range(
+                    "_/foo",
+                    semver::VersionReq::parse("^1").unwrap(),
+                ),
+            ])
+            .unwrap();
+
+        assert_eq!(2, resolved.len());
+        assert_eq!("_/bar 2.0.0", resolved[0].0.to_string());
+        assert_eq!("_/foo 1.2.0", resolved[1].0.to_string());
+        let (download_url, signature) = &resolved[1].1;
+        assert!(download_url.starts_with("file://"));
+        assert!(download_url.ends_with("_-foo-1.2.0.tar.gz"));
+        assert!(signature.is_none());
+    }
+
+    #[test]
+    fn relative_cache_dirs_resolve_to_file_urls() {
+        let current_dir = std::env::current_dir().unwrap();
+        let tmp_dir = tempfile::TempDir::new_in(&current_dir).unwrap();
+        write_cached_package(tmp_dir.path(), "_/foo", "1.0.0");
+        let relative_dir = tmp_dir.path().strip_prefix(&current_dir).unwrap();
+        assert!(relative_dir.is_relative());
+        let resolver = CacheResolver::new(relative_dir, VersionSelector::Highest);
+
+        let resolved = resolver
+            .sync_packages(vec![PackageKey::new_registry_package(
+                "_/foo",
+                semver::Version::new(1, 0, 0),
+            )])
+            .unwrap();
+
+        let (download_url, _) = &resolved[0].1;
+        let path = reqwest::Url::parse(download_url)
+            .unwrap()
+            .to_file_path()
+            .unwrap();
+        assert_eq!(tmp_dir.path().join("_-foo-1.0.0.tar.gz"), path);
+    }
+
+    #[test]
+    fn wildcard_resolves_newest_cached_version() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        write_cached_package(tmp_dir.path(), "_/foo", "1.0.0");
+        write_cached_package(tmp_dir.path(), "_/foo", "2.1.0");
+        write_cached_package(tmp_dir.path(), "_/foo", "3.0.0-beta.1");
+        let resolver = CacheResolver::new(tmp_dir.path(), VersionSelector::Highest);
+
+        let resolved = resolver
+            .sync_packages(vec![PackageKey::new_registry_package_range(
+                "_/foo",
+                semver::VersionReq::parse("*").unwrap(),
+            )])
+            .unwrap();
+
+        // pre-releases are only picked when asked for
+        assert_eq!("_/foo 2.1.0", resolved[0].0.to_string());
+    }
+
+    #[test]
+    fn version_selector_picks_highest_or_lowest_match() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        write_cached_package(tmp_dir.path(), "_/foo", "1.0.0");
+        write_cached_package(tmp_dir.path(), "_/foo", "1.1.0");
+        write_cached_package(tmp_dir.path(), "_/foo", "1.2.0");
+        let range = PackageKey::new_registry_package_range(
+            "_/foo",
+            semver::VersionReq::parse("^1.1").unwrap(),
+        );
+
+        for (version_selector, expected) in &[
+            (VersionSelector::Highest, "_/foo 1.2.0"),
+            (VersionSelector::Lowest, "_/foo 1.1.0"),
+        ] {
+            let resolver = CacheResolver::new(tmp_dir.path(), *version_selector);
+            // the same inputs always pick the same version
+            for _ in 0..2 {
+                let resolved = resolver.sync_packages(vec![range.clone()]).unwrap();
+                assert_eq!(*expected, resolved[0].0.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn uncached_package_is_an_error() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        write_cached_package(tmp_dir.path(), "_/foo", "1.0.0");
+        let resolver = CacheResolver::new(tmp_dir.path(), VersionSelector::Highest);
+
+        let result = resolver.sync_packages(vec![PackageKey::new_registry_package(
+            "_/foo",
+            semver::Version::new(2, 0, 0),
+        )]);
+
+        match result {
+            Err(Error::PackageNotCached { package, .. }) => assert_eq!("_/foo 2.0.0", package),
+            result => panic!("expected a package not cached error, got {:?}", result),
+        }
+        assert_eq!(
+            vec![semver::Version::new(1, 0, 0)],
+            resolver.available_versions("_/foo").unwrap()
+        );
+        assert!(resolver.available_versions("_/bar").unwrap().is_empty());
+    }
+
+    #[test]
+    fn cache_file_names_keep_packages_apart() {
+        let file_name = |name: &str, version: &str| {
+            cache_file_name(&WapmPackageKey {
+                name: name.into(),
+                version: semver::Version::parse(version).unwrap(),
+            })
+        };
+        assert_eq!("_%2Ffoo@1.0.0.tar.gz", file_name("_/foo", "1.0.0"));
+        assert_ne!(
+            file_name("foo/bar-baz", "1.0.0"),
+            file_name("foo-bar/baz", "1.0.0")
+        );
+        assert_ne!(
+            file_name("_/foo-1", "0.0.0"),
+            file_name("_/foo", "1.0.0-0.0.0")
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use crate::data::lock::lockfile::Lockfile;
+use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage};
+use crate::dataflow::PackageKey;
+use semver::Version;
+
+/// A resolver of any type, so resolvers of different types can be chained
+pub type BoxedResolver = Box<dyn for<'r> Resolve<'r>>;
+
+/// The Chain Resolver tries a list of resolvers in order, e.g. a private registry before the
+/// public one. Each package is resolved by the first resolver that can resolve it, and the
+/// packages that no resolver could resolve fail with the error of every resolver that was tried.
+pub struct ChainResolver {
+    /// The name of each registry, used in errors, and its resolver
+    resolvers: Vec<(String, BoxedResolver)>,
+}
+
+impl ChainResolver {
+    pub fn new(resolvers: Vec<(String, BoxedResolver)>) -> Self {
+        Self { resolvers }
+    }
+}
+
+impl<'a> Resolve<'a> for ChainResolver {
+    fn sync_packages(
+        &self,
+        added_packages: Vec<PackageKey<'a>>,
+    ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+        let mut packages: Vec<ResolvedPackage<'a>> = vec![];
+        let mut unresolved_packages = added_packages;
+        let mut failures = vec![];
+        for (registry, resolver) in self.resolvers.iter() {
+            if unresolved_packages.is_empty() {
+                break;
+            }
+            let resolved_packages = match resolver.sync_packages(unresolved_packages.clone()) {
+                Ok(resolved_packages) => resolved_packages,
+                // resolvers fail for the whole batch, so resolve the packages one at a time to
+                // keep the ones this resolver can resolve
+                Err(e) => {
+                    failures.push((registry.clone(), e.to_string()));
+                    unresolved_packages
+                        .iter()
+                        .filter_map(|key| resolver.sync_packages(vec![key.clone()]).ok())
+                        .flatten()
+                        .collect()
+                }
+            };
+            unresolved_packages.retain(|requested| {
+                !resolved_packages
+                    .iter()
+                    .any(|(resolved, _)| requested.is_resolved_by(resolved))
+            });
+            packages.extend(resolved_packages);
+        }
+
+        // without errors, unresolved packages are left out, the same as the inner resolvers do
+        if !unresolved_packages.is_empty() && !failures.is_empty() {
+            return Err(Error::NoRegistryResolved {
+                packages: unresolved_packages
+                    .iter()
+                    .map(|key| key.to_string())
+                    .collect(),
+                failures,
+            });
+        }
+        Ok(packages)
+    }
+
+    fn preload(&self, lockfile: &Lockfile, packages: &[PackageKey<'a>]) {
+        for (_, resolver) in self.resolvers.iter() {
+            resolver.preload(lockfile, packages);
+        }
+    }
+
+    /// The versions of every resolver, resolvers that fail to list them are skipped
+    fn available_versions(&self, name: &str) -> Result<Vec<Version>, Error> {
+        let mut versions: Vec<Version> = self
+            .resolvers
+            .iter()
+            .filter_map(|(_, resolver)| resolver.available_versions(name).ok())
+            .flatten()
+            .collect();
+        versions.sort();
+        versions.dedup();
+        Ok(versions)
+    }
+
+    /// Whether any resolver yanked the version, resolvers that fail to tell are skipped
+    fn is_yanked(&self, name: &str, version: &Version) -> Result<bool, Error> {
+        Ok(self
+            .resolvers
+            .iter()
+            .any(|(_, resolver)| resolver.is_yanked(name, version).unwrap_or(false)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dataflow::chain_resolver::ChainResolver;
+    use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage};
+    use crate::dataflow::{PackageKey, WapmPackageKey};
+    use std::borrow::Cow::Owned;
+
+    /// A test resolver that resolves the packages it knows to version 1.0.0, and fails for the
+    /// whole batch otherwise, like the cache resolver does
+    struct KnownPackagesResolver {
+        known_packages: Vec<&'static str>,
+    }
+
+    impl<'a> Resolve<'a> for KnownPackagesResolver {
+        fn sync_packages(
+            &self,
+            added_packages: Vec<PackageKey<'a>>,
+        ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+            added_packages
+                .into_iter()
+                .map(|key| {
+                    if !self.known_packages.contains(&key.name()) {
+                        return Err(Error::CouldNotResolvePackages(format!(
+                            "{} is not known",
+                            key.name()
+                        )));
+                    }
+                    Ok((
+                        WapmPackageKey {
+                            name: Owned(key.name().to_string()),
+                            version: semver::Version::new(1, 0, 0),
+                        },
+                        (format!("{}.tar.gz", self.known_packages[0]), None),
+                    ))
+                })
+                .collect()
+        }
+    }
+
+    fn chain_resolver() -> ChainResolver {
+        ChainResolver::new(vec![
+            (
+                "private".to_string(),
+                Box::new(KnownPackagesResolver {
+                    known_packages: vec!["_/private"],
+                }),
+            ),
+            (
+                "public".to_string(),
+                Box::new(KnownPackagesResolver {
+                    known_packages: vec!["_/public", "_/private"],
+                }),
+            ),
+        ])
+    }
+
+    fn package(name: &'static str) -> PackageKey<'static> {
+        PackageKey::new_registry_package_range(name, semver::VersionReq::parse("^1").unwrap())
+    }
+
+    #[test]
+    fn packages_resolve_from_the_first_registry_that_has_them() {
+        let mut resolved = chain_resolver()
+            .sync_packages(vec![package("_/public"), package("_/private")])
+            .unwrap();
+        resolved.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        assert_eq!(2, resolved.len());
+        assert_eq!("_/private 1.0.0", resolved[0].0.to_string());
+        assert_eq!("_/private.tar.gz", (resolved[0].1).0);
+        assert_eq!("_/public 1.0.0", resolved[1].0.to_string());
+        assert_eq!("_/public.tar.gz", (resolved[1].1).0);
+    }
+
+    #[test]
+    fn errors_list_every_registry_that_was_tried() {
+        let result = chain_resolver().sync_packages(vec![package("_/public"), package("_/other")]);
+
+        match result {
+            Err(Error::NoRegistryResolved { packages, failures }) => {
+                assert_eq!(vec!["_/other >=1.0.0, <2.0.0".to_string()], packages);
+                let registries: Vec<&str> = failures
+                    .iter()
+                    .map(|(registry, _)| registry.as_str())
+                    .collect();
+                assert_eq!(vec!["private", "public"], registries);
+            }
+            result => panic!("expected no registry to resolve _/other, got {:?}", result),
+        }
+    }
+}
@@ -1,12 +1,72 @@
 use crate::dataflow::lockfile_packages::LockfilePackages;
 use crate::dataflow::manifest_packages::ManifestPackages;
-use crate::dataflow::PackageKey;
+use crate::dataflow::{PackageKey, WapmPackageKey, WapmPackageRange};
+use semver::Version;
 use std::collections::hash_set::HashSet;
+use std::fmt;
+
+/// How a changed manifest dependency differs from the version in the lockfile.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PackageChange<'a> {
+    /// The package was not in the lockfile
+    Added(PackageKey<'a>),
+    /// An exact version newer than the locked version
+    Upgraded { key: PackageKey<'a>, from: Version },
+    /// An exact version older than the locked version
+    Downgraded { key: PackageKey<'a>, from: Version },
+    /// A version range that no longer matches the locked version, the direction is only known
+    /// once the range is resolved
+    Reresolved { key: PackageKey<'a>, from: Version },
+}
+
+impl<'a> PackageChange<'a> {
+    pub fn key(&self) -> &PackageKey<'a> {
+        match self {
+            PackageChange::Added(key)
+            | PackageChange::Upgraded { key, .. }
+            | PackageChange::Downgraded { key, .. }
+            | PackageChange::Reresolved { key, .. } => key,
+        }
+    }
+
+    pub fn into_owned(self) -> PackageChange<'static> {
+        match self {
+            PackageChange::Added(key) => PackageChange::Added(key.into_owned()),
+            PackageChange::Upgraded { key, from } => PackageChange::Upgraded {
+                key: key.into_owned(),
+                from,
+            },
+            PackageChange::Downgraded { key, from } => PackageChange::Downgraded {
+                key: key.into_owned(),
+                from,
+            },
+            PackageChange::Reresolved { key, from } => PackageChange::Reresolved {
+                key: key.into_owned(),
+                from,
+            },
+        }
+    }
+}
+
+impl<'a> fmt::Display for PackageChange<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            PackageChange::Added(key) => write!(f, "{} (new)", key),
+            PackageChange::Upgraded { key, from } => write!(f, "{} (upgrade from {})", key, from),
+            PackageChange::Downgraded { key, from } => {
+                write!(f, "{} (downgrade from {})", key, from)
+            }
+            PackageChange::Reresolved { key, from } => write!(f, "{} (was {})", key, from),
+        }
+    }
+}
 
 /// Contains the package IDs for dependencies that have changed between a manifest and an existing lockfile.
 #[derive(Clone, Debug)]
 pub struct ChangedManifestPackages<'a> {
     pub packages: HashSet<PackageKey<'a>>,
+    /// How each changed package differs from the lockfile, sorted by package
+    pub changes: Vec<PackageChange<'a>>,
 }
 
 impl<'a> ChangedManifestPackages<'a> {
@@ -33,13 +93,44 @@ impl<'a> ChangedManifestPackages<'a> {
                 }
             })
             .collect::<HashSet<PackageKey<'a>>>();
-        Self { packages }
+        let mut changes: Vec<PackageChange<'a>> = packages
+            .iter()
+            .cloned()
+            .map(|key| {
+                let name = match key {
+                    PackageKey::WapmPackage(WapmPackageKey { ref name, .. })
+                    | PackageKey::WapmPackageRange(WapmPackageRange { ref name, .. }) => name,
+                };
+                let locked_version = lockfile_keys.iter().find_map(|k| match k {
+                    PackageKey::WapmPackage(locked) if locked.name == *name => {
+                        Some(locked.version.clone())
+                    }
+                    _ => None,
+                });
+                match (locked_version, &key) {
+                    (None, _) => PackageChange::Added(key),
+                    (Some(from), PackageKey::WapmPackage(WapmPackageKey { version, .. }))
+                        if *version > from =>
+                    {
+                        PackageChange::Upgraded { key, from }
+                    }
+                    (Some(from), PackageKey::WapmPackage(_)) => {
+                        PackageChange::Downgraded { key, from }
+                    }
+                    (Some(from), PackageKey::WapmPackageRange(_)) => {
+                        PackageChange::Reresolved { key, from }
+                    }
+                }
+            })
+            .collect();
+        changes.sort_by_key(|change| change.key().to_string());
+        Self { packages, changes }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::dataflow::changed_manifest_packages::ChangedManifestPackages;
+    use crate::dataflow::changed_manifest_packages::{ChangedManifestPackages, PackageChange};
     use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
     use crate::dataflow::manifest_packages::ManifestPackages;
     use crate::dataflow::PackageKey;
@@ -192,4 +283,66 @@ mod test {
             );
         assert_eq!(1, changed_packages.packages.len());
     }
+
+    #[test]
+    fn classify_changes() {
+        let mut manifest_package_keys = HashSet::new();
+        manifest_package_keys.insert(PackageKey::new_registry_package(
+            "_/up",
+            semver::Version::new(2, 0, 0),
+        ));
+        manifest_package_keys.insert(PackageKey::new_registry_package(
+            "_/down",
+            semver::Version::new(1, 0, 0),
+        ));
+        manifest_package_keys.insert(PackageKey::new_registry_package_range(
+            "_/range",
+            semver::VersionReq::parse("^3").unwrap(),
+        ));
+        manifest_package_keys.insert(PackageKey::new_registry_package(
+            "_/new",
+            semver::Version::new(1, 0, 0),
+        ));
+        let manifest_data = ManifestPackages {
+            packages: manifest_package_keys,
+        };
+        let mut lockfile_packages = HashMap::new();
+        for (name, version) in &[("_/up", "1.0.0"), ("_/down", "1.2.0"), ("_/range", "2.0.0")] {
+            lockfile_packages.insert(
+                PackageKey::new_registry_package(*name, semver::Version::parse(version).unwrap()),
+                LockfilePackage {
+                    modules: vec![],
+                    commands: vec![],
+                },
+            );
+        }
+        let lockfile_data = LockfilePackages {
+            packages: lockfile_packages,
+        };
+        let changed_packages =
+            ChangedManifestPackages::get_changed_packages_from_manifest_and_lockfile(
+                &manifest_data,
+                &lockfile_data,
+            );
+        let changes: Vec<String> = changed_packages
+            .changes
+            .iter()
+            .map(|change| change.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "_/down 1.0.0 (downgrade from 1.2.0)",
+                "_/new 1.0.0 (new)",
+                "_/range >=3.0.0, <4.0.0 (was 2.0.0)",
+                "_/up 2.0.0 (upgrade from 1.0.0)",
+            ],
+            changes
+        );
+        match &changed_packages.changes[0] {
+            PackageChange::Downgraded { from, .. } => {
+                assert_eq!(semver::Version::new(1, 2, 0), *from)
+            }
+            change => panic!("expected a downgrade, got {:?}", change),
+        }
+    }
 }
@@ -1,5 +1,7 @@
+use crate::abi::Abi;
 use crate::config::Config;
 use crate::data::lock::lockfile::{Lockfile, LockfileError};
+use crate::data::lock::lockfile_module::ModuleOrigin;
 use crate::data::manifest::Manifest;
 use crate::dataflow::lockfile_packages::LockfileResult;
 use crate::dataflow::manifest_packages::ManifestResult;
@@ -79,8 +81,13 @@ pub enum FindCommandResult {
         source: PathBuf,
         manifest_dir: PathBuf,
         args: Option<String>,
+        /// The arguments of the lockfile command, passed before the arguments it is run with
+        default_args: Vec<String>,
         module_name: String,
+        /// The ABI of the module, to check that the runtime supports it
+        abi: Abi,
         prehashed_cache_key: Option<String>,
+        origin: ModuleOrigin,
     },
     Error(anyhow::Error),
 }
@@ -88,7 +95,9 @@ pub enum FindCommandResult {
 impl From<LockfileError> for FindCommandResult {
     fn from(error: LockfileError) -> Self {
         match error {
-            LockfileError::CommandNotFound(c) => FindCommandResult::CommandNotFound(c),
+            LockfileError::CommandNotFound { command, .. } => {
+                FindCommandResult::CommandNotFound(command)
+            }
             _ => FindCommandResult::Error(error.into()),
         }
     }
@@ -101,7 +110,7 @@ impl FindCommandResult {
         lockfile: Lockfile,
         directory: &Path,
     ) -> Self {
-        match lockfile.get_command(command_name.as_ref()) {
+        match lockfile.get_command_qualified(command_name.as_ref()) {
             Err(e) => e.into(),
             Ok(lockfile_command) => {
                 debug!("Command found in lockfile: {:?}", &lockfile_command);
@@ -112,12 +121,15 @@ impl FindCommandResult {
                     });
                     match found_module {
                         Some(module) => FindCommandResult::CommandFound {
-                            source: module.source.clone(),
+                            source: module.source_for_host().to_path_buf(),
                             manifest_dir: manifest.base_directory_path,
                             args: lockfile_command.main_args.clone(),
+                            default_args: lockfile_command.args.clone(),
                             module_name: module.name.clone(),
+                            abi: module.abi,
                             // don't use prehashed cache key for local modules
                             prehashed_cache_key: None,
+                            origin: ModuleOrigin::Local,
                         },
                         None => FindCommandResult::Error(
                             Error::CommandFoundButCorrespondingModuleIsMissing(
@@ -150,9 +162,12 @@ impl FindCommandResult {
                                 source: path,
                                 manifest_dir,
                                 args: lockfile_command.main_args.clone(),
+                                default_args: lockfile_command.args.clone(),
                                 module_name: lockfile_module.name.clone(),
+                                abi: lockfile_module.abi,
                                 prehashed_cache_key: lockfile
                                     .get_prehashed_cache_key_from_command(&lockfile_command),
+                                origin: lockfile_module.origin(),
                             }
                         }
                         Err(e) => FindCommandResult::Error(e),
@@ -167,7 +182,7 @@ impl FindCommandResult {
         lockfile: Lockfile,
         directory: &Path,
     ) -> Self {
-        match lockfile.get_command(command_name.as_ref()) {
+        match lockfile.get_command_qualified(command_name.as_ref()) {
             Ok(lockfile_command) => {
                 match lockfile.get_module(
                     &lockfile_command.package_name,
@@ -183,9 +198,12 @@ impl FindCommandResult {
                             source: path,
                             manifest_dir,
                             args: lockfile_command.main_args.clone(),
+                            default_args: lockfile_command.args.clone(),
                             module_name: lockfile_module.name.clone(),
+                            abi: lockfile_module.abi,
                             prehashed_cache_key: lockfile
                                 .get_prehashed_cache_key_from_command(&lockfile_command),
+                            origin: lockfile_module.origin(),
                         }
                     }
                     Err(_e) => {
@@ -230,11 +248,17 @@ pub struct Command {
     pub source: PathBuf,
     pub manifest_dir: PathBuf,
     pub args: Option<String>,
+    /// the arguments the command passes before the arguments it is run with
+    pub default_args: Vec<String>,
     pub module_name: String,
+    /// the ABI of the module
+    pub abi: Abi,
     /// whether the command was found in the global context
     pub is_global: bool,
     /// the prehashed module key
     pub prehashed_cache_key: Option<String>,
+    /// where the module was installed from
+    pub origin: ModuleOrigin,
 }
 
 /// Get a command from anywhere, where anywhere is the set of packages in the local lockfile and the global lockfile.
@@ -251,16 +275,22 @@ pub fn get_command_from_anywhere<S: AsRef<str>>(command_name: S) -> Result<Comma
             source,
             manifest_dir,
             args,
+            default_args,
             module_name,
+            abi,
             prehashed_cache_key,
+            origin,
         } => {
             return Ok(Command {
                 source,
                 manifest_dir,
                 args,
+                default_args,
                 module_name,
+                abi,
                 is_global: false,
                 prehashed_cache_key,
+                origin,
             });
         }
         FindCommandResult::Error(e) => {
@@ -285,16 +315,22 @@ pub fn get_command_from_anywhere<S: AsRef<str>>(command_name: S) -> Result<Comma
             source,
             manifest_dir,
             args,
+            default_args,
             module_name,
+            abi,
             prehashed_cache_key,
+            origin,
         } => {
             return Ok(Command {
                 source,
                 manifest_dir,
                 args,
+                default_args,
                 module_name,
+                abi,
                 is_global: true,
                 prehashed_cache_key,
+                origin,
             });
         }
         FindCommandResult::Error(e) => {
@@ -1,3 +1,4 @@
+use crate::data::lock::lockfile::Lockfile;
 use crate::data::manifest::Manifest;
 use crate::database;
 use crate::dataflow::manifest_packages::ManifestResult;
@@ -15,6 +16,8 @@ use std::fs::{self, OpenOptions};
 use std::io;
 use std::io::{Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
 use tar::Archive;
 use thiserror::Error;
 
@@ -40,41 +43,73 @@ pub enum Error {
     IoConnectionError(String),
     #[error("Failed to validate package {0} with key {1}: {2}")]
     FailedToValidateSignature(String, String, String),
+    #[error("The download of package \"{0}\" does not match the lockfile. {1}")]
+    DownloadMismatch(String, String),
 }
 
 /// A structure containing installed packages. Currently contains the key, the deserialized
-/// manifest, and the download url.
+/// manifest, the download url, and the size of the downloaded archive in bytes.
 #[derive(Clone, Debug)]
 pub struct InstalledPackages<'a> {
-    pub packages: Vec<(WapmPackageKey<'a>, Manifest, String)>,
+    pub packages: Vec<(WapmPackageKey<'a>, Manifest, String, u64)>,
 }
 
+/// The most packages that are downloaded and extracted at the same time
+const MAX_CONCURRENT_INSTALLS: usize = 8;
+
+/// Held while checking the keys of a package, so prompts to trust a key and writes to the keys
+/// database happen one package at a time while the downloads run concurrently
+static KEY_VERIFICATION: Mutex<()> = Mutex::new(());
+
 impl<'a> InstalledPackages<'a> {
-    /// Will install the resolved manifest packages into the specified directory.
+    /// Will install the resolved manifest packages into the specified directory. Packages that
+    /// are locked in the lockfile are checked against its integrity before they are installed.
+    /// Up to `MAX_CONCURRENT_INSTALLS` packages are installed at once; the installed packages
+    /// keep the order of the resolved packages, and the first failed package in that order is
+    /// the error.
     pub fn install<Installer: Install<'a>>(
         directory: &Path,
         resolve_packages: ResolvedPackages<'a>,
+        lockfile: Option<&Lockfile>,
         force_insecure_install: bool,
     ) -> Result<Self, Error> {
-        let packages_result: Result<Vec<(WapmPackageKey, PathBuf, String)>, Error> =
-            resolve_packages
-                .packages
-                .into_iter()
-                .map(|(key, (download_url, signature))| {
+        let workers = resolve_packages.packages.len().min(MAX_CONCURRENT_INSTALLS);
+        let queue = Mutex::new(resolve_packages.packages.into_iter().enumerate());
+        let installed = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                    let (index, (key, (download_url, signature))) = match next {
+                        Some(package) => package,
+                        None => break,
+                    };
                     info!("Installing {}@{}", key.name, key.version);
-                    Installer::install_package(
-                        &directory,
+                    let result = Installer::install_package(
+                        directory,
                         key,
                         &download_url,
                         signature,
+                        lockfile,
                         force_insecure_install,
-                    )
-                })
-                .collect();
-        let packages_result: Result<Vec<(WapmPackageKey, Manifest, String)>, Error> =
+                    );
+                    installed
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push((index, result));
+                });
+            }
+        });
+        let mut installed = installed
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        installed.sort_by_key(|(index, _)| *index);
+        let packages_result: Result<Vec<(WapmPackageKey, PathBuf, String, u64)>, Error> =
+            installed.into_iter().map(|(_, result)| result).collect();
+        let packages_result: Result<Vec<(WapmPackageKey, Manifest, String, u64)>, Error> =
             packages_result?
                 .into_iter()
-                .map(|(key, dir, download_url)| {
+                .map(|(key, dir, download_url, size)| {
                     let manifest = match ManifestResult::find_in_directory(&dir) {
                         ManifestResult::ManifestError(e) => {
                             return Err(Error::InstalledDependencyIsMissingManifest(
@@ -90,7 +125,7 @@ impl<'a> InstalledPackages<'a> {
                             ));
                         }
                     };
-                    Ok((key.clone(), manifest, download_url))
+                    Ok((key.clone(), manifest, download_url, size))
                 })
                 .collect();
         let packages = packages_result?;
@@ -100,13 +135,17 @@ impl<'a> InstalledPackages<'a> {
 
 /// A trait for injecting an installer for installing wapm packages.
 pub trait Install<'a> {
+    /// Installs the package into the directory, returning the directory of the package, the
+    /// download url, and the number of bytes downloaded. A package locked in the lockfile must
+    /// match its integrity, or it is not installed.
     fn install_package(
         directory: &Path,
         key: WapmPackageKey<'a>,
         download_url: &str,
         signature: Option<keys::WapmPackageSignature>,
+        lockfile: Option<&Lockfile>,
         force_insecure_install: bool,
-    ) -> Result<(WapmPackageKey<'a>, PathBuf, String), Error>;
+    ) -> Result<(WapmPackageKey<'a>, PathBuf, String, u64), Error>;
 }
 
 pub struct RegistryInstaller;
@@ -283,45 +322,16 @@ impl<'a> Install<'a> for RegistryInstaller {
         key: WapmPackageKey<'a>,
         download_url: &str,
         signature: Option<keys::WapmPackageSignature>,
+        lockfile: Option<&Lockfile>,
         force_insecure_install: bool,
-    ) -> Result<(WapmPackageKey<'a>, PathBuf, String), Error> {
+    ) -> Result<(WapmPackageKey<'a>, PathBuf, String, u64), Error> {
         let (namespace, pkg_name) = get_package_namespace_and_name(&key.name)
             .map_err(|e| Error::FailedToParsePackageName(key.to_string(), e.to_string()))?;
         let fully_qualified_package_name: String =
             fully_qualified_package_display_name(pkg_name, &key.version);
         let package_dir = create_package_dir(&directory, namespace, &fully_qualified_package_name)
             .map_err(|err| Error::IoErrorCreatingDirectory(key.to_string(), err.to_string()))?;
-        let client = {
-            let builder = ClientBuilder::new().gzip(false);
-            let builder = if let Some(proxy) = proxy::maybe_set_up_proxy()
-                .map_err(|e| Error::IoConnectionError(format!("{}", e)))?
-            {
-                builder.proxy(proxy)
-            } else {
-                builder
-            };
-
-            builder.build().unwrap()
-        };
-        let user_agent = format!(
-            "wapm/{} {} {}",
-            VERSION,
-            whoami::platform(),
-            whoami::os().to_lowercase(),
-        );
-        let mut response = client
-            .get(download_url)
-            .header(reqwest::header::USER_AGENT, user_agent)
-            .send()
-            .map_err(|e| {
-                let error_message = e.to_string();
-                #[cfg(feature = "telemetry")]
-                {
-                    let e = e.into();
-                    sentry::integrations::anyhow::capture_anyhow(&e);
-                }
-                Error::DownloadError(key.to_string(), error_message)
-            })?;
+        let mut response = open_package_download(&key, download_url)?;
 
         // step to perform after package is decompressed: may be a no-op or may
         // execute side effects such as logging to the user.
@@ -331,11 +341,16 @@ impl<'a> Install<'a> for RegistryInstaller {
                     insecure_install,
                     key_to_verify_package_with,
                     signature_to_use,
-                } = verify_integrity_of_package(
-                    namespace,
-                    fully_qualified_package_name.clone(),
-                    signature,
-                )?;
+                } = {
+                    let _verifying = KEY_VERIFICATION
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner);
+                    verify_integrity_of_package(
+                        namespace,
+                        fully_qualified_package_name.clone(),
+                        signature,
+                    )?
+                };
 
                 if insecure_install {
                     Box::new(|_dest| Ok(()))
@@ -381,17 +396,89 @@ impl<'a> Install<'a> for RegistryInstaller {
             .create(true)
             .open(&temp_tar_gz_path)
             .map_err(|e| Error::IoCopyError(key.to_string(), e.to_string()))?;
-        io::copy(&mut response, &mut dest)
+        let size = io::copy(&mut response, &mut dest)
             .map_err(|e| Error::DownloadError(key.to_string(), e.to_string()))?;
 
         key_sign_end_step(&mut dest)?;
 
-        Self::decompress_and_extract_archive(dest, &package_dir, &key)
+        // the package is extracted next to its final directory and checked there, so a download
+        // that does not match the lockfile is never installed
+        let staging_dir = tempfile::Builder::new()
+            .prefix(".install-")
+            .tempdir_in(package_dir.parent().unwrap_or(directory))
+            .map_err(|e| Error::IoErrorCreatingDirectory(key.to_string(), e.to_string()))?;
+        Self::decompress_and_extract_archive(dest, staging_dir.path(), &key)
             .map_err(|e| Error::DecompressionError(key.to_string(), e.to_string()))?;
-        Ok((key, package_dir, download_url.to_string()))
+        if let Some(lockfile) = lockfile {
+            if let Err(e) = lockfile.verify_package_dir(&key.name, &key.version, staging_dir.path())
+            {
+                fs::remove_dir_all(&package_dir)
+                    .map_err(|e| Error::IoCopyError(key.to_string(), e.to_string()))?;
+                return Err(Error::DownloadMismatch(key.to_string(), e.to_string()));
+            }
+        }
+        fs::remove_dir_all(&package_dir)
+            .and_then(|_| fs::rename(staging_dir.path(), &package_dir))
+            .map_err(|e| Error::IoCopyError(key.to_string(), e.to_string()))?;
+        staging_dir.into_path();
+        Ok((key, package_dir, download_url.to_string(), size))
     }
 }
 
+/// Opens the package tarball at the download url, reading `file://` urls of cached packages from
+/// disk.
+pub fn open_package_download(
+    key: &WapmPackageKey,
+    download_url: &str,
+) -> Result<Box<dyn io::Read>, Error> {
+    let response: Box<dyn io::Read> = match reqwest::Url::parse(download_url) {
+        // packages resolved from a local cache are read from disk
+        Ok(ref url) if url.scheme() == "file" => {
+            let path = url.to_file_path().map_err(|_| {
+                Error::DownloadError(key.to_string(), format!("invalid file url {}", url))
+            })?;
+            let file = fs::File::open(path)
+                .map_err(|e| Error::DownloadError(key.to_string(), e.to_string()))?;
+            Box::new(file)
+        }
+        _ => {
+            let client = {
+                let builder = ClientBuilder::new().gzip(false);
+                let builder = if let Some(proxy) = proxy::maybe_set_up_proxy()
+                    .map_err(|e| Error::IoConnectionError(format!("{}", e)))?
+                {
+                    builder.proxy(proxy)
+                } else {
+                    builder
+                };
+
+                builder.build().unwrap()
+            };
+            let user_agent = format!(
+                "wapm/{} {} {}",
+                VERSION,
+                whoami::platform(),
+                whoami::os().to_lowercase(),
+            );
+            let response = client
+                .get(download_url)
+                .header(reqwest::header::USER_AGENT, user_agent)
+                .send()
+                .map_err(|e| {
+                    let error_message = e.to_string();
+                    #[cfg(feature = "telemetry")]
+                    {
+                        let e = e.into();
+                        sentry::integrations::anyhow::capture_anyhow(&e);
+                    }
+                    Error::DownloadError(key.to_string(), error_message)
+                })?;
+            Box::new(response)
+        }
+    };
+    Ok(response)
+}
+
 /// Verifies the signature of a downloaded package archive
 fn verify_signature_on_package(
     pkv: &str,
@@ -408,3 +495,144 @@ fn verify_signature_on_package(
     minisign::verify(&public_key, &sig_box, dest, true, false)
         .map_err(|e| anyhow!("Could not validate signature: {}", e.to_string()))
 }
+
+#[cfg(test)]
+mod test {
+    use crate::data::lock::lockfile::{foo_lockfile, Lockfile};
+    use crate::dataflow::cache_resolver::write_tarball;
+    use crate::dataflow::installed_packages::{
+        Error, Install, InstalledPackages, RegistryInstaller, MAX_CONCURRENT_INSTALLS,
+    };
+    use crate::dataflow::resolved_packages::ResolvedPackages;
+    use crate::dataflow::WapmPackageKey;
+    use crate::keys;
+    use crate::util;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    static RUNNING: AtomicUsize = AtomicUsize::new(0);
+    static MOST_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+    /// Installs a package with only a manifest, slowly, counting how many install at once
+    struct SlowInstaller;
+
+    impl<'a> Install<'a> for SlowInstaller {
+        fn install_package(
+            directory: &Path,
+            key: WapmPackageKey<'a>,
+            download_url: &str,
+            _signature: Option<keys::WapmPackageSignature>,
+            _lockfile: Option<&Lockfile>,
+            _force_insecure_install: bool,
+        ) -> Result<(WapmPackageKey<'a>, PathBuf, String, u64), Error> {
+            let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+            MOST_RUNNING.fetch_max(running, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            let package_dir = directory.join(format!("{}@{}", key.name, key.version));
+            fs::create_dir_all(&package_dir).unwrap();
+            fs::write(
+                package_dir.join("wapm.toml"),
+                format!(
+                    "[package]\nname = \"{}\"\nversion = \"{}\"\ndescription = \"\"\n",
+                    key.name, key.version
+                ),
+            )
+            .unwrap();
+            RUNNING.fetch_sub(1, Ordering::SeqCst);
+            Ok((key, package_dir, download_url.to_string(), 0))
+        }
+    }
+
+    #[test]
+    fn packages_install_concurrently_in_order() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let names: Vec<String> = (0..12).map(|i| format!("_/package{:02}", i)).collect();
+        let resolved_packages = ResolvedPackages {
+            packages: names
+                .iter()
+                .map(|name| {
+                    let key = WapmPackageKey {
+                        name: name.as_str().into(),
+                        version: semver::Version::new(1, 0, 0),
+                    };
+                    (key, (format!("https://registry.wapm.io/{}", name), None))
+                })
+                .collect(),
+        };
+
+        let installed = InstalledPackages::install::<SlowInstaller>(
+            tmp_dir.path(),
+            resolved_packages,
+            None,
+            true,
+        )
+        .unwrap();
+
+        let installed_names: Vec<&str> = installed
+            .packages
+            .iter()
+            .map(|(key, _, _, _)| key.name.as_ref())
+            .collect();
+        assert_eq!(names, installed_names);
+        let most_running = MOST_RUNNING.load(Ordering::SeqCst);
+        assert!(most_running > 1, "packages were installed one at a time");
+        assert!(most_running <= MAX_CONCURRENT_INSTALLS);
+    }
+
+    fn write_foo_tarball(path: &Path, wasm: &[u8]) -> String {
+        write_tarball(path, &[("foo.wasm", wasm)]);
+        reqwest::Url::from_file_path(path).unwrap().to_string()
+    }
+
+    #[test]
+    fn downloads_that_do_not_match_the_lockfile_are_not_installed() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let mut lockfile = foo_lockfile();
+        lockfile
+            .modules
+            .get_mut("_/foo")
+            .and_then(|versions| versions.values_mut().next())
+            .and_then(|modules| modules.get_mut("foo"))
+            .unwrap()
+            .integrity = Some(util::compute_integrity(b"\0asm").1);
+        let package_dir = tmp_dir.path().join("wapm_packages/_/foo@1.0.0");
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("foo.wasm"), b"\0asm").unwrap();
+        let install = |download_url: &str| {
+            RegistryInstaller::install_package(
+                tmp_dir.path(),
+                WapmPackageKey {
+                    name: "_/foo".into(),
+                    version: semver::Version::new(1, 0, 0),
+                },
+                download_url,
+                None,
+                Some(&lockfile),
+                true,
+            )
+        };
+
+        let republished =
+            write_foo_tarball(&tmp_dir.path().join("republished.tar.gz"), b"\0asm new");
+        match install(&republished) {
+            Err(Error::DownloadMismatch(key, _)) => assert_eq!("_/foo 1.0.0", key),
+            result => panic!("expected a download mismatch, got {:?}", result),
+        }
+        assert!(!package_dir.exists());
+        // nothing of the download is left behind
+        let namespace_dir = tmp_dir.path().join("wapm_packages/_");
+        assert_eq!(0, fs::read_dir(&namespace_dir).unwrap().count());
+
+        let locked = write_foo_tarball(&tmp_dir.path().join("locked.tar.gz"), b"\0asm");
+        let (_, installed_dir, _, _) = install(&locked).unwrap();
+        assert_eq!(package_dir, installed_dir);
+        assert_eq!(
+            b"\0asm".to_vec(),
+            fs::read(package_dir.join("foo.wasm")).unwrap()
+        );
+        assert_eq!(1, fs::read_dir(&namespace_dir).unwrap().count());
+    }
+}
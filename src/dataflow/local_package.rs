@@ -5,6 +5,7 @@ use crate::data::manifest::Manifest;
 use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
 use crate::dataflow::PackageKey;
 use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
 use thiserror::Error;
 
 #[derive(Clone, Debug, Error)]
@@ -19,7 +20,12 @@ pub struct LocalPackage<'a> {
 }
 
 impl<'a> LocalPackage<'a> {
-    pub fn new_from_local_package_in_manifest(manifest: &'a Manifest) -> Result<Self, Error> {
+    /// The modules and commands of the manifest's own package, with the commands of the enabled
+    /// features
+    pub fn new_from_local_package_in_manifest(
+        manifest: &'a Manifest,
+        features: &HashSet<String>,
+    ) -> Result<Self, Error> {
         let package_name = manifest.package.name.as_str();
         let package_version = &manifest.package.version;
         let modules = manifest
@@ -37,19 +43,46 @@ impl<'a> LocalPackage<'a> {
                 )
             })
             .collect();
-        let commands = manifest
-            .command
-            .as_ref()
-            .cloned()
-            .unwrap_or_default()
-            .into_iter()
-            .map(|c| LockfileCommand::from_command(package_name, package_version.clone(), &c))
-            .collect::<Result<Vec<LockfileCommand>, lockfile_command::Error>>()
-            .map_err(|e| Error::CouldNotExtractCommandsFromManifest(e))?;
+        let commands = LockfileCommand::from_manifest_with_features(
+            package_name,
+            package_version,
+            manifest,
+            features,
+        )
+        .map_err(Error::CouldNotExtractCommandsFromManifest)?;
         let key = PackageKey::new_registry_package(package_name, package_version.clone());
         let data = LockfilePackage { modules, commands };
         Ok(LocalPackage { key, data })
     }
+
+    /// Whether the modules or commands of the package differ from the lockfile, regardless of
+    /// their order
+    pub fn differs_from(&self, lockfile_packages: &LockfilePackages) -> bool {
+        match lockfile_packages.packages.get(&self.key) {
+            Some(locked) => sorted_contents(locked) != sorted_contents(&self.data),
+            None => !(self.data.modules.is_empty() && self.data.commands.is_empty()),
+        }
+    }
+
+    /// Whether the package has the same modules as in the lockfile, but different commands
+    pub fn differs_only_in_commands(&self, lockfile_packages: &LockfilePackages) -> bool {
+        match lockfile_packages.packages.get(&self.key) {
+            Some(locked) => {
+                let (locked_modules, locked_commands) = sorted_contents(locked);
+                let (modules, commands) = sorted_contents(&self.data);
+                locked_modules == modules && locked_commands != commands
+            }
+            None => false,
+        }
+    }
+}
+
+fn sorted_contents(package: &LockfilePackage) -> (Vec<LockfileModule>, Vec<LockfileCommand>) {
+    let mut modules = package.modules.clone();
+    modules.sort_by(|x, y| x.name.cmp(&y.name));
+    let mut commands = package.commands.clone();
+    commands.sort_by(|x, y| x.name.cmp(&y.name));
+    (modules, commands)
 }
 
 impl<'a> Into<LockfilePackages<'a>> for LocalPackage<'a> {
@@ -1,4 +1,4 @@
-use crate::data::lock::lockfile::Lockfile;
+use crate::data::lock::lockfile::{Lockfile, LockfileWarning};
 use crate::data::lock::lockfile_command::{Error, LockfileCommand};
 use crate::data::lock::lockfile_module::LockfileModule;
 use crate::data::lock::migrate::{
@@ -7,7 +7,8 @@ use crate::data::lock::migrate::{
 use crate::data::lock::LOCKFILE_NAME;
 use crate::dataflow::installed_packages::InstalledPackages;
 use crate::dataflow::removed_packages::RemovedPackages;
-use crate::dataflow::{PackageKey, WapmPackageKey};
+use crate::dataflow::{normalize_global_namespace_package_name, PackageKey, WapmPackageKey};
+use std::borrow::Cow;
 use std::collections::hash_map::HashMap;
 use std::collections::hash_set::HashSet;
 use std::fs;
@@ -20,12 +21,26 @@ pub enum LockfileError {
     LockfileTomlParseError(String),
     #[error("Could not parse lockfile because {0}.")]
     IoError(String),
+    #[error("Could not parse lockfile \"{path}\" because {message}. Did you modify the generated lockfile?")]
+    ParseError { path: String, message: String },
     #[error("Could not parse lockfile because of issue parsing command. {0}")]
     CommandPackageVersionParseError(Error),
+    #[error("The command \"{command}\" of the package \"{package}\" runs the module \"{module}\", which is not in the package.")]
+    MissingModuleForCommand {
+        package: String,
+        command: String,
+        module: String,
+    },
     #[error("Lockfile version is missing or invalid. Delete `wapm.lock`.")]
     InvalidOrMissingVersion,
     #[error("Lockfile version is too high, update wapm or delete `wapm.lock` and try again.")]
     VersionTooHigh,
+    #[error("Lockfile is invalid because {0}. Delete `wapm.lock` and try again.")]
+    InvalidLockfile(String),
+    #[error("{0}")]
+    UnsupportedVersion(#[source] crate::data::lock::lockfile::LockfileError),
+    #[error("The lockfile was generated for package \"{found}\", not \"{expected}\". Delete `wapm.lock` if it was copied from another project.")]
+    WrongLockfile { expected: String, found: String },
 }
 
 /// A ternary for a lockfile: Some, None, Error.
@@ -39,23 +54,29 @@ pub enum LockfileResult {
 impl LockfileResult {
     pub fn find_in_directory<P: AsRef<Path>>(directory: P) -> Self {
         let directory = directory.as_ref();
-        if !directory.is_dir() {
-            LockfileResult::LockfileError(LockfileError::IoError(
-                "Manifest must be a file named `wapm.toml`.".to_string(),
-            ));
-        }
         let lockfile_path_buf = directory.join(LOCKFILE_NAME);
         if !lockfile_path_buf.is_file() {
-            LockfileResult::LockfileError(LockfileError::IoError(
-                "Manifest must be a file named `wapm.toml`.".to_string(),
-            ));
+            return LockfileResult::NoLockfile;
         }
         let source = match fs::read_to_string(&lockfile_path_buf) {
             Ok(s) => s,
-            Err(_) => return LockfileResult::NoLockfile,
+            Err(e) => {
+                return LockfileResult::LockfileError(LockfileError::IoError(format!(
+                    "\"{}\" could not be read: {}",
+                    lockfile_path_buf.display(),
+                    e
+                )))
+            }
         };
         let mut lockfile_version = match LockfileVersion::from_lockfile_string(&source) {
             Ok(lv) => lv,
+            // the toml error message contains the line and column of the problem
+            Err(LockfileError::LockfileTomlParseError(message)) => {
+                return LockfileResult::LockfileError(LockfileError::ParseError {
+                    path: lockfile_path_buf.display().to_string(),
+                    message,
+                })
+            }
             Err(e) => return LockfileResult::LockfileError(e),
         };
         loop {
@@ -70,10 +91,53 @@ impl LockfileResult {
                 LockfileVersion::V3(lockfile_v3) => {
                     LockfileVersion::V4(convert_lockfilev3_to_v4(lockfile_v3, directory))
                 }
-                LockfileVersion::V4(lockfile_v4) => return LockfileResult::Lockfile(lockfile_v4),
+                LockfileVersion::V4(lockfile_v4) => {
+                    if let Err(
+                        e @ crate::data::lock::lockfile::LockfileError::UnsupportedVersion {
+                            ..
+                        },
+                    ) = lockfile_v4.check_cli_version()
+                    {
+                        return LockfileResult::LockfileError(LockfileError::UnsupportedVersion(e));
+                    }
+                    return match lockfile_v4.validate() {
+                        Ok(()) => LockfileResult::Lockfile(lockfile_v4),
+                        Err(e) => LockfileResult::LockfileError(LockfileError::InvalidLockfile(
+                            e.to_string(),
+                        )),
+                    };
+                }
             }
         }
     }
+
+    /// Turns a lockfile generated for another manifest package into a `WrongLockfile` error,
+    /// e.g. a lockfile copied from another project. The package version may differ, and
+    /// lockfiles that do not record their package are accepted.
+    pub fn for_root_package(self, package_name: &str) -> Self {
+        let found = match &self {
+            LockfileResult::Lockfile(lockfile) => match &lockfile.root_package {
+                Some(root_package) => root_package.clone(),
+                None => return self,
+            },
+            _ => return self,
+        };
+        // a root package that is not a `<name> <version>` key is not the package either
+        let is_root_package = found
+            .parse::<WapmPackageKey>()
+            .map(|key| {
+                normalize_global_namespace_package_name(key.name)
+                    == normalize_global_namespace_package_name(Cow::Borrowed(package_name))
+            })
+            .unwrap_or(false);
+        if is_root_package {
+            return self;
+        }
+        LockfileResult::LockfileError(LockfileError::WrongLockfile {
+            expected: package_name.to_string(),
+            found,
+        })
+    }
 }
 
 impl Default for LockfileResult {
@@ -100,12 +164,13 @@ impl<'a> LockfilePackages<'a> {
         installed_manifest_packages: &'a InstalledPackages<'a>,
     ) -> Result<Self, LockfileError> {
         let mut packages = HashMap::default();
-        for (k, manifest, download_url) in installed_manifest_packages.packages.iter() {
+        for (k, manifest, download_url, size) in installed_manifest_packages.packages.iter() {
             let modules: Vec<LockfileModule> = match manifest.module {
                 Some(ref modules) => modules
                     .iter()
-                    .map(|module| {
-                        LockfileModule::from_module(
+                    .map(|module| LockfileModule {
+                        size: Some(*size),
+                        ..LockfileModule::from_module(
                             &manifest.base_directory_path,
                             k.name.as_ref(),
                             &k.version,
@@ -116,17 +181,17 @@ impl<'a> LockfilePackages<'a> {
                     .collect(),
                 _ => vec![],
             };
-            let commands: Vec<LockfileCommand> = match manifest.command {
-                Some(ref modules) => {
-                    let commands = modules
-                        .iter()
-                        .map(|c| LockfileCommand::from_command(&k.name, k.version.clone(), c))
-                        .collect::<Result<Vec<LockfileCommand>, Error>>()
-                        .map_err(|e| LockfileError::CommandPackageVersionParseError(e))?;
-                    commands
-                }
-                _ => vec![],
-            };
+            let commands = LockfileCommand::from_dependency_manifest(&k.name, &k.version, manifest)
+                .map_err(|e| match e {
+                    Error::ModuleForCommandDoesNotExist(command, module) => {
+                        LockfileError::MissingModuleForCommand {
+                            package: format!("{}@{}", k.name, k.version),
+                            command,
+                            module,
+                        }
+                    }
+                    e => LockfileError::CommandPackageVersionParseError(e),
+                })?;
             packages.insert(
                 PackageKey::WapmPackage(k.clone()),
                 LockfilePackage { modules, commands },
@@ -145,8 +210,9 @@ impl<'a> LockfilePackages<'a> {
         }
     }
 
-    fn new_from_lockfile(lockfile: Lockfile) -> LockfilePackages<'a> {
-        let (raw_lockfile_modules, raw_lockfile_commands) = (lockfile.modules, lockfile.commands);
+    pub(crate) fn new_from_lockfile(lockfile: Lockfile) -> LockfilePackages<'a> {
+        let (raw_lockfile_modules, raw_lockfile_commands, raw_build_modules) =
+            (lockfile.modules, lockfile.commands, lockfile.build_modules);
 
         let mut lockfile_commands_map: HashMap<PackageKey, Vec<LockfileCommand>> = HashMap::new();
         for (_name, command) in raw_lockfile_commands {
@@ -159,8 +225,10 @@ impl<'a> LockfilePackages<'a> {
             command_vec.push(command);
         }
 
+        // build dependencies are packages like any other until the lockfile is generated again
         let packages: HashMap<PackageKey, LockfilePackage> = raw_lockfile_modules
             .into_iter()
+            .chain(raw_build_modules)
             .map(|(pkg_name, pkg_versions)| {
                 pkg_versions
                     .into_iter()
@@ -230,7 +298,217 @@ impl<'a> LockfilePackages<'a> {
         }
     }
 
+    /// A warning for each command of the packages that has the name of one of wapm's own
+    /// subcommands, sorted by command name, so it can be renamed or run with `wapm run`
+    pub fn shadowing_warnings(&self) -> Vec<LockfileWarning> {
+        let mut commands: Vec<&LockfileCommand> = self
+            .packages
+            .values()
+            .flat_map(|package| package.commands.iter())
+            .filter(|command| command.shadows_built_in())
+            .collect();
+        commands.sort_by(|a, b| a.name.cmp(&b.name));
+        commands
+            .into_iter()
+            .map(|command| LockfileWarning::ShadowedCommand {
+                command: command.name.clone(),
+                package: format!("{} {}", command.package_name, command.package_version),
+            })
+            .collect()
+    }
+
     pub fn extend(&mut self, other_packages: LockfilePackages<'a>) {
         self.packages.extend(other_packages.packages);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::data::lock::lockfile::LockfileWarning;
+    use crate::data::lock::{lockfile, LOCKFILE_NAME, LOCKFILE_VERSION};
+    use crate::data::manifest::Manifest;
+    use crate::dataflow::installed_packages::InstalledPackages;
+    use crate::dataflow::lockfile_packages::{LockfileError, LockfilePackages, LockfileResult};
+    use crate::dataflow::WapmPackageKey;
+    use std::fs;
+
+    #[test]
+    fn missing_lockfile() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        match LockfileResult::find_in_directory(tmp_dir.path()) {
+            LockfileResult::NoLockfile => {}
+            otherwise => panic!("expected no lockfile, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn command_for_a_missing_module_names_the_module() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "_/foo"
+            version = "1.0.0"
+            description = ""
+
+            [[module]]
+            name = "foo"
+            source = "foo.wasm"
+            abi = "wasi"
+
+            [[command]]
+            name = "bar"
+            module = "bar"
+            "#,
+        )
+        .unwrap();
+        let installed_packages = InstalledPackages {
+            packages: vec![(
+                WapmPackageKey {
+                    name: "_/foo".into(),
+                    version: semver::Version::new(1, 0, 0),
+                },
+                manifest,
+                "https://registry.wapm.io/foo-1.0.0.tar.gz".to_string(),
+                0,
+            )],
+        };
+        match LockfilePackages::from_installed_packages(&installed_packages) {
+            Err(LockfileError::MissingModuleForCommand {
+                package,
+                command,
+                module,
+            }) => {
+                assert_eq!("_/foo@1.0.0", package);
+                assert_eq!("bar", command);
+                assert_eq!("bar", module);
+            }
+            otherwise => panic!("expected a missing module, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn commands_named_like_subcommands_are_warned_about() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "_/foo"
+            version = "1.0.0"
+            description = ""
+
+            [[module]]
+            name = "foo"
+            source = "foo.wasm"
+            abi = "wasi"
+
+            [[command]]
+            name = "install"
+            module = "foo"
+
+            [[command]]
+            name = "foo"
+            module = "foo"
+            "#,
+        )
+        .unwrap();
+        let installed_packages = InstalledPackages {
+            packages: vec![(
+                WapmPackageKey {
+                    name: "_/foo".into(),
+                    version: semver::Version::new(1, 0, 0),
+                },
+                manifest,
+                "https://registry.wapm.io/foo-1.0.0.tar.gz".to_string(),
+                0,
+            )],
+        };
+        let lockfile_packages =
+            LockfilePackages::from_installed_packages(&installed_packages).unwrap();
+
+        assert_eq!(
+            vec![LockfileWarning::ShadowedCommand {
+                command: "install".to_string(),
+                package: "_/foo 1.0.0".to_string(),
+            }],
+            lockfile_packages.shadowing_warnings()
+        );
+    }
+
+    #[test]
+    fn parse_error_contains_path_and_line() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let lockfile_path = tmp_dir.path().join(LOCKFILE_NAME);
+        fs::write(&lockfile_path, "# Lockfile v4\n[modules]\n\n[commands\n").unwrap();
+        match LockfileResult::find_in_directory(tmp_dir.path()) {
+            LockfileResult::LockfileError(e @ LockfileError::ParseError { .. }) => {
+                let message = e.to_string();
+                assert!(message.contains(&lockfile_path.display().to_string()));
+                assert!(message.contains("line 4"), "{}", message);
+            }
+            otherwise => panic!("expected a parse error, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn unsupported_versions_keep_the_lockfile_error() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(
+            tmp_dir.path().join(LOCKFILE_NAME),
+            "# Lockfile v4\nmin_cli_version = \"999.0.0\"\n[modules]\n\n[commands]\n",
+        )
+        .unwrap();
+        match LockfileResult::find_in_directory(tmp_dir.path()) {
+            LockfileResult::LockfileError(e @ LockfileError::UnsupportedVersion(_)) => {
+                let source = std::error::Error::source(&e)
+                    .and_then(|source| source.downcast_ref::<lockfile::LockfileError>())
+                    .unwrap();
+                assert_eq!(22, source.exit_code());
+            }
+            otherwise => panic!("expected an unsupported version, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn older_lockfile_is_migrated_and_saved_with_current_version() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let lockfile_path = tmp_dir.path().join(LOCKFILE_NAME);
+        let v3_lockfile_toml = toml! {
+            [modules."_/sqlite"."0.1.1".sqlite]
+            name = "sqlite"
+            package_version = "0.1.1"
+            package_name = "_/sqlite"
+            source = "registry+sqlite"
+            resolved = "https://registry-cdn.wapm.dev/packages/_/sqlite/sqlite-0.1.1.tar.gz"
+            abi = "emscripten"
+            entry = "wapm_packages/_/sqlite@0.1.1/sqlite.wasm"
+            root = "wapm_packages/_/sqlite@0.1.1"
+            [commands.sqlite]
+            name = "sqlite"
+            package_name = "_/sqlite"
+            package_version = "0.1.1"
+            module = "sqlite"
+            is_top_level_dependency = true
+        };
+        fs::write(
+            &lockfile_path,
+            format!("# Lockfile v3\n{}", v3_lockfile_toml),
+        )
+        .unwrap();
+
+        let lockfile = match LockfileResult::find_in_directory(tmp_dir.path()) {
+            LockfileResult::Lockfile(lockfile) => lockfile,
+            otherwise => panic!("expected a lockfile, got {:?}", otherwise),
+        };
+        let module = lockfile
+            .get_module("_/sqlite", &semver::Version::new(0, 1, 1), "sqlite")
+            .unwrap();
+        assert_eq!("sqlite.wasm", module.source);
+
+        lockfile.save(tmp_dir.path()).unwrap();
+        let saved_lockfile = fs::read_to_string(&lockfile_path).unwrap();
+        assert!(saved_lockfile.starts_with(&format!("# Lockfile v{}\n", LOCKFILE_VERSION)));
+        match LockfileResult::find_in_directory(tmp_dir.path()) {
+            LockfileResult::Lockfile(reopened_lockfile) => assert_eq!(lockfile, reopened_lockfile),
+            otherwise => panic!("expected a lockfile, got {:?}", otherwise),
+        }
+    }
+}
@@ -1,9 +1,16 @@
-use crate::data::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::data::lock::lockfile::LockfileError;
+use crate::data::manifest::{Dependency, Manifest, MANIFEST_FILE_NAME};
 use crate::dataflow::added_packages::AddedPackages;
 use crate::dataflow::removed_packages::RemovedPackages;
-use crate::dataflow::{normalize_global_namespace, PackageKey, WapmPackageKey};
+use crate::dataflow::{
+    normalize_global_namespace, normalize_global_namespace_package_name, PackageKey, UpdateOptions,
+    WapmPackageKey, WapmPackageRange,
+};
 use semver::{Version, VersionReq};
+use std::borrow::Cow;
+use std::collections::hash_map::HashMap;
 use std::collections::hash_set::HashSet;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 use thiserror::Error;
@@ -18,6 +25,129 @@ pub enum Error {
         "Version {0} for package {1} must be a semantic version or a semantic version requirement."
     )]
     SemVerError(String, String),
+    #[error("The feature \"{0}\" is not defined in the manifest.")]
+    UnknownFeature(String),
+    #[error(
+        "The feature \"{0}\" enables \"{1}\", which is not an optional dependency in the manifest."
+    )]
+    UnknownOptionalDependency(String, String),
+    #[error("The manifest is invalid: {0}.")]
+    InvalidManifest(String),
+    #[error("Package \"{name}\" depends on itself.")]
+    SelfDependency { name: String },
+    #[error("The version of dependency \"{name}\" is empty. Use \"*\" to allow any version.")]
+    EmptyVersion { name: String },
+}
+
+/// The version requirements of the packages that are required more than once, e.g. as a
+/// dependency and as a dev dependency, with the dependent that imposed each requirement
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VersionRequirements {
+    /// package name -> requirements and their dependents
+    pub packages: BTreeMap<String, Vec<(PackageKey<'static>, String)>>,
+}
+
+impl VersionRequirements {
+    /// Collect the requirements the same way `ManifestPackages` does. Requirements from the
+    /// manifest are imposed by the manifest package and name the section that lists them.
+    pub fn from_manifest_and_added_packages(
+        manifest: &Manifest,
+        added_packages: &AddedPackages,
+        options: &UpdateOptions,
+    ) -> Result<Self, Error> {
+        let dependent = format!("{}@{}", manifest.package.name, manifest.package.version);
+        let mut packages: BTreeMap<String, Vec<(PackageKey<'static>, String)>> = BTreeMap::new();
+        for (key, section) in
+            ManifestPackages::extract_owned_package_keys_by_section(manifest, options)?
+        {
+            if is_added(&key, added_packages) {
+                continue;
+            }
+            packages
+                .entry(key.name().to_string())
+                .or_default()
+                .push((key, format!("{} [{}]", dependent, section)));
+        }
+        let overrides = manifest.normalized_overrides();
+        for key in added_packages.packages.iter().cloned() {
+            packages.entry(key.name().to_string()).or_default().push((
+                apply_override(key, &overrides).into_owned(),
+                "the added packages".to_string(),
+            ));
+        }
+        packages.retain(|_, requirements| requirements.len() > 1);
+        Ok(Self { packages })
+    }
+
+    pub fn remove_packages(&mut self, removed_packages: &RemovedPackages) {
+        for name in removed_packages.packages.iter() {
+            self.packages
+                .remove(normalize_global_namespace_package_name(name.clone()).as_ref());
+        }
+    }
+
+    /// Errors unless one of the `versions` of each package satisfies all of its requirements.
+    /// The versions are the ones an update resolved or kept from the lockfile, packages without
+    /// any are not checked.
+    pub fn check(&self, versions: &[WapmPackageKey]) -> Result<(), LockfileError> {
+        for (name, requirements) in self.packages.iter() {
+            let mut package_versions = versions
+                .iter()
+                .filter(|key| key.name == name.as_str())
+                .map(|key| &key.version)
+                .peekable();
+            if package_versions.peek().is_none() {
+                continue;
+            }
+            let satisfied = package_versions.any(|version| {
+                requirements
+                    .iter()
+                    .all(|(requirement, _)| match requirement {
+                        PackageKey::WapmPackage(key) => key.version == *version,
+                        PackageKey::WapmPackageRange(range) => range.version_req.matches(version),
+                    })
+            });
+            if !satisfied {
+                let mut requirements: Vec<String> = requirements
+                    .iter()
+                    .map(|(requirement, dependent)| {
+                        let version = match requirement {
+                            PackageKey::WapmPackage(key) => key.version.to_string(),
+                            PackageKey::WapmPackageRange(range) => range.version_req.to_string(),
+                        };
+                        format!("{} requires {}", dependent, version)
+                    })
+                    .collect();
+                requirements.sort();
+                return Err(LockfileError::VersionConflict {
+                    package: name.clone(),
+                    requirements,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The exact key of the override version, if the manifest overrides the package
+fn apply_override<'a>(key: PackageKey<'a>, overrides: &HashMap<String, Version>) -> PackageKey<'a> {
+    let version = overrides
+        .get(normalize_global_namespace_package_name(Cow::Borrowed(key.name())).as_ref())
+        .cloned();
+    match (key, version) {
+        (PackageKey::WapmPackage(WapmPackageKey { name, .. }), Some(version))
+        | (PackageKey::WapmPackageRange(WapmPackageRange { name, .. }), Some(version)) => {
+            PackageKey::WapmPackage(WapmPackageKey { name, version })
+        }
+        (key, None) => key,
+    }
+}
+
+fn is_added(key: &PackageKey, added_packages: &AddedPackages) -> bool {
+    added_packages
+        .packages
+        .iter()
+        .any(|added| added.name() == key.name())
 }
 
 /// A ternary for a manifest: Some, None, Error.
@@ -48,7 +178,7 @@ impl ManifestResult {
         };
         match toml::from_str::<Manifest>(&source) {
             Ok(mut m) => {
-                m.base_directory_path = directory.to_owned();
+                m.set_base_directory(directory);
                 ManifestResult::Manifest(m)
             }
             Err(e) => ManifestResult::ManifestError(Error::ManifestTomlParseError(e.to_string())),
@@ -63,19 +193,31 @@ pub struct ManifestPackages<'a> {
 }
 
 impl<'a> ManifestPackages<'a> {
-    /// Construct package keys from the manifest and any other additional packages.
+    /// Construct package keys from the manifest and any other additional packages. Optional
+    /// dependencies are only included when one of the enabled features lists them, and dev
+    /// dependencies unless the options exclude them.
     /// Short-hand package names are transformed.
     pub fn new_from_manifest_and_added_packages(
         manifest: &'a Manifest,
         added_packages: &AddedPackages<'a>,
+        options: &UpdateOptions,
     ) -> Result<Self, Error> {
-        let packages = Self::extract_package_keys(&manifest)?;
+        let packages = Self::extract_package_keys(&manifest, options)?;
         let mut packages: HashSet<PackageKey> = packages
             .into_iter()
             .map(normalize_global_namespace)
             .collect();
 
-        packages.extend(added_packages.packages.iter().cloned());
+        // an added package replaces the manifest's requirement on it, unless it is overridden
+        let overrides = manifest.normalized_overrides();
+        packages.retain(|key| !is_added(key, added_packages));
+        packages.extend(
+            added_packages
+                .packages
+                .iter()
+                .cloned()
+                .map(|key| apply_override(key, &overrides)),
+        );
         Ok(Self { packages })
     }
 
@@ -83,7 +225,16 @@ impl<'a> ManifestPackages<'a> {
         self.packages.iter().cloned().collect()
     }
 
-    pub fn remove_packages(&mut self, removed_packages: &'a RemovedPackages<'a>) {
+    /// Returns true if the exact lockfile key is requested by the manifest, either as an exact
+    /// version or as a version that satisfies one of the manifest's version ranges.
+    pub fn includes(&self, lockfile_key: &PackageKey<'a>) -> bool {
+        self.packages.iter().any(|package_key| match package_key {
+            PackageKey::WapmPackage(_) => package_key == lockfile_key,
+            PackageKey::WapmPackageRange(range) => lockfile_key.matches(range),
+        })
+    }
+
+    pub fn remove_packages(&mut self, removed_packages: &RemovedPackages<'a>) {
         let removed_package_keys = removed_packages
             .packages
             .iter()
@@ -94,7 +245,9 @@ impl<'a> ManifestPackages<'a> {
                     .cloned()
                     .filter(|package_key| match package_key {
                         PackageKey::WapmPackage(WapmPackageKey { name, .. }) => name == &pkg_name,
-                        _ => false,
+                        PackageKey::WapmPackageRange(WapmPackageRange { name, .. }) => {
+                            name == &pkg_name
+                        }
                     })
                     .collect::<Vec<_>>()
             })
@@ -107,26 +260,148 @@ impl<'a> ManifestPackages<'a> {
     }
 
     /// Extract package keys from the manifest
-    fn extract_package_keys(manifest: &'a Manifest) -> Result<Vec<PackageKey<'a>>, Error> {
-        match manifest.dependencies {
-            Some(ref dependencies) => {
-                let result = dependencies
+    fn extract_package_keys(
+        manifest: &'a Manifest,
+        options: &UpdateOptions,
+    ) -> Result<Vec<PackageKey<'a>>, Error> {
+        Ok(Self::extract_package_keys_by_section(manifest, options)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect())
+    }
+
+    /// Extract package keys from the manifest, with the manifest section that lists each of them
+    fn extract_package_keys_by_section(
+        manifest: &'a Manifest,
+        options: &UpdateOptions,
+    ) -> Result<Vec<(PackageKey<'a>, &'static str)>, Error> {
+        Self::check_self_dependency(manifest)?;
+        let mut result = match manifest.dependencies {
+            // git dependencies are planned separately, see `GitDependency`
+            Some(ref dependencies) => dependencies
+                .iter()
+                .filter_map(|(name, dependency)| match dependency {
+                    Dependency::Version(version) => Some((name.as_str(), version.as_str())),
+                    Dependency::Git { .. } => None,
+                })
+                .map(|pair| Self::parse_wapm_package_key(pair).map(|key| (key, "dependencies")))
+                .collect::<Result<Vec<_>, Error>>()?,
+            None => vec![],
+        };
+        for key in Self::extract_optional_package_keys(manifest, &options.features)? {
+            result.push((key, "optional-dependencies"));
+        }
+        if !options.exclude_dev_dependencies {
+            for (name, version) in manifest.dev_dependencies.iter().flatten() {
+                result.push((
+                    Self::parse_wapm_package_key((name, version))?,
+                    "dev-dependencies",
+                ));
+            }
+        }
+        for (name, version) in manifest.build_dependencies.iter().flatten() {
+            result.push((
+                Self::parse_wapm_package_key((name, version))?,
+                "build-dependencies",
+            ));
+        }
+        // overrides replace the requirement of every section on the package
+        let overrides = manifest.normalized_overrides();
+        Ok(result
+            .into_iter()
+            .map(|(key, section)| (apply_override(key, &overrides), section))
+            .collect())
+    }
+
+    /// A package listing itself in any dependency section would be resolved and installed into
+    /// its own `wapm_packages`, so it is an error.
+    fn check_self_dependency(manifest: &Manifest) -> Result<(), Error> {
+        let package_name =
+            normalize_global_namespace_package_name(Cow::Borrowed(&manifest.package.name));
+        let dependency_names = manifest
+            .dependencies
+            .iter()
+            .flat_map(HashMap::keys)
+            .chain(
+                manifest
+                    .optional_dependencies
                     .iter()
-                    .map(|(name, value)| (name.as_str(), value.as_str()))
-                    .map(Self::parse_wapm_package_key)
-                    .collect::<Result<Vec<_>, Error>>()?;
-                Ok(result)
+                    .flat_map(HashMap::keys),
+            )
+            .chain(manifest.dev_dependencies.iter().flat_map(HashMap::keys))
+            .chain(manifest.build_dependencies.iter().flat_map(HashMap::keys));
+        for name in dependency_names {
+            if normalize_global_namespace_package_name(Cow::Borrowed(name)) == package_name {
+                return Err(Error::SelfDependency { name: name.clone() });
             }
-            None => Ok(vec![]),
         }
+        Ok(())
+    }
+
+    /// Like `extract_package_keys_by_section`, but with short-hand package names transformed and
+    /// the names copied, so the keys do not borrow from the manifest. Used where dependencies
+    /// are collected or combined with other packages after the manifest is gone.
+    pub fn extract_owned_package_keys_by_section(
+        manifest: &Manifest,
+        options: &UpdateOptions,
+    ) -> Result<Vec<(PackageKey<'static>, &'static str)>, Error> {
+        Ok(
+            ManifestPackages::extract_package_keys_by_section(manifest, options)?
+                .into_iter()
+                .map(|(key, section)| (normalize_global_namespace(key).into_owned(), section))
+                .collect(),
+        )
+    }
+
+    /// The optional dependencies enabled by the features, every feature in the manifest must
+    /// list known optional dependencies even when it is disabled.
+    fn extract_optional_package_keys(
+        manifest: &'a Manifest,
+        features: &HashSet<String>,
+    ) -> Result<Vec<PackageKey<'a>>, Error> {
+        let no_features = HashMap::new();
+        let manifest_features = manifest.features.as_ref().unwrap_or(&no_features);
+        let is_optional_dependency = |name: &String| {
+            manifest
+                .optional_dependencies
+                .as_ref()
+                .is_some_and(|optional_dependencies| optional_dependencies.contains_key(name))
+        };
+        for (feature, dependencies) in manifest_features.iter() {
+            if let Some(dependency) = dependencies.iter().find(|d| !is_optional_dependency(d)) {
+                return Err(Error::UnknownOptionalDependency(
+                    feature.clone(),
+                    dependency.clone(),
+                ));
+            }
+        }
+        let mut enabled: HashSet<&str> = HashSet::new();
+        for feature in features {
+            let dependencies = manifest_features
+                .get(feature)
+                .ok_or_else(|| Error::UnknownFeature(feature.clone()))?;
+            enabled.extend(dependencies.iter().map(|d| d.as_str()));
+        }
+        manifest
+            .optional_dependencies
+            .iter()
+            .flatten()
+            .filter(|(name, _)| enabled.contains(name.as_str()))
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .map(Self::parse_wapm_package_key)
+            .collect()
     }
 
     /// Parse a raw pair of strings as an exact wapm package or a range. May fail with a semver
-    /// error.
-    fn parse_wapm_package_key(
+    /// error. An empty version is an error rather than any version, `"*"` asks for any version.
+    pub(crate) fn parse_wapm_package_key(
         (name, version): (&'a str, &'a str),
     ) -> Result<PackageKey<'a>, Error> {
-        if let Ok(version) = Version::parse(version) {
+        if version.trim().is_empty() {
+            Err(Error::EmptyVersion {
+                name: name.to_string(),
+            })
+        } else if let Ok(version) = Version::parse(version) {
             Ok(PackageKey::new_registry_package(name, version))
         } else if let Ok(version_req) = VersionReq::parse(version) {
             Ok(PackageKey::new_registry_package_range(name, version_req))
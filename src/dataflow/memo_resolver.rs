@@ -0,0 +1,137 @@
+use crate::data::lock::lockfile::Lockfile;
+use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage};
+use crate::dataflow::{PackageKey, WapmPackageKey};
+use semver::Version;
+use std::borrow::Cow::Owned;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// The Memo Resolver wraps another resolver and remembers what each package key resolved to, so a
+/// package requested more than once during an update is only resolved by the wrapped resolver once.
+pub struct MemoResolver<'r, R> {
+    resolver: &'r R,
+    cache: RefCell<HashMap<PackageKey<'static>, ResolvedPackage<'static>>>,
+}
+
+impl<'r, R> MemoResolver<'r, R> {
+    pub fn new(resolver: &'r R) -> Self {
+        Self {
+            resolver,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl<'a, 'r, R: for<'b> Resolve<'b>> Resolve<'a> for MemoResolver<'r, R> {
+    fn sync_packages(
+        &self,
+        added_packages: Vec<PackageKey<'a>>,
+    ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+        let mut cache = self.cache.borrow_mut();
+        let mut uncached_packages: Vec<PackageKey<'static>> = vec![];
+        for key in added_packages.iter().cloned().map(PackageKey::into_owned) {
+            if !cache.contains_key(&key) && !uncached_packages.contains(&key) {
+                uncached_packages.push(key);
+            }
+        }
+
+        if !uncached_packages.is_empty() {
+            let resolved_packages = self.resolver.sync_packages(uncached_packages.clone())?;
+            for requested in uncached_packages {
+                if let Some((key, data)) = resolved_packages
+                    .iter()
+                    .find(|(resolved, _)| requested.is_resolved_by(resolved))
+                {
+                    let key = WapmPackageKey {
+                        name: Owned(key.name.to_string()),
+                        version: key.version.clone(),
+                    };
+                    cache.insert(requested, (key, data.clone()));
+                }
+            }
+        }
+
+        // unresolved packages are left out, the same as the wrapped resolver does
+        let mut packages: Vec<ResolvedPackage<'a>> = vec![];
+        for requested in added_packages {
+            if let Some(resolved) = cache.get(&requested.into_owned()) {
+                if !packages.iter().any(|(key, _)| *key == resolved.0) {
+                    packages.push(resolved.clone());
+                }
+            }
+        }
+        Ok(packages)
+    }
+
+    fn preload(&self, lockfile: &Lockfile, packages: &[PackageKey<'a>]) {
+        self.resolver.preload(lockfile, packages)
+    }
+
+    fn available_versions(&self, name: &str) -> Result<Vec<Version>, Error> {
+        self.resolver.available_versions(name)
+    }
+
+    fn is_yanked(&self, name: &str, version: &Version) -> Result<bool, Error> {
+        self.resolver.is_yanked(name, version)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dataflow::memo_resolver::MemoResolver;
+    use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage};
+    use crate::dataflow::{PackageKey, WapmPackageKey, WapmPackageRange};
+    use std::cell::Cell;
+
+    /// A test resolver that resolves every package to version 1.0.0 and counts the packages it was
+    /// asked to resolve.
+    #[derive(Default)]
+    struct CountingResolver {
+        resolved_count: Cell<usize>,
+    }
+
+    impl<'a> Resolve<'a> for CountingResolver {
+        fn sync_packages(
+            &self,
+            added_packages: Vec<PackageKey<'a>>,
+        ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+            self.resolved_count
+                .set(self.resolved_count.get() + added_packages.len());
+            Ok(added_packages
+                .into_iter()
+                .map(|k| match k {
+                    PackageKey::WapmPackage(WapmPackageKey { name, .. })
+                    | PackageKey::WapmPackageRange(WapmPackageRange { name, .. }) => (
+                        WapmPackageKey {
+                            name,
+                            version: semver::Version::new(1, 0, 0),
+                        },
+                        ("url".to_string(), None),
+                    ),
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn duplicate_packages_are_resolved_once() {
+        let counting_resolver = CountingResolver::default();
+        let resolver = MemoResolver::new(&counting_resolver);
+        let foo = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0));
+        let bar = PackageKey::new_registry_package_range(
+            "_/bar",
+            semver::VersionReq::parse("^1").unwrap(),
+        );
+
+        let resolved = resolver
+            .sync_packages(vec![foo.clone(), bar.clone(), foo.clone()])
+            .unwrap();
+        assert_eq!(2, resolved.len());
+        assert_eq!(2, counting_resolver.resolved_count.get());
+
+        let resolved = resolver.sync_packages(vec![bar, foo]).unwrap();
+        assert_eq!(2, resolved.len());
+        assert_eq!("_/bar 1.0.0", resolved[0].0.to_string());
+        assert_eq!(2, counting_resolver.resolved_count.get());
+    }
+}
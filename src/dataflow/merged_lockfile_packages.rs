@@ -1,10 +1,15 @@
-use crate::data::lock::lockfile::{CommandMap, Lockfile, ModuleMap};
+use crate::data::lock::lockfile::{CommandMap, Lockfile, LockfileMetadata, ModuleMap};
+use crate::data::manifest::PACKAGES_DIR_NAME;
 use crate::dataflow::bin_script::save_bin_script;
 use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
+use crate::dataflow::manifest_packages::ManifestResult;
 use crate::dataflow::retained_lockfile_packages::RetainedLockfilePackages;
-use crate::dataflow::{PackageKey, WapmPackageKey};
+use crate::dataflow::{normalize_global_namespace_package_name, PackageKey, WapmPackageKey};
+use semver::Version;
+use std::borrow::Cow;
 use std::collections::btree_map::BTreeMap;
 use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
 use std::path::Path;
 use thiserror::Error;
 
@@ -12,6 +17,15 @@ use thiserror::Error;
 pub enum Error {
     #[error("Could not save generated lockfile because {0}.")]
     FailedToSaveLockfile(String),
+    #[error("Command \"{command}\" is provided by more than one package: {}", .packages.join(", "))]
+    CommandConflict {
+        command: String,
+        packages: Vec<String>,
+    },
+    #[error(
+        "Command alias \"{alias}\" refers to command \"{command}\", which no package provides"
+    )]
+    UnknownCommandAlias { alias: String, command: String },
 }
 
 /// Merge two sets, and keep upgraded packages and all other unchanged packages.
@@ -19,6 +33,10 @@ pub enum Error {
 #[derive(Clone, Debug)]
 pub struct MergedLockfilePackages<'a> {
     pub packages: HashMap<PackageKey<'a>, LockfilePackage>,
+    /// The packages that are only build dependencies, locked as `build_modules`
+    pub build_packages: HashSet<PackageKey<'a>>,
+    /// Package name -> override version, for the overridden packages, see `set_overrides`
+    pub overrides: BTreeMap<String, Version>,
 }
 
 impl<'a> MergedLockfilePackages<'a> {
@@ -46,16 +64,210 @@ impl<'a> MergedLockfilePackages<'a> {
             .map(|(_, (key, data))| (key, data))
             .collect();
 
-        Self { packages }
+        Self {
+            packages,
+            build_packages: HashSet::new(),
+            overrides: BTreeMap::new(),
+        }
+    }
+
+    /// Record the packages with these normalized names as build dependencies. Their modules are
+    /// locked apart from the runtime modules and their commands are dropped, so no command runs a
+    /// build tool.
+    pub fn set_build_only(&mut self, build_only_names: &HashSet<String>) {
+        self.build_packages.clear();
+        for (key, data) in self.packages.iter_mut() {
+            let name = normalize_global_namespace_package_name(Cow::Borrowed(key.name()));
+            if build_only_names.contains(name.as_ref()) {
+                data.commands.clear();
+                self.build_packages.insert(key.clone());
+            }
+        }
+    }
+
+    /// Record the packages that are locked at the version the manifest overrides them with, by
+    /// normalized package name, so the lockfile notes why they are not at a version their
+    /// requirements ask for
+    pub fn set_overrides(&mut self, overrides: &HashMap<String, Version>) {
+        self.overrides = self
+            .packages
+            .keys()
+            .filter_map(|key| match key {
+                PackageKey::WapmPackage(key) => Some(key),
+                PackageKey::WapmPackageRange(_) => None,
+            })
+            .filter_map(|key| {
+                let name = normalize_global_namespace_package_name(key.name.clone());
+                overrides
+                    .get(name.as_ref())
+                    .filter(|version| **version == key.version)
+                    .map(|version| (key.name.to_string(), version.clone()))
+            })
+            .collect();
+    }
+
+    /// Record why every other package is in the lockfile. Packages in a manifest project are all
+    /// direct dependencies of the manifest's package, and also depend on each other: a package
+    /// whose installed manifest in the directory depends on another locked package is one of its
+    /// dependents, so the dependents form the chains `Lockfile::check_depth` limits.
+    pub fn set_dependents(&mut self, root: &WapmPackageKey, directory: &Path) {
+        let root_key = PackageKey::WapmPackage(root.clone());
+        let root = format!("{}@{}", root.name, root.version);
+        // package name -> the locked packages whose manifest depends on it
+        let mut required_by: HashMap<String, Vec<String>> = HashMap::new();
+        for (key, data) in self.packages.iter() {
+            let key = match key {
+                PackageKey::WapmPackage(package_key) if *key != root_key => package_key,
+                _ => continue,
+            };
+            let package_path = match data.modules.first() {
+                Some(module) => &module.package_path,
+                None => continue,
+            };
+            let package_dir = directory.join(PACKAGES_DIR_NAME).join(package_path);
+            if let ManifestResult::Manifest(manifest) =
+                ManifestResult::find_in_directory(&package_dir)
+            {
+                for name in manifest.dependencies.iter().flatten().map(|(name, _)| name) {
+                    let name = normalize_global_namespace_package_name(Cow::Borrowed(name));
+                    required_by
+                        .entry(name.into_owned())
+                        .or_default()
+                        .push(format!("{}@{}", key.name, key.version));
+                }
+            }
+        }
+        for (key, data) in self.packages.iter_mut() {
+            if *key == root_key {
+                continue;
+            }
+            let mut dependents = vec![root.clone()];
+            dependents.extend(required_by.get(key.name()).cloned().unwrap_or_default());
+            dependents.sort();
+            dependents.dedup();
+            for module in data.modules.iter_mut() {
+                module.dependents = dependents.clone();
+            }
+        }
     }
 
-    pub fn generate_lockfile(self, directory: &'a Path) -> Result<(), Error> {
+    /// Installs commands under the aliases of the manifest, alias -> command or
+    /// `package::command`. The command keeps its module and records its original name. Aliases of
+    /// the previous lockfile are undone first, so removing an alias restores the command's name.
+    pub fn apply_command_aliases(
+        &mut self,
+        aliases: &HashMap<String, String>,
+    ) -> Result<(), Error> {
+        for package in self.packages.values_mut() {
+            for command in package.commands.iter_mut() {
+                if let Some(original_name) = command.original_name.take() {
+                    command.name = original_name;
+                }
+            }
+        }
+        // sort so that errors are stable
+        let mut aliases: Vec<(&String, &String)> = aliases.iter().collect();
+        aliases.sort();
+        for (alias, target) in aliases {
+            let (package_name, command_name) = match target.rfind("::") {
+                Some(index) => (Some(&target[..index]), &target[index + 2..]),
+                None => (None, target.as_str()),
+            };
+            let mut found = false;
+            for (key, package) in self.packages.iter_mut() {
+                let in_package = match (key, package_name) {
+                    (_, None) => true,
+                    (PackageKey::WapmPackage(key), Some(package_name)) => {
+                        key.name == package_name || key.name == format!("_/{}", package_name)
+                    }
+                    (PackageKey::WapmPackageRange(_), Some(_)) => false,
+                };
+                if !in_package {
+                    continue;
+                }
+                for command in package.commands.iter_mut().filter(|command| {
+                    command.name == command_name && command.original_name.is_none()
+                }) {
+                    command.original_name = Some(command.name.clone());
+                    command.name = alias.clone();
+                    found = true;
+                }
+            }
+            if !found {
+                return Err(Error::UnknownCommandAlias {
+                    alias: alias.clone(),
+                    command: target.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Error if two different packages provide a command with the same name, instead of letting
+    /// whichever package is inserted last silently win.
+    fn detect_command_conflicts(&self) -> Result<(), Error> {
+        let mut command_packages: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for (key, package) in self.packages.iter() {
+            for command in package.commands.iter() {
+                command_packages
+                    .entry(command.name.as_str())
+                    .or_default()
+                    .push(key.to_string());
+            }
+        }
+        match command_packages
+            .into_iter()
+            .find(|(_, packages)| packages.len() > 1)
+        {
+            Some((command, mut packages)) => {
+                // sort so that the error message is stable
+                packages.sort();
+                Err(Error::CommandConflict {
+                    command: command.to_string(),
+                    packages,
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Saves the lockfile and the bin scripts of its commands. Manifest projects pass the
+    /// `Manifest::dependencies_hash` and the package key of the manifest the lockfile is
+    /// generated from.
+    pub fn generate_lockfile(
+        self,
+        directory: &'a Path,
+        dependencies_hash: Option<String>,
+        root_package: Option<String>,
+    ) -> Result<(), Error> {
+        let lockfile = self.build_lockfile(dependencies_hash, root_package)?;
+        save_lockfile(directory, &lockfile)
+    }
+
+    /// Like `generate_lockfile`, but returns the lockfile instead of saving it, e.g. to check it
+    /// first. Nothing is written, see `save_lockfile`.
+    pub fn build_lockfile(
+        self,
+        dependencies_hash: Option<String>,
+        root_package: Option<String>,
+    ) -> Result<Lockfile, Error> {
+        self.detect_command_conflicts()?;
         let mut modules: ModuleMap = BTreeMap::new();
+        let mut build_modules: ModuleMap = BTreeMap::new();
         let mut commands: CommandMap = BTreeMap::new();
-        for (key, package) in self.packages {
+        // add the packages in a stable order, so the lockfile never depends on the order they were
+        // collected in
+        let mut packages: Vec<_> = self.packages.into_iter().collect();
+        packages.sort_by(|(a, _), (b, _)| a.cmp_by_name_and_version(b));
+        for (key, package) in packages {
+            let module_map = if self.build_packages.contains(&key) {
+                &mut build_modules
+            } else {
+                &mut modules
+            };
             match key {
                 PackageKey::WapmPackage(WapmPackageKey { name, version }) => {
-                    let versions = modules.entry(name.to_owned().to_string()).or_default();
+                    let versions = module_map.entry(name.to_owned().to_string()).or_default();
                     let modules = versions.entry(version).or_default();
                     for module in package.modules {
                         let name = module.name.clone();
@@ -63,11 +275,7 @@ impl<'a> MergedLockfilePackages<'a> {
                     }
                     for command in package.commands {
                         let name = command.name.clone();
-                        let script_name = command.name.clone();
                         commands.insert(name, command);
-                        // save the bin script to execute this command from the terminal
-                        save_bin_script(directory, script_name)
-                            .map_err(|e| Error::FailedToSaveLockfile(e.to_string()))?;
                     }
                 }
                 PackageKey::WapmPackageRange(_) => {
@@ -76,21 +284,43 @@ impl<'a> MergedLockfilePackages<'a> {
             }
         }
 
-        let lockfile = Lockfile { modules, commands };
-
+        let mut lockfile = Lockfile {
+            dependencies_hash,
+            min_cli_version: None,
+            root_package,
+            metadata: Some(Box::new(LockfileMetadata::current())),
+            modules,
+            commands,
+            build_modules,
+            overrides: self.overrides,
+        };
+        lockfile.min_cli_version = lockfile.required_cli_version();
         lockfile
-            .save(directory)
+            .validate()
             .map_err(|e| Error::FailedToSaveLockfile(e.to_string()))?;
-        Ok(())
+        Ok(lockfile)
     }
 }
 
+/// Saves the lockfile and the bin scripts to execute its commands from the terminal
+pub fn save_lockfile(directory: &Path, lockfile: &Lockfile) -> Result<(), Error> {
+    for command_name in lockfile.command_names() {
+        save_bin_script(directory, command_name.to_string())
+            .map_err(|e| Error::FailedToSaveLockfile(e.to_string()))?;
+    }
+    lockfile
+        .save(directory)
+        .map_err(|e| Error::FailedToSaveLockfile(e.to_string()))
+}
+
 #[cfg(test)]
 mod test {
+    use crate::data::lock::lockfile_command::LockfileCommand;
+    use crate::data::lock::lockfile_module::LockfileModule;
     use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
-    use crate::dataflow::merged_lockfile_packages::MergedLockfilePackages;
+    use crate::dataflow::merged_lockfile_packages::{Error, MergedLockfilePackages};
     use crate::dataflow::retained_lockfile_packages::RetainedLockfilePackages;
-    use crate::dataflow::PackageKey;
+    use crate::dataflow::{PackageKey, WapmPackageKey};
     use std::collections::HashMap;
 
     #[test]
@@ -170,4 +400,248 @@ mod test {
 
         assert_eq!(3, result.packages.len());
     }
+
+    fn run_command(package_name: &str) -> LockfileCommand {
+        LockfileCommand {
+            name: "run".to_string(),
+            package_name: package_name.to_string(),
+            package_version: semver::Version::new(1, 0, 0),
+            module: "main".to_string(),
+            is_top_level_dependency: true,
+            main_args: None,
+            args: vec![],
+            abi: None,
+            original_name: None,
+            extra: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_command_conflict() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let mut packages = HashMap::new();
+        packages.insert(
+            PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0)),
+            LockfilePackage {
+                modules: vec![],
+                commands: vec![run_command("_/foo")],
+            },
+        );
+        packages.insert(
+            PackageKey::new_registry_package("_/bar", semver::Version::new(1, 0, 0)),
+            LockfilePackage {
+                modules: vec![],
+                commands: vec![run_command("_/bar")],
+            },
+        );
+        let merged = MergedLockfilePackages {
+            packages,
+            build_packages: Default::default(),
+            overrides: Default::default(),
+        };
+
+        match merged.generate_lockfile(tmp_dir.path(), None, None) {
+            Err(Error::CommandConflict { command, packages }) => {
+                assert_eq!("run", command);
+                assert_eq!(
+                    vec!["_/bar 1.0.0".to_string(), "_/foo 1.0.0".to_string()],
+                    packages
+                );
+            }
+            otherwise => panic!("expected a command conflict, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn test_command_aliases() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let foo = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0));
+        let mut packages = HashMap::new();
+        for (key, package_name) in &[
+            (foo.clone(), "_/foo"),
+            (
+                PackageKey::new_registry_package("_/bar", semver::Version::new(1, 0, 0)),
+                "_/bar",
+            ),
+        ] {
+            packages.insert(
+                key.clone(),
+                LockfilePackage {
+                    modules: vec![],
+                    commands: vec![run_command(package_name)],
+                },
+            );
+        }
+        let mut merged = MergedLockfilePackages {
+            packages,
+            build_packages: Default::default(),
+            overrides: Default::default(),
+        };
+
+        let aliases_of = |aliases: &[(&str, &str)]| -> HashMap<String, String> {
+            aliases
+                .iter()
+                .map(|(alias, command)| (alias.to_string(), command.to_string()))
+                .collect()
+        };
+        let aliases = aliases_of(&[("run-foo", "foo::run")]);
+        merged.apply_command_aliases(&aliases).unwrap();
+        let command = &merged.packages[&foo].commands[0];
+        assert_eq!("run-foo", command.name);
+        assert_eq!(Some("run"), command.original_name.as_deref());
+        assert_eq!("main", command.module);
+
+        // applying the aliases again, e.g. to a retained package, gives the same result
+        merged.apply_command_aliases(&aliases).unwrap();
+        assert_eq!("run-foo", merged.packages[&foo].commands[0].name);
+
+        // both packages provide `run`, so installing both under one alias is a conflict
+        let mut conflicting_merged = merged.clone();
+        conflicting_merged
+            .apply_command_aliases(&aliases_of(&[("run-any", "run")]))
+            .unwrap();
+        match conflicting_merged.generate_lockfile(tmp_dir.path(), None, None) {
+            Err(Error::CommandConflict { command, .. }) => assert_eq!("run-any", command),
+            otherwise => panic!("expected a command conflict, got {:?}", otherwise),
+        }
+
+        match merged.apply_command_aliases(&aliases_of(&[("web", "serve")])) {
+            Err(Error::UnknownCommandAlias { alias, command }) => {
+                assert_eq!("web", alias);
+                assert_eq!("serve", command);
+            }
+            otherwise => panic!("expected an unknown command alias, got {:?}", otherwise),
+        }
+
+        // without aliases the command gets its name back
+        merged.apply_command_aliases(&HashMap::new()).unwrap();
+        assert_eq!("run", merged.packages[&foo].commands[0].name);
+        assert!(merged.packages[&foo].commands[0].original_name.is_none());
+    }
+
+    #[test]
+    fn test_set_dependent() {
+        let local_key = WapmPackageKey {
+            name: "_/test".into(),
+            version: semver::Version::new(0, 1, 0),
+        };
+        let module = |name: &str| LockfileModule {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let mut packages = HashMap::new();
+        packages.insert(
+            PackageKey::WapmPackage(local_key.clone()),
+            LockfilePackage {
+                modules: vec![module("test")],
+                commands: vec![],
+            },
+        );
+        packages.insert(
+            PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0)),
+            LockfilePackage {
+                modules: vec![module("foo")],
+                commands: vec![],
+            },
+        );
+        let mut merged = MergedLockfilePackages {
+            packages,
+            build_packages: Default::default(),
+            overrides: Default::default(),
+        };
+
+        // no package has a manifest in the directory
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        merged.set_dependents(&local_key, tmp_dir.path());
+
+        for (key, data) in merged.packages.iter() {
+            let expected: Vec<String> = match key.to_string().as_str() {
+                "_/test 0.1.0" => vec![],
+                _ => vec!["_/test@0.1.0".to_string()],
+            };
+            assert_eq!(expected, data.modules[0].dependents);
+        }
+    }
+
+    #[test]
+    fn overridden_packages_are_noted() {
+        let mut packages = HashMap::new();
+        for (name, version) in &[("_/foo", "1.0.3"), ("_/bar", "2.0.0")] {
+            packages.insert(
+                PackageKey::new_registry_package(*name, semver::Version::parse(version).unwrap()),
+                LockfilePackage {
+                    modules: vec![],
+                    commands: vec![],
+                },
+            );
+        }
+        let mut merged = MergedLockfilePackages {
+            packages,
+            build_packages: Default::default(),
+            overrides: Default::default(),
+        };
+        let overrides: HashMap<String, semver::Version> = vec![
+            ("_/foo".to_string(), semver::Version::new(1, 0, 3)),
+            // not locked at the override version, e.g. when the update fell back to the lockfile
+            ("_/bar".to_string(), semver::Version::new(2, 0, 1)),
+            ("_/baz".to_string(), semver::Version::new(1, 0, 0)),
+        ]
+        .into_iter()
+        .collect();
+
+        merged.set_overrides(&overrides);
+
+        assert_eq!(
+            vec![("_/foo".to_string(), semver::Version::new(1, 0, 3))],
+            merged.overrides.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn build_only_packages_are_locked_apart() {
+        use crate::data::lock::lockfile::Lockfile;
+        use std::collections::HashSet;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let version = semver::Version::new(1, 0, 0);
+        let module = |package_name: &str| LockfileModule {
+            name: "main".to_string(),
+            package_name: package_name.to_string(),
+            package_version: "1.0.0".to_string(),
+            package_path: format!("{}@1.0.0", package_name),
+            source: "main.wasm".to_string(),
+            ..Default::default()
+        };
+        let mut packages = HashMap::new();
+        for package_name in &["_/foo", "_/codegen"] {
+            packages.insert(
+                PackageKey::new_registry_package(*package_name, version.clone()),
+                LockfilePackage {
+                    modules: vec![module(package_name)],
+                    commands: vec![run_command(package_name)],
+                },
+            );
+        }
+        let mut merged = MergedLockfilePackages {
+            packages,
+            build_packages: Default::default(),
+            overrides: Default::default(),
+        };
+
+        let build_only: HashSet<String> = vec!["_/codegen".to_string()].into_iter().collect();
+        merged.set_build_only(&build_only);
+        // the command of the build tool is dropped, so it does not conflict
+        merged
+            .generate_lockfile(tmp_dir.path(), None, None)
+            .unwrap();
+
+        let lockfile = Lockfile::open(tmp_dir.path()).unwrap();
+        assert!(lockfile.get_module("_/codegen", &version, "main").is_err());
+        assert!(!lockfile.contains_module("_/codegen", &version, "main"));
+        assert!(lockfile.contains_build_module("_/codegen", &version, "main"));
+        assert!(lockfile.get_module("_/foo", &version, "main").is_ok());
+        assert_eq!("_/foo", lockfile.get_command("run").unwrap().package_name);
+        assert_eq!(1, lockfile.iter_modules().count());
+        assert_eq!(1, lockfile.iter_build_modules().count());
+    }
 }
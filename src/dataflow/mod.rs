@@ -1,35 +1,53 @@
-use crate::data::manifest::Manifest;
+use crate::data::lock::lockfile::{Lockfile, LockfileWarning};
+use crate::data::lock::lockfile_command::LockfileCommand;
+use crate::data::manifest::{Manifest, PACKAGES_DIR_NAME};
 use crate::dataflow::added_packages::AddedPackages;
-use crate::dataflow::changed_manifest_packages::ChangedManifestPackages;
+use crate::dataflow::bin_script::{delete_bin_script, save_bin_script};
+use crate::dataflow::changed_manifest_packages::PackageChange;
 use crate::dataflow::installed_packages::{InstalledPackages, RegistryInstaller};
 use crate::dataflow::local_package::LocalPackage;
 use crate::dataflow::lockfile_packages::{LockfileError, LockfilePackages, LockfileResult};
-use crate::dataflow::manifest_packages::{ManifestPackages, ManifestResult};
+use crate::dataflow::manifest_packages::ManifestResult;
+use crate::dataflow::memo_resolver::MemoResolver;
 use crate::dataflow::merged_lockfile_packages::MergedLockfilePackages;
-use crate::dataflow::removed_lockfile_packages::RemovedLockfilePackages;
 use crate::dataflow::removed_packages::RemovedPackages;
-use crate::dataflow::resolved_packages::{RegistryResolver, ResolvedPackages};
-use crate::dataflow::retained_lockfile_packages::RetainedLockfilePackages;
+use crate::dataflow::resolution_observer::{
+    NoopObserver, ObservedResolver, ResolutionEvent, ResolutionObserver,
+};
+use crate::dataflow::resolution_plan::{validate_manifest, ResolutionPlan};
+use crate::dataflow::resolved_packages::{RegistryResolver, Resolve, ResolvedPackages};
 use semver::{Version, VersionReq};
 use std::borrow::{Borrow, Cow};
-use std::collections::{HashMap, HashSet};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt;
+use std::fs;
 use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
 
 pub mod added_packages;
 pub mod bin_script;
+pub mod cache_resolver;
+pub mod chain_resolver;
 pub mod changed_manifest_packages;
 pub mod find_command_result;
+pub mod git_packages;
 pub mod installed_packages;
 pub mod interfaces;
 pub mod local_package;
 pub mod lockfile_packages;
 pub mod manifest_packages;
+pub mod memo_resolver;
 pub mod merged_lockfile_packages;
+pub mod prefer_cache_resolver;
 pub mod removed_lockfile_packages;
 pub mod removed_packages;
+pub mod resolution_observer;
+pub mod resolution_plan;
 pub mod resolved_packages;
 pub mod retained_lockfile_packages;
+pub mod retry_resolver;
 use thiserror::Error;
 
 #[derive(Clone, Debug, Error)]
@@ -44,6 +62,8 @@ pub enum Error {
     InstallError(installed_packages::Error),
     #[error("Could not resolve package(s). {0}")]
     ResolveError(resolved_packages::Error),
+    #[error("Could not install git package. {0}")]
+    CouldNotInstallGitPackage(git_packages::Error),
     #[error("Could not save manifest file because {0}.")]
     SaveError(String),
     #[error("Could not install new packages. {0}")]
@@ -52,13 +72,44 @@ pub enum Error {
     LocalPackageError(local_package::Error),
     #[error("Could not cleanup old artifacts. {0}")]
     CleanupError(removed_lockfile_packages::Error),
+    #[error("Could not resolve package(s). {0}")]
+    VersionConflict(#[source] crate::data::lock::lockfile::LockfileError),
+    #[error("Could not parse package \"{0}\", expected a name and a version separated by a space")]
+    InvalidPackageKey(String),
+    #[error("Package {0} is not a dependency of the manifest")]
+    NotADependency(String),
     #[error("Attempting to install multiple versions of package {0} ({1} and {2})")]
     DuplicatePackage(String, String, String),
+    #[error("The lockfile needs to be updated but frozen mode is enabled. {}", would_change_message(.added, .changed, .removed))]
+    WouldChange {
+        added: Vec<String>,
+        changed: Vec<String>,
+        removed: Vec<String>,
+    },
+    #[error("Could not refresh the commands of the lockfile. {0}")]
+    CouldNotRefreshCommands(#[source] crate::data::lock::lockfile::LockfileError),
+    #[error("Could not lock the dependencies. {0}")]
+    DepthExceeded(#[source] crate::data::lock::lockfile::LockfileError),
+    #[error("Could not install from the lockfile alone. {0}")]
+    NotLocked(#[source] crate::data::lock::lockfile::LockfileError),
+    #[error("The commands \"{0}\" and \"{1}\" differ only by case, so they collide on case-insensitive filesystems")]
+    CommandCaseCollision(String, String),
+    #[error("The manifest overrides package {package} with version {version}, which the registry does not have")]
+    OverrideNotFound { package: String, version: String },
+}
+
+fn would_change_message(added: &[String], changed: &[String], removed: &[String]) -> String {
+    [("Added", added), ("Changed", changed), ("Removed", removed)]
+        .iter()
+        .filter(|(_, packages)| !packages.is_empty())
+        .map(|(label, packages)| format!("{}: {}.", label, packages.join(", ")))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 /// A package key for a package in the wapm.io registry.
 /// This Is currently defined as name and a version.
-#[derive(Clone, Debug, Eq, Hash, PartialOrd, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
 pub struct WapmPackageKey<'a> {
     pub name: Cow<'a, str>,
     pub version: Version,
@@ -77,6 +128,25 @@ impl<'a> fmt::Display for WapmPackageKey<'a> {
     }
 }
 
+/// Parses the `<name> <version>` that package keys display as, e.g. the `package` of a manifest
+/// command. Versions never contain spaces, so the version is what follows the last one.
+impl FromStr for WapmPackageKey<'static> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidPackageKey(s.to_string());
+        let (name, version) = s.trim().rsplit_once(' ').ok_or_else(invalid)?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(invalid());
+        }
+        Ok(WapmPackageKey {
+            name: Cow::Owned(name.to_string()),
+            version: Version::parse(version).map_err(|_| invalid())?,
+        })
+    }
+}
+
 pub fn detect_duplicate_packages(packages: &HashSet<PackageKey>) -> Result<(), Error> {
     let mut seen_pkg_versions = HashMap::new();
 
@@ -132,6 +202,54 @@ impl<'a> PackageKey<'a> {
         })
     }
 
+    pub fn name(&self) -> &str {
+        match self {
+            PackageKey::WapmPackage(WapmPackageKey { name, .. }) => name,
+            PackageKey::WapmPackageRange(WapmPackageRange { name, .. }) => name,
+        }
+    }
+
+    /// Copy a borrowed package name so the key no longer borrows from e.g. the manifest.
+    pub fn into_owned(self) -> PackageKey<'static> {
+        match self {
+            PackageKey::WapmPackage(WapmPackageKey { name, version }) => {
+                PackageKey::new_registry_package(name.into_owned(), version)
+            }
+            PackageKey::WapmPackageRange(WapmPackageRange { name, version_req }) => {
+                PackageKey::new_registry_package_range(name.into_owned(), version_req)
+            }
+        }
+    }
+
+    /// Whether a resolved package key is the resolution of this requested package key
+    pub fn is_resolved_by(&self, resolved: &WapmPackageKey) -> bool {
+        match self {
+            PackageKey::WapmPackage(key) => key == resolved,
+            PackageKey::WapmPackageRange(WapmPackageRange { name, version_req }) => {
+                *name == resolved.name && version_req.matches(&resolved.version)
+            }
+        }
+    }
+
+    /// Orders keys by name, then by version. Exact versions come before the ranges of the same
+    /// name and are ordered by semver precedence, ranges by how they display. Resolution
+    /// processes packages in this order, so an update does the same work in the same order on
+    /// every run, whatever order the packages were collected in.
+    pub fn cmp_by_name_and_version(&self, other: &PackageKey) -> Ordering {
+        self.name()
+            .cmp(other.name())
+            .then_with(|| match (self, other) {
+                (PackageKey::WapmPackage(a), PackageKey::WapmPackage(b)) => {
+                    a.version.cmp(&b.version)
+                }
+                (PackageKey::WapmPackage(_), PackageKey::WapmPackageRange(_)) => Ordering::Less,
+                (PackageKey::WapmPackageRange(_), PackageKey::WapmPackage(_)) => Ordering::Greater,
+                (PackageKey::WapmPackageRange(a), PackageKey::WapmPackageRange(b)) => {
+                    a.version_req.to_string().cmp(&b.version_req.to_string())
+                }
+            })
+    }
+
     pub fn matches(&self, range: &WapmPackageRange) -> bool {
         match self {
             PackageKey::WapmPackage(key) => {
@@ -146,7 +264,9 @@ impl<'a> fmt::Display for PackageKey<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
             PackageKey::WapmPackage(wapm_package_key) => write!(f, "{}", wapm_package_key),
-            _ => panic!("Non wapm registry keys are unsupported."),
+            PackageKey::WapmPackageRange(WapmPackageRange { name, version_req }) => {
+                write!(f, "{} {}", name, version_req)
+            }
         }
     }
 }
@@ -183,130 +303,304 @@ pub fn normalize_global_namespace(key: PackageKey) -> PackageKey {
 
 /// If there is no mainfest, then this is a non-manifest project. All installations are retained
 /// in the lockfile, and installs are additive.
-/// This function returns a bool on success indicating if any changes were applied
-pub fn update_with_no_manifest<P: AsRef<Path>>(
+/// This function returns a bool on success indicating if any changes were applied, and which
+/// packages were reused, fetched, and removed
+pub fn update_with_no_manifest<P: AsRef<Path>, Resolver: for<'r> Resolve<'r>>(
     directory: P,
     added_packages: AddedPackages,
     removed_packages: RemovedPackages,
-) -> Result<bool, Error> {
+    options: &UpdateOptions,
+    resolver: &Resolver,
+) -> Result<(bool, UpdateReport), Error> {
     let directory = directory.as_ref();
-    // get lockfile data
-    let lockfile_result = LockfileResult::find_in_directory(&directory);
-    let mut lockfile_packages =
-        LockfilePackages::new_from_result(lockfile_result).map_err(Error::LockfileError)?;
-    detect_duplicate_packages(&added_packages.packages)?;
-
-    // capture the initial lockfile keys before any modifications
-    let initial_package_keys: HashSet<_> = lockfile_packages.package_keys();
+    let existing_lockfile = match LockfileResult::find_in_directory(directory) {
+        LockfileResult::Lockfile(lockfile) => Some(lockfile),
+        _ => None,
+    };
+    let plan = ResolutionPlan::new_with_no_manifest(directory, added_packages, removed_packages)?;
+    if options.locked {
+        check_locked(&plan)?;
+    }
+    let ResolutionPlan {
+        added_packages,
+        retained_packages: retained_lockfile_packages,
+        removed_packages: removed_lockfile_packages,
+        initial_package_keys,
+        ..
+    } = plan;
 
-    let removed_lockfile_packages = RemovedLockfilePackages::from_removed_packages_and_lockfile(
-        &removed_packages,
-        &lockfile_packages,
-    );
+    let removed = sorted_strings(removed_lockfile_packages.packages.keys());
 
     // cleanup any old artifacts
     removed_lockfile_packages
         .cleanup_old_packages(&directory)
         .map_err(Error::CleanupError)?;
 
-    // remove/uninstall packages
-    lockfile_packages.remove_packages(removed_packages);
-
-    // check that the added packages are not already installed
-    let lockfile_package_keys = lockfile_packages.package_keys();
-    let added_packages = added_packages.prune_already_installed_packages(lockfile_package_keys);
-    // check for missing packages e.g. deleting stuff from wapm_packages
-    // install any missing or newly added packages
-    let missing_packages = lockfile_packages.find_missing_packages(&directory);
-    let added_packages = added_packages.add_missing_packages(missing_packages);
-
-    let resolved_packages =
-        ResolvedPackages::new_from_added_packages::<RegistryResolver>(added_packages)
-            .map_err(Error::ResolveError)?;
-    let installed_packages =
-        InstalledPackages::install::<RegistryInstaller>(&directory, resolved_packages, false)
-            .map_err(Error::InstallError)?;
+    let resolved_packages = ResolvedPackages::new_from_added_packages(added_packages, resolver)
+        .map_err(Error::ResolveError)?;
+    let mut report = UpdateReport {
+        reused: sorted_strings(retained_lockfile_packages.packages.keys()),
+        fetched: sorted_strings(resolved_packages.packages.iter().map(|(key, _)| key)),
+        removed,
+        ..UpdateReport::default()
+    };
+    let installed_packages = InstalledPackages::install::<RegistryInstaller>(
+        &directory,
+        resolved_packages,
+        existing_lockfile.as_ref(),
+        false,
+    )
+    .map_err(Error::InstallError)?;
     let added_lockfile_data = LockfilePackages::from_installed_packages(&installed_packages)
         .map_err(Error::LockfileError)?;
-
-    let retained_lockfile_packages =
-        RetainedLockfilePackages::from_lockfile_packages(lockfile_packages);
+    report.warnings = added_lockfile_data.shadowing_warnings();
 
     // merge the lockfile data, and generate the new lockfile
     let final_lockfile_data =
         MergedLockfilePackages::merge(added_lockfile_data, retained_lockfile_packages);
     let final_package_keys: HashSet<_> = final_lockfile_data.packages.keys().cloned().collect();
     if final_package_keys != initial_package_keys {
-        final_lockfile_data
-            .generate_lockfile(&directory)
+        let mut lockfile = final_lockfile_data
+            .build_lockfile(None, None)
+            .map_err(Error::GenerateLockfileError)?;
+        keep_unchanged(&mut lockfile, existing_lockfile.as_ref());
+        merged_lockfile_packages::save_lockfile(directory, &lockfile)
             .map_err(Error::GenerateLockfileError)?;
-        Ok(true)
+        Ok((true, report))
     } else {
-        Ok(false)
+        Ok((false, report))
     }
 }
 
+/// Which packages an update took from the lockfile, resolved and installed, and removed, as
+/// `<name> <version>`, sorted, and what looked wrong about the installed packages
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UpdateReport {
+    pub reused: Vec<String>,
+    pub fetched: Vec<String>,
+    pub removed: Vec<String>,
+    /// Warnings for the installed packages, for the caller to show
+    pub warnings: Vec<LockfileWarning>,
+}
+
+impl UpdateReport {
+    /// The report of an update with a manifest, before the resolved packages are installed
+    fn for_manifest_plan(
+        plan: &ResolutionPlan,
+        resolved_packages: &ResolvedPackages,
+        manifest: &Manifest,
+    ) -> Self {
+        UpdateReport {
+            reused: sorted_strings(plan.retained_packages.packages.keys()),
+            fetched: sorted_strings(
+                resolved_packages
+                    .packages
+                    .iter()
+                    .map(|(key, _)| key.to_string())
+                    .chain(
+                        plan.git_packages
+                            .iter()
+                            .map(|git_package| git_package.dependency.to_string()),
+                    ),
+            ),
+            // the local package is always regenerated, it is not removed
+            removed: sorted_strings(
+                plan.removed_packages
+                    .packages
+                    .keys()
+                    .filter(|key| !is_local_package(key, manifest)),
+            ),
+            warnings: vec![],
+        }
+    }
+}
+
+fn sorted_strings<T: ToString>(items: impl Iterator<Item = T>) -> Vec<String> {
+    let mut strings: Vec<String> = items.map(|item| item.to_string()).collect();
+    strings.sort();
+    strings
+}
+
+fn is_local_package(key: &PackageKey, manifest: &Manifest) -> bool {
+    key.name() == manifest.package.name
+}
+
+/// Options for updating the lockfile
+#[derive(Clone, Debug, Default)]
+pub struct UpdateOptions {
+    /// The features of the manifest, enabling its optional dependencies
+    pub features: HashSet<String>,
+    /// Keep the locked version of a changed package instead of failing when it can not be
+    /// resolved, e.g. without network access. New packages must still resolve.
+    pub fallback_to_lockfile: bool,
+    /// Leave out the dev dependencies of the manifest, like the package's consumers do
+    pub exclude_dev_dependencies: bool,
+    /// Manifest dependencies that are resolved again even if their locked version still
+    /// satisfies the manifest, e.g. to pick up a newer release
+    pub update_packages: Vec<String>,
+    /// The longest chain of dependents the lockfile may have, unlimited if `None`. See
+    /// `Lockfile::check_depth`.
+    pub max_depth: Option<usize>,
+    /// Only use the lockfile and the installed packages: the resolver is never called, and a
+    /// package that would have to be resolved or fetched is an error. Stronger than a frozen
+    /// install, which may still reinstall missing packages.
+    pub locked: bool,
+    /// Fail instead of warning when two locked commands differ only by case, see
+    /// `Lockfile::case_collisions`
+    pub deny_case_collisions: bool,
+}
+
 /// If there is a manifest, then we construct lockfile data from manifest dependencies, and merge
 /// with existing lockfile data.
-/// This function returns a bool on success indicating if any changes were applied
-pub fn update_with_manifest<P: AsRef<Path>>(
+/// This function returns a bool on success indicating if any changes were applied, and which
+/// packages were reused, fetched, and removed
+pub fn update_with_manifest<P: AsRef<Path>, Resolver: for<'r> Resolve<'r>>(
     directory: P,
     manifest: Manifest,
     added_packages: AddedPackages,
     removed_packages: RemovedPackages,
-) -> Result<bool, Error> {
+    options: &UpdateOptions,
+    resolver: &Resolver,
+) -> Result<(bool, UpdateReport), Error> {
     let directory = directory.as_ref();
+    validate_manifest(&manifest)?;
 
-    let mut manifest_packages =
-        ManifestPackages::new_from_manifest_and_added_packages(&manifest, &added_packages)
-            .map_err(Error::ManifestError)?;
-
-    detect_duplicate_packages(&manifest_packages.packages)?;
+    let dependencies_hash =
+        manifest_with_changes(manifest.clone(), &added_packages, &removed_packages)
+            .dependencies_hash(&options.features, !options.exclude_dev_dependencies);
+    // read the lockfile once, everything up to resolving only looks at it
+    let existing_lockfile = match LockfileResult::find_in_directory(directory) {
+        LockfileResult::Lockfile(lockfile) => Some(lockfile),
+        _ => None,
+    };
+    if let Some(lockfile) = existing_lockfile
+        .as_ref()
+        .filter(|_| options.update_packages.is_empty())
+    {
+        let lockfile_packages = LockfilePackages::new_from_lockfile(lockfile.clone());
+        let up_to_date = lockfile_is_up_to_date(
+            directory,
+            lockfile,
+            &lockfile_packages,
+            &manifest,
+            &options.features,
+            &dependencies_hash,
+        )?;
+        if up_to_date {
+            check_lockfile(lockfile, options)?;
+        }
+        if up_to_date
+            || refresh_lockfile_commands(
+                directory,
+                lockfile,
+                &lockfile_packages,
+                &manifest,
+                options,
+                &dependencies_hash,
+            )?
+        {
+            let report = UpdateReport {
+                reused: sorted_strings(
+                    lockfile_packages
+                        .packages
+                        .keys()
+                        .filter(|key| !is_local_package(key, &manifest)),
+                ),
+                ..UpdateReport::default()
+            };
+            return Ok((false, report));
+        }
+    }
 
-    // remove/uninstall packages
-    manifest_packages.remove_packages(&removed_packages);
+    let mut plan = ResolutionPlan::new_with_manifest(
+        directory,
+        &manifest,
+        &added_packages,
+        &removed_packages,
+        options,
+    )?;
+    if options.locked {
+        check_locked(&plan)?;
+    }
 
-    // get lockfile data
-    let lockfile_result = LockfileResult::find_in_directory(&directory);
-    let lockfile_packages =
-        LockfilePackages::new_from_result(lockfile_result).map_err(Error::LockfileError)?;
-    // store lockfile package keys before updating it
-    let initial_package_keys = lockfile_packages.package_keys();
+    if !plan.added_packages.packages.is_empty() {
+        if let Some(lockfile) = existing_lockfile.as_ref() {
+            let mut packages: Vec<PackageKey> =
+                plan.added_packages.packages.iter().cloned().collect();
+            packages.sort_by(|a, b| a.cmp_by_name_and_version(b));
+            resolver.preload(lockfile, &packages);
+        }
+    }
 
-    // get the local package modules and commands from the manifest
-    let local_package = LocalPackage::new_from_local_package_in_manifest(&manifest)
-        .map_err(Error::LocalPackageError)?;
+    // resolve before cleaning up, so the locked packages are still there to fall back to
+    let resolved_manifest_packages =
+        match ResolvedPackages::new_from_added_packages(plan.added_packages.clone(), resolver) {
+            Ok(resolved_packages) => {
+                check_overrides_resolved(&manifest, &plan.added_packages, &resolved_packages)?;
+                resolved_packages
+            }
+            Err(e) if options.fallback_to_lockfile => {
+                if let Err(key) = plan.fall_back_to_lockfile() {
+                    return Err(Error::ResolveError(
+                        resolved_packages::Error::CouldNotResolvePackages(format!(
+                            "{} There is no locked version of {} to fall back to.",
+                            e, key
+                        )),
+                    ));
+                }
+                ResolvedPackages::default()
+            }
+            Err(e) => return Err(Error::ResolveError(e)),
+        };
+    let versions: Vec<WapmPackageKey> = resolved_manifest_packages
+        .packages
+        .iter()
+        .map(|(key, _)| key.clone())
+        .chain(
+            plan.retained_packages
+                .packages
+                .keys()
+                .filter_map(|key| match key {
+                    PackageKey::WapmPackage(key) => Some(key.clone()),
+                    PackageKey::WapmPackageRange(_) => None,
+                }),
+        )
+        .collect();
+    plan.version_requirements
+        .check(&versions)
+        .map_err(Error::VersionConflict)?;
 
-    let changed_manifest_data =
-        ChangedManifestPackages::get_changed_packages_from_manifest_and_lockfile(
-            &manifest_packages,
-            &lockfile_packages,
-        );
+    let mut report = UpdateReport::for_manifest_plan(&plan, &resolved_manifest_packages, &manifest);
 
-    let packages_to_install = AddedPackages {
-        packages: changed_manifest_data.packages,
-    };
+    let ResolutionPlan {
+        changes,
+        git_packages,
+        retained_packages: retained_lockfile_packages,
+        removed_packages: removed_lockfile_packages,
+        initial_package_keys,
+        ..
+    } = plan;
 
-    let missing_lockfile_packages = lockfile_packages.find_missing_packages(&directory);
-    let new_added_packages = packages_to_install.add_missing_packages(missing_lockfile_packages);
+    for change in changes.iter() {
+        if let PackageChange::Downgraded { .. } = change {
+            warn!("Downgrading package {}", change);
+        }
+    }
 
-    let removed_lockfile_packages =
-        RemovedLockfilePackages::from_manifest_and_lockfile(&manifest_packages, &lockfile_packages);
+    // get the local package modules and commands from the manifest
+    let local_package =
+        LocalPackage::new_from_local_package_in_manifest(&manifest, &options.features)
+            .map_err(Error::LocalPackageError)?;
 
     // cleanup any old artifacts
     removed_lockfile_packages
         .cleanup_old_packages(&directory)
         .map_err(Error::CleanupError)?;
 
-    let retained_lockfile_packages =
-        RetainedLockfilePackages::from_manifest_and_lockfile(&manifest_packages, lockfile_packages);
-
-    let resolved_manifest_packages =
-        ResolvedPackages::new_from_added_packages::<RegistryResolver>(new_added_packages)
-            .map_err(Error::ResolveError)?;
     let installed_manifest_packages = InstalledPackages::install::<RegistryInstaller>(
         &directory,
         resolved_manifest_packages,
+        existing_lockfile.as_ref(),
         false,
     )
     .map_err(Error::InstallError)?;
@@ -314,23 +608,336 @@ pub fn update_with_manifest<P: AsRef<Path>>(
         LockfilePackages::from_installed_packages(&installed_manifest_packages)
             .map_err(Error::LockfileError)?;
 
+    for git_package in git_packages.iter() {
+        let (key, package) = git_package
+            .install(directory)
+            .map_err(Error::CouldNotInstallGitPackage)?;
+        manifest_lockfile_data.packages.insert(key, package);
+    }
+
+    report.warnings = manifest_lockfile_data.shadowing_warnings();
+    let declared_commands = local_package.data.commands.clone();
+    let installed_package_paths: BTreeSet<String> = manifest_lockfile_data
+        .packages
+        .values()
+        .flat_map(|package| package.modules.iter())
+        .map(|module| module.package_path.clone())
+        .collect();
     manifest_lockfile_data.extend(local_package.into());
 
     // merge the lockfile data, and generate the new lockfile
-    let final_lockfile_data =
+    let mut final_lockfile_data =
         MergedLockfilePackages::merge(manifest_lockfile_data, retained_lockfile_packages);
+    final_lockfile_data
+        .apply_command_aliases(manifest.command_aliases.as_ref().unwrap_or(&HashMap::new()))
+        .map_err(Error::GenerateLockfileError)?;
+    let root_package = WapmPackageKey {
+        name: Cow::Borrowed(&manifest.package.name),
+        version: manifest.package.version.clone(),
+    };
+    final_lockfile_data.set_dependents(&root_package, directory);
+    final_lockfile_data.set_build_only(&manifest.build_only_dependencies());
+    final_lockfile_data.set_overrides(&manifest.normalized_overrides());
+    for (name, version) in final_lockfile_data.overrides.iter() {
+        info!(
+            "Locking {} at version {}, as the manifest overrides it",
+            name, version
+        );
+    }
     let final_package_keys: HashSet<_> = final_lockfile_data.packages.keys().cloned().collect();
 
-    final_lockfile_data
-        .generate_lockfile(&directory)
+    let mut lockfile = final_lockfile_data
+        .build_lockfile(Some(dependencies_hash), Some(root_package.to_string()))
+        .map_err(Error::GenerateLockfileError)?;
+    // retained packages keep the commands the manifest declared for them, even dropped ones
+    let provided_commands = provided_commands(directory, &lockfile);
+    let undeclared_commands: Vec<String> = lockfile
+        .iter_commands()
+        .filter(|(_, command)| {
+            is_undeclared_dependency_command(command, &provided_commands, &declared_commands)
+        })
+        .map(|(name, _)| name.to_string())
+        .collect();
+    for command_name in undeclared_commands.iter() {
+        lockfile.commands.remove(command_name);
+    }
+    keep_unchanged(&mut lockfile, existing_lockfile.as_ref());
+    if let Err(e) = check_lockfile(&lockfile, options) {
+        // the lockfile stays as it was, so the packages this update installed are not locked
+        for package_path in installed_package_paths {
+            let package_dir = directory.join(PACKAGES_DIR_NAME).join(package_path);
+            if let Err(e) = fs::remove_dir_all(&package_dir) {
+                warn!("Could not remove {}: {}", package_dir.display(), e);
+            }
+        }
+        return Err(e);
+    }
+    merged_lockfile_packages::save_lockfile(directory, &lockfile)
         .map_err(Error::GenerateLockfileError)?;
+    for command_name in undeclared_commands {
+        delete_bin_script(directory, command_name).map_err(|e| {
+            Error::GenerateLockfileError(merged_lockfile_packages::Error::FailedToSaveLockfile(
+                e.to_string(),
+            ))
+        })?;
+    }
 
     // update the manifest, if applicable
     if final_package_keys != initial_package_keys {
         update_manifest(manifest.clone(), &added_packages, &removed_packages)?;
-        Ok(true)
+        Ok((true, report))
     } else {
-        Ok(false)
+        Ok((false, report))
+    }
+}
+
+/// Keeps what a regenerated lockfile did not change from the `previous` one: the resolution time
+/// of reinstalled modules, and the `metadata` when it resolves to the same modules and commands,
+/// so regenerating it does not show up as a diff.
+fn keep_unchanged(lockfile: &mut Lockfile, previous: Option<&Lockfile>) {
+    if let Some(previous) = previous {
+        lockfile.keep_resolved_at(previous);
+        if lockfile.semantic_eq(previous) && previous.metadata.is_some() {
+            lockfile.metadata = previous.metadata.clone();
+        }
+    }
+}
+
+/// Checks a lockfile before it, or any of its bin scripts, is saved: its dependents may not be
+/// deeper than `max_depth`, and commands that differ only by case are an error with
+/// `deny_case_collisions`, and a warning otherwise.
+fn check_lockfile(lockfile: &Lockfile, options: &UpdateOptions) -> Result<(), Error> {
+    if let Some(max_depth) = options.max_depth {
+        lockfile
+            .check_depth(max_depth)
+            .map_err(Error::DepthExceeded)?;
+    }
+    for (first, second) in lockfile.case_collisions() {
+        if options.deny_case_collisions {
+            return Err(Error::CommandCaseCollision(first, second));
+        }
+        warn!(
+            "The commands \"{}\" and \"{}\" differ only by case, one of them can not be run on case-insensitive filesystems",
+            first, second
+        );
+    }
+    Ok(())
+}
+
+/// Errors if the registry did not resolve the version of an overridden package. Resolvers leave
+/// out the packages they can not find, which would drop the package from the lockfile instead.
+fn check_overrides_resolved(
+    manifest: &Manifest,
+    added_packages: &AddedPackages,
+    resolved_packages: &ResolvedPackages,
+) -> Result<(), Error> {
+    let overrides = manifest.normalized_overrides();
+    for key in added_packages.packages.iter() {
+        let key = match key {
+            PackageKey::WapmPackage(key) => key,
+            PackageKey::WapmPackageRange(_) => continue,
+        };
+        let name = normalize_global_namespace_package_name(key.name.clone());
+        if overrides.get(name.as_ref()) != Some(&key.version) {
+            continue;
+        }
+        if !resolved_packages.packages.iter().any(|(resolved, _)| {
+            resolved.version == key.version
+                && normalize_global_namespace_package_name(resolved.name.clone()) == name
+        }) {
+            return Err(Error::OverrideNotFound {
+                package: key.name.to_string(),
+                version: key.version.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Fails with the first package, by name, that the plan would resolve or fetch, for updates in
+/// locked mode. Runs before anything is resolved, so the resolver is never called.
+fn check_locked(plan: &ResolutionPlan) -> Result<(), Error> {
+    let mut added: Vec<&PackageKey> = plan.added_packages.packages.iter().collect();
+    added.sort_by(|a, b| a.cmp_by_name_and_version(b));
+    let name = added.first().map(|key| key.name().to_string()).or_else(|| {
+        plan.git_packages
+            .iter()
+            .map(|git_package| git_package.dependency.name.clone())
+            .min()
+    });
+    match name {
+        Some(name) => Err(Error::NotLocked(
+            crate::data::lock::lockfile::LockfileError::NotLocked { name },
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Whether the lockfile was generated from a manifest with these dependencies, the local package
+/// is unchanged, and every locked package is installed. An update has nothing to do then, so it
+/// can skip planning and leave the lockfile as it is.
+fn lockfile_is_up_to_date(
+    directory: &Path,
+    lockfile: &Lockfile,
+    lockfile_packages: &LockfilePackages,
+    manifest: &Manifest,
+    features: &HashSet<String>,
+    dependencies_hash: &str,
+) -> Result<bool, Error> {
+    if lockfile.dependencies_hash.as_deref() != Some(dependencies_hash) {
+        return Ok(false);
+    }
+    let local_package = LocalPackage::new_from_local_package_in_manifest(manifest, features)
+        .map_err(Error::LocalPackageError)?;
+    if has_undeclared_dependency_commands(directory, lockfile, &local_package) {
+        return Ok(false);
+    }
+    if !lockfile_packages
+        .find_missing_packages(directory)
+        .is_empty()
+    {
+        return Ok(false);
+    }
+    Ok(!local_package.differs_from(lockfile_packages))
+}
+
+/// Whether the lockfile has a command for the module of a dependency that the dependency does
+/// not provide itself, and that the manifest no longer declares.
+fn has_undeclared_dependency_commands(
+    directory: &Path,
+    lockfile: &Lockfile,
+    local_package: &LocalPackage,
+) -> bool {
+    let provided_commands = provided_commands(directory, lockfile);
+    lockfile.iter_commands().any(|(_, command)| {
+        is_undeclared_dependency_command(command, &provided_commands, &local_package.data.commands)
+    })
+}
+
+/// Whether a command is for the module of a dependency that neither provides it itself, nor has
+/// it declared by the manifest, as one of `declared_commands`. Such a command was declared by an
+/// earlier version of the manifest.
+fn is_undeclared_dependency_command(
+    command: &LockfileCommand,
+    provided_commands: &HashMap<String, HashSet<String>>,
+    declared_commands: &[LockfileCommand],
+) -> bool {
+    let name = command.original_name.as_ref().unwrap_or(&command.name);
+    let is_provided = provided_commands
+        .get(&command.package_key().to_string())
+        .is_none_or(|provided| provided.contains(name));
+    let is_declared = declared_commands
+        .iter()
+        .any(|declared| &declared.name == name && declared.package_key() == command.package_key());
+    !is_provided && !is_declared
+}
+
+/// Like `lockfile_is_up_to_date`, but for when only the commands of the local package changed.
+/// The commands are then replaced in the lockfile, along with their bin scripts, without
+/// resolving or installing anything. Returns whether the commands were refreshed.
+fn refresh_lockfile_commands(
+    directory: &Path,
+    lockfile: &Lockfile,
+    lockfile_packages: &LockfilePackages,
+    manifest: &Manifest,
+    options: &UpdateOptions,
+    dependencies_hash: &str,
+) -> Result<bool, Error> {
+    let features = &options.features;
+    let local_package = LocalPackage::new_from_local_package_in_manifest(manifest, features)
+        .map_err(Error::LocalPackageError)?;
+    let commands_changed = local_package.differs_only_in_commands(lockfile_packages)
+        || has_undeclared_dependency_commands(directory, lockfile, &local_package);
+    if !commands_changed
+        || !lockfile_packages
+            .find_missing_packages(directory)
+            .is_empty()
+    {
+        return Ok(false);
+    }
+    if lockfile.dependencies_hash.as_deref() != Some(dependencies_hash) {
+        return Ok(false);
+    }
+
+    let mut lockfile = lockfile.clone();
+    let generate_error = |e: String| {
+        Error::GenerateLockfileError(merged_lockfile_packages::Error::FailedToSaveLockfile(e))
+    };
+    let old_command_names: Vec<String> = lockfile.command_names().map(String::from).collect();
+    lockfile
+        .refresh_commands(manifest, features, &provided_commands(directory, &lockfile))
+        .map_err(Error::CouldNotRefreshCommands)?;
+    check_lockfile(&lockfile, options)?;
+    for command_name in old_command_names {
+        if !lockfile.contains_command(&command_name) {
+            delete_bin_script(directory, command_name)
+                .map_err(|e| generate_error(e.to_string()))?;
+        }
+    }
+    for command_name in lockfile.command_names() {
+        save_bin_script(directory, command_name.to_string())
+            .map_err(|e| generate_error(e.to_string()))?;
+    }
+    lockfile
+        .save(directory)
+        .map_err(|e| generate_error(e.to_string()))?;
+    debug!("Only the commands of the manifest changed, refreshed them in the lockfile");
+    Ok(true)
+}
+
+/// The commands each locked package provides itself, package key -> command names, read from the
+/// manifests of the installed packages. Packages without a manifest are left out.
+fn provided_commands(directory: &Path, lockfile: &Lockfile) -> HashMap<String, HashSet<String>> {
+    let mut provided_commands = HashMap::new();
+    for (_, module) in lockfile.iter_modules() {
+        let key = format!("{} {}", module.package_name, module.package_version);
+        if provided_commands.contains_key(&key) {
+            continue;
+        }
+        let package_dir = directory.join(PACKAGES_DIR_NAME).join(&module.package_path);
+        let manifest = match ManifestResult::find_in_directory(&package_dir) {
+            ManifestResult::Manifest(manifest) => manifest,
+            _ => continue,
+        };
+        let version = match Version::parse(&module.package_version) {
+            Ok(version) => version,
+            Err(_) => continue,
+        };
+        if let Ok(commands) =
+            LockfileCommand::from_dependency_manifest(&module.package_name, &version, &manifest)
+        {
+            let names = commands.into_iter().map(|command| command.name).collect();
+            provided_commands.insert(key, names);
+        }
+    }
+    provided_commands
+}
+
+/// Computes what `update` would do without resolving or installing anything.
+pub fn plan<P: AsRef<Path>>(
+    added_packages: Vec<(&str, &str)>,
+    removed_packages: Vec<&str>,
+    directory: P,
+    options: &UpdateOptions,
+) -> Result<ResolutionPlan<'static>, Error> {
+    let directory = directory.as_ref();
+    let added_packages =
+        AddedPackages::new_from_str_pairs(added_packages).map_err(Error::AddError)?;
+    let removed_packages = RemovedPackages::new_from_package_names(removed_packages);
+    match ManifestResult::find_in_directory(&directory) {
+        ManifestResult::NoManifest => {
+            ResolutionPlan::new_with_no_manifest(directory, added_packages, removed_packages)
+                .map(ResolutionPlan::into_owned)
+        }
+        ManifestResult::Manifest(manifest) => ResolutionPlan::new_with_manifest(
+            directory,
+            &manifest,
+            &added_packages,
+            &removed_packages,
+            options,
+        )
+        .map(ResolutionPlan::into_owned),
+        ManifestResult::ManifestError(e) => Err(Error::ManifestError(e)),
     }
 }
 
@@ -341,18 +948,121 @@ pub fn update<P: AsRef<Path>>(
     removed_packages: Vec<&str>,
     directory: P,
 ) -> Result<bool, Error> {
+    update_with_resolver(
+        added_packages,
+        removed_packages,
+        directory,
+        &UpdateOptions::default(),
+        &RegistryResolver::default(),
+    )
+}
+
+/// Like `update`, but with the given options, resolving the packages to install with the given
+/// resolver instead of the registry.
+pub fn update_with_resolver<P: AsRef<Path>, Resolver: for<'r> Resolve<'r>>(
+    added_packages: Vec<(&str, &str)>,
+    removed_packages: Vec<&str>,
+    directory: P,
+    options: &UpdateOptions,
+    resolver: &Resolver,
+) -> Result<bool, Error> {
+    update_with_resolver_reported(
+        added_packages,
+        removed_packages,
+        directory,
+        options,
+        resolver,
+    )
+    .map(|(changed, report)| {
+        for warning in report.warnings {
+            warn!("{}", warning);
+        }
+        changed
+    })
+}
+
+/// Like `update_with_resolver`, and also reports which packages were reused from the lockfile,
+/// fetched, and removed.
+pub fn update_with_resolver_reported<P: AsRef<Path>, Resolver: for<'r> Resolve<'r>>(
+    added_packages: Vec<(&str, &str)>,
+    removed_packages: Vec<&str>,
+    directory: P,
+    options: &UpdateOptions,
+    resolver: &Resolver,
+) -> Result<(bool, UpdateReport), Error> {
+    update_with_resolver_observed(
+        added_packages,
+        removed_packages,
+        directory,
+        options,
+        resolver,
+        &NoopObserver,
+    )
+}
+
+/// Like `update_with_resolver_reported`, and also reports the start and end of the update and of
+/// every resolver call to the observer.
+pub fn update_with_resolver_observed<P: AsRef<Path>, Resolver: for<'r> Resolve<'r>>(
+    added_packages: Vec<(&str, &str)>,
+    removed_packages: Vec<&str>,
+    directory: P,
+    options: &UpdateOptions,
+    resolver: &Resolver,
+    observer: &dyn ResolutionObserver,
+) -> Result<(bool, UpdateReport), Error> {
+    observer.observe(&ResolutionEvent::Started);
+    let start = Instant::now();
+    let observed_resolver = ObservedResolver::new(resolver, observer);
+    let result = update_with_observed_resolver(
+        added_packages,
+        removed_packages,
+        directory,
+        options,
+        &observed_resolver,
+    );
+    observer.observe(&ResolutionEvent::Finished {
+        report: result.as_ref().ok().map(|(_, report)| report.clone()),
+        elapsed: start.elapsed(),
+    });
+    result
+}
+
+fn update_with_observed_resolver<P: AsRef<Path>, Resolver: for<'r> Resolve<'r>>(
+    added_packages: Vec<(&str, &str)>,
+    removed_packages: Vec<&str>,
+    directory: P,
+    options: &UpdateOptions,
+    resolver: &Resolver,
+) -> Result<(bool, UpdateReport), Error> {
     let directory = directory.as_ref();
     let added_packages =
         AddedPackages::new_from_str_pairs(added_packages).map_err(Error::AddError)?;
     let removed_packages = RemovedPackages::new_from_package_names(removed_packages);
+    // resolve each package at most once per update
+    let resolver = MemoResolver::new(resolver);
     let manifest_result = ManifestResult::find_in_directory(&directory);
     match manifest_result {
         ManifestResult::NoManifest => {
-            update_with_no_manifest(directory, added_packages, removed_packages)
-        }
-        ManifestResult::Manifest(manifest) => {
-            update_with_manifest(directory, manifest, added_packages, removed_packages)
+            // without a manifest nothing is a dependency
+            if let Some(name) = options.update_packages.first() {
+                return Err(Error::NotADependency(name.clone()));
+            }
+            update_with_no_manifest(
+                directory,
+                added_packages,
+                removed_packages,
+                options,
+                &resolver,
+            )
         }
+        ManifestResult::Manifest(manifest) => update_with_manifest(
+            directory,
+            manifest,
+            added_packages,
+            removed_packages,
+            options,
+            &resolver,
+        ),
         ManifestResult::ManifestError(e) => return Err(Error::ManifestError(e)),
     }
 }
@@ -367,6 +1077,19 @@ pub fn update_manifest(
         return Ok(());
     }
 
+    manifest_with_changes(manifest, added_packages, removed_packages)
+        .save()
+        .map_err(|e| Error::SaveError(e.to_string()))?;
+
+    Ok(())
+}
+
+/// The manifest with the added packages as dependencies, and without the removed packages
+fn manifest_with_changes(
+    manifest: Manifest,
+    added_packages: &AddedPackages,
+    removed_packages: &RemovedPackages,
+) -> Manifest {
     let mut manifest = manifest;
     for key in added_packages.packages.iter().cloned() {
         match key {
@@ -382,10 +1105,726 @@ pub fn update_manifest(
     for package_name in removed_packages.packages.iter().cloned() {
         manifest.remove_dependency(package_name.borrow());
     }
-
     manifest
-        .save()
-        .map_err(|e| Error::SaveError(e.to_string()))?;
+}
 
-    Ok(())
+#[cfg(test)]
+mod test {
+    use crate::data::lock::lockfile::{self, foo_lockfile, Lockfile};
+    use crate::data::manifest::Manifest;
+    use crate::dataflow::added_packages::AddedPackages;
+    use crate::dataflow::cache_resolver::{self, CacheResolver};
+    use crate::dataflow::lockfile_packages::LockfilePackages;
+    use crate::dataflow::removed_packages::RemovedPackages;
+    use crate::dataflow::resolution_observer::{RecordingObserver, ResolutionEvent};
+    use crate::dataflow::resolution_plan::ResolutionPlan;
+    use crate::dataflow::resolved_packages::{
+        self, Resolve, ResolvedPackage, ResolvedPackages, VersionSelector,
+    };
+    use crate::dataflow::{
+        bin_script, check_overrides_resolved, lockfile_is_up_to_date, update_with_manifest,
+        update_with_resolver_observed, Error, PackageKey, UpdateOptions, UpdateReport,
+        WapmPackageKey,
+    };
+    use std::collections::HashSet;
+    use std::fs;
+
+    #[test]
+    fn package_keys_parse_from_their_display() {
+        let key: WapmPackageKey = "_/foo 1.2.3".parse().unwrap();
+        assert_eq!("_/foo", key.name);
+        assert_eq!(semver::Version::new(1, 2, 3), key.version);
+        assert_eq!("_/foo 1.2.3", key.to_string());
+
+        let key: WapmPackageKey = "my package 1.0.0".parse().unwrap();
+        assert_eq!("my package", key.name);
+
+        for invalid in &["_/foo", " 1.0.0", "_/foo one"] {
+            match invalid.parse::<WapmPackageKey>() {
+                Err(Error::InvalidPackageKey(s)) => assert_eq!(*invalid, s),
+                result => panic!("expected {:?} not to parse, got {:?}", invalid, result),
+            }
+        }
+    }
+
+    #[test]
+    fn overrides_must_be_in_the_registry() {
+        let manifest: Manifest = toml::from_str(
+            "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n[dependencies]\nfoo = \"^1\"\n[overrides]\nfoo = \"1.0.3\"",
+        )
+        .unwrap();
+        let key = WapmPackageKey {
+            name: "_/foo".into(),
+            version: semver::Version::new(1, 0, 3),
+        };
+        let added_packages = AddedPackages {
+            packages: vec![PackageKey::WapmPackage(key.clone())]
+                .into_iter()
+                .collect(),
+        };
+
+        match check_overrides_resolved(&manifest, &added_packages, &ResolvedPackages::default()) {
+            Err(Error::OverrideNotFound { package, version }) => {
+                assert_eq!("_/foo", package);
+                assert_eq!("1.0.3", version);
+            }
+            result => panic!("expected an override not found error, got {:?}", result),
+        }
+        let resolved_packages = ResolvedPackages {
+            packages: vec![(key, ("https://example.com/foo.tar.gz".to_string(), None))],
+        };
+        assert!(check_overrides_resolved(&manifest, &added_packages, &resolved_packages).is_ok());
+    }
+
+    #[test]
+    fn up_to_date_lockfile_is_left_alone() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest: Manifest = toml::from_str(
+            "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n[dependencies]\n\"_/foo\" = \"1.0.0\"",
+        )
+        .unwrap();
+        let no_features = HashSet::new();
+        let dependencies_hash = manifest.dependencies_hash(&no_features, true);
+        let is_up_to_date = |lockfile: &Lockfile, dependencies_hash: &str| {
+            let lockfile_packages = LockfilePackages::new_from_lockfile(lockfile.clone());
+            lockfile_is_up_to_date(
+                tmp_dir.path(),
+                lockfile,
+                &lockfile_packages,
+                &manifest,
+                &no_features,
+                dependencies_hash,
+            )
+            .unwrap()
+        };
+        let mut lockfile = foo_lockfile();
+        // lockfiles without a hash are always planned
+        assert!(!is_up_to_date(&lockfile, &dependencies_hash));
+
+        lockfile.dependencies_hash = Some(dependencies_hash.clone());
+        // the locked package is not installed
+        assert!(!is_up_to_date(&lockfile, &dependencies_hash));
+
+        let package_dir = tmp_dir.path().join("wapm_packages/_/foo@1.0.0");
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("foo.wasm"), "").unwrap();
+        assert!(is_up_to_date(&lockfile, &dependencies_hash));
+        assert!(!is_up_to_date(&lockfile, "other hash"));
+    }
+
+    #[test]
+    fn update_reports_reused_and_removed_packages() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest_for = |dependencies: &str| -> Manifest {
+            toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n[dependencies]\n{}",
+                dependencies
+            ))
+            .unwrap()
+        };
+        let manifest = manifest_for("\"_/foo\" = \"1.0.0\"");
+        let mut lockfile = foo_lockfile();
+        lockfile.dependencies_hash = Some(manifest.dependencies_hash(&HashSet::new(), true));
+        lockfile.save(tmp_dir.path()).unwrap();
+        let package_dir = tmp_dir.path().join("wapm_packages/_/foo@1.0.0");
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("foo.wasm"), "").unwrap();
+        let resolver = CacheResolver::new(tmp_dir.path().join("cache"), VersionSelector::Highest);
+        let update = |manifest: Manifest| {
+            update_with_manifest(
+                tmp_dir.path(),
+                manifest,
+                AddedPackages::default(),
+                RemovedPackages::default(),
+                &UpdateOptions::default(),
+                &resolver,
+            )
+            .unwrap()
+        };
+
+        let (changed, report) = update(manifest);
+        assert!(!changed);
+        assert_eq!(vec!["_/foo 1.0.0".to_string()], report.reused);
+        assert!(report.fetched.is_empty());
+
+        let (changed, report) = update(manifest_for(""));
+        assert!(changed);
+        assert_eq!(
+            UpdateReport {
+                removed: vec!["_/foo 1.0.0".to_string()],
+                ..UpdateReport::default()
+            },
+            report
+        );
+    }
+
+    #[test]
+    fn update_reports_fetched_packages() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "_/test"
+            version = "0.1.0"
+            description = ""
+
+            [dependencies]
+            "_/foo" = "1.0.0"
+            "_/bar" = "1.0.0"
+            "#,
+        )
+        .unwrap();
+        let lockfile_toml = toml! {
+            [modules."_/foo"."1.0.0".foo]
+            name = "foo"
+            package_version = "1.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@1.0.0"
+            resolved = ""
+            resolved_source = "registry+foo"
+            abi = "none"
+            source = "foo.wasm"
+            [modules."_/baz"."1.0.0".baz]
+            name = "baz"
+            package_version = "1.0.0"
+            package_name = "_/baz"
+            package_path = "_/baz@1.0.0"
+            resolved = ""
+            resolved_source = "registry+baz"
+            abi = "none"
+            source = "baz.wasm"
+            [commands]
+        };
+        let lockfile: Lockfile = lockfile_toml.try_into().unwrap();
+        lockfile.save(tmp_dir.path()).unwrap();
+        for package in &["foo", "baz"] {
+            let package_dir = tmp_dir
+                .path()
+                .join(format!("wapm_packages/_/{}@1.0.0", package));
+            fs::create_dir_all(&package_dir).unwrap();
+            fs::write(package_dir.join(format!("{}.wasm", package)), "").unwrap();
+        }
+        let cache_dir = tmp_dir.path().join("cache");
+        fs::create_dir_all(&cache_dir).unwrap();
+        cache_resolver::write_cached_package(&cache_dir, "_/bar", "1.0.0");
+
+        let plan = ResolutionPlan::new_with_manifest(
+            tmp_dir.path(),
+            &manifest,
+            &AddedPackages::default(),
+            &RemovedPackages::default(),
+            &UpdateOptions::default(),
+        )
+        .unwrap();
+        let resolver = CacheResolver::new(&cache_dir, VersionSelector::Highest);
+        let resolved_packages =
+            ResolvedPackages::new_from_added_packages(plan.added_packages.clone(), &resolver)
+                .unwrap();
+
+        assert_eq!(
+            UpdateReport {
+                reused: vec!["_/foo 1.0.0".to_string()],
+                fetched: vec!["_/bar 1.0.0".to_string()],
+                warnings: vec![],
+                removed: vec!["_/baz 1.0.0".to_string()],
+            },
+            UpdateReport::for_manifest_plan(&plan, &resolved_packages, &manifest)
+        );
+    }
+
+    /// A resolver for updates that must not resolve anything
+    struct PanickingResolver;
+
+    impl<'a> Resolve<'a> for PanickingResolver {
+        fn sync_packages(
+            &self,
+            added_packages: Vec<PackageKey<'a>>,
+        ) -> Result<Vec<ResolvedPackage<'a>>, resolved_packages::Error> {
+            panic!("the resolver was asked for {:?}", added_packages)
+        }
+
+        fn preload(&self, _lockfile: &Lockfile, _packages: &[PackageKey<'a>]) {
+            panic!("the resolver was preloaded")
+        }
+    }
+
+    #[test]
+    fn updates_are_observed_from_start_to_finish() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest_toml =
+            "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n[dependencies]\n\"_/foo\" = \"1.0.0\"\n";
+        fs::write(tmp_dir.path().join("wapm.toml"), manifest_toml).unwrap();
+        let manifest: Manifest = toml::from_str(manifest_toml).unwrap();
+        let mut lockfile = foo_lockfile();
+        lockfile.dependencies_hash = Some(manifest.dependencies_hash(&HashSet::new(), true));
+        lockfile.save(tmp_dir.path()).unwrap();
+        let package_dir = tmp_dir.path().join("wapm_packages/_/foo@1.0.0");
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("foo.wasm"), "").unwrap();
+        let options = UpdateOptions {
+            locked: true,
+            ..UpdateOptions::default()
+        };
+        let observe = |added_packages: Vec<(&str, &str)>| {
+            let observer = RecordingObserver::default();
+            let result = update_with_resolver_observed(
+                added_packages,
+                vec![],
+                tmp_dir.path(),
+                &options,
+                &PanickingResolver,
+                &observer,
+            );
+            (result, observer.events.into_inner())
+        };
+
+        let (result, events) = observe(vec![]);
+        let (_, report) = result.unwrap();
+        assert_eq!(2, events.len());
+        assert_eq!(ResolutionEvent::Started, events[0]);
+        match &events[1] {
+            ResolutionEvent::Finished {
+                report: Some(finished_report),
+                ..
+            } => assert_eq!(report, *finished_report),
+            event => panic!("expected a finished update, got {:?}", event),
+        }
+
+        // a failed update still finishes, without a report
+        let (result, events) = observe(vec![("_/bar", "1.0.0")]);
+        assert!(result.is_err());
+        assert_eq!(ResolutionEvent::Started, events[0]);
+        match events.last() {
+            Some(ResolutionEvent::Finished { report: None, .. }) => {}
+            event => panic!("expected a failed update, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn locked_updates_never_call_the_resolver() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest_for = |dependencies: &str| -> Manifest {
+            toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n[dependencies]\n{}",
+                dependencies
+            ))
+            .unwrap()
+        };
+        let lockfile = foo_lockfile();
+        lockfile.save(tmp_dir.path()).unwrap();
+        let options = UpdateOptions {
+            locked: true,
+            ..UpdateOptions::default()
+        };
+        let update = |manifest: Manifest| {
+            update_with_manifest(
+                tmp_dir.path(),
+                manifest,
+                AddedPackages::default(),
+                RemovedPackages::default(),
+                &options,
+                &PanickingResolver,
+            )
+        };
+        let not_locked = |result: Result<(bool, UpdateReport), Error>| match result {
+            Err(Error::NotLocked(e)) => e.to_string(),
+            result => panic!("expected a not locked error, got {:?}", result),
+        };
+
+        // a locked package that is not installed would have to be resolved again
+        assert_eq!(
+            "Package \"_/foo\" is not locked or not installed, and locked mode never resolves packages",
+            not_locked(update(manifest_for("\"_/foo\" = \"1.0.0\"")))
+        );
+
+        let package_dir = tmp_dir.path().join("wapm_packages/_/foo@1.0.0");
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("foo.wasm"), "").unwrap();
+        let (_, report) = update(manifest_for("\"_/foo\" = \"1.0.0\"")).unwrap();
+        assert_eq!(vec!["_/foo 1.0.0".to_string()], report.reused);
+        let lockfile = Lockfile::open(tmp_dir.path()).unwrap();
+
+        // nothing is changed when a new dependency is not locked
+        let message = not_locked(update(manifest_for(
+            "\"_/foo\" = \"1.0.0\"\n\"_/baz\" = \"^2\"\n\"_/bar\" = \"1.0.0\"",
+        )));
+        assert!(message.starts_with("Package \"_/bar\" is not locked"));
+        assert_eq!(Lockfile::open(tmp_dir.path()).unwrap(), lockfile);
+    }
+
+    #[test]
+    fn feature_commands_follow_the_enabled_features() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let mut manifest: Manifest = toml::from_str(
+            "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n[features]\ndebug = []\n[[module]]\nname = \"test\"\nsource = \"test.wasm\"\nabi = \"wasi\"\n[[command]]\nname = \"test\"\nmodule = \"test\"\n[[command]]\nname = \"debug-dump\"\nmodule = \"test\"\nfeature = \"debug\"",
+        )
+        .unwrap();
+        manifest.base_directory_path = tmp_dir.path().to_path_buf();
+        let resolver = CacheResolver::new(tmp_dir.path().join("cache"), VersionSelector::Highest);
+        let command_names = |features: &[&str]| -> Vec<String> {
+            let options = UpdateOptions {
+                features: features.iter().map(|f| f.to_string()).collect(),
+                ..UpdateOptions::default()
+            };
+            update_with_manifest(
+                tmp_dir.path(),
+                manifest.clone(),
+                AddedPackages::default(),
+                RemovedPackages::default(),
+                &options,
+                &resolver,
+            )
+            .unwrap();
+            let lockfile = Lockfile::open(tmp_dir.path()).unwrap();
+            lockfile.command_names().map(String::from).collect()
+        };
+
+        assert_eq!(vec!["test"], command_names(&[]));
+        assert_eq!(vec!["debug-dump", "test"], command_names(&["debug"]));
+        assert_eq!(vec!["test"], command_names(&[]));
+    }
+
+    #[test]
+    fn editing_a_command_alias_updates_the_lockfile() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest_with_alias = |alias: &str| -> Manifest {
+            toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n[dependencies]\n\"_/foo\" = \"1.0.0\"\n[command-aliases]\n{} = \"foo\"",
+                alias
+            ))
+            .unwrap()
+        };
+        let lockfile_toml = toml! {
+            [modules."_/foo"."1.0.0".foo]
+            name = "foo"
+            package_version = "1.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@1.0.0"
+            resolved = ""
+            resolved_source = "registry+foo"
+            abi = "none"
+            source = "foo.wasm"
+            [commands.foo]
+            name = "foo"
+            package_name = "_/foo"
+            package_version = "1.0.0"
+            module = "foo"
+            is_top_level_dependency = true
+        };
+        let lockfile: Lockfile = lockfile_toml.try_into().unwrap();
+        lockfile.save(tmp_dir.path()).unwrap();
+        let package_dir = tmp_dir.path().join("wapm_packages/_/foo@1.0.0");
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("foo.wasm"), "").unwrap();
+        let update = |manifest: Manifest| {
+            update_with_manifest(
+                tmp_dir.path(),
+                manifest,
+                AddedPackages::default(),
+                RemovedPackages::default(),
+                &UpdateOptions::default(),
+                &PanickingResolver,
+            )
+            .unwrap();
+            Lockfile::open(tmp_dir.path())
+                .unwrap()
+                .command_names()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec!["f"], update(manifest_with_alias("f")));
+        // only the alias changed, the dependencies did not
+        assert_eq!(vec!["g"], update(manifest_with_alias("g")));
+    }
+
+    #[test]
+    fn lockfile_errors_keep_their_exit_code() {
+        let exit_code = |e: Error| {
+            anyhow::Error::from(e)
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<lockfile::LockfileError>())
+                .map(lockfile::LockfileError::exit_code)
+        };
+        let conflict = lockfile::LockfileError::VersionConflict {
+            package: "_/foo".to_string(),
+            requirements: vec!["^1".to_string(), "^2".to_string()],
+        };
+        assert_eq!(Some(21), exit_code(Error::VersionConflict(conflict)));
+        let too_deep = lockfile::LockfileError::DepthExceeded { depth: 3, limit: 2 };
+        assert_eq!(Some(27), exit_code(Error::DepthExceeded(too_deep)));
+        let not_locked = lockfile::LockfileError::NotLocked {
+            name: "_/foo".to_string(),
+        };
+        assert_eq!(Some(29), exit_code(Error::NotLocked(not_locked)));
+    }
+
+    #[test]
+    fn max_depth_limits_chains_of_installed_dependencies() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        // _/test -> _/a -> _/b -> _/c, the manifest depends on all of them
+        let mut lockfile_toml = String::new();
+        for (name, dependency) in &[("a", Some("b")), ("b", Some("c")), ("c", None)] {
+            lockfile_toml.push_str(&format!(
+                "[modules.\"_/{0}\".\"1.0.0\".{0}]\nname = \"{0}\"\npackage_version = \"1.0.0\"\npackage_name = \"_/{0}\"\npackage_path = \"_/{0}@1.0.0\"\nresolved = \"\"\nresolved_source = \"registry+{0}\"\nabi = \"none\"\nsource = \"{0}.wasm\"\n",
+                name
+            ));
+            let package_dir = tmp_dir
+                .path()
+                .join(format!("wapm_packages/_/{}@1.0.0", name));
+            fs::create_dir_all(&package_dir).unwrap();
+            fs::write(package_dir.join(format!("{}.wasm", name)), "").unwrap();
+            let dependencies = dependency
+                .map(|dependency| format!("[dependencies]\n\"_/{}\" = \"1.0.0\"\n", dependency))
+                .unwrap_or_default();
+            fs::write(
+                package_dir.join("wapm.toml"),
+                format!(
+                    "[package]\nname = \"_/{}\"\nversion = \"1.0.0\"\ndescription = \"\"\n{}",
+                    name, dependencies
+                ),
+            )
+            .unwrap();
+        }
+        lockfile_toml.push_str("[commands]\n");
+        let lockfile: Lockfile = toml::from_str(&lockfile_toml).unwrap();
+        lockfile.save(tmp_dir.path()).unwrap();
+        let manifest: Manifest = toml::from_str(
+            "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n[dependencies]\n\"_/a\" = \"1.0.0\"\n\"_/b\" = \"1.0.0\"\n\"_/c\" = \"1.0.0\"",
+        )
+        .unwrap();
+        let update = |max_depth: Option<usize>| {
+            let options = UpdateOptions {
+                max_depth,
+                ..UpdateOptions::default()
+            };
+            update_with_manifest(
+                tmp_dir.path(),
+                manifest.clone(),
+                AddedPackages::default(),
+                RemovedPackages::default(),
+                &options,
+                &PanickingResolver,
+            )
+        };
+
+        match update(Some(2)) {
+            Err(Error::DepthExceeded(lockfile::LockfileError::DepthExceeded { depth, limit })) => {
+                assert_eq!((3, 2), (depth, limit))
+            }
+            result => panic!("expected the depth to be exceeded, got {:?}", result),
+        }
+        assert_eq!(lockfile, Lockfile::open(tmp_dir.path()).unwrap());
+
+        update(Some(3)).unwrap();
+        let c = Lockfile::open(tmp_dir.path())
+            .unwrap()
+            .iter_modules()
+            .find(|(_, module)| module.name == "c")
+            .map(|(_, module)| module.dependents.clone())
+            .unwrap();
+        assert_eq!(vec!["_/b@1.0.0", "_/test@0.1.0"], c);
+
+        // an up to date lockfile is checked as well
+        assert!(matches!(update(Some(2)), Err(Error::DepthExceeded(_))));
+    }
+
+    #[test]
+    fn commands_declared_for_dependencies_are_removed_with_their_declaration() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let lockfile_toml = toml! {
+            [modules."_/bar"."1.0.0".bar]
+            name = "bar"
+            package_version = "1.0.0"
+            package_name = "_/bar"
+            package_path = "_/bar@1.0.0"
+            resolved = ""
+            resolved_source = "registry+bar"
+            abi = "none"
+            source = "bar.wasm"
+            [commands.bar]
+            name = "bar"
+            package_name = "_/bar"
+            package_version = "1.0.0"
+            module = "bar"
+            is_top_level_dependency = true
+        };
+        let lockfile: Lockfile = lockfile_toml.try_into().unwrap();
+        lockfile.save(tmp_dir.path()).unwrap();
+        let package_dir = tmp_dir.path().join("wapm_packages/_/bar@1.0.0");
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("bar.wasm"), "").unwrap();
+        fs::write(
+            package_dir.join("wapm.toml"),
+            "[package]\nname = \"_/bar\"\nversion = \"1.0.0\"\ndescription = \"\"\n[[module]]\nname = \"bar\"\nsource = \"bar.wasm\"\nabi = \"wasi\"\n[[command]]\nname = \"bar\"\nmodule = \"bar\"",
+        )
+        .unwrap();
+        let manifest_with_commands = |commands: &str| -> Manifest {
+            let mut manifest: Manifest = toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n[dependencies]\n\"_/bar\" = \"1.0.0\"\n[[module]]\nname = \"test\"\nsource = \"test.wasm\"\nabi = \"wasi\"\n[[module]]\nname = \"bar\"\nsource = \"bar.wasm\"\nabi = \"wasi\"\n[[command]]\nname = \"run\"\nmodule = \"test\"\n{}",
+                commands
+            ))
+            .unwrap();
+            manifest.base_directory_path = tmp_dir.path().to_path_buf();
+            manifest
+        };
+        let update = |manifest: Manifest| {
+            update_with_manifest(
+                tmp_dir.path(),
+                manifest,
+                AddedPackages::default(),
+                RemovedPackages::default(),
+                &UpdateOptions::default(),
+                &PanickingResolver,
+            )
+            .unwrap();
+            Lockfile::open(tmp_dir.path())
+                .unwrap()
+                .command_names()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        fs::write(tmp_dir.path().join("test.wasm"), "").unwrap();
+        fs::write(tmp_dir.path().join("bar.wasm"), "").unwrap();
+        let bar_extra =
+            "[[command]]\nname = \"bar-extra\"\nmodule = \"bar\"\npackage = \"_/bar 1.0.0\"";
+
+        assert_eq!(
+            vec!["bar", "bar-extra", "run"],
+            update(manifest_with_commands(bar_extra))
+        );
+        assert_eq!(vec!["bar", "run"], update(manifest_with_commands("")));
+        assert!(!tmp_dir
+            .path()
+            .join(crate::data::manifest::PACKAGES_DIR_NAME)
+            .join(bin_script::BIN_DIR_NAME)
+            .join("bar-extra")
+            .exists());
+    }
+
+    #[test]
+    fn regenerating_an_unchanged_lockfile_keeps_its_metadata() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest_with_commands = |commands: &str| -> Manifest {
+            let mut manifest: Manifest = toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n[[module]]\nname = \"test\"\nsource = \"test.wasm\"\nabi = \"wasi\"\n{}",
+                commands
+            ))
+            .unwrap();
+            manifest.base_directory_path = tmp_dir.path().to_path_buf();
+            manifest
+        };
+        let update = |manifest: Manifest| {
+            update_with_manifest(
+                tmp_dir.path(),
+                manifest,
+                AddedPackages::default(),
+                RemovedPackages::default(),
+                &UpdateOptions::default(),
+                &PanickingResolver,
+            )
+            .unwrap();
+            Lockfile::open(tmp_dir.path()).unwrap()
+        };
+        // without a dependencies hash, the lockfile is regenerated from scratch
+        let age_lockfile = || {
+            let mut lockfile = Lockfile::open(tmp_dir.path()).unwrap();
+            let metadata = lockfile.metadata.as_mut().unwrap();
+            metadata.generated_at = metadata.generated_at - chrono::Duration::days(30);
+            lockfile.dependencies_hash = None;
+            lockfile.save(tmp_dir.path()).unwrap();
+            lockfile.metadata.unwrap()
+        };
+        let run = "[[command]]\nname = \"run\"\nmodule = \"test\"";
+        update(manifest_with_commands(run));
+
+        let metadata = age_lockfile();
+        let lockfile = update(manifest_with_commands(run));
+        assert_eq!(Some(metadata.clone()), lockfile.metadata);
+
+        // a lockfile that changed gets new metadata
+        age_lockfile();
+        let lockfile = update(manifest_with_commands(""));
+        assert!(lockfile.metadata.unwrap().generated_at > metadata.generated_at);
+    }
+
+    #[test]
+    fn case_collisions_are_denied_when_configured() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest_with_commands = |commands: &[&str]| -> Manifest {
+            let commands: String = commands
+                .iter()
+                .map(|name| format!("[[command]]\nname = \"{}\"\nmodule = \"test\"\n", name))
+                .collect();
+            let mut manifest: Manifest = toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n[[module]]\nname = \"test\"\nsource = \"test.wasm\"\nabi = \"wasi\"\n{}",
+                commands
+            ))
+            .unwrap();
+            manifest.base_directory_path = tmp_dir.path().to_path_buf();
+            manifest
+        };
+        let manifest = manifest_with_commands(&["run", "Run"]);
+        let resolver = CacheResolver::new(tmp_dir.path().join("cache"), VersionSelector::Highest);
+        let update_manifest = |manifest: &Manifest, options: &UpdateOptions| {
+            update_with_manifest(
+                tmp_dir.path(),
+                manifest.clone(),
+                AddedPackages::default(),
+                RemovedPackages::default(),
+                options,
+                &resolver,
+            )
+        };
+        let update = |options: &UpdateOptions| update_manifest(&manifest, options);
+        let bin_dir = tmp_dir
+            .path()
+            .join(crate::data::manifest::PACKAGES_DIR_NAME)
+            .join(bin_script::BIN_DIR_NAME);
+
+        let options = UpdateOptions {
+            deny_case_collisions: true,
+            ..UpdateOptions::default()
+        };
+        match update(&options) {
+            Err(Error::CommandCaseCollision(first, second)) => {
+                assert_eq!(("Run", "run"), (first.as_str(), second.as_str()))
+            }
+            result => panic!("expected a case collision, got {:?}", result),
+        }
+        assert!(!tmp_dir
+            .path()
+            .join(crate::data::lock::LOCKFILE_NAME)
+            .exists());
+        assert!(!bin_dir.exists());
+
+        // by default the collision is only a warning
+        update(&UpdateOptions::default()).unwrap();
+        let lockfile = Lockfile::open(tmp_dir.path()).unwrap();
+        assert_eq!(
+            vec!["Run", "run"],
+            lockfile.command_names().collect::<Vec<_>>()
+        );
+
+        // an up to date lockfile is checked as well
+        assert!(matches!(
+            update(&options),
+            Err(Error::CommandCaseCollision(..))
+        ));
+
+        // and so are refreshed commands, before their bin scripts are saved
+        update_manifest(&manifest_with_commands(&["run"]), &UpdateOptions::default()).unwrap();
+        assert!(matches!(
+            update_manifest(&manifest_with_commands(&["run", "RUN"]), &options),
+            Err(Error::CommandCaseCollision(..))
+        ));
+        assert!(!bin_dir.join("RUN").exists());
+        assert_eq!(
+            vec!["run"],
+            Lockfile::open(tmp_dir.path())
+                .unwrap()
+                .command_names()
+                .collect::<Vec<_>>()
+        );
+    }
 }
@@ -0,0 +1,304 @@
+use crate::data::lock::lockfile::Lockfile;
+use crate::dataflow::cache_resolver::{cache_file_name, CacheResolver};
+use crate::dataflow::installed_packages::open_package_download;
+use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage, VersionSelector};
+use crate::dataflow::{normalize_global_namespace_package_name, PackageKey, WapmPackageKey};
+use semver::Version;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The Prefer Cache Resolver resolves packages from a cache directory like the `CacheResolver`,
+/// and only asks the wrapped resolver, e.g. the `RegistryResolver`, for the packages missing from
+/// the cache. The tarballs of those packages are downloaded into the cache, so the next install
+/// finds them there.
+pub struct PreferCacheResolver<R> {
+    cache_dir: PathBuf,
+    cache: CacheResolver,
+    resolver: R,
+}
+
+impl<R> PreferCacheResolver<R> {
+    /// The version selector picks the cached version of package ranges, the wrapped resolver
+    /// picks its own versions
+    pub fn new<P: AsRef<Path>>(
+        cache_dir: P,
+        resolver: R,
+        version_selector: VersionSelector,
+    ) -> Self {
+        Self {
+            cache_dir: cache_dir.as_ref().to_path_buf(),
+            cache: CacheResolver::new(cache_dir, version_selector),
+            resolver,
+        }
+    }
+
+    /// Downloads the package into the cache and returns the `file://` url of the cached tarball
+    fn populate_cache(&self, key: &WapmPackageKey, download_url: &str) -> anyhow::Result<String> {
+        fs::create_dir_all(&self.cache_dir)?;
+        let mut download = open_package_download(key, download_url)?;
+        // a failed download never leaves a partial tarball in the cache
+        let mut file = tempfile::NamedTempFile::new_in(&self.cache_dir)?;
+        io::copy(&mut download, &mut file)?;
+        let path = self.cache_dir.join(cache_file_name(key));
+        file.persist(&path)?;
+        let path = path.canonicalize()?;
+        reqwest::Url::from_file_path(&path)
+            .map(|url| url.to_string())
+            .map_err(|_| anyhow!("{} is not an absolute path", path.display()))
+    }
+}
+
+impl<'a, R: for<'b> Resolve<'b>> Resolve<'a> for PreferCacheResolver<R> {
+    fn sync_packages(
+        &self,
+        added_packages: Vec<PackageKey<'a>>,
+    ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+        let mut packages: Vec<ResolvedPackage<'a>> = vec![];
+        let mut uncached_packages = vec![];
+        // the cache resolver fails for the whole batch on a miss, so look up each package alone,
+        // in an index of the cache that is read once
+        let cached_packages = self.cache.cached_packages()?;
+        for key in added_packages {
+            match self.cache.resolve_cached(&cached_packages, key.clone()) {
+                Ok(cached) => packages.push(cached),
+                Err(Error::PackageNotCached { .. }) => uncached_packages.push(key),
+                Err(e) => return Err(e),
+            }
+        }
+        if uncached_packages.is_empty() {
+            return Ok(packages);
+        }
+
+        for (key, (download_url, signature)) in self.resolver.sync_packages(uncached_packages)? {
+            // a package that can not be cached is still installed from where it was resolved
+            let download_url = match self.populate_cache(&key, &download_url) {
+                Ok(cached_url) => cached_url,
+                Err(e) => {
+                    warn!("Could not add {} to the cache: {}", key, e);
+                    download_url
+                }
+            };
+            packages.push((key, (download_url, signature)));
+        }
+        Ok(packages)
+    }
+
+    /// Downloads the locked versions of the packages missing from the cache from their `resolved`
+    /// url, so the cache can resolve them if they still match. Other locked packages are not
+    /// downloaded, the update keeps them as they are installed.
+    fn preload(&self, lockfile: &Lockfile, packages: &[PackageKey<'a>]) {
+        let names: BTreeSet<Cow<str>> = packages
+            .iter()
+            .map(|key| normalize_global_namespace_package_name(key.name().into()))
+            .collect();
+        // name, version -> download url
+        let mut locked_packages: BTreeMap<(String, Version), String> = BTreeMap::new();
+        for (_, module) in lockfile.iter_modules() {
+            // local and git modules are not installed from a package tarball
+            if !module.resolved_source.starts_with("registry+") {
+                continue;
+            }
+            let name = normalize_global_namespace_package_name(module.package_name.as_str().into());
+            if !names.contains(&name) {
+                continue;
+            }
+            if let Ok(version) = Version::parse(&module.package_version) {
+                locked_packages
+                    .entry((module.package_name.clone(), version))
+                    .or_insert_with(|| module.resolved.clone());
+            }
+        }
+        if locked_packages.is_empty() {
+            return;
+        }
+        let cached_packages = match self.cache.cached_packages() {
+            Ok(cached_packages) => cached_packages,
+            Err(e) => {
+                warn!("Could not read the cache: {}", e);
+                return;
+            }
+        };
+        for ((name, version), download_url) in locked_packages {
+            let is_cached = cached_packages
+                .get(&name)
+                .is_some_and(|versions| versions.contains_key(&version));
+            if is_cached {
+                continue;
+            }
+            let key = WapmPackageKey {
+                name: name.into(),
+                version,
+            };
+            match self.populate_cache(&key, &download_url) {
+                Ok(_) => debug!("Preloaded {} into the cache", key),
+                Err(e) => warn!("Could not add {} to the cache: {}", key, e),
+            }
+        }
+    }
+
+    /// The cached versions and the versions of the wrapped resolver
+    fn available_versions(&self, name: &str) -> Result<Vec<Version>, Error> {
+        let mut versions = self.cache.available_versions(name)?;
+        versions.extend(self.resolver.available_versions(name)?);
+        versions.sort();
+        versions.dedup();
+        Ok(versions)
+    }
+
+    /// Cached tarballs are never yanked, the wrapped resolver knows about the registry's versions
+    fn is_yanked(&self, name: &str, version: &Version) -> Result<bool, Error> {
+        self.resolver.is_yanked(name, version)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::lock::lockfile::Lockfile;
+    use crate::dataflow::cache_resolver::{write_cached_package, CacheResolver};
+    use crate::dataflow::prefer_cache_resolver::PreferCacheResolver;
+    use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage, VersionSelector};
+    use crate::dataflow::{PackageKey, WapmPackageKey, WapmPackageRange};
+    use std::cell::RefCell;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A test resolver standing in for the registry. It resolves every package to version 1.0.0
+    /// of a tarball in its directory and records the packages it was asked for.
+    struct FakeNetworkResolver {
+        directory: PathBuf,
+        requested: RefCell<Vec<String>>,
+    }
+
+    impl<'a> Resolve<'a> for FakeNetworkResolver {
+        fn sync_packages(
+            &self,
+            added_packages: Vec<PackageKey<'a>>,
+        ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+            Ok(added_packages
+                .into_iter()
+                .map(|k| {
+                    self.requested.borrow_mut().push(k.to_string());
+                    let name = match k {
+                        PackageKey::WapmPackage(WapmPackageKey { name, .. })
+                        | PackageKey::WapmPackageRange(WapmPackageRange { name, .. }) => name,
+                    };
+                    let tarball = write_cached_package(&self.directory, &name, "1.0.0");
+                    let url = reqwest::Url::from_file_path(tarball).unwrap().to_string();
+                    (
+                        WapmPackageKey {
+                            name,
+                            version: semver::Version::new(1, 0, 0),
+                        },
+                        (url, None),
+                    )
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn missing_packages_are_resolved_and_cached() {
+        let cache_dir = tempfile::TempDir::new().unwrap();
+        let network_dir = tempfile::TempDir::new().unwrap();
+        write_cached_package(cache_dir.path(), "_/foo", "1.0.0");
+        let resolver = PreferCacheResolver::new(
+            cache_dir.path(),
+            FakeNetworkResolver {
+                directory: network_dir.path().to_path_buf(),
+                requested: RefCell::new(vec![]),
+            },
+            VersionSelector::Highest,
+        );
+        let foo = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0));
+        let bar = PackageKey::new_registry_package_range(
+            "_/bar",
+            semver::VersionReq::parse("^1").unwrap(),
+        );
+
+        let resolved = resolver.sync_packages(vec![foo, bar.clone()]).unwrap();
+        assert_eq!(2, resolved.len());
+        assert_eq!("_/bar 1.0.0", resolved[1].0.to_string());
+        assert!(resolved[1].1 .0.ends_with("_%252Fbar@1.0.0.tar.gz"));
+        assert!(!resolved[1]
+            .1
+             .0
+            .contains(&*network_dir.path().to_string_lossy()));
+        assert_eq!(1, resolver.resolver.requested.borrow().len());
+
+        // the second time the cache has it
+        fs::remove_dir_all(network_dir.path()).unwrap();
+        let resolved = CacheResolver::new(cache_dir.path(), VersionSelector::Highest)
+            .sync_packages(vec![bar])
+            .unwrap();
+        assert_eq!("_/bar 1.0.0", resolved[0].0.to_string());
+    }
+
+    #[test]
+    fn locked_packages_are_preloaded() {
+        let cache_dir = tempfile::TempDir::new().unwrap();
+        let network_dir = tempfile::TempDir::new().unwrap();
+        let tarball = write_cached_package(network_dir.path(), "_/foo", "1.0.0");
+        let resolved = reqwest::Url::from_file_path(tarball).unwrap().to_string();
+        let baz_tarball = write_cached_package(network_dir.path(), "_/baz", "1.0.0");
+        let baz_resolved = reqwest::Url::from_file_path(baz_tarball)
+            .unwrap()
+            .to_string();
+        let lockfile_toml = toml! {
+            [modules."_/foo"."1.0.0".foo]
+            name = "foo"
+            package_version = "1.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@1.0.0"
+            resolved = resolved
+            resolved_source = "registry+foo"
+            abi = "none"
+            source = "foo.wasm"
+            [modules."_/bar"."1.0.0".bar]
+            name = "bar"
+            package_version = "1.0.0"
+            package_name = "_/bar"
+            package_path = "_/bar@1.0.0"
+            resolved = "local"
+            resolved_source = "local"
+            abi = "none"
+            source = "bar.wasm"
+            [modules."_/baz"."1.0.0".baz]
+            name = "baz"
+            package_version = "1.0.0"
+            package_name = "_/baz"
+            package_path = "_/baz@1.0.0"
+            resolved = baz_resolved
+            resolved_source = "registry+baz"
+            abi = "none"
+            source = "baz.wasm"
+            [commands]
+        };
+        let lockfile: Lockfile = lockfile_toml.try_into().unwrap();
+        let resolver = PreferCacheResolver::new(
+            cache_dir.path(),
+            FakeNetworkResolver {
+                directory: network_dir.path().to_path_buf(),
+                requested: RefCell::new(vec![]),
+            },
+            VersionSelector::Highest,
+        );
+
+        // only the packages the update resolves again are preloaded
+        let foo =
+            PackageKey::new_registry_package_range("foo", semver::VersionReq::parse("^1").unwrap());
+        resolver.preload(&lockfile, &[foo]);
+        fs::remove_dir_all(network_dir.path()).unwrap();
+        let resolved = CacheResolver::new(cache_dir.path(), VersionSelector::Highest)
+            .sync_packages(vec![PackageKey::new_registry_package(
+                "_/foo",
+                semver::Version::new(1, 0, 0),
+            )])
+            .unwrap();
+        assert_eq!("_/foo 1.0.0", resolved[0].0.to_string());
+        assert_eq!(1, fs::read_dir(cache_dir.path()).unwrap().count());
+        assert!(resolver.resolver.requested.borrow().is_empty());
+    }
+}
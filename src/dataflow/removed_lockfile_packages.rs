@@ -4,7 +4,6 @@ use crate::dataflow::manifest_packages::ManifestPackages;
 use crate::dataflow::removed_packages::RemovedPackages;
 use crate::dataflow::{bin_script, PackageKey, WapmPackageKey};
 use std::collections::hash_map::HashMap;
-use std::collections::hash_set::HashSet;
 use std::path::Path;
 use thiserror::Error;
 
@@ -21,26 +20,22 @@ pub struct RemovedLockfilePackages<'a> {
 
 impl<'a> RemovedLockfilePackages<'a> {
     pub fn from_manifest_and_lockfile(
-        manifest_packages: &'a ManifestPackages<'a>,
-        lockfile_packages: &'a LockfilePackages<'a>,
+        manifest_packages: &ManifestPackages<'a>,
+        lockfile_packages: &LockfilePackages<'a>,
     ) -> Self {
-        // collect all removed packages
-        let old_package_keys: HashSet<_> = lockfile_packages.packages.keys().cloned().collect();
-        let packages = old_package_keys
-            .difference(&manifest_packages.packages)
-            .map(|key| {
-                (
-                    key.clone(),
-                    lockfile_packages.packages.get(&key).cloned().unwrap(),
-                )
-            })
+        // collect all removed packages, a locked version that satisfies a range is not removed
+        let packages = lockfile_packages
+            .packages
+            .iter()
+            .filter(|(key, _)| !manifest_packages.includes(key))
+            .map(|(key, data)| (key.clone(), data.clone()))
             .collect();
         Self { packages }
     }
 
     pub fn from_removed_packages_and_lockfile(
-        removed_packages: &'a RemovedPackages<'a>,
-        lockfile_packages: &'a LockfilePackages<'a>,
+        removed_packages: &RemovedPackages<'a>,
+        lockfile_packages: &LockfilePackages<'a>,
     ) -> Self {
         let packages = removed_packages
             .packages
@@ -132,6 +127,31 @@ mod test {
             .unwrap();
     }
 
+    #[test]
+    fn locked_version_matching_manifest_range_is_not_removed() {
+        let mut manifest_packages = ManifestPackages::default();
+        let mut packages = HashSet::default();
+        packages.insert(PackageKey::new_registry_package_range(
+            "_/foo",
+            semver::VersionReq::parse("^1.0.0").unwrap(),
+        ));
+        manifest_packages.packages = packages;
+
+        let mut lockfile_packages = LockfilePackages::default();
+        let mut packages = HashMap::default();
+        packages.insert(
+            PackageKey::new_registry_package("_/foo", semver::Version::parse("1.3.0").unwrap()),
+            LockfilePackage::default(),
+        );
+        lockfile_packages.packages = packages;
+
+        let removed_lockfile_packages = RemovedLockfilePackages::from_manifest_and_lockfile(
+            &manifest_packages,
+            &lockfile_packages,
+        );
+        assert!(removed_lockfile_packages.packages.is_empty());
+    }
+
     #[test]
     fn get_removed_lockfile_packages_from_removed_packages_and_lockfile() {
         let mut removed_packages = RemovedPackages::default();
@@ -0,0 +1,171 @@
+use crate::data::lock::lockfile::Lockfile;
+use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage};
+use crate::dataflow::{PackageKey, UpdateReport};
+use semver::Version;
+use std::time::{Duration, Instant};
+
+/// Something that happened while an update resolved its packages
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResolutionEvent {
+    /// The update started
+    Started,
+    /// A resolver was asked for the packages, as `<name> <version>` or `<name> <range>`
+    Resolving { packages: Vec<String> },
+    /// The resolver returned what the packages resolved to
+    Resolved {
+        packages: Vec<String>,
+        elapsed: Duration,
+    },
+    /// The resolver failed for the packages
+    ResolveFailed {
+        packages: Vec<String>,
+        error: String,
+        elapsed: Duration,
+    },
+    /// The update finished, with the packages it reused, fetched, and removed if it succeeded
+    Finished {
+        report: Option<UpdateReport>,
+        elapsed: Duration,
+    },
+}
+
+/// Receives the events of an update as they happen, e.g. to show progress. Updates only report
+/// to the observer, so the same resolution can be shown as a progress bar or as log lines.
+pub trait ResolutionObserver {
+    fn observe(&self, _event: &ResolutionEvent) {}
+}
+
+/// An observer that ignores every event
+pub struct NoopObserver;
+
+impl ResolutionObserver for NoopObserver {}
+
+/// The Observed Resolver wraps another resolver and reports the start and end of each of its
+/// calls to the observer.
+pub struct ObservedResolver<'r, R> {
+    resolver: &'r R,
+    observer: &'r dyn ResolutionObserver,
+}
+
+impl<'r, R> ObservedResolver<'r, R> {
+    pub fn new(resolver: &'r R, observer: &'r dyn ResolutionObserver) -> Self {
+        Self { resolver, observer }
+    }
+}
+
+impl<'a, 'r, R: for<'b> Resolve<'b>> Resolve<'a> for ObservedResolver<'r, R> {
+    fn sync_packages(
+        &self,
+        added_packages: Vec<PackageKey<'a>>,
+    ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+        let packages: Vec<String> = added_packages.iter().map(|key| key.to_string()).collect();
+        self.observer.observe(&ResolutionEvent::Resolving {
+            packages: packages.clone(),
+        });
+        let start = Instant::now();
+        let result = self.resolver.sync_packages(added_packages);
+        let elapsed = start.elapsed();
+        match result {
+            Ok(ref resolved_packages) => self.observer.observe(&ResolutionEvent::Resolved {
+                packages: resolved_packages
+                    .iter()
+                    .map(|(key, _)| key.to_string())
+                    .collect(),
+                elapsed,
+            }),
+            Err(ref e) => self.observer.observe(&ResolutionEvent::ResolveFailed {
+                packages,
+                error: e.to_string(),
+                elapsed,
+            }),
+        }
+        result
+    }
+
+    fn preload(&self, lockfile: &Lockfile, packages: &[PackageKey<'a>]) {
+        self.resolver.preload(lockfile, packages)
+    }
+
+    fn available_versions(&self, name: &str) -> Result<Vec<Version>, Error> {
+        self.resolver.available_versions(name)
+    }
+
+    fn is_yanked(&self, name: &str, version: &Version) -> Result<bool, Error> {
+        self.resolver.is_yanked(name, version)
+    }
+}
+
+/// An observer that keeps every event, for tests
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct RecordingObserver {
+    pub events: std::cell::RefCell<Vec<ResolutionEvent>>,
+}
+
+#[cfg(test)]
+impl ResolutionObserver for RecordingObserver {
+    fn observe(&self, event: &ResolutionEvent) {
+        self.events.borrow_mut().push(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dataflow::resolution_observer::{
+        ObservedResolver, RecordingObserver, ResolutionEvent,
+    };
+    use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage};
+    use crate::dataflow::PackageKey;
+
+    /// A test resolver that resolves the `_/foo` package and fails for every other package
+    struct FooResolver;
+
+    impl<'a> Resolve<'a> for FooResolver {
+        fn sync_packages(
+            &self,
+            added_packages: Vec<PackageKey<'a>>,
+        ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+            added_packages
+                .into_iter()
+                .map(|key| match key {
+                    PackageKey::WapmPackage(key) if key.name == "_/foo" => {
+                        Ok((key, ("url".to_string(), None)))
+                    }
+                    key => Err(Error::CouldNotResolvePackages(key.to_string())),
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn resolve_calls_are_observed() {
+        let observer = RecordingObserver::default();
+        let resolver = ObservedResolver::new(&FooResolver, &observer);
+        let foo = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0));
+        let bar = PackageKey::new_registry_package("_/bar", semver::Version::new(1, 0, 0));
+
+        resolver.sync_packages(vec![foo]).unwrap();
+        assert!(resolver.sync_packages(vec![bar]).is_err());
+
+        let events = observer.events.borrow();
+        assert_eq!(4, events.len());
+        assert_eq!(
+            ResolutionEvent::Resolving {
+                packages: vec!["_/foo 1.0.0".to_string()]
+            },
+            events[0]
+        );
+        match &events[1] {
+            ResolutionEvent::Resolved { packages, .. } => {
+                assert_eq!(vec!["_/foo 1.0.0".to_string()], *packages)
+            }
+            event => panic!("expected a resolved event, got {:?}", event),
+        }
+        match &events[3] {
+            ResolutionEvent::ResolveFailed { packages, .. } => {
+                assert_eq!(vec!["_/bar 1.0.0".to_string()], *packages)
+            }
+            event => panic!("expected a resolve failed event, got {:?}", event),
+        }
+    }
+}
@@ -0,0 +1,1156 @@
+use crate::data::manifest::Manifest;
+use crate::dataflow::added_packages::AddedPackages;
+use crate::dataflow::changed_manifest_packages::{ChangedManifestPackages, PackageChange};
+use crate::dataflow::git_packages::{GitDependency, GitPackage};
+use crate::dataflow::local_package::LocalPackage;
+use crate::dataflow::lockfile_packages::{LockfilePackages, LockfileResult};
+use crate::dataflow::manifest_packages::{self, ManifestPackages, VersionRequirements};
+use crate::dataflow::removed_lockfile_packages::RemovedLockfilePackages;
+use crate::dataflow::removed_packages::RemovedPackages;
+use crate::dataflow::retained_lockfile_packages::RetainedLockfilePackages;
+use crate::dataflow::{
+    detect_duplicate_packages, normalize_global_namespace_package_name, Error, PackageKey,
+    UpdateOptions, WapmPackageKey,
+};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// The changes an update will make, computed from the manifest and lockfile without resolving or
+/// installing anything. Updates execute this plan, so a dry-run reports exactly what a real run
+/// would do.
+#[derive(Clone, Debug)]
+pub struct ResolutionPlan<'a> {
+    /// Packages that will be resolved and installed
+    pub added_packages: AddedPackages<'a>,
+    /// How the changed manifest dependencies differ from the lockfile, empty without a manifest
+    pub changes: Vec<PackageChange<'a>>,
+    /// Git dependencies that will be fetched, because they are new, their revision changed, or
+    /// they are missing from `wapm_packages`
+    pub git_packages: Vec<GitPackage>,
+    /// Lockfile packages that are reused without resolving them again
+    pub retained_packages: RetainedLockfilePackages<'a>,
+    /// Lockfile packages that will be removed
+    pub removed_packages: RemovedLockfilePackages<'a>,
+    /// The manifest's own package, it is always regenerated from the manifest
+    pub local_package: Option<PackageKey<'a>>,
+    /// Whether the modules or commands of the manifest's own package differ from the lockfile
+    pub local_package_changed: bool,
+    /// The lockfile package keys before the update
+    pub initial_package_keys: HashSet<PackageKey<'a>>,
+    /// The requirements of packages that are required more than once, checked against the
+    /// resolved versions
+    pub version_requirements: VersionRequirements,
+}
+
+impl<'a> ResolutionPlan<'a> {
+    /// Plan an update of a manifest project. The lockfile keeps the packages that still satisfy
+    /// the manifest dependencies, including the optional dependencies of the enabled features
+    /// and the dev dependencies unless the options exclude them, and except the packages the
+    /// options update.
+    pub fn new_with_manifest(
+        directory: &Path,
+        manifest: &'a Manifest,
+        added_packages: &AddedPackages<'a>,
+        removed_packages: &RemovedPackages<'a>,
+        options: &UpdateOptions,
+    ) -> Result<Self, Error> {
+        validate_manifest(manifest)?;
+        let mut manifest_packages = ManifestPackages::new_from_manifest_and_added_packages(
+            manifest,
+            added_packages,
+            options,
+        )
+        .map_err(Error::ManifestError)?;
+
+        detect_duplicate_packages(&manifest_packages.packages)?;
+        let mut version_requirements = VersionRequirements::from_manifest_and_added_packages(
+            manifest,
+            added_packages,
+            options,
+        )
+        .map_err(Error::ManifestError)?;
+
+        // remove/uninstall packages
+        manifest_packages.remove_packages(removed_packages);
+        version_requirements.remove_packages(removed_packages);
+
+        // get lockfile data
+        let lockfile_result =
+            LockfileResult::find_in_directory(directory).for_root_package(&manifest.package.name);
+        let locked_build_packages: HashSet<PackageKey<'a>> = match lockfile_result {
+            LockfileResult::Lockfile(ref lockfile) => lockfile
+                .build_modules
+                .iter()
+                .flat_map(|(name, version_map)| {
+                    version_map.keys().map(move |version| {
+                        PackageKey::new_registry_package(name.clone(), version.clone())
+                    })
+                })
+                .collect(),
+            _ => HashSet::new(),
+        };
+        let mut lockfile_packages =
+            LockfilePackages::new_from_result(lockfile_result).map_err(Error::LockfileError)?;
+        // store lockfile package keys before updating it
+        let initial_package_keys = lockfile_packages.package_keys();
+
+        // git packages locked at the manifest's revision are kept like exact dependencies
+        let mut git_packages = vec![];
+        let mut locked_git_packages = HashMap::new();
+        for dependency in GitDependency::from_manifest(manifest) {
+            if removed_packages.packages.iter().any(|name| {
+                normalize_global_namespace_package_name(name.clone()) == dependency.name
+            }) {
+                continue;
+            }
+            match dependency.find_locked(&lockfile_packages) {
+                Some((key, commit)) => {
+                    manifest_packages.packages.insert(key.clone());
+                    locked_git_packages.insert(key, (dependency, commit));
+                }
+                None => git_packages.push(GitPackage {
+                    dependency,
+                    locked_commit: None,
+                }),
+            }
+        }
+
+        let mut changed_manifest_data =
+            ChangedManifestPackages::get_changed_packages_from_manifest_and_lockfile(
+                &manifest_packages,
+                &lockfile_packages,
+            );
+
+        // the packages to update are resolved again even if their locked version still
+        // satisfies the manifest, every other package keeps its locked version
+        let mut reresolved_lockfile_packages = HashSet::new();
+        for name in options.update_packages.iter() {
+            let name = normalize_global_namespace_package_name(Cow::Borrowed(name));
+            let key = manifest_packages
+                .packages
+                .iter()
+                .find(|key| key.name() == name)
+                .cloned()
+                .ok_or_else(|| Error::NotADependency(name.to_string()))?;
+            // an exact version resolves to the version it is locked at
+            if let PackageKey::WapmPackage(_) = key {
+                continue;
+            }
+            let locked_key = lockfile_packages
+                .packages
+                .keys()
+                .find(|locked_key| {
+                    locked_key.name() == name && manifest_packages.includes(locked_key)
+                })
+                .cloned();
+            if let Some(locked_key) = locked_key {
+                if changed_manifest_data.packages.insert(key.clone()) {
+                    let from = match locked_key {
+                        PackageKey::WapmPackage(WapmPackageKey { ref version, .. }) => {
+                            version.clone()
+                        }
+                        PackageKey::WapmPackageRange(_) => unreachable!(
+                            "Lockfile should only contain exact wapm package versions."
+                        ),
+                    };
+                    changed_manifest_data
+                        .changes
+                        .push(PackageChange::Reresolved { key, from });
+                }
+                reresolved_lockfile_packages.insert(locked_key);
+            }
+        }
+        // packages that became or stopped being build dependencies are installed again at their
+        // locked version, so their commands are dropped or installed
+        let build_only_names = manifest.build_only_dependencies();
+        for key in lockfile_packages.packages.keys() {
+            let name = normalize_global_namespace_package_name(Cow::Borrowed(key.name()));
+            let is_build_only = build_only_names.contains(name.as_ref());
+            if is_build_only != locked_build_packages.contains(key)
+                && manifest_packages.includes(key)
+                && !reresolved_lockfile_packages.contains(key)
+            {
+                changed_manifest_data.packages.insert(key.clone());
+                reresolved_lockfile_packages.insert(key.clone());
+            }
+        }
+        changed_manifest_data
+            .changes
+            .sort_by_key(|change| change.key().to_string());
+
+        let changes = changed_manifest_data.changes;
+        let packages_to_install = AddedPackages {
+            packages: changed_manifest_data.packages,
+        };
+
+        // reinstall missing packages, unless they are no longer dependencies of the manifest.
+        // Missing git packages are fetched again at their locked commit.
+        let mut missing_lockfile_packages = lockfile_packages.find_missing_packages(directory);
+        for (key, (dependency, commit)) in locked_git_packages {
+            if missing_lockfile_packages.remove(&key) {
+                git_packages.push(GitPackage {
+                    dependency,
+                    locked_commit: Some(commit),
+                });
+            }
+        }
+        let missing_lockfile_packages = missing_lockfile_packages
+            .into_iter()
+            .filter(|key| {
+                manifest_packages.includes(key) && !reresolved_lockfile_packages.contains(key)
+            })
+            .collect();
+        let added_packages = packages_to_install.add_missing_packages(missing_lockfile_packages);
+
+        let local_package =
+            LocalPackage::new_from_local_package_in_manifest(manifest, &options.features)
+                .map_err(Error::LocalPackageError)?;
+        let local_package_changed = local_package.differs_from(&lockfile_packages);
+
+        let mut removed_packages = RemovedLockfilePackages::from_manifest_and_lockfile(
+            &manifest_packages,
+            &lockfile_packages,
+        );
+        for key in reresolved_lockfile_packages {
+            if let Some(data) = lockfile_packages.packages.remove(&key) {
+                removed_packages.packages.insert(key, data);
+            }
+        }
+        let retained_packages = RetainedLockfilePackages::from_manifest_and_lockfile(
+            &manifest_packages,
+            lockfile_packages,
+        );
+
+        git_packages.sort_by(|a, b| a.dependency.name.cmp(&b.dependency.name));
+
+        Ok(Self {
+            added_packages,
+            changes,
+            git_packages,
+            retained_packages,
+            removed_packages,
+            local_package: Some(local_package.key),
+            local_package_changed,
+            initial_package_keys,
+            version_requirements,
+        })
+    }
+
+    /// Plan an update of a non-manifest project. All installations are retained in the
+    /// lockfile, and installs are additive.
+    pub fn new_with_no_manifest(
+        directory: &Path,
+        added_packages: AddedPackages<'a>,
+        removed_packages: RemovedPackages<'a>,
+    ) -> Result<Self, Error> {
+        // get lockfile data
+        let lockfile_result = LockfileResult::find_in_directory(directory);
+        let mut lockfile_packages =
+            LockfilePackages::new_from_result(lockfile_result).map_err(Error::LockfileError)?;
+        detect_duplicate_packages(&added_packages.packages)?;
+
+        // capture the initial lockfile keys before any modifications
+        let initial_package_keys: HashSet<_> = lockfile_packages.package_keys();
+
+        let removed_lockfile_packages = RemovedLockfilePackages::from_removed_packages_and_lockfile(
+            &removed_packages,
+            &lockfile_packages,
+        );
+
+        // remove/uninstall packages
+        lockfile_packages.remove_packages(removed_packages);
+
+        // check that the added packages are not already installed
+        let lockfile_package_keys = lockfile_packages.package_keys();
+        let added_packages = added_packages.prune_already_installed_packages(lockfile_package_keys);
+        // check for missing packages e.g. deleting stuff from wapm_packages
+        // install any missing or newly added packages
+        let missing_packages = lockfile_packages.find_missing_packages(directory);
+        let added_packages = added_packages.add_missing_packages(missing_packages);
+
+        let retained_packages = RetainedLockfilePackages::from_lockfile_packages(lockfile_packages);
+
+        Ok(Self {
+            added_packages,
+            changes: vec![],
+            git_packages: vec![],
+            retained_packages,
+            removed_packages: removed_lockfile_packages,
+            local_package: None,
+            local_package_changed: false,
+            initial_package_keys,
+            version_requirements: VersionRequirements::default(),
+        })
+    }
+
+    /// Errors if executing the plan would change the lockfile, like `cargo build --frozen`.
+    /// Reinstalling locked packages that are missing from `wapm_packages` is not a change.
+    pub fn assert_unchanged(&self) -> Result<(), Error> {
+        let new_keys: Vec<&PackageKey> = self
+            .added_packages
+            .packages
+            .iter()
+            .filter(|key| !self.initial_package_keys.contains(key))
+            .collect();
+        let mut added = vec![];
+        let mut changed = vec![];
+        for key in new_keys.iter() {
+            if self
+                .removed_packages
+                .packages
+                .keys()
+                .any(|k| same_name(k, key))
+            {
+                changed.push(key.to_string());
+            } else {
+                added.push(key.to_string());
+            }
+        }
+        // refetching a missing git package at its locked commit is not a change
+        let fetched_git_packages: Vec<&GitDependency> = self
+            .git_packages
+            .iter()
+            .filter(|git_package| git_package.locked_commit.is_none())
+            .map(|git_package| &git_package.dependency)
+            .collect();
+        for dependency in fetched_git_packages.iter() {
+            if self
+                .removed_packages
+                .packages
+                .keys()
+                .any(|k| package_name(k) == dependency.name)
+            {
+                changed.push(dependency.to_string());
+            } else {
+                added.push(dependency.to_string());
+            }
+        }
+        let mut removed: Vec<String> = self
+            .removed_packages
+            .packages
+            .keys()
+            .filter(|key| Some(*key) != self.local_package.as_ref())
+            // a removed package with a new version installed was already reported as changed
+            .filter(|key| !new_keys.iter().any(|k| same_name(k, key)))
+            .filter(|key| {
+                !fetched_git_packages
+                    .iter()
+                    .any(|dependency| package_name(key) == dependency.name)
+            })
+            .map(|key| key.to_string())
+            .collect();
+        if self.local_package_changed {
+            if let Some(ref key) = self.local_package {
+                changed.push(key.to_string());
+            }
+        }
+        if added.is_empty() && changed.is_empty() && removed.is_empty() {
+            return Ok(());
+        }
+        added.sort();
+        changed.sort();
+        removed.sort();
+        Err(Error::WouldChange {
+            added,
+            changed,
+            removed,
+        })
+    }
+
+    /// Keep the locked version of every package the plan would install, used when resolving the
+    /// packages fails, e.g. without network access. Errors with the first package that has no
+    /// locked version to fall back to.
+    pub fn fall_back_to_lockfile(&mut self) -> Result<(), PackageKey<'a>> {
+        for key in self.added_packages.packages.iter() {
+            if self.initial_package_keys.contains(key) {
+                warn!("Could not reinstall package {}", key);
+                continue;
+            }
+            let locked_key = self
+                .removed_packages
+                .packages
+                .keys()
+                .find(|locked_key| same_name(locked_key, key))
+                .cloned()
+                .ok_or_else(|| key.clone())?;
+            warn!(
+                "Could not resolve package {}, keeping the locked version {}",
+                key, locked_key
+            );
+            let data = self.removed_packages.packages.remove(&locked_key).unwrap();
+            self.retained_packages.packages.insert(locked_key, data);
+        }
+        self.added_packages.packages.clear();
+        self.changes.clear();
+        Ok(())
+    }
+
+    /// Copy the borrowed package names so the plan can outlive the manifest it was created from.
+    pub fn into_owned(self) -> ResolutionPlan<'static> {
+        ResolutionPlan {
+            added_packages: AddedPackages {
+                packages: self
+                    .added_packages
+                    .packages
+                    .into_iter()
+                    .map(PackageKey::into_owned)
+                    .collect(),
+            },
+            changes: self
+                .changes
+                .into_iter()
+                .map(PackageChange::into_owned)
+                .collect(),
+            git_packages: self.git_packages,
+            retained_packages: RetainedLockfilePackages {
+                packages: self
+                    .retained_packages
+                    .packages
+                    .into_iter()
+                    .map(|(key, data)| (key.into_owned(), data))
+                    .collect(),
+            },
+            removed_packages: RemovedLockfilePackages {
+                packages: self
+                    .removed_packages
+                    .packages
+                    .into_iter()
+                    .map(|(key, data)| (key.into_owned(), data))
+                    .collect(),
+            },
+            local_package: self.local_package.map(PackageKey::into_owned),
+            local_package_changed: self.local_package_changed,
+            initial_package_keys: self
+                .initial_package_keys
+                .into_iter()
+                .map(PackageKey::into_owned)
+                .collect(),
+            version_requirements: self.version_requirements,
+        }
+    }
+}
+
+/// Fails early on a manifest that is not valid, instead of e.g. leaving out commands that refer
+/// to modules the manifest does not have
+pub fn validate_manifest(manifest: &Manifest) -> Result<(), Error> {
+    manifest
+        .validate()
+        .map_err(|e| Error::ManifestError(manifest_packages::Error::InvalidManifest(e.to_string())))
+}
+
+fn package_name(key: &PackageKey) -> String {
+    key.name().to_string()
+}
+
+fn same_name(a: &PackageKey, b: &PackageKey) -> bool {
+    package_name(a) == package_name(b)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::lock::lockfile::{foo_lockfile, Lockfile, LockfileError};
+    use crate::data::manifest::Manifest;
+    use crate::dataflow::added_packages::AddedPackages;
+    use crate::dataflow::changed_manifest_packages::PackageChange;
+    use crate::dataflow::manifest_packages;
+    use crate::dataflow::removed_packages::RemovedPackages;
+    use crate::dataflow::resolution_plan::ResolutionPlan;
+    use crate::dataflow::{Error, PackageKey, UpdateOptions, WapmPackageKey};
+    use std::path::Path;
+
+    #[test]
+    fn plan_new_manifest_dependencies() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "_/test"
+            version = "0.1.0"
+            description = ""
+
+            [dependencies]
+            "_/foo" = "1.0.0"
+            "_/bar" = "^2"
+            "#,
+        )
+        .unwrap();
+
+        let plan = ResolutionPlan::new_with_manifest(
+            tmp_dir.path(),
+            &manifest,
+            &AddedPackages::default(),
+            &RemovedPackages::default(),
+            &UpdateOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(2, plan.added_packages.packages.len());
+        assert!(plan
+            .added_packages
+            .packages
+            .contains(&PackageKey::new_registry_package(
+                "_/foo",
+                semver::Version::new(1, 0, 0)
+            )));
+        assert!(plan.retained_packages.packages.is_empty());
+        assert!(plan.removed_packages.packages.is_empty());
+        assert_eq!(
+            Some(PackageKey::new_registry_package(
+                "_/test",
+                semver::Version::new(0, 1, 0)
+            )),
+            plan.local_package
+        );
+    }
+
+    /// Lock the package `_/foo 1.0.0` with the module `foo` in the directory
+    fn lock_foo(directory: &Path) {
+        foo_lockfile().save(directory).unwrap();
+    }
+
+    #[test]
+    fn packages_moved_to_the_build_dependencies_are_installed_again() {
+        use crate::data::manifest::PACKAGES_DIR_NAME;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        lock_foo(tmp_dir.path());
+        let package_dir = tmp_dir.path().join(PACKAGES_DIR_NAME).join("_/foo@1.0.0");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        std::fs::write(package_dir.join("foo.wasm"), b"\0asm").unwrap();
+        let added_packages_for = |section: &str| {
+            let manifest: Manifest = toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n\n[{}]\n\"_/foo\" = \"1.0.0\"",
+                section
+            ))
+            .unwrap();
+            let plan = ResolutionPlan::new_with_manifest(
+                tmp_dir.path(),
+                &manifest,
+                &AddedPackages::default(),
+                &RemovedPackages::default(),
+                &UpdateOptions::default(),
+            )
+            .unwrap();
+            plan.added_packages
+                .packages
+                .iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(added_packages_for("dependencies").is_empty());
+        assert_eq!(
+            vec!["_/foo 1.0.0".to_string()],
+            added_packages_for("build-dependencies")
+        );
+    }
+
+    #[test]
+    fn frozen_plan_detects_lockfile_changes() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        lock_foo(tmp_dir.path());
+        let plan_for = |dependencies: &str| {
+            let manifest: Manifest = toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n\n[dependencies]\n{}",
+                dependencies
+            ))
+            .unwrap();
+            ResolutionPlan::new_with_manifest(
+                tmp_dir.path(),
+                &manifest,
+                &AddedPackages::default(),
+                &RemovedPackages::default(),
+                &UpdateOptions::default(),
+            )
+            .unwrap()
+            .into_owned()
+        };
+
+        // the locked package is missing from wapm_packages, reinstalling it is not a change
+        plan_for("\"_/foo\" = \"1.0.0\"")
+            .assert_unchanged()
+            .unwrap();
+
+        match plan_for("\"_/foo\" = \"2.0.0\"\n\"_/bar\" = \"1.0.0\"").assert_unchanged() {
+            Err(Error::WouldChange {
+                added,
+                changed,
+                removed,
+            }) => {
+                assert_eq!(vec!["_/bar 1.0.0".to_string()], added);
+                assert_eq!(vec!["_/foo 2.0.0".to_string()], changed);
+                assert!(removed.is_empty());
+            }
+            result => panic!("expected the lockfile to change, got {:?}", result),
+        }
+
+        match plan_for("").assert_unchanged() {
+            Err(Error::WouldChange { removed, .. }) => {
+                assert_eq!(vec!["_/foo 1.0.0".to_string()], removed)
+            }
+            result => panic!("expected the lockfile to change, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn self_dependencies_are_rejected() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        for section in &["dependencies", "dev-dependencies"] {
+            let manifest: Manifest = toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n\n[{}]\n\"test\" = \"0.1.0\"",
+                section
+            ))
+            .unwrap();
+            let result = ResolutionPlan::new_with_manifest(
+                tmp_dir.path(),
+                &manifest,
+                &AddedPackages::default(),
+                &RemovedPackages::default(),
+                &UpdateOptions::default(),
+            );
+            match result {
+                Err(Error::ManifestError(manifest_packages::Error::SelfDependency { name })) => {
+                    assert_eq!("test", name)
+                }
+                result => panic!("expected a self dependency error, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn empty_versions_are_rejected() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        for version in &["", "  "] {
+            let manifest: Manifest = toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n\n[dependencies]\n\"_/foo\" = \"{}\"",
+                version
+            ))
+            .unwrap();
+            let result = ResolutionPlan::new_with_manifest(
+                tmp_dir.path(),
+                &manifest,
+                &AddedPackages::default(),
+                &RemovedPackages::default(),
+                &UpdateOptions::default(),
+            );
+            match result {
+                Err(Error::ManifestError(manifest_packages::Error::EmptyVersion { name })) => {
+                    assert_eq!("_/foo", name)
+                }
+                result => panic!("expected an empty version error, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn lockfiles_of_other_packages_are_rejected() {
+        use crate::dataflow::lockfile_packages::LockfileError;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        lock_foo(tmp_dir.path());
+        let manifest: Manifest = toml::from_str(
+            "[package]\nname = \"test\"\nversion = \"0.1.0\"\ndescription = \"\"\n\n[dependencies]\n\"_/foo\" = \"1.0.0\"",
+        )
+        .unwrap();
+        let plan_with_root_package = |root_package: &str| {
+            let mut lockfile = Lockfile::open(tmp_dir.path()).unwrap();
+            lockfile.root_package = Some(root_package.to_string());
+            lockfile.save(tmp_dir.path()).unwrap();
+            ResolutionPlan::new_with_manifest(
+                tmp_dir.path(),
+                &manifest,
+                &AddedPackages::default(),
+                &RemovedPackages::default(),
+                &UpdateOptions::default(),
+            )
+            .map(|_| ())
+        };
+
+        // the lockfile of an older version of the package is fine
+        plan_with_root_package("_/test 0.0.9").unwrap();
+        match plan_with_root_package("_/other 1.0.0") {
+            Err(Error::LockfileError(LockfileError::WrongLockfile { expected, found })) => {
+                assert_eq!("test", expected);
+                assert_eq!("_/other 1.0.0", found);
+            }
+            result => panic!("expected a wrong lockfile error, got {:?}", result),
+        }
+        // the root package is a package key
+        plan_with_root_package(" _/test 0.0.9 ").unwrap();
+        assert!(matches!(
+            plan_with_root_package("_/test"),
+            Err(Error::LockfileError(LockfileError::WrongLockfile { .. }))
+        ));
+    }
+
+    #[test]
+    fn short_versions_match_the_locked_version() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        lock_foo(tmp_dir.path());
+        let plan_for = |version: &str| {
+            let manifest: Manifest = toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n\n[dependencies]\n\"_/foo\" = \"{}\"",
+                version
+            ))
+            .unwrap();
+            ResolutionPlan::new_with_manifest(
+                tmp_dir.path(),
+                &manifest,
+                &AddedPackages::default(),
+                &RemovedPackages::default(),
+                &UpdateOptions::default(),
+            )
+            .unwrap()
+            .into_owned()
+        };
+
+        // versions are compared parsed, `1.0` is the range `^1.0` which the locked 1.0.0 satisfies
+        let plan = plan_for("1.0");
+        assert!(plan.changes.is_empty());
+        assert!(plan
+            .retained_packages
+            .packages
+            .contains_key(&PackageKey::new_registry_package(
+                "_/foo",
+                semver::Version::new(1, 0, 0)
+            )));
+        plan.assert_unchanged().unwrap();
+
+        // pre-release and build metadata are kept exactly
+        let plan = plan_for("1.0.0-beta.1+build.5");
+        assert!(plan
+            .added_packages
+            .packages
+            .iter()
+            .any(|key| key.to_string() == "_/foo 1.0.0-beta.1+build.5"));
+    }
+
+    #[test]
+    fn features_enable_optional_dependencies() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        lock_foo(tmp_dir.path());
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "_/test"
+            version = "0.1.0"
+            description = ""
+
+            [optional-dependencies]
+            "_/foo" = "1.0.0"
+
+            [features]
+            foo = ["_/foo"]
+            "#,
+        )
+        .unwrap();
+        let plan_with = |features: &[&str]| {
+            ResolutionPlan::new_with_manifest(
+                tmp_dir.path(),
+                &manifest,
+                &AddedPackages::default(),
+                &RemovedPackages::default(),
+                &UpdateOptions {
+                    features: features.iter().map(|f| f.to_string()).collect(),
+                    ..UpdateOptions::default()
+                },
+            )
+        };
+        let foo = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0));
+
+        // with the feature the locked module is kept, without it the module is removed
+        let plan = plan_with(&["foo"]).unwrap();
+        assert!(!plan.removed_packages.packages.contains_key(&foo));
+        let plan = plan_with(&[]).unwrap();
+        assert!(plan.removed_packages.packages.contains_key(&foo));
+        assert!(!plan.retained_packages.packages.contains_key(&foo));
+        assert!(!plan.added_packages.packages.contains(&foo));
+
+        match plan_with(&["bar"]) {
+            Err(Error::ManifestError(e)) => {
+                assert_eq!(
+                    "The feature \"bar\" is not defined in the manifest.",
+                    e.to_string()
+                )
+            }
+            result => panic!("expected an unknown feature, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn fall_back_to_locked_versions() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        lock_foo(tmp_dir.path());
+        let manifest_with = |dependencies: &str| -> Manifest {
+            toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n\n[dependencies]\n{}",
+                dependencies
+            ))
+            .unwrap()
+        };
+        let foo = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0));
+
+        let manifest = manifest_with("\"_/foo\" = \"2.0.0\"");
+        let mut plan = ResolutionPlan::new_with_manifest(
+            tmp_dir.path(),
+            &manifest,
+            &AddedPackages::default(),
+            &RemovedPackages::default(),
+            &UpdateOptions::default(),
+        )
+        .unwrap();
+        plan.fall_back_to_lockfile().unwrap();
+        assert!(plan.added_packages.packages.is_empty());
+        assert!(plan.retained_packages.packages.contains_key(&foo));
+        assert!(!plan.removed_packages.packages.contains_key(&foo));
+
+        // a new dependency has no locked version
+        let manifest = manifest_with("\"_/foo\" = \"1.0.0\"\n\"_/bar\" = \"1.0.0\"");
+        let mut plan = ResolutionPlan::new_with_manifest(
+            tmp_dir.path(),
+            &manifest,
+            &AddedPackages::default(),
+            &RemovedPackages::default(),
+            &UpdateOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            PackageKey::new_registry_package("_/bar", semver::Version::new(1, 0, 0)),
+            plan.fall_back_to_lockfile().unwrap_err()
+        );
+    }
+
+    #[test]
+    fn git_dependencies_are_locked_to_their_revision() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let lockfile_toml = toml! {
+            [modules."_/foo"."1.0.0".foo]
+            name = "foo"
+            package_version = "1.0.0"
+            package_name = "_/foo"
+            package_path = "_/foo@1.0.0"
+            resolved = "https://example.com/foo.git"
+            resolved_source = "git+https://example.com/foo.git?rev=v1#0123abcd"
+            abi = "none"
+            source = "foo.wasm"
+            [commands]
+        };
+        let lockfile: Lockfile = lockfile_toml.try_into().unwrap();
+        lockfile.save(tmp_dir.path()).unwrap();
+        let foo = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0));
+        let manifest_with_rev = |rev: &str| -> Manifest {
+            toml::from_str(&format!(
+                "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n\n[dependencies]\nfoo = {{ git = \"https://example.com/foo.git\", rev = \"{}\" }}",
+                rev
+            ))
+            .unwrap()
+        };
+        let plan_for = |manifest: &Manifest| {
+            ResolutionPlan::new_with_manifest(
+                tmp_dir.path(),
+                manifest,
+                &AddedPackages::default(),
+                &RemovedPackages::default(),
+                &UpdateOptions::default(),
+            )
+            .unwrap()
+            .into_owned()
+        };
+
+        // a missing locked package is fetched again at the locked commit
+        let manifest = manifest_with_rev("v1");
+        let plan = plan_for(&manifest);
+        assert_eq!(1, plan.git_packages.len());
+        assert_eq!(
+            Some("0123abcd".to_string()),
+            plan.git_packages[0].locked_commit
+        );
+        assert!(plan.added_packages.packages.is_empty());
+        assert!(plan.retained_packages.packages.contains_key(&foo));
+        assert!(plan.assert_unchanged().is_ok());
+
+        let package_dir = tmp_dir.path().join("wapm_packages/_/foo@1.0.0");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        std::fs::write(package_dir.join("foo.wasm"), "").unwrap();
+        let plan = plan_for(&manifest);
+        assert!(plan.git_packages.is_empty());
+        assert!(plan.removed_packages.packages.is_empty());
+
+        // a changed revision is fetched and replaces the locked package
+        let manifest = manifest_with_rev("v2");
+        let plan = plan_for(&manifest);
+        assert_eq!(None, plan.git_packages[0].locked_commit);
+        assert!(plan.removed_packages.packages.contains_key(&foo));
+        match plan.assert_unchanged() {
+            Err(Error::WouldChange {
+                added,
+                changed,
+                removed,
+            }) => {
+                assert!(added.is_empty());
+                assert_eq!(
+                    vec!["_/foo (git https://example.com/foo.git v2)".to_string()],
+                    changed
+                );
+                assert!(removed.is_empty());
+            }
+            result => panic!("expected the lockfile to change, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn dev_dependencies_can_be_excluded() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        lock_foo(tmp_dir.path());
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "_/test"
+            version = "0.1.0"
+            description = ""
+
+            [dev-dependencies]
+            "_/foo" = "1.0.0"
+            "#,
+        )
+        .unwrap();
+        let plan_with = |exclude_dev_dependencies: bool| {
+            ResolutionPlan::new_with_manifest(
+                tmp_dir.path(),
+                &manifest,
+                &AddedPackages::default(),
+                &RemovedPackages::default(),
+                &UpdateOptions {
+                    exclude_dev_dependencies,
+                    ..UpdateOptions::default()
+                },
+            )
+            .unwrap()
+        };
+        let foo = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0));
+
+        // dev dependencies are installed by default, and removed when they are excluded
+        let plan = plan_with(false);
+        assert!(plan.retained_packages.packages.contains_key(&foo));
+        let plan = plan_with(true);
+        assert!(plan.removed_packages.packages.contains_key(&foo));
+        assert!(plan.assert_unchanged().is_err());
+    }
+
+    #[test]
+    fn conflicting_version_requirements() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "_/test"
+            version = "0.1.0"
+            description = ""
+
+            [dependencies]
+            foo = "^1"
+
+            [dev-dependencies]
+            "_/foo" = "^2"
+            "#,
+        )
+        .unwrap();
+        let plan = ResolutionPlan::new_with_manifest(
+            tmp_dir.path(),
+            &manifest,
+            &AddedPackages::default(),
+            &RemovedPackages::default(),
+            &UpdateOptions::default(),
+        )
+        .unwrap();
+        let versions = |versions: &[&str]| -> Vec<WapmPackageKey> {
+            versions
+                .iter()
+                .map(|version| WapmPackageKey {
+                    name: "_/foo".into(),
+                    version: semver::Version::parse(version).unwrap(),
+                })
+                .collect()
+        };
+
+        match plan
+            .version_requirements
+            .check(&versions(&["1.2.0", "2.0.0"]))
+        {
+            Err(LockfileError::VersionConflict {
+                package,
+                requirements,
+            }) => {
+                assert_eq!("_/foo", package);
+                assert_eq!(
+                    vec![
+                        "_/test@0.1.0 [dependencies] requires >=1.0.0, <2.0.0",
+                        "_/test@0.1.0 [dev-dependencies] requires >=2.0.0, <3.0.0",
+                    ],
+                    requirements
+                );
+            }
+            result => panic!("expected a version conflict, got {:?}", result),
+        }
+        assert!(plan
+            .version_requirements
+            .check(&versions(&["1.2.0"]))
+            .is_err());
+
+        // added packages replace the manifest requirement, and are only checked against the rest
+        let added_packages = AddedPackages::new_from_str_pairs(vec![("foo", "2.1.0")]).unwrap();
+        let plan = ResolutionPlan::new_with_manifest(
+            tmp_dir.path(),
+            &manifest,
+            &added_packages,
+            &RemovedPackages::default(),
+            &UpdateOptions::default(),
+        )
+        .unwrap();
+        assert!(plan
+            .version_requirements
+            .check(&versions(&["2.1.0"]))
+            .is_ok());
+        let plan = ResolutionPlan::new_with_manifest(
+            tmp_dir.path(),
+            &manifest,
+            &AddedPackages::default(),
+            &RemovedPackages::default(),
+            &UpdateOptions {
+                exclude_dev_dependencies: true,
+                ..UpdateOptions::default()
+            },
+        )
+        .unwrap();
+        assert!(plan.version_requirements.packages.is_empty());
+    }
+
+    #[test]
+    fn overrides_replace_every_requirement() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "_/test"
+            version = "0.1.0"
+            description = ""
+
+            [dependencies]
+            foo = "^1"
+
+            [dev-dependencies]
+            "_/foo" = "^2"
+
+            [overrides]
+            foo = "1.0.3"
+            "#,
+        )
+        .unwrap();
+        let added_packages = AddedPackages::new_from_str_pairs(vec![("foo", "2.1.0")]).unwrap();
+        let plan = ResolutionPlan::new_with_manifest(
+            tmp_dir.path(),
+            &manifest,
+            &added_packages,
+            &RemovedPackages::default(),
+            &UpdateOptions::default(),
+        )
+        .unwrap();
+        let override_key = WapmPackageKey {
+            name: "_/foo".into(),
+            version: semver::Version::new(1, 0, 3),
+        };
+
+        assert_eq!(
+            vec!["_/foo 1.0.3".to_string()],
+            plan.added_packages
+                .packages
+                .iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>()
+        );
+        assert!(plan.version_requirements.check(&[override_key]).is_ok());
+    }
+
+    #[test]
+    fn wildcard_dependency_matches_any_locked_version() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let manifest: Manifest = toml::from_str(
+            "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n\n[dependencies]\nfoo = \"*\"",
+        )
+        .unwrap();
+        let plan = || {
+            ResolutionPlan::new_with_manifest(
+                tmp_dir.path(),
+                &manifest,
+                &AddedPackages::default(),
+                &RemovedPackages::default(),
+                &UpdateOptions::default(),
+            )
+            .unwrap()
+        };
+
+        // the first update resolves any version, which resolvers pick the newest of
+        let any_foo = PackageKey::new_registry_package_range(
+            "_/foo",
+            semver::VersionReq::parse("*").unwrap(),
+        );
+        let first_plan = plan();
+        assert!(first_plan.added_packages.packages.contains(&any_foo));
+        assert!(first_plan.assert_unchanged().is_err());
+
+        // once locked, the locked version satisfies the wildcard and nothing is resolved again
+        lock_foo(tmp_dir.path());
+        let locked_plan = plan();
+        let foo = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0));
+        assert!(locked_plan.retained_packages.packages.contains_key(&foo));
+        assert!(locked_plan.changes.is_empty());
+        locked_plan.assert_unchanged().unwrap();
+    }
+
+    #[test]
+    fn update_packages_are_resolved_again() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        lock_foo(tmp_dir.path());
+        let manifest: Manifest = toml::from_str(
+            "[package]\nname = \"_/test\"\nversion = \"0.1.0\"\ndescription = \"\"\n\n[dependencies]\nfoo = \"^1\"",
+        )
+        .unwrap();
+        let plan_updating = |name: &str| {
+            ResolutionPlan::new_with_manifest(
+                tmp_dir.path(),
+                &manifest,
+                &AddedPackages::default(),
+                &RemovedPackages::default(),
+                &UpdateOptions {
+                    update_packages: vec![name.to_string()],
+                    ..UpdateOptions::default()
+                },
+            )
+        };
+
+        // the locked version satisfies the range, but is resolved again instead of reinstalled
+        let plan = plan_updating("foo").unwrap();
+        let foo_range = PackageKey::new_registry_package_range(
+            "_/foo",
+            semver::VersionReq::parse("^1").unwrap(),
+        );
+        let foo = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0));
+        assert_eq!(1, plan.added_packages.packages.len());
+        assert!(plan.added_packages.packages.contains(&foo_range));
+        assert!(plan.retained_packages.packages.is_empty());
+        assert!(plan.removed_packages.packages.contains_key(&foo));
+        match plan.changes.as_slice() {
+            [PackageChange::Reresolved { key, from }] => {
+                assert_eq!(foo_range, *key);
+                assert_eq!(semver::Version::new(1, 0, 0), *from);
+            }
+            changes => panic!("expected foo to be resolved again, got {:?}", changes),
+        }
+
+        match plan_updating("bar") {
+            Err(Error::NotADependency(name)) => assert_eq!("_/bar", name),
+            result => panic!("expected bar not to be a dependency, got {:?}", result),
+        }
+    }
+}
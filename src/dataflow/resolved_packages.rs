@@ -1,10 +1,11 @@
 use crate::constants::*;
+use crate::data::lock::lockfile::Lockfile;
 use crate::dataflow::added_packages::AddedPackages;
 use crate::dataflow::{PackageKey, WapmPackageKey, WapmPackageRange};
 use crate::graphql::{execute_query, DateTime};
 use crate::keys;
 use graphql_client::*;
-use semver::Version;
+use semver::{Version, VersionReq};
 use std::borrow::Cow::Owned;
 use std::collections::hash_map::HashMap;
 use std::collections::hash_set::HashSet;
@@ -22,71 +23,314 @@ struct GetPackagesQuery;
 pub enum Error {
     #[error("There was a problem resolve dependencies. {0}")]
     CouldNotResolvePackages(String),
+    #[error("Could not reach the registry. {0}")]
+    RegistryUnreachable(String),
+    #[error("Package \"{package}\" was not found in the package cache at \"{cache_dir}\". Install it while online first, or add its package tarball to the cache.")]
+    PackageNotCached { package: String, cache_dir: String },
+    #[error("None of the registries could resolve {}. {}", .packages.join(", "), registry_failures_message(.failures))]
+    NoRegistryResolved {
+        packages: Vec<String>,
+        /// The name of every registry that failed, with its error
+        failures: Vec<(String, String)>,
+    },
+    #[error("Could not resolve {name} {version}. {reason}{}", available_versions_message(.available_versions))]
+    ResolveFailed {
+        name: String,
+        version: String,
+        reason: String,
+        /// The versions of the package the resolver has, empty if it could not tell
+        available_versions: Vec<Version>,
+    },
+    #[error("Gave up resolving after {attempts} attempts. {error}")]
+    RetriesExhausted { attempts: u32, error: Box<Error> },
+}
+
+fn available_versions_message(versions: &[Version]) -> String {
+    if versions.is_empty() {
+        return String::new();
+    }
+    let versions: Vec<String> = versions.iter().map(Version::to_string).collect();
+    format!(" Available versions: {}", versions.join(", "))
+}
+
+fn registry_failures_message(failures: &[(String, String)]) -> String {
+    failures
+        .iter()
+        .map(|(registry, error)| format!("{}: {}", registry, error))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Error {
+    /// Whether resolving again could succeed, i.e. after a network error. Packages missing from
+    /// the cache or from the registry stay missing, and other errors, like an error the registry
+    /// answered with, are not transient either.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::RegistryUnreachable(_) => true,
+            Error::CouldNotResolvePackages(_)
+            | Error::PackageNotCached { .. }
+            | Error::NoRegistryResolved { .. }
+            | Error::ResolveFailed { .. }
+            | Error::RetriesExhausted { .. } => false,
+        }
+    }
+
+    fn resolve_failed(key: &PackageKey, reason: String, available_versions: Vec<Version>) -> Self {
+        let version = match key {
+            PackageKey::WapmPackage(WapmPackageKey { version, .. }) => version.to_string(),
+            PackageKey::WapmPackageRange(WapmPackageRange { version_req, .. }) => {
+                version_req.to_string()
+            }
+        };
+        Error::ResolveFailed {
+            name: key.name().to_string(),
+            version,
+            reason,
+            available_versions,
+        }
+    }
 }
 
 /// Struct containing wapm registry resolved packages. This is realized as a pairing of wapm.io keys
 /// and download URLs.
 #[derive(Clone, Debug, Default)]
 pub struct ResolvedPackages<'a> {
-    pub packages: Vec<(
-        WapmPackageKey<'a>,
-        (String, Option<keys::WapmPackageSignature>),
-    )>,
+    pub packages: Vec<ResolvedPackage<'a>>,
 }
 
 impl<'a> ResolvedPackages<'a> {
     /// Consume changed manifest packages and produce keys with download urls. Will query the registry
     /// for the download urls.
-    fn new<Resolver>(packages: HashSet<PackageKey<'a>>) -> Result<Self, Error>
+    fn new<Resolver>(packages: HashSet<PackageKey<'a>>, resolver: &Resolver) -> Result<Self, Error>
     where
         Resolver: Resolve<'a>,
     {
-        let wapm_pkgs: Vec<PackageKey> = packages.into_iter().collect();
+        let mut wapm_pkgs: Vec<PackageKey> = packages.into_iter().collect();
+        wapm_pkgs.sort_by(|a, b| a.cmp_by_name_and_version(b));
         // return early if no packages to resolve
         if wapm_pkgs.is_empty() {
             return Ok(Self::default());
         }
-        let packages = Resolver::sync_packages(wapm_pkgs)
-            .map_err(|e| Error::CouldNotResolvePackages(e.to_string()))?;
+        let mut packages = resolver
+            .sync_packages(wapm_pkgs.clone())
+            .map_err(|e| Self::attribute_error(e, wapm_pkgs, resolver))?;
+        // resolvers may return packages in any order, sort them so installs happen in a stable order
+        packages.sort_by(|(a, _), (b, _)| a.cmp(b));
         Ok(Self { packages })
     }
 
+    /// Names the package that could not be resolved. Resolvers fail for the whole batch, so a
+    /// failed batch is retried one package at a time to find the package that fails.
+    fn attribute_error<Resolver>(
+        error: Error,
+        packages: Vec<PackageKey<'a>>,
+        resolver: &Resolver,
+    ) -> Error
+    where
+        Resolver: Resolve<'a>,
+    {
+        // a network error is not about any one package
+        if let Error::PackageNotCached { .. }
+        | Error::ResolveFailed { .. }
+        | Error::NoRegistryResolved { .. }
+        | Error::RegistryUnreachable(_) = error
+        {
+            return error;
+        }
+        if let [key] = packages.as_slice() {
+            let available_versions = resolver.available_versions(key.name()).unwrap_or_else(|e| {
+                debug!("Could not list the versions of {}: {}", key.name(), e);
+                vec![]
+            });
+            return Error::resolve_failed(key, error.to_string(), available_versions);
+        }
+        for key in packages {
+            if let Err(e) = resolver.sync_packages(vec![key.clone()]) {
+                return Self::attribute_error(e, vec![key], resolver);
+            }
+        }
+        Error::CouldNotResolvePackages(error.to_string())
+    }
+
     pub fn new_from_added_packages<Resolver>(
         added_packages: AddedPackages<'a>,
+        resolver: &Resolver,
     ) -> Result<Self, Error>
     where
         Resolver: Resolve<'a>,
     {
-        Self::new::<Resolver>(added_packages.packages)
+        Self::new(added_packages.packages, resolver)
     }
 }
 
+/// A resolved package: its key, and its download url and signature
+pub type ResolvedPackage<'a> = (
+    WapmPackageKey<'a>,
+    (String, Option<keys::WapmPackageSignature>),
+);
+
 /// A Resolve trait to enable testing and dependency injection
+///
+/// Resolvers receive all packages that need resolving in one call so they can resolve them
+/// together, e.g. the `RegistryResolver` fetches every package in a single GraphQL query. The
+/// packages are sorted by `PackageKey::cmp_by_name_and_version`, and a failed batch is retried
+/// one package at a time in the same order.
 pub trait Resolve<'a> {
     fn sync_packages(
+        &self,
         added_packages: Vec<PackageKey<'a>>,
-    ) -> Result<
-        Vec<(
-            WapmPackageKey<'a>,
-            (String, Option<keys::WapmPackageSignature>),
-        )>,
-        Error,
-    >;
+    ) -> Result<Vec<ResolvedPackage<'a>>, Error>;
+
+    /// Called once with the existing lockfile before an update resolves its changed packages, so
+    /// resolvers backed by a cache can warm it from the `resolved` urls of the locked modules of
+    /// those `packages`. Does nothing by default.
+    fn preload(&self, _lockfile: &Lockfile, _packages: &[PackageKey<'a>]) {}
+
+    /// The versions of the package this resolver could resolve, oldest first, so errors about a
+    /// version that can not be resolved can list the alternatives. Empty by default.
+    fn available_versions(&self, _name: &str) -> Result<Vec<Version>, Error> {
+        Ok(vec![])
+    }
+
+    /// Whether the registry yanked the version of the package, so a lockfile that pins it can
+    /// warn about it. Versions are not yanked by default.
+    fn is_yanked(&self, _name: &str, _version: &Version) -> Result<bool, Error> {
+        Ok(false)
+    }
 }
 
-pub struct RegistryResolver;
+/// How a resolver picks the version of a package range when several versions satisfy it. The
+/// choice only depends on the versions and the range, so resolving the same packages with the
+/// same selector always gives the same lockfile.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum VersionSelector {
+    /// The newest matching version
+    #[default]
+    Highest,
+    /// The oldest matching version, e.g. to test that a package works with the minimum versions
+    /// its dependency ranges allow
+    Lowest,
+}
 
-impl<'a> RegistryResolver {
-    fn get_response(added_pkgs: Vec<PackageKey<'a>>) -> get_packages_query::ResponseData {
-        let names = added_pkgs
-            .into_iter()
-            .map(|key| match key {
-                PackageKey::WapmPackageRange(WapmPackageRange { name, .. }) => name.to_string(),
-                PackageKey::WapmPackage(WapmPackageKey { name, .. }) => name.to_string(),
+impl VersionSelector {
+    /// Picks one of the versions that match the requirement, `None` if no version matches.
+    /// Matching follows the semver pre-release rules like every other version check of an
+    /// update: a pre-release only matches a requirement that names a pre-release of the same
+    /// `major.minor.patch`, so `^1.0` never picks `1.1.0-beta` or `2.0.0-alpha`.
+    pub fn select<'v>(
+        &self,
+        version_req: &VersionReq,
+        versions: impl IntoIterator<Item = &'v Version>,
+    ) -> Option<&'v Version> {
+        let matching_versions = versions.into_iter().filter(|v| version_req.matches(v));
+        match self {
+            VersionSelector::Highest => matching_versions.max(),
+            VersionSelector::Lowest => matching_versions.min(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RegistryResolver {
+    version_selector: VersionSelector,
+}
+
+impl RegistryResolver {
+    pub fn new(version_selector: VersionSelector) -> Self {
+        Self { version_selector }
+    }
+
+    fn get_response(names: Vec<String>) -> Result<get_packages_query::ResponseData, Error> {
+        let q = GetPackagesQuery::build_query(get_packages_query::Variables { names });
+        execute_query(&q).map_err(|e| match e.downcast_ref::<reqwest::Error>() {
+            // the registry answered, but not with packages
+            Some(e) if e.is_decode() => Error::CouldNotResolvePackages(e.to_string()),
+            Some(_) => Error::RegistryUnreachable(e.to_string()),
+            None => Error::CouldNotResolvePackages(e.to_string()),
+        })
+    }
+}
+
+/// A package version the registry returned: the name, version, download url, and signature
+type RegistryVersion = (String, Version, String, Option<keys::WapmPackageSignature>);
+
+impl RegistryResolver {
+    /// Picks the version and download url of every added package from the versions the registry
+    /// returned. A package with no such version is an error that lists the versions the
+    /// registry has, instead of being left out.
+    fn select_packages<'a>(
+        &self,
+        added_packages: Vec<PackageKey<'a>>,
+        all_packages_and_download_urls: Vec<RegistryVersion>,
+    ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+        // lookup by exact package key
+        let exact_package_lookup: HashMap<_, _> = all_packages_and_download_urls
+            .iter()
+            .cloned()
+            .map(|(name, version, download_url, signature)| {
+                (
+                    WapmPackageKey {
+                        name: Owned(name),
+                        version,
+                    },
+                    (download_url, signature),
+                )
             })
             .collect();
-        let q = GetPackagesQuery::build_query(get_packages_query::Variables { names });
-        execute_query(&q).unwrap()
+
+        // lookup versions by name, used for matching package version ranges
+        let mut package_versions_lookup: HashMap<String, Vec<Version>> = HashMap::new();
+        for (name, version, _, _) in all_packages_and_download_urls {
+            let versions = package_versions_lookup.entry(name).or_default();
+            versions.push(version);
+        }
+
+        // filter all the package-versions + download_urls by exact version or version range
+        added_packages
+            .into_iter()
+            .map(|added_package| {
+                let requested = added_package.clone();
+                let key_and_data = match added_package {
+                    // if exact, then use the lookup table
+                    PackageKey::WapmPackage(wapm_package_key) => exact_package_lookup
+                        .get(&wapm_package_key)
+                        .map(|(d, s)| (wapm_package_key, (d.clone(), s.clone()))),
+                    // if a range, then filter by the requirements, and let the selector pick a
+                    // version
+                    PackageKey::WapmPackageRange(range) => package_versions_lookup
+                        .get(range.name.as_ref())
+                        .and_then(|versions| {
+                            self.version_selector
+                                .select(&range.version_req, versions)
+                                .cloned()
+                        })
+                        // join the key with the download url by using the package-key lookup
+                        // table
+                        .and_then(|version| {
+                            let key = WapmPackageKey {
+                                name: range.name,
+                                version,
+                            };
+                            let data = exact_package_lookup.get(&key);
+                            data.cloned()
+                                .map(|(dl_url, signature)| (key, (dl_url, signature)))
+                        }),
+                };
+                key_and_data.ok_or_else(|| {
+                    let mut available_versions = package_versions_lookup
+                        .get(requested.name())
+                        .cloned()
+                        .unwrap_or_default();
+                    available_versions.sort();
+                    Error::resolve_failed(
+                        &requested,
+                        "The registry has no matching version.".to_string(),
+                        available_versions,
+                    )
+                })
+            })
+            .collect()
     }
 }
 
@@ -94,22 +338,17 @@ impl<'a> RegistryResolver {
 impl<'a> Resolve<'a> for RegistryResolver {
     /// This gross function queries the GraphQL server. See the schema in `/graphql/queries/get_packages.graphql`
     fn sync_packages(
+        &self,
         added_packages: Vec<PackageKey<'a>>,
-    ) -> Result<
-        Vec<(
-            WapmPackageKey<'a>,
-            (String, Option<keys::WapmPackageSignature>),
-        )>,
-        Error,
-    > {
+    ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
         // fetch data from graphql server
-        let response = Self::get_response(added_packages.clone());
-        let all_packages_and_download_urls: Vec<(
-            String,
-            Version,
-            String,
-            Option<keys::WapmPackageSignature>,
-        )> = response
+        let response = Self::get_response(
+            added_packages
+                .iter()
+                .map(|key| key.name().to_string())
+                .collect(),
+        )?;
+        let all_packages_and_download_urls: Vec<RegistryVersion> = response
             .package
             .into_iter()
             .filter_map(|p| p)
@@ -152,78 +391,33 @@ impl<'a> Resolve<'a> for RegistryResolver {
             })
             .collect::<Result<Vec<(_, _, _, _)>, Error>>()?;
 
-        // lookup by exact package key
-        let exact_package_lookup: HashMap<_, _> = all_packages_and_download_urls
-            .iter()
-            .cloned()
-            .map(|(name, version, download_url, signature)| {
-                (
-                    WapmPackageKey {
-                        name: Owned(name),
-                        version,
-                    },
-                    (download_url, signature),
-                )
-            })
-            .collect();
-
-        // lookup versions by name, used for matching package version ranges
-        let mut package_versions_lookup: HashMap<String, Vec<Version>> = HashMap::new();
-        for (name, version, _, _) in all_packages_and_download_urls {
-            let versions = package_versions_lookup.entry(name).or_default();
-            versions.push(version);
-        }
+        self.select_packages(added_packages, all_packages_and_download_urls)
+    }
 
-        // filter all the package-versions + download_urls by exact version or version range
-        let packages_and_download_urls: Vec<(
-            WapmPackageKey,
-            (String, Option<keys::WapmPackageSignature>),
-        )> = added_packages
+    fn available_versions(&self, name: &str) -> Result<Vec<Version>, Error> {
+        let response = Self::get_response(vec![name.to_string()])?;
+        let mut versions: Vec<Version> = response
+            .package
             .into_iter()
-            .filter_map(|added_package| match added_package {
-                // if exact, then use the lookup table
-                PackageKey::WapmPackage(wapm_package_key) => exact_package_lookup
-                    .get(&wapm_package_key)
-                    .map(|(d, s)| (wapm_package_key, (d.clone(), s.clone()))),
-                // if a range, then filter by the requirements, and find the max version
-                PackageKey::WapmPackageRange(range) => {
-                    let matching_version: Option<Version> = package_versions_lookup
-                        .get(range.name.as_ref())
-                        .and_then(|versions| {
-                            let max_version: Option<Version> = versions
-                                .iter()
-                                .cloned()
-                                .filter(|v| range.version_req.matches(v))
-                                .max(); // get the max version number after filtering by version requirement
-                            max_version
-                        });
-                    // join the key with the download url by using the package-key lookup table
-                    let key_and_data: Option<(
-                        WapmPackageKey,
-                        (String, Option<keys::WapmPackageSignature>),
-                    )> = matching_version.and_then(|version| {
-                        let key = WapmPackageKey {
-                            name: range.name,
-                            version,
-                        };
-                        let data = exact_package_lookup.get(&key);
-                        data.cloned()
-                            .map(|(dl_url, signature)| (key, (dl_url, signature)))
-                    });
-                    key_and_data
-                }
-            })
+            .flatten()
+            .filter(|p| p.name == name)
+            .flat_map(|p| p.versions.unwrap_or_default())
+            .flatten()
+            .filter_map(|v| Version::parse(&v.version).ok())
             .collect();
-        Ok(packages_and_download_urls)
+        versions.sort();
+        Ok(versions)
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::dataflow::added_packages::AddedPackages;
-    use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackages};
+    use crate::dataflow::resolved_packages::{
+        Error, RegistryResolver, Resolve, ResolvedPackage, ResolvedPackages, VersionSelector,
+    };
     use crate::dataflow::{PackageKey, WapmPackageKey, WapmPackageRange};
-    use crate::keys;
+    use std::cell::RefCell;
     use std::collections::HashSet;
 
     struct TestResolver;
@@ -231,14 +425,9 @@ mod test {
     /// A test resolver that does not resolve the "baz" and "bar" packages but contains everything else.
     impl<'a> Resolve<'a> for TestResolver {
         fn sync_packages(
+            &self,
             added_packages: Vec<PackageKey<'a>>,
-        ) -> Result<
-            Vec<(
-                WapmPackageKey<'a>,
-                (String, Option<keys::WapmPackageSignature>),
-            )>,
-            Error,
-        > {
+        ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
             Ok(added_packages
                 .into_iter()
                 .filter(|k| {
@@ -283,7 +472,7 @@ mod test {
             packages: packages_set,
         };
         let resolve_packages =
-            ResolvedPackages::new_from_added_packages::<TestResolver>(added_packages).unwrap();
+            ResolvedPackages::new_from_added_packages(added_packages, &TestResolver).unwrap();
         assert_eq!(1, resolve_packages.packages.len());
     }
 
@@ -303,7 +492,7 @@ mod test {
             packages: packages_set,
         };
         let resolve_packages =
-            ResolvedPackages::new_from_added_packages::<TestResolver>(added_packages).unwrap();
+            ResolvedPackages::new_from_added_packages(added_packages, &TestResolver).unwrap();
         assert_eq!(1, resolve_packages.packages.len());
     }
 
@@ -324,7 +513,7 @@ mod test {
             packages: packages_set,
         };
         let resolve_packages =
-            ResolvedPackages::new_from_added_packages::<TestResolver>(added_packages).unwrap();
+            ResolvedPackages::new_from_added_packages(added_packages, &TestResolver).unwrap();
         assert_eq!(1, resolve_packages.packages.len());
         resolve_packages
             .packages
@@ -332,4 +521,288 @@ mod test {
             .find(|(p, _s)| p.name == "_/foo")
             .unwrap();
     }
+
+    #[test]
+    fn test_resolved_packages_are_sorted() {
+        let mut packages_set = HashSet::new();
+        for name in ["_/qux", "_/foo", "_/abc", "_/quux"].iter().copied() {
+            packages_set.insert(PackageKey::new_registry_package(
+                name,
+                semver::Version::new(1, 0, 0),
+            ));
+        }
+        let added_packages = AddedPackages {
+            packages: packages_set,
+        };
+        let resolve_packages =
+            ResolvedPackages::new_from_added_packages(added_packages, &TestResolver).unwrap();
+        let names: Vec<&str> = resolve_packages
+            .packages
+            .iter()
+            .map(|(k, _)| k.name.as_ref())
+            .collect();
+        assert_eq!(vec!["_/abc", "_/foo", "_/quux", "_/qux"], names);
+    }
+
+    /// A test resolver that fails to resolve any batch containing the "bar" package
+    struct FailingResolver;
+
+    impl<'a> Resolve<'a> for FailingResolver {
+        fn sync_packages(
+            &self,
+            added_packages: Vec<PackageKey<'a>>,
+        ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+            if added_packages.iter().any(|key| key.name() == "_/bar") {
+                return Err(Error::CouldNotResolvePackages(
+                    "The registry returned an error.".to_string(),
+                ));
+            }
+            TestResolver.sync_packages(added_packages)
+        }
+
+        fn available_versions(&self, name: &str) -> Result<Vec<semver::Version>, Error> {
+            Ok(match name {
+                "_/bar" => vec![semver::Version::new(1, 0, 0), semver::Version::new(2, 0, 0)],
+                _ => vec![],
+            })
+        }
+    }
+
+    #[test]
+    fn resolve_errors_name_the_failing_package() {
+        let mut packages_set = HashSet::new();
+        packages_set.insert(PackageKey::new_registry_package(
+            "_/foo",
+            semver::Version::new(1, 0, 0),
+        ));
+        packages_set.insert(PackageKey::new_registry_package(
+            "_/bar",
+            semver::Version::new(3, 0, 0),
+        ));
+        let added_packages = AddedPackages {
+            packages: packages_set,
+        };
+
+        let error = ResolvedPackages::new_from_added_packages(added_packages, &FailingResolver)
+            .unwrap_err();
+        match error {
+            Error::ResolveFailed {
+                ref name,
+                ref version,
+                ..
+            } => {
+                assert_eq!("_/bar", name);
+                assert_eq!("3.0.0", version);
+            }
+            ref e => panic!("expected a resolve failure, got {:?}", e),
+        }
+        assert!(error
+            .to_string()
+            .starts_with("Could not resolve _/bar 3.0.0."));
+        assert!(error
+            .to_string()
+            .ends_with(". Available versions: 1.0.0, 2.0.0"));
+    }
+
+    /// A test resolver that records the order of the packages it is asked for, and fails like the
+    /// `FailingResolver` for the "bar" and "baz" packages
+    struct RecordingResolver(RefCell<Vec<Vec<String>>>);
+
+    impl<'a> Resolve<'a> for RecordingResolver {
+        fn sync_packages(
+            &self,
+            added_packages: Vec<PackageKey<'a>>,
+        ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+            self.0
+                .borrow_mut()
+                .push(added_packages.iter().map(|key| key.to_string()).collect());
+            if added_packages
+                .iter()
+                .any(|key| key.name() == "_/bar" || key.name() == "_/baz")
+            {
+                return Err(Error::CouldNotResolvePackages("offline".to_string()));
+            }
+            TestResolver.sync_packages(added_packages)
+        }
+    }
+
+    #[test]
+    fn packages_are_resolved_in_a_stable_order() {
+        let keys = vec![
+            PackageKey::new_registry_package_range(
+                "_/foo",
+                semver::VersionReq::parse("^1").unwrap(),
+            ),
+            PackageKey::new_registry_package("_/qux", semver::Version::new(1, 0, 0)),
+            PackageKey::new_registry_package("_/foo", semver::Version::new(1, 10, 0)),
+            PackageKey::new_registry_package("_/foo", semver::Version::new(1, 9, 0)),
+            PackageKey::new_registry_package("_/abc", semver::Version::new(2, 0, 0)),
+        ];
+        let resolve = |keys: &[PackageKey<'static>]| {
+            let resolver = RecordingResolver(RefCell::new(vec![]));
+            let added_packages = AddedPackages {
+                packages: keys.iter().cloned().collect::<HashSet<_>>(),
+            };
+            let resolved: Vec<String> =
+                ResolvedPackages::new_from_added_packages(added_packages, &resolver)
+                    .unwrap()
+                    .packages
+                    .iter()
+                    .map(|(key, (url, _))| format!("{} {}", key, url))
+                    .collect();
+            (resolver.0.into_inner(), resolved)
+        };
+
+        let (calls, resolved) = resolve(&keys);
+        assert_eq!(
+            vec![vec![
+                "_/abc 2.0.0",
+                "_/foo 1.9.0",
+                "_/foo 1.10.0",
+                "_/foo >=1.0.0, <2.0.0",
+                "_/qux 1.0.0"
+            ]],
+            calls
+        );
+        let mut reversed = keys.clone();
+        reversed.reverse();
+        for shuffled in &[
+            reversed,
+            vec![
+                keys[2].clone(),
+                keys[4].clone(),
+                keys[0].clone(),
+                keys[1].clone(),
+                keys[3].clone(),
+            ],
+        ] {
+            let (shuffled_calls, shuffled_resolved) = resolve(shuffled);
+            assert_eq!(calls, shuffled_calls);
+            assert_eq!(resolved, shuffled_resolved);
+        }
+
+        // of several failing packages, the first one in the order is named
+        let mut failing = keys.clone();
+        failing.push(PackageKey::new_registry_package(
+            "_/baz",
+            semver::Version::new(1, 0, 0),
+        ));
+        failing.push(PackageKey::new_registry_package(
+            "_/bar",
+            semver::Version::new(1, 0, 0),
+        ));
+        for _ in 0..5 {
+            let resolver = RecordingResolver(RefCell::new(vec![]));
+            let added_packages = AddedPackages {
+                packages: failing.iter().cloned().collect::<HashSet<_>>(),
+            };
+            match ResolvedPackages::new_from_added_packages(added_packages, &resolver) {
+                Err(Error::ResolveFailed { name, .. }) => assert_eq!("_/bar", name),
+                result => panic!("expected a resolve failure, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn pre_releases_are_only_selected_when_asked_for() {
+        let versions: Vec<semver::Version> = ["1.0.0-beta.2", "1.2.0", "1.3.0-beta", "2.0.0-alpha"]
+            .iter()
+            .map(|v| semver::Version::parse(v).unwrap())
+            .collect();
+        let select = |selector: VersionSelector, version_req: &str| {
+            selector
+                .select(&semver::VersionReq::parse(version_req).unwrap(), &versions)
+                .map(|v| v.to_string())
+        };
+
+        assert_eq!(
+            Some("1.2.0".to_string()),
+            select(VersionSelector::Highest, "^1.0")
+        );
+        assert_eq!(
+            Some("1.2.0".to_string()),
+            select(VersionSelector::Lowest, "^1.0")
+        );
+        assert_eq!(
+            Some("1.2.0".to_string()),
+            select(VersionSelector::Highest, ">=1.0.0-beta")
+        );
+        assert_eq!(
+            Some("1.0.0-beta.2".to_string()),
+            select(VersionSelector::Lowest, ">=1.0.0-beta")
+        );
+        assert_eq!(
+            Some("2.0.0-alpha".to_string()),
+            select(VersionSelector::Highest, "2.0.0-alpha")
+        );
+        assert_eq!(None, select(VersionSelector::Highest, "^2"));
+
+        // a locked pre-release is kept or resolved again by the same rules
+        let range = |version_req: &str| WapmPackageRange {
+            name: "_/foo".into(),
+            version_req: semver::VersionReq::parse(version_req).unwrap(),
+        };
+        let locked = |version: &str| {
+            PackageKey::new_registry_package("_/foo", semver::Version::parse(version).unwrap())
+        };
+        assert!(!locked("2.0.0-alpha").matches(&range("^1.0")));
+        assert!(locked("1.0.0-beta.2").matches(&range(">=1.0.0-beta")));
+    }
+
+    #[test]
+    fn the_registry_resolver_fails_for_versions_it_does_not_have() {
+        let versions = ["1.0.0", "1.1.0", "0.9.0"]
+            .iter()
+            .map(|version| {
+                (
+                    "_/foo".to_string(),
+                    semver::Version::parse(version).unwrap(),
+                    format!("https://registry.wapm.io/foo-{}.tar.gz", version),
+                    None,
+                )
+            })
+            .collect::<Vec<_>>();
+        let resolver = RegistryResolver::default();
+        let select =
+            |key: PackageKey<'static>| resolver.select_packages(vec![key], versions.clone());
+
+        let resolved = select(PackageKey::new_registry_package_range(
+            "_/foo",
+            semver::VersionReq::parse("^1").unwrap(),
+        ))
+        .unwrap();
+        assert_eq!(semver::Version::new(1, 1, 0), resolved[0].0.version);
+
+        match select(PackageKey::new_registry_package(
+            "_/foo",
+            semver::Version::new(2, 0, 0),
+        )) {
+            Err(Error::ResolveFailed {
+                name,
+                version,
+                available_versions,
+                ..
+            }) => {
+                assert_eq!(("_/foo", "2.0.0"), (name.as_str(), version.as_str()));
+                let available_versions: Vec<String> =
+                    available_versions.iter().map(|v| v.to_string()).collect();
+                assert_eq!(vec!["0.9.0", "1.0.0", "1.1.0"], available_versions);
+            }
+            result => panic!("expected the resolve to fail, got {:?}", result),
+        }
+        match select(PackageKey::new_registry_package_range(
+            "_/bar",
+            semver::VersionReq::parse("*").unwrap(),
+        )) {
+            Err(Error::ResolveFailed {
+                name,
+                available_versions,
+                ..
+            }) => {
+                assert_eq!("_/bar", name);
+                assert!(available_versions.is_empty());
+            }
+            result => panic!("expected the resolve to fail, got {:?}", result),
+        }
+    }
 }
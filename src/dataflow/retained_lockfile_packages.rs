@@ -1,29 +1,23 @@
 use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
 use crate::dataflow::manifest_packages::ManifestPackages;
 use crate::dataflow::PackageKey;
-use std::collections::hash_set::HashSet;
 use std::collections::HashMap;
 
+#[derive(Clone, Debug)]
 pub struct RetainedLockfilePackages<'a> {
     pub packages: HashMap<PackageKey<'a>, LockfilePackage>,
 }
 
 impl<'a> RetainedLockfilePackages<'a> {
     pub fn from_manifest_and_lockfile(
-        manifest_packages: &'a ManifestPackages<'a>,
+        manifest_packages: &ManifestPackages<'a>,
         lockfile_packages: LockfilePackages<'a>,
     ) -> Self {
-        let manifest_keys = manifest_packages.keys();
-        let lockfile_keys: HashSet<_> = lockfile_packages.packages.keys().cloned().collect();
-        let keys: HashSet<_> = manifest_keys
-            .intersection(&lockfile_keys)
-            .cloned()
-            .collect();
-
+        // keep exact matches, and locked versions that still satisfy a manifest version range
         let packages: HashMap<_, _> = lockfile_packages
             .packages
             .into_iter()
-            .filter(|(k, _)| keys.contains(k))
+            .filter(|(k, _)| manifest_packages.includes(k))
             .collect();
 
         RetainedLockfilePackages { packages }
@@ -119,4 +113,44 @@ mod retained_lockfile_packages_tests {
             &PackageKey::new_registry_package("_/bar", semver::Version::new(2, 2, 0))
         ));
     }
+
+    #[test]
+    fn retain_lockfile_packages_matching_range() {
+        let mut manifest_package_keys = HashSet::new();
+        manifest_package_keys.insert(PackageKey::new_registry_package_range(
+            "_/foo",
+            semver::VersionReq::parse("^1").unwrap(),
+        ));
+        manifest_package_keys.insert(PackageKey::new_registry_package_range(
+            "_/bar",
+            semver::VersionReq::parse("^2").unwrap(),
+        ));
+        let manifest_packages = ManifestPackages {
+            packages: manifest_package_keys,
+        };
+
+        let mut lockfile_package_map = HashMap::new();
+        // the locked version of foo satisfies the range, but bar no longer does
+        lockfile_package_map.insert(
+            PackageKey::new_registry_package("_/foo", semver::Version::new(1, 2, 0)),
+            LockfilePackage::default(),
+        );
+        lockfile_package_map.insert(
+            PackageKey::new_registry_package("_/bar", semver::Version::new(1, 0, 0)),
+            LockfilePackage::default(),
+        );
+        let lockfile_packages = LockfilePackages {
+            packages: lockfile_package_map,
+        };
+
+        let retained_lockfile_packages = RetainedLockfilePackages::from_manifest_and_lockfile(
+            &manifest_packages,
+            lockfile_packages,
+        );
+
+        assert_eq!(1, retained_lockfile_packages.packages.len());
+        assert!(retained_lockfile_packages.packages.contains_key(
+            &PackageKey::new_registry_package("_/foo", semver::Version::new(1, 2, 0))
+        ));
+    }
 }
@@ -0,0 +1,225 @@
+use crate::data::lock::lockfile::Lockfile;
+use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage};
+use crate::dataflow::PackageKey;
+use semver::Version;
+use std::thread;
+use std::time::Duration;
+
+/// Decides which resolve errors are worth retrying
+pub trait RetryPolicy {
+    fn is_retryable(&self, error: &Error) -> bool {
+        error.is_retryable()
+    }
+}
+
+/// Retries the errors that `Error::is_retryable` considers transient
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultRetryPolicy;
+
+impl RetryPolicy for DefaultRetryPolicy {}
+
+/// The Retry Resolver wraps another resolver, e.g. the `RegistryResolver`, and calls it again when
+/// it fails with an error the policy considers transient. The wait before each retry doubles,
+/// starting at `backoff`. After `max_attempts` failed calls the last error is returned.
+pub struct RetryResolver<R, P = DefaultRetryPolicy> {
+    resolver: R,
+    max_attempts: u32,
+    backoff: Duration,
+    policy: P,
+}
+
+impl<R> RetryResolver<R> {
+    pub fn new(resolver: R, max_attempts: u32, backoff: Duration) -> Self {
+        Self::with_policy(resolver, max_attempts, backoff, DefaultRetryPolicy)
+    }
+}
+
+impl<R, P> RetryResolver<R, P> {
+    pub fn with_policy(resolver: R, max_attempts: u32, backoff: Duration, policy: P) -> Self {
+        Self {
+            resolver,
+            // the resolver is always called at least once
+            max_attempts: max_attempts.max(1),
+            backoff,
+            policy,
+        }
+    }
+}
+
+impl<'a, R: for<'b> Resolve<'b>, P: RetryPolicy> Resolve<'a> for RetryResolver<R, P> {
+    fn sync_packages(
+        &self,
+        added_packages: Vec<PackageKey<'a>>,
+    ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+        let mut backoff = self.backoff;
+        let mut attempts = 1;
+        loop {
+            let error = match self.resolver.sync_packages(added_packages.clone()) {
+                Ok(packages) => return Ok(packages),
+                Err(e) => e,
+            };
+            if !self.policy.is_retryable(&error) {
+                return Err(error);
+            }
+            if attempts >= self.max_attempts {
+                return Err(if attempts > 1 {
+                    Error::RetriesExhausted {
+                        attempts,
+                        error: Box::new(error),
+                    }
+                } else {
+                    error
+                });
+            }
+            warn!(
+                "Resolving packages failed, retrying in {}ms. {}",
+                backoff.as_millis(),
+                error
+            );
+            thread::sleep(backoff);
+            backoff *= 2;
+            attempts += 1;
+        }
+    }
+
+    fn preload(&self, lockfile: &Lockfile, packages: &[PackageKey<'a>]) {
+        self.resolver.preload(lockfile, packages)
+    }
+
+    fn available_versions(&self, name: &str) -> Result<Vec<Version>, Error> {
+        self.resolver.available_versions(name)
+    }
+
+    fn is_yanked(&self, name: &str, version: &Version) -> Result<bool, Error> {
+        self.resolver.is_yanked(name, version)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackage};
+    use crate::dataflow::retry_resolver::{RetryPolicy, RetryResolver};
+    use crate::dataflow::{PackageKey, WapmPackageKey};
+    use std::cell::{Cell, RefCell};
+    use std::time::Duration;
+
+    /// A test resolver that fails with the given errors before it resolves every package to
+    /// version 1.0.0
+    struct FlakyResolver {
+        errors: RefCell<Vec<Error>>,
+        calls: Cell<u32>,
+    }
+
+    impl FlakyResolver {
+        fn new(errors: Vec<Error>) -> Self {
+            Self {
+                errors: RefCell::new(errors),
+                calls: Cell::new(0),
+            }
+        }
+    }
+
+    impl<'a> Resolve<'a> for FlakyResolver {
+        fn sync_packages(
+            &self,
+            added_packages: Vec<PackageKey<'a>>,
+        ) -> Result<Vec<ResolvedPackage<'a>>, Error> {
+            self.calls.set(self.calls.get() + 1);
+            if !self.errors.borrow().is_empty() {
+                return Err(self.errors.borrow_mut().remove(0));
+            }
+            Ok(added_packages
+                .into_iter()
+                .map(|key| {
+                    (
+                        WapmPackageKey {
+                            name: key.name().to_string().into(),
+                            version: semver::Version::new(1, 0, 0),
+                        },
+                        ("url".to_string(), None),
+                    )
+                })
+                .collect())
+        }
+    }
+
+    fn network_error() -> Error {
+        Error::RegistryUnreachable("connection reset".to_string())
+    }
+
+    fn foo() -> PackageKey<'static> {
+        PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0))
+    }
+
+    #[test]
+    fn transient_errors_are_retried() {
+        let resolver = RetryResolver::new(
+            FlakyResolver::new(vec![network_error(), network_error()]),
+            3,
+            Duration::from_millis(1),
+        );
+        assert_eq!(1, resolver.sync_packages(vec![foo()]).unwrap().len());
+        assert_eq!(3, resolver.resolver.calls.get());
+
+        let resolver = RetryResolver::new(
+            FlakyResolver::new(vec![network_error(), network_error()]),
+            2,
+            Duration::from_millis(1),
+        );
+        match resolver.sync_packages(vec![foo()]) {
+            Err(e @ Error::RetriesExhausted { attempts: 2, .. }) => assert_eq!(
+                "Gave up resolving after 2 attempts. Could not reach the registry. connection reset",
+                e.to_string()
+            ),
+            result => panic!("expected the retries to run out, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn permanent_errors_are_not_retried() {
+        // the registry answered with an error
+        let resolver = RetryResolver::new(
+            FlakyResolver::new(vec![Error::CouldNotResolvePackages(
+                "permission denied".to_string(),
+            )]),
+            3,
+            Duration::from_millis(1),
+        );
+        assert!(matches!(
+            resolver.sync_packages(vec![foo()]),
+            Err(Error::CouldNotResolvePackages(_))
+        ));
+        assert_eq!(1, resolver.resolver.calls.get());
+
+        let not_cached = Error::PackageNotCached {
+            package: "_/foo 1.0.0".to_string(),
+            cache_dir: "cache".to_string(),
+        };
+        let resolver = RetryResolver::new(
+            FlakyResolver::new(vec![not_cached.clone()]),
+            3,
+            Duration::from_millis(1),
+        );
+        match resolver.sync_packages(vec![foo()]) {
+            Err(Error::PackageNotCached { .. }) => {}
+            result => panic!("expected the first error, got {:?}", result),
+        }
+        assert_eq!(1, resolver.resolver.calls.get());
+
+        /// Retries every error
+        struct RetryAll;
+        impl RetryPolicy for RetryAll {
+            fn is_retryable(&self, _error: &Error) -> bool {
+                true
+            }
+        }
+        let resolver = RetryResolver::with_policy(
+            FlakyResolver::new(vec![not_cached]),
+            3,
+            Duration::from_millis(1),
+            RetryAll,
+        );
+        resolver.sync_packages(vec![foo()]).unwrap();
+        assert_eq!(2, resolver.resolver.calls.get());
+    }
+}
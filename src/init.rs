@@ -80,6 +80,12 @@ pub fn init(dir: PathBuf, force_yes: bool) -> anyhow::Result<()> {
         Manifest {
             base_directory_path: dir.clone(),
             fs: None,
+            optional_dependencies: None,
+            dev_dependencies: None,
+            build_dependencies: None,
+            overrides: None,
+            command_aliases: None,
+            features: None,
             package: Package {
                 name: dir
                     .clone()
@@ -105,6 +111,7 @@ pub fn init(dir: PathBuf, force_yes: bool) -> anyhow::Result<()> {
                 source: "entry.wasm".into(),
                 abi: Abi::default(),
                 interfaces: None,
+                targets: None,
             }]),
             command: None,
         }
@@ -155,6 +162,7 @@ Press ^C at any time to quit."
                         source: PathBuf::from("none"),
                         abi: Abi::default(),
                         interfaces: None,
+                        targets: None,
                     }
                 }
             };
@@ -219,7 +227,11 @@ Press ^C at any time to quit."
                                 name: command_string,
                                 module: module.name.clone(),
                                 main_args: None,
+                                args: vec![],
                                 package: None,
+                                abi: None,
+                                feature: None,
+                                private: false,
                             });
                     all_commands.extend(module_commands);
                 }
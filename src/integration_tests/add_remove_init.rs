@@ -3,6 +3,7 @@
 use maplit::hashmap;
 
 use super::prelude::*;
+use crate::data::manifest::Dependency;
 
 #[test]
 fn it_works() {
@@ -24,8 +25,8 @@ fn it_works() {
         assert_eq!(
             manifest.dependencies,
             Some(hashmap! {
-                "mark2/python".to_string() => "0.0.4".to_string(),
-                "mark2/dog2".to_string() => "0.0.13".to_string(),
+                "mark2/python".to_string() => Dependency::Version("0.0.4".to_string()),
+                "mark2/dog2".to_string() => Dependency::Version("0.0.13".to_string()),
             })
         );
     }
@@ -38,16 +39,16 @@ fn it_works() {
         assert_eq!(
             manifest_before.dependencies,
             Some(hashmap! {
-                "mark2/python".to_string() => "0.0.4".to_string(),
-                "mark2/dog2".to_string() => "0.0.13".to_string(),
-                "lolcat".to_string() => "0.1.1".to_string(),
+                "mark2/python".to_string() => Dependency::Version("0.0.4".to_string()),
+                "mark2/dog2".to_string() => Dependency::Version("0.0.13".to_string()),
+                "lolcat".to_string() => Dependency::Version("0.1.1".to_string()),
             })
         );
         assert_eq!(
             manifest_after.dependencies,
             Some(hashmap! {
-                "mark2/python".to_string() => "0.0.4".to_string(),
-                "mark2/dog2".to_string() => "0.0.13".to_string(),
+                "mark2/python".to_string() => Dependency::Version("0.0.4".to_string()),
+                "mark2/dog2".to_string() => Dependency::Version("0.0.13".to_string()),
             })
         );
     }
@@ -223,6 +223,62 @@ pub fn get_hashed_module_key(_path: &Path) -> Option<String> {
     None
 }
 
+/// Computes the sha256 digest of a module, as a hex `hash` and a subresource-integrity style
+/// `integrity` string (`sha256-<base64>`).
+pub fn compute_module_integrity(path: &Path) -> anyhow::Result<(String, String)> {
+    Ok(compute_integrity(&fs::read(path)?))
+}
+
+/// Like `compute_module_integrity`, for the bytes of a module that is not on disk
+pub fn compute_integrity(bytes: &[u8]) -> (String, String) {
+    use sha2::{Digest, Sha256};
+
+    let digest = Sha256::digest(bytes);
+    let hash = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let integrity = format!("sha256-{}", base64::encode(digest));
+    (hash, integrity)
+}
+
+/// The number of single character insertions, deletions, and substitutions that turn `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // the distances from the prefix of `a` so far to every prefix of `b`
+    let mut distances: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut previous_diagonal = distances[0];
+        distances[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous_diagonal + if a_char == *b_char { 0 } else { 1 };
+            previous_diagonal = distances[j + 1];
+            distances[j + 1] = substitution.min(distances[j] + 1).min(distances[j + 1] + 1);
+        }
+    }
+    distances[b.len()]
+}
+
+/// The candidate closest to `name` if it is likely a typo of it: at most two edits away, and
+/// fewer edits than `name` has characters. Ties go to the first candidate.
+pub fn closest_match<'a, I: IntoIterator<Item = &'a str>>(
+    name: &str,
+    candidates: I,
+) -> Option<&'a str> {
+    let max_distance = 2.min(name.chars().count().saturating_sub(1));
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Whether a target triple like `x86_64-unknown-linux-gnu` is the machine wapm runs on
+pub fn is_host_target(target: &str) -> bool {
+    let mut parts = target.split('-');
+    let os = env::consts::OS;
+    parts.next() == Some(env::consts::ARCH)
+        && parts.any(|part| part == os || (os == "macos" && part == "darwin"))
+}
+
 #[cfg(feature = "update-notifications")]
 pub fn get_latest_runtime_version(runtime: &str) -> Result<String, String> {
     use std::process::Command;
@@ -312,6 +368,46 @@ mod test {
         assert_eq!(compare_versions("0.1.1", "0.2.0"), Some(false));
     }
 
+    #[test]
+    pub fn test_compute_module_integrity() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let path = tmp_dir.path().join("module.wasm");
+        fs::write(&path, b"hello world").unwrap();
+
+        let (hash, integrity) = compute_module_integrity(&path).unwrap();
+
+        assert_eq!(
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+            hash
+        );
+        assert_eq!(
+            "sha256-uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=",
+            integrity
+        );
+        assert!(compute_module_integrity(&tmp_dir.path().join("missing.wasm")).is_err());
+    }
+
+    #[test]
+    pub fn test_closest_match() {
+        assert_eq!(0, edit_distance("serve", "serve"));
+        assert_eq!(1, edit_distance("serv", "serve"));
+        assert_eq!(2, edit_distance("sevre", "serve"));
+        assert_eq!(5, edit_distance("", "serve"));
+
+        let commands = ["build", "serve", "server"];
+        assert_eq!(
+            Some("serve"),
+            closest_match("serv", commands.iter().copied())
+        );
+        assert_eq!(
+            Some("build"),
+            closest_match("biuld", commands.iter().copied())
+        );
+        assert_eq!(None, closest_match("deploy", commands.iter().copied()));
+        // a single character is not a typo of every other single character
+        assert_eq!(None, closest_match("a", ["b"].iter().copied()));
+    }
+
     #[test]
     pub fn test_split_runtime_and_args() {
         assert_eq!(
@@ -12,13 +12,9 @@ pub fn validate_directory(pkg_path: PathBuf) -> anyhow::Result<()> {
         ManifestResult::ManifestError(e) => return Err(e.into()),
         ManifestResult::Manifest(manifest) => manifest,
     };
-    if let Some(modules) = manifest.module {
+    if let Some(modules) = manifest.module.clone() {
         for module in modules.into_iter() {
-            let source_path = if module.source.is_relative() {
-                manifest.base_directory_path.join(&module.source)
-            } else {
-                module.source.clone()
-            };
+            let source_path = manifest.resolve_path(&module.source);
             let source_path_string = source_path.to_string_lossy().to_string();
             let mut wasm_file =
                 fs::File::open(&source_path).map_err(|_| ValidationError::MissingFile {